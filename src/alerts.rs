@@ -0,0 +1,94 @@
+use crate::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+
+/// Default interval between bells for an error nobody acknowledged yet.
+const DEFAULT_ALERT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Terminal bell, most emulators flash or beep and mark the tab as urgent.
+const BELL: &str = "\x07";
+
+/// The printer error waiting for an `:ack`, if any.
+#[derive(Clone, Default)]
+pub struct Alerts(Arc<Mutex<State>>);
+
+struct State {
+    pending: Option<Alert>,
+    interval: Duration,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            pending: None,
+            interval: DEFAULT_ALERT_INTERVAL,
+        }
+    }
+}
+
+struct Alert {
+    message: String,
+    raised: Instant,
+    rung: Option<Instant>,
+}
+
+impl Alerts {
+    /// Sets how long to wait before ringing again for the same error.
+    pub fn set_interval(&self, interval: Duration) {
+        self.0.lock().unwrap().interval = interval;
+    }
+
+    /// Rings the bell right away, then again every interval until
+    /// acknowledged. A new error replaces the pending one.
+    pub fn raise(&self, message: &str) {
+        self.0.lock().unwrap().pending = Some(Alert {
+            message: message.to_string(),
+            raised: Instant::now(),
+            rung: None,
+        });
+    }
+
+    /// Silences the pending alert, returning its message.
+    pub fn acknowledge(&self) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .pending
+            .take()
+            .map(|alert| alert.message)
+    }
+
+    fn due(&self) -> Option<String> {
+        let mut state = self.0.lock().unwrap();
+        let interval = state.interval;
+        let alert = state.pending.as_mut()?;
+
+        match alert.rung {
+            Some(rung) if rung.elapsed() < interval => None,
+            Some(_) => {
+                alert.rung = Some(Instant::now());
+                let minutes = alert.raised.elapsed().as_secs() / 60;
+                Some(format!("{} {} minutes ago", alert.message, minutes))
+            }
+            None => {
+                alert.rung = Some(Instant::now());
+                Some(alert.message.clone())
+            }
+        }
+    }
+}
+
+/// Rings the bell for pending alerts, re-alerting until acknowledged.
+pub async fn run(alerts: Alerts, notice_tx: Sender<String>) -> Result<(), Error> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        ticker.tick().await;
+
+        if let Some(message) = alerts.due() {
+            let notice = format!("{}{}, :ack to silence", BELL, message);
+            notice_tx.send(notice).await?;
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::auth::{self, Auth};
+use crate::cli::OutputFormat;
+use crate::output;
+use crate::tls::Tls;
+use crate::{format_json, klippy_ready, Error, JSON};
+use reqwest::Method;
+
+/// `moonraker-cli api <method> <path> [--data <json>]`, calls any HTTP
+/// endpoint with the credentials applied and prints the response, JSON
+/// pretty printed or in `format`. Error statuses fail the command after
+/// printing it.
+pub async fn run(
+    url: &str,
+    method: &str,
+    path: &str,
+    data: Option<&str>,
+    format: OutputFormat,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let method = Method::from_bytes(method.to_uppercase().as_bytes())
+        .map_err(|_| Error::Env(format!("Invalid HTTP method {}", method)))?;
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+
+    let endpoint = format!("{}/{}", url, path.trim_start_matches('/'));
+    let mut request = auth::authorize(client.request(method, endpoint), url);
+
+    if let Some(data) = data {
+        let body: JSON = serde_json::from_str(data)?;
+        request = request.json(&body);
+    }
+
+    let resp = request.send().await?;
+    let status = resp.status();
+    let text = resp.text().await?;
+
+    match serde_json::from_str::<JSON>(&text) {
+        Ok(value) => match output::render(value.clone(), format)? {
+            Some(output) => println!("{}", output),
+            None => println!("{}", format_json(value)?),
+        },
+        Err(_) => println!("{}", text),
+    }
+
+    if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+        // Moonraker's answer to printer endpoints while Klippy is down
+        klippy_ready(&client, url).await?;
+    }
+
+    if !status.is_success() {
+        return Err(Error::Rpc(format!("{} answered {}", path, status)));
+    }
+
+    Ok(())
+}
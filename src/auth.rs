@@ -0,0 +1,370 @@
+use crate::cli::Cli;
+use crate::config::{config_dir, Config};
+use crate::{rpc_result, unix_time, Error, JSON};
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+/// Environment variable holding the API key when `--api-key` is not given.
+const API_KEY_VAR: &str = "MOONRAKER_API_KEY";
+
+/// Access tokens are refreshed this long before they expire, in seconds.
+const REFRESH_MARGIN: f64 = 5.0 * 60.0;
+
+/// How often the access token expiry is checked.
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The users logged in with `:login`, by printer URL. Kept globally, rather
+/// than in the shared state, as every request helper only takes a client
+/// and a URL.
+static SESSIONS: Mutex<BTreeMap<String, Session>> = Mutex::new(BTreeMap::new());
+
+/// The API key of each printer, by URL, for the same reason.
+static API_KEYS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+struct Session {
+    username: String,
+    token: String,
+    refresh_token: String,
+    /// Unix time the access token expires at, none when the token doesn't
+    /// say, which leaves it alone rather than refreshing it every check
+    expires: Option<f64>,
+}
+
+/// Service name the credentials are stored under in the system keyring.
+const KEYRING_SERVICE: &str = "moonraker-cli";
+
+/// How requests authenticate to Moonraker instances with `force_logins`
+/// or that don't trust our address.
+#[derive(Clone)]
+pub struct Auth {
+    /// From `--api-key`, for the printer connected at startup
+    api_key: Option<String>,
+    /// From `$MOONRAKER_API_KEY`, for any printer without a key of its own
+    env_api_key: Option<String>,
+    /// Stored in plaintext, so only used when there's none elsewhere
+    config_api_key: Option<String>,
+    /// From the printers in the config, by URL
+    printer_api_keys: BTreeMap<String, String>,
+    /// Whether credentials are kept in the system keyring, per printer
+    keyring: bool,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Auth {
+            api_key: None,
+            env_api_key: None,
+            config_api_key: None,
+            printer_api_keys: BTreeMap::new(),
+            keyring: true,
+        }
+    }
+}
+
+impl Auth {
+    /// `--api-key` and `--no-keyring`, the key is looked up elsewhere by
+    /// `for_printer`.
+    pub fn new(cli: &Cli, config: &Config) -> Auth {
+        Auth {
+            api_key: cli.api_key.clone(),
+            env_api_key: env::var(API_KEY_VAR).ok(),
+            config_api_key: config.api_key.clone(),
+            printer_api_keys: config
+                .printers
+                .values()
+                .filter_map(|printer| Some((printer.url.clone(), printer.api_key.clone()?)))
+                .collect(),
+            keyring: !cli.no_keyring && config.keyring.unwrap_or(true),
+        }
+    }
+
+    /// Registers the API key for `url`, the printer connected at startup,
+    /// from `--api-key`, or as any other printer with `register`.
+    pub fn for_printer(mut self, url: &str) -> Auth {
+        match self.api_key.take() {
+            Some(api_key) => {
+                API_KEYS.lock().unwrap().insert(url.to_string(), api_key);
+            }
+            None => self.register(url),
+        }
+
+        self
+    }
+
+    /// `--save-api-key`, keeps `--api-key` in the keyring for `url` so it
+    /// needn't be given again.
+    pub fn save_api_key(&self, url: &str) -> Result<(), Error> {
+        let Some(api_key) = &self.api_key else {
+            return Err(Error::Env("--save-api-key needs --api-key".to_string()));
+        };
+        let Some(entry) = self.keyring_entry("api_key", url) else {
+            return Err(Error::Env(
+                "--save-api-key needs the keyring, drop --no-keyring".to_string(),
+            ));
+        };
+
+        entry.set_password(api_key).map_err(|err| {
+            Error::Env(format!(
+                "Unable to store the API key in the keyring: {}",
+                err
+            ))
+        })
+    }
+
+    /// Registers the API key for `url` from the printer in the config, the
+    /// keyring, `$MOONRAKER_API_KEY`, the config, or the plaintext `api_key`
+    /// file in the config directory with `--no-keyring`, in this order. The
+    /// keys of the printer come first, the others apply to every printer.
+    pub fn register(&self, url: &str) {
+        let api_key = self
+            .printer_api_keys
+            .get(url)
+            .cloned()
+            .or_else(|| self.keyring_entry("api_key", url)?.get_password().ok())
+            .or_else(|| self.env_api_key.clone())
+            .or_else(|| self.config_api_key.clone())
+            .or_else(|| {
+                if self.keyring {
+                    return None;
+                }
+
+                let content = fs::read_to_string(config_dir()?.join("api_key")).ok()?;
+                content.lines().next().map(str::to_string)
+            })
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty());
+
+        if let Some(api_key) = api_key {
+            API_KEYS.lock().unwrap().insert(url.to_string(), api_key);
+        }
+    }
+
+    /// The keyring entry holding `kind` of credential for `url`, none with
+    /// `--no-keyring`.
+    fn keyring_entry(&self, kind: &str, url: &str) -> Option<keyring::Entry> {
+        if !self.keyring {
+            return None;
+        }
+
+        keyring::Entry::new(KEYRING_SERVICE, &format!("{} {}", kind, url)).ok()
+    }
+
+    /// Logs in to `url` with `access.login`, replacing any previous session.
+    /// The refresh token is kept in the keyring, restoring the session next
+    /// time.
+    pub async fn login(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<String, Error> {
+        let params =
+            Some(json!({ "username": username, "password": password, "source": "moonraker" }));
+        let resp = rpc_result(client, url, "access.login", params).await?;
+
+        let (Some(token), Some(refresh_token)) =
+            (resp["token"].as_str(), resp["refresh_token"].as_str())
+        else {
+            return Err(Error::Rpc("access.login returned no tokens".to_string()));
+        };
+
+        if let Some(entry) = self.keyring_entry("refresh_token", url) {
+            let _ = entry.set_password(refresh_token);
+        }
+
+        SESSIONS.lock().unwrap().insert(
+            url.to_string(),
+            Session {
+                username: username.to_string(),
+                token: token.to_string(),
+                refresh_token: refresh_token.to_string(),
+                expires: expiry(token),
+            },
+        );
+
+        Ok(format!("Logged in as {}", username))
+    }
+
+    /// Logs in again with the refresh token kept in the keyring for `url`,
+    /// if any, returning the username.
+    pub async fn restore(&self, client: &reqwest::Client, url: &str) -> Option<String> {
+        let entry = self.keyring_entry("refresh_token", url)?;
+        let refresh_token = entry.get_password().ok()?;
+
+        let params = Some(json!({ "refresh_token": refresh_token }));
+        let Ok(resp) = rpc_result(client, url, "access.refresh_jwt", params).await else {
+            // Expired or revoked, no use keeping it
+            let _ = entry.delete_credential();
+            return None;
+        };
+
+        let (username, token) = (resp["username"].as_str()?, resp["token"].as_str()?);
+
+        SESSIONS.lock().unwrap().insert(
+            url.to_string(),
+            Session {
+                username: username.to_string(),
+                token: token.to_string(),
+                refresh_token,
+                expires: expiry(token),
+            },
+        );
+
+        Some(username.to_string())
+    }
+
+    pub async fn logout(&self, client: &reqwest::Client, url: &str) -> Result<String, Error> {
+        let username = SESSIONS
+            .lock()
+            .unwrap()
+            .get(url)
+            .map(|session| session.username.clone());
+
+        let Some(username) = username else {
+            return Ok("Not logged in".to_string());
+        };
+
+        if let Some(entry) = self.keyring_entry("refresh_token", url) {
+            let _ = entry.delete_credential();
+        }
+
+        // Forget the tokens even if the server can't be told
+        let resp = rpc_result(client, url, "access.logout", None).await;
+        SESSIONS.lock().unwrap().remove(url);
+        resp?;
+
+        Ok(format!("Logged out {}", username))
+    }
+}
+
+/// Attaches the API key of the printer and the access token of the logged
+/// in user to requests going to the instance they logged in to.
+pub fn authorize(request: reqwest::RequestBuilder, url: &str) -> reqwest::RequestBuilder {
+    let request = match API_KEYS.lock().unwrap().get(url) {
+        Some(api_key) => request.header("X-Api-Key", api_key),
+        None => request,
+    };
+
+    match SESSIONS.lock().unwrap().get(url) {
+        Some(session) => request.bearer_auth(&session.token),
+        None => request,
+    }
+}
+
+/// Whether there's an API key for `url`.
+pub fn has_api_key(url: &str) -> bool {
+    API_KEYS.lock().unwrap().contains_key(url)
+}
+
+/// Headers carrying the credentials, for the websocket upgrade request.
+pub fn headers(url: &str) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+
+    if let Some(api_key) = API_KEYS.lock().unwrap().get(url) {
+        let value = HeaderValue::from_str(api_key)
+            .map_err(|_| Error::Env("The API key is not a valid header value".to_string()))?;
+        headers.insert("X-Api-Key", value);
+    }
+
+    Ok(headers)
+}
+
+/// Adds the credentials to `server.connection.identify`, for proxies
+/// that drop the headers of the websocket upgrade request, and the
+/// access token of the logged in user, if any.
+pub fn identify(url: &str, params: &mut JSON) {
+    if let Some(api_key) = API_KEYS.lock().unwrap().get(url) {
+        params["api_key"] = JSON::String(api_key.clone());
+    }
+
+    if let Some(session) = SESSIONS.lock().unwrap().get(url) {
+        params["access_token"] = JSON::String(session.token.clone());
+    }
+}
+
+/// A token authorizing a single websocket connection, passed in the query
+/// string as the Moonraker docs require for authenticated instances.
+/// Fetched over HTTP, only when there are credentials to send.
+pub async fn oneshot_token(client: &reqwest::Client, url: &str) -> Result<Option<String>, Error> {
+    let logged_in = SESSIONS.lock().unwrap().contains_key(url);
+    let has_api_key = API_KEYS.lock().unwrap().contains_key(url);
+
+    if !has_api_key && !logged_in {
+        return Ok(None);
+    }
+
+    let token = rpc_result(client, url, "access.oneshot_token", None).await?;
+
+    Ok(token.as_str().map(str::to_string))
+}
+
+/// Keeps the access tokens fresh with `access.refresh_jwt`, shortly before
+/// they expire.
+pub async fn run(client: reqwest::Client, notice_tx: Sender<String>) -> Result<(), Error> {
+    let mut ticker = tokio::time::interval(REFRESH_CHECK_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+
+        let due: Vec<(String, String)> = SESSIONS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, session)| {
+                session
+                    .expires
+                    .is_some_and(|expires| expires - unix_time() < REFRESH_MARGIN)
+            })
+            .map(|(url, session)| (url.clone(), session.refresh_token.clone()))
+            .collect();
+
+        for (url, refresh_token) in due {
+            let params = Some(json!({ "refresh_token": refresh_token }));
+
+            match rpc_result(&client, &url, "access.refresh_jwt", params).await {
+                Ok(resp) => {
+                    let mut sessions = SESSIONS.lock().unwrap();
+
+                    if let (Some(session), Some(token)) =
+                        (sessions.get_mut(&url), resp["token"].as_str())
+                    {
+                        session.token = token.to_string();
+                        session.expires = expiry(token);
+                    }
+                }
+                Err(err) => {
+                    notice_tx
+                        .send(format!(
+                            "Unable to refresh the login to {}, :login again: {}",
+                            url, err
+                        ))
+                        .await?;
+                    SESSIONS.lock().unwrap().remove(&url);
+                }
+            }
+        }
+    }
+}
+
+/// When a JWT expires, from the `exp` claim of its payload, none for tokens
+/// that can't be read or don't expire.
+fn expiry(token: &str) -> Option<f64> {
+    token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(payload.trim_end_matches('='))
+                .ok()
+        })
+        .and_then(|payload| serde_json::from_slice::<JSON>(&payload).ok())
+        .and_then(|claims| claims["exp"].as_f64())
+}
@@ -0,0 +1,42 @@
+use crate::{rpc_result, Error};
+use futures_util::future;
+use serde_json::json;
+use tokio::sync::mpsc::Sender;
+
+/// Sends `script` to every printer, given as name and URL, all at once.
+/// Each reply comes as a notice tagged with the printer name, in the order
+/// they arrive, the returned summary once all of them answered.
+pub async fn send(
+    client: &reqwest::Client,
+    printers: &[(String, String)],
+    script: &str,
+    notice_tx: &Sender<String>,
+) -> String {
+    if printers.is_empty() {
+        return "No printers to send to, add them to the config as [printer.<name>]".to_string();
+    }
+
+    let replies = printers.iter().map(|(name, url)| async move {
+        let params = Some(json!({ "script": script }));
+        let resp = rpc_result(client, url, "printer.gcode.script", params).await;
+        let notice = match &resp {
+            Ok(value) => format!("[{}] {}", name, value.as_str().unwrap_or("ok")),
+            Err(err) => format!("[{}] Unable to send: {}", name, err),
+        };
+
+        let _ = notice_tx.send(notice).await;
+        resp.map(|_| ())
+    });
+
+    let results: Vec<Result<(), Error>> = future::join_all(replies).await;
+    let failed = results.iter().filter(|result| result.is_err()).count();
+
+    match failed {
+        0 => format!("Sent to {} printers", results.len()),
+        failed => format!(
+            "Sent to {} printers, {} of them failed",
+            results.len(),
+            failed
+        ),
+    }
+}
@@ -0,0 +1,109 @@
+use crate::download::file_url;
+use crate::{auth, ics, rpc_result, unix_time, Error, JSON};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+use std::fs::File;
+
+/// Files fetched from Moonraker, as (path in the bundle, path on the server).
+const SERVER_FILES: &[(&str, &str)] = &[
+    ("klippy.log", "logs/klippy.log"),
+    ("moonraker.log", "logs/moonraker.log"),
+    ("printer.cfg", "config/printer.cfg"),
+];
+
+/// Collects what's usually asked for when reporting a failed print on the
+/// Klipper discourse into `moonraker-debug-<timestamp>.tar.gz`: the logs,
+/// `printer.cfg`, the last job with its file metadata and the console
+/// transcript. Whatever can't be fetched is listed in `MANIFEST.txt`.
+pub async fn collect(
+    client: &reqwest::Client,
+    url: &str,
+    transcript: &str,
+) -> Result<String, Error> {
+    let now = unix_time();
+    let name = format!("moonraker-debug-{}", ics::format_utc(now));
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut manifest = vec![format!("Printer: {}", url)];
+
+    for (path, server_path) in SERVER_FILES {
+        match download(client, url, server_path).await {
+            Ok(content) => {
+                manifest.push(format!("{}: {} bytes", path, content.len()));
+                files.push((path.to_string(), content));
+            }
+            Err(err) => manifest.push(format!("{}: missing, {}", path, err)),
+        }
+    }
+
+    match last_job(client, url).await {
+        Ok(job) => {
+            files.push((
+                "last_job.json".to_string(),
+                serde_json::to_vec_pretty(&job)?,
+            ));
+            manifest.push("last_job.json: included".to_string());
+        }
+        Err(err) => manifest.push(format!("last_job.json: missing, {}", err)),
+    }
+
+    files.push(("transcript.txt".to_string(), transcript.as_bytes().to_vec()));
+    manifest.push("transcript.txt: included".to_string());
+    files.push((
+        "MANIFEST.txt".to_string(),
+        (manifest.join("\n") + "\n").into_bytes(),
+    ));
+
+    let path = format!("{}.tar.gz", name);
+    let encoder = GzEncoder::new(File::create(&path)?, Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+
+    for (file, content) in &files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(now as u64);
+        header.set_cksum();
+        archive.append_data(
+            &mut header,
+            format!("{}/{}", name, file),
+            content.as_slice(),
+        )?;
+    }
+
+    archive.into_inner()?.finish()?;
+
+    Ok(format!("Debug bundle saved to {}", path))
+}
+
+async fn download(client: &reqwest::Client, url: &str, path: &str) -> Result<Vec<u8>, Error> {
+    let request = client.get(file_url(url, path)?);
+    let resp = auth::authorize(request, url)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// The most recent job in the print history, along with its file metadata.
+async fn last_job(client: &reqwest::Client, url: &str) -> Result<JSON, Error> {
+    let params = Some(json!({ "limit": 1, "order": "desc" }));
+    let mut history = rpc_result(client, url, "server.history.list", params).await?;
+    let job = history
+        .get_mut("jobs")
+        .and_then(|jobs| jobs.get_mut(0))
+        .map(JSON::take)
+        .unwrap_or_default();
+
+    let Some(filename) = job["filename"].as_str() else {
+        return Err(Error::Rpc("no jobs in the print history".to_string()));
+    };
+
+    let params = Some(json!({ "filename": filename }));
+    let metadata = rpc_result(client, url, "server.files.metadata", params)
+        .await
+        .unwrap_or(JSON::Null);
+
+    Ok(json!({ "job": job, "metadata": metadata }))
+}
@@ -0,0 +1,200 @@
+/// Replaces the arithmetic in braces with its value, like `G1 Z{2.5+0.2}`
+/// with `G1 Z2.7`. Braces holding anything but numbers, `+ - * /` and
+/// parentheses are left alone, they may be meant for Klipper.
+pub fn expand(line: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        let expr = &rest[start + 1..end];
+        expanded.push_str(&rest[..start]);
+
+        if is_arithmetic(expr) {
+            let value =
+                evaluate(expr).ok_or_else(|| format!("Invalid expression {{{}}}", expr.trim()))?;
+            expanded.push_str(&format_number(value));
+        } else {
+            expanded.push_str(&rest[start..=end]);
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn is_arithmetic(expr: &str) -> bool {
+    expr.chars().any(|c| c.is_ascii_digit())
+        && expr
+            .chars()
+            .all(|c| c.is_ascii_digit() || " .+-*/()".contains(c))
+}
+
+/// The value of `expr`, none if it's malformed or divides by zero.
+fn evaluate(expr: &str) -> Option<f64> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        position: 0,
+    };
+    let value = parser.sum()?;
+
+    (parser.position == parser.tokens.len() && value.is_finite()).then_some(value)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Symbol(char),
+}
+
+/// The numbers and symbols of `expr`, the whitespace between them skipped,
+/// none if a number is malformed.
+fn tokenize(expr: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        if !(c.is_ascii_digit() || c == '.') {
+            tokens.push(Token::Symbol(c));
+            continue;
+        }
+
+        let mut number = c.to_string();
+
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+            number.push(c);
+        }
+
+        tokens.push(Token::Number(number.parse().ok()?));
+    }
+
+    Some(tokens)
+}
+
+/// Recursive descent over `sum := product (('+'|'-') product)*`,
+/// `product := factor (('*'|'/') factor)*` and
+/// `factor := '-' factor | '(' sum ')' | number`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn sum(&mut self) -> Option<f64> {
+        let mut value = self.product()?;
+
+        while let Some(Token::Symbol(op @ ('+' | '-'))) = self.peek() {
+            self.position += 1;
+            let rhs = self.product()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+
+        Some(value)
+    }
+
+    fn product(&mut self) -> Option<f64> {
+        let mut value = self.factor()?;
+
+        while let Some(Token::Symbol(op @ ('*' | '/'))) = self.peek() {
+            self.position += 1;
+            let rhs = self.factor()?;
+            value = if op == '*' {
+                value * rhs
+            } else if rhs != 0.0 {
+                value / rhs
+            } else {
+                return None;
+            };
+        }
+
+        Some(value)
+    }
+
+    fn factor(&mut self) -> Option<f64> {
+        let token = self.peek()?;
+        self.position += 1;
+
+        match token {
+            Token::Number(value) => Some(value),
+            Token::Symbol('-') => Some(-self.factor()?),
+            Token::Symbol('(') => {
+                let value = self.sum()?;
+
+                if self.peek()? != Token::Symbol(')') {
+                    return None;
+                }
+
+                self.position += 1;
+                Some(value)
+            }
+            Token::Symbol(_) => None,
+        }
+    }
+}
+
+/// Up to six decimals without trailing zeros, G-code doesn't need more.
+fn format_number(value: f64) -> String {
+    let formatted = format!("{:.6}", value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+
+    match formatted {
+        "-0" => "0".to_string(),
+        formatted => formatted.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_with_precedence() {
+        assert_eq!(evaluate("2.5+0.2"), Some(2.7));
+        assert_eq!(evaluate("1 + 2 * 3"), Some(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(evaluate("-(4 - 6) / 4"), Some(0.5));
+        assert_eq!(evaluate(" .5 * 4 "), Some(2.0));
+    }
+
+    #[test]
+    fn refuses_malformed_expressions() {
+        for expr in ["1 2", "1.5 .5", "1.2.3", "1 +", "(1", "1)", "2 / 0", ""] {
+            assert_eq!(evaluate(expr), None, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn expands_arithmetic_in_braces() {
+        assert_eq!(expand("G1 Z{2.5+0.2} F{60*100}").unwrap(), "G1 Z2.7 F6000");
+        assert_eq!(expand("G1 X{0.1+0.2}").unwrap(), "G1 X0.3");
+        assert!(expand("G1 Z{1 2}").is_err());
+    }
+
+    #[test]
+    fn leaves_klipper_templates_alone() {
+        let line = "RESPOND MSG={printer.toolhead.position.z}";
+
+        assert_eq!(expand(line).unwrap(), line);
+        assert_eq!(expand("G1 X{").unwrap(), "G1 X{");
+    }
+
+    #[test]
+    fn formats_without_trailing_zeros() {
+        assert_eq!(format_number(2.0), "2");
+        assert_eq!(format_number(-0.0000001), "0");
+        assert_eq!(format_number(1.0 / 3.0), "0.333333");
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+
+/// The calibration shortcuts, with the objects Klipper reports when the
+/// printer config has what they need, none for those any printer has.
+const ACTIONS: &[(&str, &[&str], &str)] = &[
+    ("G28", &[], "home all axes"),
+    (
+        "QUAD_GANTRY_LEVEL",
+        &["quad_gantry_level"],
+        "level the gantry",
+    ),
+    ("Z_TILT_ADJUST", &["z_tilt"], "align the Z steppers"),
+    ("BED_MESH_CALIBRATE", &["bed_mesh"], "probe a new bed mesh"),
+    (
+        "PROBE_CALIBRATE",
+        &["probe", "bltouch", "smart_effector", "probe_eddy_current"],
+        "find the probe Z offset",
+    ),
+];
+
+/// What `:calibrate` does with the shortcuts the printer supports.
+pub enum Command {
+    List,
+    /// Runs the nth shortcut listed, from 1
+    Run(usize),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        match args.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => Ok(Command::List),
+            [n] => n
+                .parse::<usize>()
+                .map(Command::Run)
+                .map_err(|_| format!("Invalid calibration {}, expected a number", n)),
+            _ => Err(format!(
+                "Unknown :calibrate argument {}, try :help",
+                args.trim()
+            )),
+        }
+    }
+}
+
+pub async fn run(client: &reqwest::Client, url: &str, command: Command) -> Result<String, Error> {
+    let objects = rpc_result(client, url, "printer.objects.list", None).await?;
+    let actions = supported(&objects);
+
+    match command {
+        Command::List => Ok(render(&actions)),
+        Command::Run(n) => {
+            let (gcode, ..) = n
+                .checked_sub(1)
+                .and_then(|index| actions.get(index))
+                .ok_or_else(|| {
+                    Error::Env(format!(
+                        "No calibration {}, expected 1 to {}",
+                        n,
+                        actions.len()
+                    ))
+                })?;
+
+            let params = Some(json!({ "objects": { "print_stats": ["state"] } }));
+            let status = rpc_result(client, url, "printer.objects.query", params).await?;
+
+            if let Some(state @ ("printing" | "paused")) =
+                status["status"]["print_stats"]["state"].as_str()
+            {
+                return Err(Error::Env(format!("Not while the print is {}", state)));
+            }
+
+            let params = Some(json!({ "script": gcode }));
+            rpc_result(client, url, "printer.gcode.script", params).await?;
+
+            if *gcode == "PROBE_CALIBRATE" {
+                Ok(format!(
+                    "{} started, lower the nozzle with TESTZ Z=-0.1 and finish with ACCEPT",
+                    gcode
+                ))
+            } else {
+                Ok(format!("{} done", gcode))
+            }
+        }
+    }
+}
+
+/// The shortcuts whose objects the printer reports, like `bed_mesh` or
+/// `probe_eddy_current btt`.
+fn supported(objects: &JSON) -> Vec<(&'static str, &'static str)> {
+    let objects: Vec<&str> = objects["objects"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(JSON::as_str)
+        .collect();
+    let reported = |feature: &str| {
+        objects
+            .iter()
+            .any(|object| object.split_whitespace().next() == Some(feature))
+    };
+
+    ACTIONS
+        .iter()
+        .filter(|(_, features, _)| {
+            features.is_empty() || features.iter().any(|feature| reported(feature))
+        })
+        .map(|(gcode, _, description)| (*gcode, *description))
+        .collect()
+}
+
+fn render(actions: &[(&str, &str)]) -> String {
+    let mut lines = popup::numbered(
+        actions
+            .iter()
+            .map(|(gcode, description)| (gcode.to_string(), description.to_string()))
+            .collect(),
+    );
+
+    lines.push(String::new());
+    lines.push(":calibrate <n> runs the nth, the printer must be idle".to_string());
+
+    popup::render("Calibration", &lines)
+}
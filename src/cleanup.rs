@@ -0,0 +1,264 @@
+use crate::{popup, rpc_result, unix_time, Error};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Jobs fetched per `server.history.list` page.
+const HISTORY_PAGE: usize = 100;
+
+/// What `:cleanup` does with the G-code files not printed in a while.
+pub enum Command {
+    /// Lists the files not printed in this many days
+    List(f64),
+    /// Picks the listed files to delete, from 1, all of them when empty
+    Select(Vec<usize>),
+    /// Deletes the files picked
+    Confirm,
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+
+        match words[..] {
+            [] => Err("Missing argument for :cleanup, try :help".to_string()),
+            ["confirm"] => Ok(Command::Confirm),
+            ["delete", ref numbers @ ..] => numbers
+                .iter()
+                .map(|n| {
+                    n.parse::<usize>()
+                        .map_err(|_| format!("Invalid file {}, expected a number", n))
+                })
+                .collect::<Result<_, _>>()
+                .map(Command::Select),
+            [days] => days
+                .parse::<f64>()
+                .ok()
+                .filter(|days| days.is_finite() && *days >= 0.0)
+                .map(Command::List)
+                .ok_or_else(|| format!("Invalid age {}, expected days or delete", days)),
+            _ => Err(format!(
+                "Unknown :cleanup argument {}, try :help",
+                args.trim()
+            )),
+        }
+    }
+}
+
+/// The files listed by the last `:cleanup <days>` and those picked from
+/// them by `:cleanup delete`, only good for the printer they were listed on.
+#[derive(Clone, Default)]
+pub struct Candidates(Arc<Mutex<Listed>>);
+
+#[derive(Default)]
+struct Listed {
+    /// Of the Moonraker instance listed, empty once cleared
+    url: String,
+    /// Paths under the gcodes root with their size
+    files: Vec<(String, u64)>,
+    /// Waiting for `:cleanup confirm`
+    selected: Vec<String>,
+}
+
+impl Candidates {
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        command: Command,
+    ) -> Result<String, Error> {
+        match command {
+            Command::List(days) => self.list(client, url, days).await,
+            Command::Select(numbers) => self.select(url, &numbers),
+            Command::Confirm => self.delete(client, url).await,
+        }
+    }
+
+    /// Forgets the listed files, the numbers mean nothing on another printer.
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = Listed::default();
+    }
+
+    /// Lists the G-code files not printed in the last `days` days, or never
+    /// printed and uploaded before then, with the space deleting them frees.
+    async fn list(&self, client: &reqwest::Client, url: &str, days: f64) -> Result<String, Error> {
+        let cutoff = unix_time() - days * 86400.0;
+        let last_printed = last_printed(client, url).await?;
+
+        let params = Some(json!({ "root": "gcodes" }));
+        let files = rpc_result(client, url, "server.files.list", params).await?;
+
+        let mut old: Vec<(String, u64)> = files
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|file| {
+                let path = file["path"].as_str()?;
+                let used = last_printed
+                    .get(path)
+                    .copied()
+                    .or_else(|| file["modified"].as_f64())?;
+
+                (used < cutoff).then(|| (path.to_string(), file["size"].as_u64().unwrap_or(0)))
+            })
+            .collect();
+
+        old.sort();
+
+        if old.is_empty() {
+            self.clear();
+            return Ok(format!("Every file was printed in the last {} days", days));
+        }
+
+        let total: u64 = old.iter().map(|(_, size)| size).sum();
+        let mut lines = popup::numbered(
+            old.iter()
+                .map(|(path, size)| (format!("{:>10}", format_size(*size)), path.clone()))
+                .collect(),
+        );
+
+        lines.push(format!(
+            "{} files not printed in {} days, {} reclaimable, :cleanup delete [n ...] picks them",
+            old.len(),
+            days,
+            format_size(total)
+        ));
+
+        *self.0.lock().unwrap() = Listed {
+            url: url.to_string(),
+            files: old,
+            selected: Vec::new(),
+        };
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Picks the listed files to delete and asks to confirm.
+    fn select(&self, url: &str, numbers: &[usize]) -> Result<String, Error> {
+        let mut listed = self.0.lock().unwrap();
+
+        if listed.files.is_empty() || listed.url != url {
+            return Err(Error::Env(
+                "Nothing listed on this printer, list old files with :cleanup <days> first"
+                    .to_string(),
+            ));
+        }
+
+        let picked: Vec<(String, u64)> = if numbers.is_empty() {
+            listed.files.clone()
+        } else {
+            numbers
+                .iter()
+                .map(|n| {
+                    n.checked_sub(1)
+                        .and_then(|index| listed.files.get(index))
+                        .cloned()
+                        .ok_or_else(|| {
+                            Error::Env(format!(
+                                "No file {}, expected 1 to {}",
+                                n,
+                                listed.files.len()
+                            ))
+                        })
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut paths: Vec<String> = picked.iter().map(|(path, _)| path.clone()).collect();
+        paths.dedup();
+        let total: u64 = picked.iter().map(|(_, size)| size).sum();
+
+        let mut lines: Vec<String> = paths.iter().map(|path| format!("  {}", path)).collect();
+        lines.push(format!(
+            "Delete these {} files, {}? :cleanup confirm deletes them",
+            paths.len(),
+            format_size(total)
+        ));
+
+        listed.selected = paths;
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Deletes the files picked by the last `:cleanup delete`.
+    async fn delete(&self, client: &reqwest::Client, url: &str) -> Result<String, Error> {
+        let paths = {
+            let mut listed = self.0.lock().unwrap();
+
+            if listed.url != url {
+                Vec::new()
+            } else {
+                std::mem::take(&mut listed.selected)
+            }
+        };
+
+        if paths.is_empty() {
+            return Ok(
+                "Nothing to delete, pick files with :cleanup delete [n ...] first".to_string(),
+            );
+        }
+
+        let mut failed = Vec::new();
+
+        for path in &paths {
+            let params = Some(json!({ "path": format!("gcodes/{}", path) }));
+
+            if let Err(err) = rpc_result(client, url, "server.files.delete_file", params).await {
+                failed.push(format!("{}: {}", path, err));
+            }
+        }
+
+        // The numbers shift once files are gone
+        self.clear();
+
+        let mut lines = vec![format!(
+            "Deleted {} of {} files",
+            paths.len() - failed.len(),
+            paths.len()
+        )];
+        lines.extend(failed);
+
+        Ok(lines.join("\n"))
+    }
+}
+
+/// When each file was last printed, walking the whole print history.
+async fn last_printed(client: &reqwest::Client, url: &str) -> Result<HashMap<String, f64>, Error> {
+    let mut last_printed = HashMap::new();
+    let mut start = 0;
+
+    loop {
+        let params = Some(json!({ "start": start, "limit": HISTORY_PAGE, "order": "desc" }));
+        let history = rpc_result(client, url, "server.history.list", params).await?;
+        let jobs = history["jobs"].as_array().cloned().unwrap_or_default();
+
+        for job in &jobs {
+            let (Some(filename), Some(time)) =
+                (job["filename"].as_str(), job["start_time"].as_f64())
+            else {
+                continue;
+            };
+
+            let last = last_printed.entry(filename.to_string()).or_insert(time);
+            *last = f64::max(*last, time);
+        }
+
+        if jobs.len() < HISTORY_PAGE {
+            return Ok(last_printed);
+        }
+
+        start += HISTORY_PAGE;
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let bytes = bytes as f64;
+
+    match bytes {
+        bytes if bytes >= 1024.0 * 1024.0 * 1024.0 => {
+            format!("{:.1} GiB", bytes / (1024.0 * 1024.0 * 1024.0))
+        }
+        bytes if bytes >= 1024.0 * 1024.0 => format!("{:.1} MiB", bytes / (1024.0 * 1024.0)),
+        bytes => format!("{:.1} KiB", bytes / 1024.0),
+    }
+}
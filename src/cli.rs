@@ -0,0 +1,416 @@
+use crate::statusline;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+
+/// An interactive console for Klipper printers running Moonraker.
+///
+/// Options given here override the ones in the config file.
+#[derive(Parser)]
+#[command(version, after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Moonraker URL, like mainsailos.local or ws://192.168.1.50:7125,
+    /// http://localhost:7125 by default
+    #[arg(long, global = true)]
+    pub url: Option<String>,
+
+    /// Same as --url, kept for the invocations predating it
+    #[arg(hide = true, conflicts_with_all = ["url", "printer"])]
+    pub positional_url: Option<String>,
+
+    /// A printer from the config to connect to
+    #[arg(long, global = true, conflicts_with = "url")]
+    pub printer: Option<String>,
+
+    /// A plain line-oriented console, no status bar, replies awaited in
+    /// turn and input accepted from a pipe, for slow links, expect and
+    /// editors
+    #[arg(long)]
+    pub no_tui: bool,
+
+    /// Printers from the config :all sends to, all of them by default
+    #[arg(long, value_delimiter = ',')]
+    pub printers: Option<Vec<String>>,
+
+    /// Config file, config.toml in the config directory by default
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Moonraker API key for the printer, $MOONRAKER_API_KEY applies to
+    /// those without a key of their own
+    #[arg(long, global = true)]
+    pub api_key: Option<String>,
+
+    /// Store --api-key in the system keyring for the printer, so it needn't
+    /// be given again
+    #[arg(
+        long,
+        global = true,
+        requires = "api_key",
+        conflicts_with = "no_keyring"
+    )]
+    pub save_api_key: bool,
+
+    /// Don't read or store credentials in the system keyring
+    #[arg(long, global = true)]
+    pub no_keyring: bool,
+
+    /// CA certificate to trust, PEM encoded
+    #[arg(long, global = true, value_name = "PATH")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Client certificate for mutual TLS, PEM encoded
+    #[arg(long, global = true, value_name = "PATH", requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// Client private key for mutual TLS, PEM encoded PKCS#8
+    #[arg(long, global = true, value_name = "PATH", requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Skip verifying the printer certificate
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    /// Shell command run when a print completes
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pub on_complete: Option<String>,
+
+    /// Shell command run when a print fails
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pub on_error: Option<String>,
+
+    /// Shell command run when Klippy shuts down
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pub on_shutdown: Option<String>,
+
+    /// What goes to moonraker-cli.log in the state directory
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Warn)]
+    pub log_level: LogLevel,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Told apart by scripts running the subcommands.
+const EXIT_CODES: &str = "Exit codes:
+  1  other errors
+  2  invalid arguments
+  3  printer unreachable
+  4  command failed
+  5  Klippy not ready, shut down or in error
+  6  timed out";
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Printer(PrinterCommand),
+    /// Check the config, the connection, the credentials and the websocket
+    /// step by step, the first thing to run when connecting fails
+    Doctor,
+}
+
+/// The subcommands run against the printer once the config is loaded, the
+/// doctor checks the config itself.
+#[derive(Subcommand)]
+pub enum PrinterCommand {
+    /// Print a single status line for tmux, waybar or polybar and exit
+    Statusline {
+        /// Placeholders are {state}, {filename}, {progress}, {nozzle},
+        /// {nozzle_target}, {bed} and {bed_target}
+        #[arg(long, default_value = statusline::DEFAULT_FORMAT)]
+        format: String,
+
+        /// Same as --url, kept for the invocations predating it
+        #[arg(hide = true, conflicts_with_all = ["url", "printer"])]
+        positional_url: Option<String>,
+    },
+    /// Send G-code commands in turn, print the responses and exit
+    Exec {
+        /// Commands to send, - reads them from stdin a line at a time
+        #[arg(required_unless_present = "stdin", value_name = "GCODE")]
+        commands: Vec<String>,
+
+        /// Read the commands from stdin, same as -
+        #[arg(long, conflicts_with = "commands")]
+        stdin: bool,
+
+        /// Send the remaining commands after one fails
+        #[arg(long)]
+        keep_going: bool,
+
+        /// How the results print
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Print the print state, the toolhead and the heaters and exit
+    Status {
+        /// Same as --output json, kept for the invocations predating it
+        #[arg(long, conflicts_with = "output")]
+        json: bool,
+
+        /// How the results print
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Wait until the printer reaches a state, like the end of a print
+    Wait {
+        #[arg(long, value_enum)]
+        until: Until,
+
+        /// Give up after this long, like 90s, 30m or 2h
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+    },
+    /// Call any Moonraker HTTP endpoint with the credentials applied
+    Api {
+        /// GET, POST, DELETE...
+        method: String,
+
+        /// Like /server/info or /printer/objects/query?extruder
+        path: String,
+
+        /// JSON body of the request
+        #[arg(long, value_name = "JSON")]
+        data: Option<String>,
+
+        /// How the results print
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Upload a G-code file to the printer, with a progress bar
+    Upload {
+        file: PathBuf,
+
+        /// Start printing it once uploaded
+        #[arg(long)]
+        print: bool,
+
+        /// How the results print
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Download a file from the printer, with a progress bar
+    Download {
+        /// Under the gcodes, config or logs root, like gcodes/part.gcode
+        path: String,
+
+        /// Where to save it, the file name in the current directory by default,
+        /// --output being how results print
+        #[arg(short = 'o', long, value_name = "FILE")]
+        dest: Option<PathBuf>,
+
+        /// Replace the destination if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Upload the G-code files of a local directory that are new or changed,
+    /// like a slicer output folder
+    Sync {
+        local: PathBuf,
+
+        /// Under the gcodes root, like gcodes/queue
+        remote: String,
+
+        /// Compare the content of files of the same size rather than when
+        /// they were modified, downloading them from the printer
+        #[arg(long)]
+        checksum: bool,
+
+        /// List what would be uploaded without uploading it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show or change Moonraker's job queue, printing it after
+    Queue {
+        #[command(subcommand)]
+        command: Option<QueueCommand>,
+
+        /// How the queue prints
+        #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Delete, move, copy files on the printer and create directories, paths
+    /// under the gcodes, config or logs root
+    Files {
+        #[command(subcommand)]
+        command: FilesCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FilesCommand {
+    Delete {
+        path: String,
+    },
+    /// Delete a directory, if empty unless forced
+    Rmdir {
+        path: String,
+
+        /// Delete what's inside too
+        #[arg(long)]
+        force: bool,
+    },
+    /// Move or rename a file or directory
+    Move {
+        source: String,
+        dest: String,
+    },
+    Copy {
+        source: String,
+        dest: String,
+    },
+    Mkdir {
+        path: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum QueueCommand {
+    List,
+    /// Queue files under the gcodes root, in this order
+    Add {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Remove jobs by their number in the list, from 1
+    Remove {
+        #[arg(required = true)]
+        jobs: Vec<usize>,
+    },
+    /// Remove every job
+    Clear,
+    Pause,
+    /// Start the next job once the printer is idle
+    Start,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Until {
+    /// Not printing, whichever way the print ended
+    Idle,
+    /// The print is complete, failing if it's cancelled or fails
+    Complete,
+    Paused,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// For people, each command its own way
+    Text,
+    Json,
+    Yaml,
+    Table,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+        }
+    }
+}
+
+/// Seconds, or a number followed by s, m or h.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("Unknown unit {}, expected s, m or h", unit)),
+    };
+
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| *number > 0.0)
+        .and_then(|number| Duration::try_from_secs_f64(number * seconds).ok())
+        .ok_or_else(|| format!("Invalid duration {}", duration))
+}
+
+impl Cli {
+    /// Parses the command line, exiting on invalid arguments. A positional
+    /// URL conflicting with a global option given on the other side of the
+    /// subcommand gets past clap, it would otherwise win silently.
+    pub fn parse_args() -> Cli {
+        let cli = Cli::parse();
+        let statusline_url = match &cli.command {
+            Some(Command::Printer(PrinterCommand::Statusline { positional_url, .. })) => {
+                positional_url.as_ref()
+            }
+            _ => None,
+        };
+
+        let conflict = match (&cli.positional_url, statusline_url) {
+            (Some(_), Some(_)) => Some("[POSITIONAL_URL]"),
+            (None, None) => None,
+            _ if cli.url.is_some() => Some("--url <URL>"),
+            _ if cli.printer.is_some() => Some("--printer <PRINTER>"),
+            _ => None,
+        };
+
+        if let Some(arg) = conflict {
+            let message = format!(
+                "the argument '{}' cannot be used with '[POSITIONAL_URL]'",
+                arg
+            );
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+
+        cli
+    }
+
+    /// The URL given on the command line, if any.
+    pub fn url(&self) -> Option<&String> {
+        self.url.as_ref().or(self.positional_url.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn refuses_invalid_durations() {
+        for duration in [
+            "",
+            "0",
+            "-5m",
+            "5d",
+            "m",
+            "1.2.3s",
+            "NaN",
+            "100000000000000000000h",
+        ] {
+            assert!(parse_duration(duration).is_err(), "{}", duration);
+        }
+    }
+}
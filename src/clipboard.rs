@@ -0,0 +1,95 @@
+use base64::Engine;
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Terminals ignore longer OSC 52 sequences, xterm's limit.
+const MAX_OSC52_LENGTH: usize = 100_000;
+
+/// Where `:copy` puts text, `clipboard` in the config. The automatic
+/// choice follows the display server, falling back to OSC 52 over SSH.
+#[derive(Clone, Copy, Default)]
+pub enum Backend {
+    #[default]
+    Auto,
+    WlClipboard,
+    Xclip,
+    /// Asks the terminal to set the clipboard, works across SSH
+    Osc52,
+}
+
+impl Backend {
+    pub fn parse(backend: &str) -> Result<Backend, String> {
+        match backend {
+            "auto" => Ok(Backend::Auto),
+            "wl-clipboard" => Ok(Backend::WlClipboard),
+            "xclip" => Ok(Backend::Xclip),
+            "osc52" => Ok(Backend::Osc52),
+            backend => Err(format!(
+                "Unknown clipboard {}, expected auto, wl-clipboard, xclip or osc52",
+                backend
+            )),
+        }
+    }
+
+    /// Copies `text`, returning the backend name for the confirmation.
+    pub fn copy(self, text: &str) -> Result<&'static str, String> {
+        match self {
+            Backend::Auto => {
+                let backend = if env::var_os("WAYLAND_DISPLAY").is_some() {
+                    Backend::WlClipboard
+                } else if env::var_os("DISPLAY").is_some() {
+                    Backend::Xclip
+                } else {
+                    Backend::Osc52
+                };
+
+                // Display tools missing, the terminal may still do it
+                backend.copy(text).or_else(|_| Backend::Osc52.copy(text))
+            }
+            Backend::WlClipboard => pipe("wl-copy", &[], text).map(|_| "wl-clipboard"),
+            Backend::Xclip => pipe("xclip", &["-selection", "clipboard"], text).map(|_| "xclip"),
+            Backend::Osc52 => osc52(text)
+                .map(|_| "the terminal")
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+fn pipe(program: &str, args: &[&str], text: &str) -> Result<(), String> {
+    let run = || -> io::Result<bool> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+
+        Ok(child.wait()?.success())
+    };
+
+    match run() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("{} failed", program)),
+        Err(err) => Err(format!("Unable to run {}: {}", program, err)),
+    }
+}
+
+/// The terminal sets the clipboard from the base64 text, unless it
+/// doesn't support or allow it, which can't be told from here.
+fn osc52(text: &str) -> io::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+
+    if encoded.len() > MAX_OSC52_LENGTH {
+        return Err(io::Error::other("Too long for the terminal clipboard"));
+    }
+
+    let mut stdout = io::stdout().lock();
+
+    write!(stdout, "\x1b]52;c;{}\x07", encoded)?;
+    stdout.flush()
+}
@@ -0,0 +1,186 @@
+use crate::cost::Prices;
+use crate::hooks::Hooks;
+use crate::preheat::Preset;
+use crate::units::Units;
+use crate::{http_url, Error};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Settings from `config.toml` in the config directory, all optional.
+/// Command line options take precedence over them.
+///
+/// ```toml
+/// url = "http://voron.local:7125"
+/// timeout = 600           # seconds, 0 disables it
+/// screensaver = 10        # minutes
+/// alert-interval = 5      # minutes between bells for an unacknowledged error
+/// mode = "gcode"          # or "rpc", "db"
+/// filter = ".result"
+///
+/// [printer.voron]         # :printer voron or --printer voron switches to it
+/// url = "http://voron.local:7125"
+/// api-key = "..."
+/// profile = "Voron 2.4"   # slicer printer profile, :check warns of others
+///
+/// [templates]             # typed like G-code, missing parameters are asked for
+/// purge = "G1 E{LENGTH} F300"
+///
+/// [units]
+/// temperature = "fahrenheit"
+/// length = "in"
+///
+/// [filament.default]      # per kg, see Prices
+/// price = 20.0
+///
+/// [presets.pla]           # :preheat pla, heater targets in °C
+/// extruder = 210
+/// heater_bed = 60
+///
+/// [theme]
+/// status-bar = "44;97"    # SGR parameters, reverse video by default
+///
+/// [hooks]                 # shell commands, see Hooks
+/// complete = "notify-send 'Print complete'"
+///
+/// [keybindings]           # keys as in inputrc, in literal strings
+/// '"\C-x"' = "unix-line-discard"
+/// '"\C-h"' = "G28"        # anything not a readline function replaces the line
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    pub keyring: Option<bool>,
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub insecure: Option<bool>,
+    pub timeout: Option<u64>,
+    pub screensaver: Option<f64>,
+    pub alert_interval: Option<f64>,
+    pub mode: Option<String>,
+    /// Status bar density, compact or detailed
+    pub density: Option<String>,
+    pub filter: Option<String>,
+    /// Longer replies are left for `:page` instead of printed
+    pub pager_lines: Option<usize>,
+    /// auto, wl-clipboard, xclip or osc52
+    pub clipboard: Option<String>,
+    /// How thumbnails are drawn, auto, kitty, sixel, blocks or none
+    pub graphics: Option<String>,
+    pub units: Units,
+    /// Filament prices by profile
+    pub filament: Prices,
+    /// Heater targets by preset name, PLA, PETG and ABS when empty
+    pub presets: BTreeMap<String, Preset>,
+    pub theme: Theme,
+    pub keybindings: BTreeMap<String, String>,
+    /// G-code with `{NAME}` parameters, by name
+    pub templates: BTreeMap<String, String>,
+    pub hooks: Hooks,
+    #[serde(rename = "printer")]
+    pub printers: BTreeMap<String, Printer>,
+}
+
+/// A printer set up in the config, `[printer.<name>]`.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Printer {
+    pub url: String,
+    pub api_key: Option<String>,
+    /// Slicer printer profile files are expected to be sliced for
+    pub profile: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Theme {
+    pub status_bar: Option<String>,
+}
+
+impl Theme {
+    /// SGR parameters the status bar is drawn with.
+    pub fn status_bar(&self) -> &str {
+        self.status_bar.as_deref().unwrap_or("7")
+    }
+}
+
+impl Config {
+    /// Reads `path`, or else `config.toml` in the config directory, which
+    /// may be missing for an empty config.
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let (path, required) = match (path, config_dir()) {
+            (Some(path), _) => (path.to_path_buf(), true),
+            (None, Some(dir)) => (dir.join("config.toml"), false),
+            (None, None) => return Ok(Config::default()),
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default())
+            }
+            Err(err) => {
+                return Err(Error::Env(format!(
+                    "Unable to read {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+        };
+
+        let mut config: Config = toml::from_str(&content)
+            .map_err(|err| Error::Env(format!("Invalid config {}: {}", path.display(), err)))?;
+
+        // Resolved once, so URLs compare equal however they were written
+        for (name, printer) in &mut config.printers {
+            printer.url = http_url(&printer.url).map_err(|err| {
+                Error::Env(format!("Printer {} in {}: {}", name, path.display(), err))
+            })?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn printer(&self, name: &str) -> Result<&Printer, String> {
+        self.printers.get(name).ok_or_else(|| {
+            if self.printers.is_empty() {
+                return format!("Unknown printer {}, none in the config", name);
+            }
+
+            let names: Vec<&str> = self.printers.keys().map(String::as_str).collect();
+            format!("Unknown printer {}, expected {}", name, names.join(", "))
+        })
+    }
+
+    /// The slicer printer profile set up for the printer at `url`, if any.
+    pub fn profile(&self, url: &str) -> Option<&str> {
+        self.printers
+            .values()
+            .find(|printer| printer.url == url)
+            .and_then(|printer| printer.profile.as_deref())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/moonraker-cli`, defaulting to `~/.config/moonraker-cli`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(base.join("moonraker-cli"))
+}
+
+/// `$XDG_CACHE_HOME/moonraker-cli`, defaulting to `~/.cache/moonraker-cli`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    Some(base.join("moonraker-cli"))
+}
@@ -0,0 +1,1465 @@
+use crate::clipboard::Backend;
+use crate::fan;
+use crate::files;
+use crate::inputrc;
+use crate::jog;
+use crate::meta::{self, Input, MetaCommand, Mode};
+use crate::palette;
+use crate::pending::Pending;
+use crate::popup;
+use crate::preheat;
+use crate::prompt;
+use crate::screensaver::Screensaver;
+use crate::status::Density;
+use crate::template;
+use crate::transcript::Transcript;
+use crate::tune::{Adjust, Factor};
+use crate::{Control, Error, Request, SharedState};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::history::DefaultHistory;
+use rustyline::line_buffer::LineBuffer;
+use rustyline::validate::Validator;
+use rustyline::Changeset;
+use rustyline::{
+    Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, ExternalPrinter,
+    Helper, KeyCode, KeyEvent, Modifiers, Movement, RepeatCount,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, Sender};
+use uuid::Uuid;
+
+/// How many palette matches `:palette` lists.
+const PALETTE_RESULTS: usize = 10;
+
+/// The Z offset change of Alt-Up and Alt-Down, in mm.
+const BABYSTEP: f64 = 0.01;
+
+/// The speed factor change of Alt-, and Alt-., in percentage points.
+const SPEED_STEP: f64 = 10.0;
+
+/// The flow factor change of Alt-{ and Alt-}, in percentage points.
+const FLOW_STEP: f64 = 1.0;
+
+/// How long Alt-X waits for the second press confirming the cancel.
+const CANCEL_CONFIRMATION: Duration = Duration::from_secs(3);
+
+/// Keys bound in the console, shown by the help popup.
+const KEYBINDINGS: &[(&str, &str)] = &[
+    ("F1, ?", "show this help, ? only on an empty line"),
+    ("Ctrl-P", "command palette, Tab cycles through the matches"),
+    (
+        "Ctrl-T",
+        "find a G-code file, Tab cycles through the matches",
+    ),
+    ("Tab", "complete"),
+    ("Up, Down", "browse history"),
+    ("Right, End", "accept the suggestion from history"),
+    ("Ctrl-R", "search history"),
+    ("Ctrl-L", "clear the screen"),
+    ("Alt-W", "copy the line, with OSC 52 over SSH"),
+    ("Alt-P, Alt-R", "pause or resume the print"),
+    ("Alt-X", "cancel the print, pressed twice to confirm"),
+    (
+        "Alt-Up, Alt-Down",
+        "raise or lower the nozzle 0.01 mm while printing",
+    ),
+    ("Alt-,, Alt-.", "slow down or speed up the print by 10%"),
+    ("Alt-{, Alt-}", "lower or raise the flow by 1%"),
+    ("Alt-C", "turn every heater off, unless printing"),
+    (
+        "Alt-F, Alt--, Alt-=",
+        "pick the next fan, lower or raise its speed by 10%",
+    ),
+    (
+        "Alt-J",
+        "toggle the jog pad, arrows jog X and Y, Page Up and Down Z, 1 to 4 pick the step",
+    ),
+    (
+        "Alt-O",
+        "toggle the file browser pane, Up and Down select, Enter opens, Alt-D E M K N act",
+    ),
+    (
+        "Ctrl-C",
+        "discard the current line, on an empty one abandon the last command",
+    ),
+    ("Ctrl-D", "quit"),
+];
+
+/// Reads lines from the terminal, handles meta-commands locally and
+/// forwards everything else to the network loop.
+pub fn run(
+    io_tx: Sender<(Uuid, Request)>,
+    mut network_rx: Receiver<(Uuid, String)>,
+    notice_tx: Sender<String>,
+    mut notice_rx: Receiver<String>,
+    shared: SharedState,
+) -> Result<(), Error> {
+    let _span = tracing::info_span!("console").entered();
+
+    // The user's inputrc bindings come first, so the console's own win
+    let inputrc = inputrc::load();
+    let history_config = inputrc.config.clone();
+    let mut editor: Editor<ConsoleHelper, DefaultHistory> = Editor::with_config(inputrc.config)?;
+
+    for (keys, cmd) in inputrc.bindings {
+        editor.bind_sequence(keys, cmd);
+    }
+
+    // Then the config ones, a binding to anything but a readline function
+    // replaces the line with it, like a macro
+    for (keys, action) in &shared.config.keybindings {
+        let Some(keys) = inputrc::parse_keys(keys) else {
+            continue;
+        };
+        let cmd = inputrc::command(action)
+            .unwrap_or_else(|| Cmd::Replace(Movement::WholeLine, Some(action.clone())));
+
+        editor.bind_sequence(keys, cmd);
+    }
+
+    let mut stdout = io::stdout();
+    let transcript = Transcript::default();
+
+    // Notices arrive at any time, the external printer writes them
+    // above the prompt without messing up the line being edited
+    let mut printer: Box<dyn ExternalPrinter + Send> = if shared.plain {
+        Box::new(PlainPrinter)
+    } else {
+        Box::new(editor.create_external_printer()?)
+    };
+    let plain = shared.plain;
+    let notice_transcript = transcript.clone();
+    let notice_screensaver = shared.screensaver.clone();
+    let notice_split = shared.split.clone();
+    let notice_images = shared.images.clone();
+    // Whether the line right above the prompt is a temperature report
+    let last_is_report = Arc::new(AtomicBool::new(false));
+    let notice_last_is_report = last_is_report.clone();
+
+    thread::spawn(move || {
+        let _span = tracing::info_span!("notices").entered();
+
+        while let Some(notice) = notice_rx.blocking_recv() {
+            notice_screensaver.wake();
+
+            // Heating reports a temperature every second, consecutive reports
+            // overwrite each other instead of flooding the console
+            let is_report = is_temperature_report(&notice);
+            let replace = !plain && is_report && notice_last_is_report.load(Ordering::SeqCst);
+            let mut output = notice.clone();
+
+            if replace {
+                output = format!("\x1b[A\x1b[2K{}", notice);
+                notice_transcript.replace_last(notice);
+            } else {
+                notice_transcript.push(notice);
+            }
+
+            notice_last_is_report.store(is_report, Ordering::SeqCst);
+
+            if printer
+                .print(notice_images.draw(notice_split.fit(output)))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    // Replies are printed as they come, headed by the command they answer,
+    // so the prompt is free for the next command meanwhile. Without the
+    // TUI the console waits for them instead.
+    let reply_pending = shared.pending.clone();
+    let reply_notice_tx = notice_tx.clone();
+    let (reply_pager, pager_lines) = (shared.pager.clone(), shared.config.pager_lines);
+    let mut plain_replies = None;
+
+    if shared.plain {
+        plain_replies = Some(network_rx);
+    } else {
+        thread::spawn(move || {
+            while let Some((id, resp)) = network_rx.blocking_recv() {
+                // Abandoned with Ctrl-C, nobody is waiting for it anymore
+                let Some(request) = reply_pending.finish(id) else {
+                    continue;
+                };
+
+                let resp = reply_pager.keep(resp, pager_lines);
+
+                if reply_notice_tx
+                    .blocking_send(format!("< {}\n{}", request.command, resp))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    editor.set_helper(Some(ConsoleHelper {
+        macros: shared.macros.clone(),
+        files: shared.files.clone(),
+        hinter: HistoryHinter::new(),
+    }));
+    editor.bind_sequence(
+        Event::Any,
+        EventHandler::Conditional(Box::new(WakeScreen(shared.screensaver.clone()))),
+    );
+    editor.bind_sequence(
+        KeyEvent::ctrl('p'),
+        EventHandler::Conditional(Box::new(OpenPalette(shared.screensaver.clone()))),
+    );
+    editor.bind_sequence(
+        KeyEvent::ctrl('t'),
+        EventHandler::Conditional(Box::new(OpenFinder(shared.screensaver.clone()))),
+    );
+    editor.bind_sequence(
+        KeyEvent::ctrl('c'),
+        EventHandler::Conditional(Box::new(Abandon(notice_tx.clone(), shared.pending.clone()))),
+    );
+    editor.bind_sequence(
+        KeyEvent::alt('w'),
+        EventHandler::Conditional(Box::new(CopyLine(shared.clipboard))),
+    );
+    for (key, control) in [
+        ('p', Control::Pause),
+        ('r', Control::Resume),
+        ('x', Control::Cancel),
+    ] {
+        editor.bind_sequence(
+            KeyEvent::alt(key),
+            EventHandler::Conditional(Box::new(ControlPrint {
+                control,
+                io_tx: io_tx.clone(),
+                notice_tx: notice_tx.clone(),
+                shared: shared.clone(),
+                armed: Mutex::new(None),
+            })),
+        );
+    }
+    for (key, tweak) in [
+        (
+            KeyEvent(KeyCode::Up, Modifiers::ALT),
+            Tweak::ZOffset(BABYSTEP),
+        ),
+        (
+            KeyEvent(KeyCode::Down, Modifiers::ALT),
+            Tweak::ZOffset(-BABYSTEP),
+        ),
+        // Not Alt-[, terminals send it as the start of an escape sequence
+        (KeyEvent::alt('.'), Tweak::Factor(Factor::Speed, SPEED_STEP)),
+        (
+            KeyEvent::alt(','),
+            Tweak::Factor(Factor::Speed, -SPEED_STEP),
+        ),
+        (KeyEvent::alt('}'), Tweak::Factor(Factor::Flow, FLOW_STEP)),
+        (KeyEvent::alt('{'), Tweak::Factor(Factor::Flow, -FLOW_STEP)),
+    ] {
+        editor.bind_sequence(
+            key,
+            EventHandler::Conditional(Box::new(TunePrint {
+                tweak,
+                io_tx: io_tx.clone(),
+                notice_tx: notice_tx.clone(),
+                shared: shared.clone(),
+            })),
+        );
+    }
+    editor.bind_sequence(
+        KeyEvent::alt('c'),
+        EventHandler::Conditional(Box::new(Cooldown {
+            io_tx: io_tx.clone(),
+            notice_tx: notice_tx.clone(),
+            shared: shared.clone(),
+        })),
+    );
+    for (key, command, label) in [
+        ('f', fan::Command::Next, ":fans next".to_string()),
+        (
+            '-',
+            fan::Command::By(-fan::STEP),
+            format!(":fans -{}", fan::STEP),
+        ),
+        (
+            '=',
+            fan::Command::By(fan::STEP),
+            format!(":fans +{}", fan::STEP),
+        ),
+    ] {
+        editor.bind_sequence(
+            KeyEvent::alt(key),
+            EventHandler::Conditional(Box::new(FanControl {
+                command,
+                label,
+                io_tx: io_tx.clone(),
+                notice_tx: notice_tx.clone(),
+                shared: shared.clone(),
+            })),
+        );
+    }
+    // Esc, Up and Down are shared with the jog pad, whichever is on acts
+    let mut pane_keys = vec![
+        (KeyEvent::alt('o'), FileKey::Toggle),
+        (KeyEvent(KeyCode::Esc, Modifiers::NONE), FileKey::Leave),
+        (KeyEvent(KeyCode::Up, Modifiers::NONE), FileKey::Select(-1)),
+        (KeyEvent(KeyCode::Down, Modifiers::NONE), FileKey::Select(1)),
+        (KeyEvent(KeyCode::Enter, Modifiers::NONE), FileKey::Open),
+        (KeyEvent(KeyCode::Backspace, Modifiers::NONE), FileKey::Up),
+        // With Alt, typing G-code with the pane on never acts on a file
+        (KeyEvent::alt('d'), FileKey::Delete),
+        (KeyEvent::alt('e'), FileKey::Ask("rename")),
+        (KeyEvent::alt('m'), FileKey::Ask("move")),
+        (KeyEvent::alt('k'), FileKey::Ask("copy")),
+        (KeyEvent::alt('n'), FileKey::Mkdir),
+    ];
+    let file_pane = |key| FilePane {
+        key,
+        io_tx: io_tx.clone(),
+        notice_tx: notice_tx.clone(),
+        shared: shared.clone(),
+    };
+    let steps = jog::PAD_STEPS
+        .iter()
+        .zip('1'..)
+        .map(|(step, key)| (KeyEvent::new(key, Modifiers::NONE), PadKey::Step(*step)));
+    for (key, pad_key) in [
+        (KeyEvent::alt('j'), PadKey::Toggle),
+        (KeyEvent(KeyCode::Esc, Modifiers::NONE), PadKey::Leave),
+        (
+            KeyEvent(KeyCode::Left, Modifiers::NONE),
+            PadKey::Move('x', -1.0),
+        ),
+        (
+            KeyEvent(KeyCode::Right, Modifiers::NONE),
+            PadKey::Move('x', 1.0),
+        ),
+        (
+            KeyEvent(KeyCode::Down, Modifiers::NONE),
+            PadKey::Move('y', -1.0),
+        ),
+        (
+            KeyEvent(KeyCode::Up, Modifiers::NONE),
+            PadKey::Move('y', 1.0),
+        ),
+        (
+            KeyEvent(KeyCode::PageDown, Modifiers::NONE),
+            PadKey::Move('z', -1.0),
+        ),
+        (
+            KeyEvent(KeyCode::PageUp, Modifiers::NONE),
+            PadKey::Move('z', 1.0),
+        ),
+    ]
+    .into_iter()
+    .chain(steps)
+    {
+        let jog_pad = JogPad {
+            key: pad_key,
+            io_tx: io_tx.clone(),
+            notice_tx: notice_tx.clone(),
+            shared: shared.clone(),
+        };
+        let handler: Box<dyn ConditionalEventHandler> =
+            match pane_keys.iter().position(|(pane_key, _)| *pane_key == key) {
+                Some(index) => Box::new(EitherPane(file_pane(pane_keys.remove(index).1), jog_pad)),
+                None => Box::new(jog_pad),
+            };
+        editor.bind_sequence(key, EventHandler::Conditional(handler));
+    }
+    for (key, file_key) in pane_keys {
+        editor.bind_sequence(
+            key,
+            EventHandler::Conditional(Box::new(file_pane(file_key))),
+        );
+    }
+    editor.bind_sequence(
+        KeyEvent(KeyCode::End, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(AcceptHint)),
+    );
+    editor.bind_sequence(
+        KeyEvent(KeyCode::F(1), Modifiers::NONE),
+        EventHandler::Conditional(Box::new(ShowHelp(
+            notice_tx.clone(),
+            shared.screensaver.clone(),
+        ))),
+    );
+    editor.bind_sequence(
+        KeyEvent::new('?', Modifiers::NONE),
+        EventHandler::Conditional(Box::new(ShowHelp(notice_tx, shared.screensaver.clone()))),
+    );
+
+    let mut mode = shared.mode;
+    let mut printer = shared.printer.clone();
+    // History and mode of the printers switched away from
+    let mut sessions: HashMap<Option<String>, (DefaultHistory, Mode)> = HashMap::new();
+    // History and mode of the watched printer, :swap gives them back
+    let mut swapped: Option<(DefaultHistory, Mode)> = None;
+
+    loop {
+        let line = match editor.readline(mode.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        // The entered line now sits between the last report and what follows
+        last_is_report.store(false, Ordering::SeqCst);
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(line.as_str())?;
+        transcript.push(format!("{}{}", mode.prompt(), line.trim()));
+
+        // Templates from the config expand to G-code, asking for missing values
+        let template = template::find(&shared.config.templates, &line);
+        let line = match template.filter(|_| mode == Mode::Gcode) {
+            Some((body, args)) => {
+                let parameters = template::parameters(body);
+                let mut values = match template::values(&parameters, args) {
+                    Ok(values) => values,
+                    Err(err) => {
+                        print(&mut stdout, &transcript, &shared, err)?;
+                        continue;
+                    }
+                };
+
+                for parameter in &parameters {
+                    if values.contains_key(parameter) {
+                        continue;
+                    }
+
+                    match editor.readline(&format!("{}: ", parameter)) {
+                        Ok(value) if !value.trim().is_empty() => {
+                            values.insert(parameter.clone(), value.trim().to_string());
+                        }
+                        _ => break,
+                    }
+                }
+
+                if values.len() < parameters.len() {
+                    continue;
+                }
+
+                template::fill(body, &values)
+            }
+            None => line,
+        };
+
+        let request = match Input::parse(&line, mode) {
+            Ok(Input::Gcode(script)) => Request::Gcode(script),
+            Ok(Input::Meta(MetaCommand::Connect(url))) => Request::Connect(url),
+            Ok(Input::Meta(MetaCommand::Filter(filter))) => Request::Filter(filter),
+            Ok(Input::Meta(MetaCommand::ExportQueue(path))) => Request::ExportQueue(path),
+            Ok(Input::Meta(MetaCommand::Rpc(method, params))) => Request::Rpc(method, params),
+            Ok(Input::Meta(MetaCommand::Timeout(timeout))) => Request::Timeout(timeout),
+            Ok(Input::Meta(MetaCommand::Watch(url))) => {
+                // Another printer starts with a history of its own
+                swapped = None;
+                Request::Watch(url)
+            }
+            Ok(Input::Meta(MetaCommand::Swap)) => {
+                if shared.watched.lock().unwrap().is_none() {
+                    let output = "Not watching any printer, try :watch <url>".to_string();
+                    print(&mut stdout, &transcript, &shared, output)?;
+                    continue;
+                }
+
+                // Each side keeps its own history and mode
+                let (history, new_mode) = swapped
+                    .take()
+                    .unwrap_or_else(|| (DefaultHistory::with_config(&history_config), shared.mode));
+                swapped = Some((
+                    std::mem::replace(editor.history_mut(), history),
+                    std::mem::replace(&mut mode, new_mode),
+                ));
+
+                Request::Swap
+            }
+            Ok(Input::Meta(MetaCommand::Split)) => {
+                let output = match shared.split.toggle() {
+                    true if shared.watched.lock().unwrap().is_none() => {
+                        "Split, :watch <url> picks the printer shown on the right".to_string()
+                    }
+                    true => "Split, :swap moves the input to the printer on the right".to_string(),
+                    false => "Split off".to_string(),
+                };
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Compare(first, second))) => Request::Compare(first, second),
+            Ok(Input::Meta(MetaCommand::Plot(heater, seconds))) => Request::Plot(heater, seconds),
+            Ok(Input::Meta(MetaCommand::Jog(axis, distance))) => Request::Jog(axis, distance),
+            Ok(Input::Meta(MetaCommand::Extrude(length, heat))) => Request::Extrude(length, heat),
+            Ok(Input::Meta(MetaCommand::Check(file))) => Request::Check(file),
+            Ok(Input::Meta(MetaCommand::Logout)) => Request::Logout,
+            Ok(Input::Meta(MetaCommand::Cleanup(command))) => Request::Cleanup(command),
+            Ok(Input::Meta(MetaCommand::Files(command))) => Request::Files(command),
+            Ok(Input::Meta(MetaCommand::Queue(command))) => Request::Queue(command),
+            Ok(Input::Meta(MetaCommand::History(command))) => Request::History(command),
+            Ok(Input::Meta(MetaCommand::Stats(monthly))) => Request::Stats(monthly),
+            Ok(Input::Meta(MetaCommand::Cost)) => Request::Cost,
+            Ok(Input::Meta(MetaCommand::Pause)) => Request::Control(Control::Pause),
+            Ok(Input::Meta(MetaCommand::Resume)) => Request::Control(Control::Resume),
+            Ok(Input::Meta(MetaCommand::Cancel)) => Request::Control(Control::Cancel),
+            Ok(Input::Meta(MetaCommand::Objects(command))) => Request::Objects(command),
+            Ok(Input::Meta(MetaCommand::Calibrate(command))) => Request::Calibrate(command),
+            Ok(Input::Meta(MetaCommand::Preheat(command))) => Request::Preheat(command),
+            Ok(Input::Meta(MetaCommand::Fans(command))) => Request::Fans(command),
+            Ok(Input::Meta(MetaCommand::Leds(command))) => Request::Leds(command),
+            Ok(Input::Meta(MetaCommand::Power(command))) => Request::Power(command),
+            Ok(Input::Meta(MetaCommand::Pins(command))) => Request::Pins(command),
+            Ok(Input::Meta(MetaCommand::Endstops)) => Request::Endstops,
+            Ok(Input::Meta(MetaCommand::Mesh(profile))) => Request::Mesh(profile),
+            Ok(Input::Meta(MetaCommand::Screws(command))) => Request::Screws(command),
+            Ok(Input::Meta(MetaCommand::Tune(factor, adjust))) => Request::Tune(factor, adjust),
+            Ok(Input::Meta(MetaCommand::Spool(command))) => Request::Spool(command),
+            Ok(Input::Meta(MetaCommand::Upload(file, print))) => Request::Upload(file, print),
+            Ok(Input::Meta(MetaCommand::System(command))) => Request::System(command),
+            Ok(Input::Meta(MetaCommand::All(script))) => Request::All(script),
+            Ok(Input::Meta(MetaCommand::DebugState)) => Request::DebugState,
+            Ok(Input::Meta(MetaCommand::Density(density))) => {
+                let mut current = shared.density.lock().unwrap();
+                *current = density.unwrap_or(match *current {
+                    Density::Compact => Density::Detailed,
+                    Density::Detailed => Density::Compact,
+                });
+
+                let output = format!("Status bar {}", current.name());
+                drop(current);
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Copy(lines))) => {
+                let text = match lines {
+                    Some(lines) => Some(transcript.last_lines(lines)),
+                    None => shared.pager.last(),
+                };
+                let output = match text {
+                    Some(text) => match shared.clipboard.copy(&text) {
+                        Ok(backend) => {
+                            format!("Copied {} lines with {}", text.lines().count(), backend)
+                        }
+                        Err(err) => err,
+                    },
+                    None => "No reply to copy yet".to_string(),
+                };
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Page)) => {
+                if let Err(err) = shared.pager.show() {
+                    print(&mut stdout, &transcript, &shared, err)?;
+                }
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Prompt(None))) => {
+                let output = shared
+                    .prompts
+                    .current()
+                    .unwrap_or_else(|| "No dialog open".to_string());
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Prompt(Some(n)))) => match shared.prompts.button(n) {
+                Some(gcode) => Request::Gcode(gcode),
+                None => {
+                    print(
+                        &mut stdout,
+                        &transcript,
+                        &shared,
+                        format!("No button {}", n),
+                    )?;
+                    continue;
+                }
+            },
+            Ok(Input::Meta(MetaCommand::PromptClose)) => {
+                if !shared.prompts.close() {
+                    print(
+                        &mut stdout,
+                        &transcript,
+                        &shared,
+                        "No dialog open".to_string(),
+                    )?;
+                    continue;
+                }
+
+                Request::Gcode(prompt::CLOSE_GCODE.to_string())
+            }
+            Ok(Input::Meta(MetaCommand::Printer(name))) => {
+                if let Err(err) = shared.config.printer(&name) {
+                    print(&mut stdout, &transcript, &shared, err)?;
+                    continue;
+                }
+
+                if printer.as_ref() != Some(&name) {
+                    let (history, new_mode) =
+                        sessions.remove(&Some(name.clone())).unwrap_or_else(|| {
+                            (DefaultHistory::with_config(&history_config), shared.mode)
+                        });
+                    let previous = (
+                        std::mem::replace(editor.history_mut(), history),
+                        std::mem::replace(&mut mode, new_mode),
+                    );
+                    sessions.insert(printer.replace(name.clone()), previous);
+                }
+
+                Request::Printer(name)
+            }
+            Ok(Input::Meta(MetaCommand::Login(username))) => {
+                let username = match username {
+                    Some(username) => username,
+                    None => match editor.readline("Username: ") {
+                        Ok(username) if !username.trim().is_empty() => username.trim().to_string(),
+                        _ => continue,
+                    },
+                };
+                // Not echoed, nor kept in the history or the transcript
+                let Ok(password) = rpassword::prompt_password("Password: ") else {
+                    continue;
+                };
+                Request::Login(username, password)
+            }
+            Ok(Input::Meta(MetaCommand::Print(file, force))) => Request::Print(file, force),
+            Ok(Input::Meta(MetaCommand::DebugBundle)) => Request::DebugBundle(transcript.text()),
+            Ok(Input::Meta(MetaCommand::Clear)) => {
+                stdout.write_all(b"\x1b[2J\x1b[H")?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Save(path))) => {
+                let output = match fs::write(&path, transcript.text()) {
+                    Ok(()) => format!("Transcript saved to {}", path),
+                    Err(err) => format!("Unable to save transcript to {}: {}", path, err),
+                };
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Export(export))) => {
+                let output = transcript.export(&export).unwrap_or_else(|err| err);
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Palette(query))) => {
+                let entries = palette::entries(&shared.macros.lock().unwrap());
+                let output = palette::search(&query, &entries)
+                    .iter()
+                    .take(PALETTE_RESULTS)
+                    .map(|entry| format!("{:30}  {}", entry.command, entry.description))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Webhook(webhook))) => {
+                let output = match &webhook {
+                    Some(webhook) if webhook.snapshot => {
+                        format!("Notifying {} with webcam snapshots", webhook.url)
+                    }
+                    Some(webhook) => format!("Notifying {}", webhook.url),
+                    None => "Webhook removed".to_string(),
+                };
+                shared.webhooks.set(webhook);
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Screensaver(timeout))) => {
+                let output = match timeout {
+                    Some(timeout) => format!(
+                        "Blanking the screen after {} minutes of inactivity",
+                        timeout.as_secs_f64() / 60.0
+                    ),
+                    None => "Screensaver disabled".to_string(),
+                };
+                shared.screensaver.set_timeout(timeout);
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Mode(new_mode))) => {
+                mode = new_mode;
+                let output = format!("Sending input as {}", mode.name());
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Replay)) => {
+                let queued = shared.offline_queue.take();
+                let output = format!("Replaying {} queued commands", queued.len());
+                print(&mut stdout, &transcript, &shared, output)?;
+
+                for (line, request) in queued {
+                    let id = shared.pending.start(&line);
+                    io_tx.blocking_send((id, request))?;
+
+                    if let Some(network_rx) = plain_replies.as_mut() {
+                        wait_reply(network_rx, id, &shared, &mut stdout, &transcript)?;
+                    }
+                }
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Discard)) => {
+                let output = format!(
+                    "Discarded {} queued commands",
+                    shared.offline_queue.take().len()
+                );
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::KeepOut(Some(zone)))) => {
+                let zone = zone.to_mm(shared.config.units);
+                shared.keepout.add(zone);
+                print(
+                    &mut stdout,
+                    &transcript,
+                    &shared,
+                    format!("Keeping out of {}", zone.format(shared.config.units)),
+                )?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::KeepOut(None))) => {
+                let zones = shared.keepout.zones();
+                let output = if zones.is_empty() {
+                    "No keep-out zones".to_string()
+                } else {
+                    zones
+                        .iter()
+                        .map(|zone| format!("Keep-out zone {}", zone.format(shared.config.units)))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                };
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::KeepOutClear)) => {
+                shared.keepout.clear();
+                print(
+                    &mut stdout,
+                    &transcript,
+                    &shared,
+                    "Keep-out zones cleared".to_string(),
+                )?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Ack)) => {
+                let output = match shared.alerts.acknowledge() {
+                    Some(message) => format!("Acknowledged: {}", message),
+                    None => "No pending alerts".to_string(),
+                };
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::JogPad(step))) => {
+                let units = shared.config.units;
+                let output = match step {
+                    Some(step) => {
+                        shared.jog_pad.set_step(step);
+                        shared.browser.leave_pane();
+                        shared.jog_pad.panel(units)
+                    }
+                    None if shared.jog_pad.toggle() => {
+                        shared.browser.leave_pane();
+                        shared.jog_pad.panel(units)
+                    }
+                    None => "Jog pad off".to_string(),
+                };
+                print(&mut stdout, &transcript, &shared, output)?;
+                continue;
+            }
+            Ok(Input::Meta(MetaCommand::Help)) => {
+                print(&mut stdout, &transcript, &shared, help_popup())?;
+                continue;
+            }
+            Err(err) => {
+                print(&mut stdout, &transcript, &shared, err)?;
+                continue;
+            }
+        };
+
+        let is_printer_command = matches!(request, Request::Gcode(_) | Request::Rpc(..));
+
+        // Only once the printer was seen and went away, before that there's
+        // nothing to come back. Without the TUI commands are sent anyway and
+        // fail if the printer is unreachable
+        if is_printer_command && shared.status.is_lost() && !shared.plain {
+            let queued = shared.offline_queue.push(line.trim(), request);
+            let output = format!(
+                "Queued {} until the printer is back, {} queued",
+                line.trim(),
+                queued
+            );
+            print(&mut stdout, &transcript, &shared, output)?;
+            continue;
+        }
+
+        let id = shared.pending.start(line.trim());
+        tracing::debug!(%id, command = line.trim(), "sent");
+        io_tx.blocking_send((id, request))?;
+
+        if let Some(network_rx) = plain_replies.as_mut() {
+            wait_reply(network_rx, id, &shared, &mut stdout, &transcript)?;
+        }
+    }
+}
+
+/// Prints replies until the one to `id`, so without the TUI each reply
+/// comes before the next prompt.
+fn wait_reply(
+    network_rx: &mut Receiver<(Uuid, String)>,
+    id: Uuid,
+    shared: &SharedState,
+    stdout: &mut io::Stdout,
+    transcript: &Transcript,
+) -> io::Result<()> {
+    while let Some((reply_id, resp)) = network_rx.blocking_recv() {
+        if let Some(request) = shared.pending.finish(reply_id) {
+            let resp = shared.pager.keep(resp, shared.config.pager_lines);
+            print(
+                stdout,
+                transcript,
+                shared,
+                format!("< {}\n{}", request.command, resp),
+            )?;
+        }
+
+        if reply_id == id {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints notices as lines, for `--no-tui` where the prompt isn't redrawn
+/// around them.
+struct PlainPrinter;
+
+impl ExternalPrinter for PlainPrinter {
+    fn print(&mut self, msg: String) -> rustyline::Result<()> {
+        println!("{}", msg);
+        Ok(())
+    }
+}
+
+/// Prints `output` left of the `:split` pane, if any, with the thumbnails
+/// it has placeholders for.
+fn print(
+    stdout: &mut io::Stdout,
+    transcript: &Transcript,
+    shared: &SharedState,
+    output: String,
+) -> io::Result<()> {
+    let shown = shared.images.draw(shared.split.fit(output.clone()));
+    writeln!(stdout, "{}", shown)?;
+    transcript.push(output);
+    Ok(())
+}
+
+/// Tells Klipper temperature reports, like `T:210.1 /210.0 B:60.0 /60.0`
+/// or `ok B:59.8 /60.0 T0:205.3 /210.0`, from other G-code responses.
+fn is_temperature_report(line: &str) -> bool {
+    let line = line.trim();
+    let line = line.strip_prefix("ok ").unwrap_or(line);
+    let is_number = |value: &str| !value.is_empty() && value.parse::<f64>().is_ok();
+
+    let mut heaters = 0;
+
+    for word in line.split_whitespace() {
+        if let Some(target) = word.strip_prefix('/') {
+            if !is_number(target) {
+                return false;
+            }
+        } else if let Some((heater, temp)) = word.split_once(':') {
+            let is_heater = heater.starts_with(['T', 'B', 'C'])
+                && heater.chars().all(|c| c.is_ascii_alphanumeric());
+
+            if !is_heater || !is_number(temp) {
+                return false;
+            }
+
+            heaters += 1;
+        } else {
+            return false;
+        }
+    }
+
+    heaters > 0
+}
+
+fn help_popup() -> String {
+    let width = KEYBINDINGS
+        .iter()
+        .map(|(keys, _)| keys.len())
+        .max()
+        .unwrap_or(0);
+    let mut lines = vec!["Keybindings".to_string()];
+
+    lines.extend(
+        KEYBINDINGS
+            .iter()
+            .map(|(keys, description)| format!("  {:width$}  {}", keys, description)),
+    );
+    lines.push(String::new());
+    lines.push("Meta-commands".to_string());
+    lines.extend(meta::help().lines().map(|line| format!("  {}", line)));
+
+    popup::render("Help", &lines)
+}
+
+/// Line editor hooks, completes `:palette <query>` and `:files <query>`
+/// with the best matches.
+struct ConsoleHelper {
+    macros: Arc<Mutex<Vec<String>>>,
+    files: files::Index,
+    hinter: HistoryHinter,
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        _pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        if let Some(query) = line.strip_prefix(":files ") {
+            return Ok((0, self.complete_file(query)));
+        }
+
+        let Some(query) = line.strip_prefix(":palette ") else {
+            return Ok((0, Vec::new()));
+        };
+
+        let entries = palette::entries(&self.macros.lock().unwrap());
+        let candidates = palette::search(query, &entries)
+            .into_iter()
+            .map(|entry| Pair {
+                display: format!("{}  {}", entry.command, entry.description),
+                replacement: entry.command.clone(),
+            })
+            .collect();
+
+        Ok((0, candidates))
+    }
+
+    /// The selected entry replaces the whole line, wherever the cursor is.
+    fn update(&self, line: &mut LineBuffer, _start: usize, elected: &str, cl: &mut Changeset) {
+        line.update(elected, elected.len(), cl);
+    }
+}
+
+impl ConsoleHelper {
+    /// The files matching `query` as `:files <path>`, unless it's another
+    /// `:files` command.
+    fn complete_file(&self, query: &str) -> Vec<Pair> {
+        let first = query.split_whitespace().next().unwrap_or_default();
+
+        if first.is_empty() || first.parse::<usize>().is_ok() {
+            return Vec::new();
+        }
+
+        if matches!(first, ".." | "find" | "mkdir") && query.trim() != first {
+            return Vec::new();
+        }
+
+        self.files
+            .search(query, PALETTE_RESULTS)
+            .into_iter()
+            .map(|path| Pair {
+                replacement: format!(":files {}", path),
+                display: path,
+            })
+            .collect()
+    }
+}
+
+/// Suggests the latest history entry starting with the line, fish-style.
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<String> {
+        self.hinter.hint(line, pos, ctx)
+    }
+}
+
+impl Highlighter for ConsoleHelper {
+    /// Dimmed, so it's not taken for typed text.
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
+
+/// End accepts the history suggestion like Right does, when there's one.
+struct AcceptHint;
+
+impl ConditionalEventHandler for AcceptHint {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        (ctx.has_hint() && ctx.pos() == ctx.line().len()).then_some(Cmd::CompleteHint)
+    }
+}
+
+/// Ctrl-P starts a palette query, or turns what was already typed into one,
+/// Tab then cycles through the matches.
+struct OpenPalette(Screensaver);
+
+impl ConditionalEventHandler for OpenPalette {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if self.0.wake() || ctx.line().starts_with(":palette ") {
+            Some(Cmd::Noop)
+        } else if ctx.line().is_empty() {
+            Some(Cmd::Insert(1, ":palette ".to_string()))
+        } else {
+            let query = format!(":palette {}", ctx.line());
+            Some(Cmd::Replace(Movement::WholeLine, Some(query)))
+        }
+    }
+}
+
+/// Ctrl-T starts a file query like Ctrl-P a palette one, Tab then cycles
+/// through the matching G-code files.
+struct OpenFinder(Screensaver);
+
+impl ConditionalEventHandler for OpenFinder {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if self.0.wake() || ctx.line().starts_with(":files ") {
+            Some(Cmd::Noop)
+        } else if ctx.line().is_empty() {
+            Some(Cmd::Insert(1, ":files ".to_string()))
+        } else {
+            let query = format!(":files {}", ctx.line());
+            Some(Cmd::Replace(Movement::WholeLine, Some(query)))
+        }
+    }
+}
+
+/// Prints the help popup above the prompt, leaving the edited line untouched.
+/// Bound to `?` too, which still inserts itself unless the line is empty.
+struct ShowHelp(Sender<String>, Screensaver);
+
+impl ConditionalEventHandler for ShowHelp {
+    fn handle(&self, evt: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if self.1.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        let is_question_mark = evt.get(0) == Some(&KeyEvent::new('?', Modifiers::NONE));
+
+        if is_question_mark && !ctx.line().is_empty() {
+            return None;
+        }
+
+        let _ = self.0.try_send(help_popup());
+        Some(Cmd::Noop)
+    }
+}
+
+/// Alt-W copies the line being edited, the terminal does it with OSC 52
+/// when there's no display, like over SSH.
+struct CopyLine(Backend);
+
+impl ConditionalEventHandler for CopyLine {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        // Nowhere to report a failure without disturbing the line
+        let _ = self.0.copy(ctx.line());
+        Some(Cmd::Noop)
+    }
+}
+
+/// Ctrl-C on an empty line gives up on the last command still waiting for
+/// a reply, otherwise it discards the line as usual.
+struct Abandon(Sender<String>, Pending);
+
+impl ConditionalEventHandler for Abandon {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !ctx.line().is_empty() {
+            return None;
+        }
+
+        let request = self.1.abandon_latest()?;
+        let notice = format!(
+            "Abandoned {}, the printer may still run it",
+            request.command
+        );
+        let _ = self.0.try_send(notice);
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// Alt-P, Alt-R and Alt-X pause, resume and cancel the print whatever the
+/// line holds, which is left alone. Cancelling takes a second press within
+/// `CANCEL_CONFIRMATION`.
+struct ControlPrint {
+    control: Control,
+    io_tx: Sender<(Uuid, Request)>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+    /// When Alt-X was first pressed
+    armed: Mutex<Option<Instant>>,
+}
+
+impl ConditionalEventHandler for ControlPrint {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        if self.shared.screensaver.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        let verb = self.control.verb();
+        let state = self.shared.status.print_state().unwrap_or_default();
+
+        if !self.shared.status.is_online() || !self.control.applies(&state) {
+            let _ = self.notice_tx.try_send(format!("No print to {}", verb));
+            return Some(Cmd::Noop);
+        }
+
+        if let Control::Cancel = self.control {
+            let mut armed = self.armed.lock().unwrap();
+
+            if !armed.is_some_and(|pressed| pressed.elapsed() < CANCEL_CONFIRMATION) {
+                *armed = Some(Instant::now());
+                let _ = self
+                    .notice_tx
+                    .try_send("Press Alt-X again to cancel the print".to_string());
+                return Some(Cmd::Noop);
+            }
+
+            *armed = None;
+        }
+
+        let command = format!(":{}", verb);
+        let id = self.shared.pending.start(&command);
+
+        if self
+            .io_tx
+            .try_send((id, Request::Control(self.control)))
+            .is_err()
+        {
+            self.shared.pending.finish(id);
+        }
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// Alt-C turns every heater off, the network loop refuses while printing.
+struct Cooldown {
+    io_tx: Sender<(Uuid, Request)>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+}
+
+impl ConditionalEventHandler for Cooldown {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        if self.shared.screensaver.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        if !self.shared.status.is_online() {
+            let _ = self.notice_tx.try_send("Printer offline".to_string());
+            return Some(Cmd::Noop);
+        }
+
+        let id = self.shared.pending.start(":cooldown");
+
+        if self
+            .io_tx
+            .try_send((id, Request::Preheat(preheat::Command::Cooldown)))
+            .is_err()
+        {
+            self.shared.pending.finish(id);
+        }
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// Alt-F picks the next fan, Alt-- and Alt-= step its speed, the reply
+/// drawing it as a slider.
+struct FanControl {
+    command: fan::Command,
+    /// The `:fans` command doing the same, shown while it's pending
+    label: String,
+    io_tx: Sender<(Uuid, Request)>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+}
+
+impl ConditionalEventHandler for FanControl {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        if self.shared.screensaver.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        if !self.shared.status.is_online() {
+            let _ = self.notice_tx.try_send("Printer offline".to_string());
+            return Some(Cmd::Noop);
+        }
+
+        let id = self.shared.pending.start(&self.label);
+
+        if self
+            .io_tx
+            .try_send((id, Request::Fans(self.command)))
+            .is_err()
+        {
+            self.shared.pending.finish(id);
+        }
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// What a key tuning the print adjusts, and by how much.
+#[derive(Clone, Copy)]
+enum Tweak {
+    /// In mm, the nozzle moves right away
+    ZOffset(f64),
+    /// In percentage points
+    Factor(Factor, f64),
+}
+
+/// Alt-Up and Alt-Down babystep the Z offset, Alt-, and Alt-. step the
+/// speed, Alt-{ and Alt-} the flow, only while printing. The status bar
+/// shows the live values.
+struct TunePrint {
+    tweak: Tweak,
+    io_tx: Sender<(Uuid, Request)>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+}
+
+impl ConditionalEventHandler for TunePrint {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        if self.shared.screensaver.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        if !self.shared.status.is_online() || !self.shared.status.has_print() {
+            let _ = self.notice_tx.try_send("Only while printing".to_string());
+            return Some(Cmd::Noop);
+        }
+
+        let (command, request) = match self.tweak {
+            Tweak::ZOffset(step) => {
+                let script = format!("SET_GCODE_OFFSET Z_ADJUST={:+.3} MOVE=1", step);
+                (script.clone(), Request::Gcode(script))
+            }
+            Tweak::Factor(factor, points) => (
+                format!(":{} {:+}", factor.command(), points),
+                Request::Tune(factor, Adjust::By(points)),
+            ),
+        };
+        let id = self.shared.pending.start(&command);
+
+        if self.io_tx.try_send((id, request)).is_err() {
+            self.shared.pending.finish(id);
+        }
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// What a key does on the jog pad.
+#[derive(Clone, Copy)]
+enum PadKey {
+    Toggle,
+    Leave,
+    /// Picks the step, in the display unit
+    Step(f64),
+    /// Jogs the axis by the step, in the direction of the sign
+    Move(char, f64),
+}
+
+/// Alt-J toggles the jog pad, the other keys only act while it's on and
+/// otherwise keep their usual meaning. Homing and limits are checked by
+/// the jog itself.
+struct JogPad {
+    key: PadKey,
+    io_tx: Sender<(Uuid, Request)>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+}
+
+impl ConditionalEventHandler for JogPad {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        if self.shared.screensaver.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        let pad = &self.shared.jog_pad;
+        let units = self.shared.config.units;
+        let step = pad.active();
+
+        match (self.key, step) {
+            (PadKey::Toggle, _) => {
+                let notice = if pad.toggle() {
+                    self.shared.browser.leave_pane();
+                    pad.panel(units)
+                } else {
+                    "Jog pad off".to_string()
+                };
+                let _ = self.notice_tx.try_send(notice);
+            }
+            (_, None) => return None,
+            (PadKey::Leave, Some(_)) => {
+                pad.leave();
+                let _ = self.notice_tx.try_send("Jog pad off".to_string());
+            }
+            (PadKey::Step(step), Some(_)) => {
+                pad.set_step(step);
+                let _ = self.notice_tx.try_send(format!(
+                    "Jogging by {}",
+                    units.format_length(units.to_mm(step))
+                ));
+            }
+            (PadKey::Move(axis, direction), Some(step)) => {
+                let distance = direction * step;
+                let id = self
+                    .shared
+                    .pending
+                    .start(&format!(":jog {} {}", axis, distance));
+
+                if self
+                    .io_tx
+                    .try_send((id, Request::Jog(axis, distance)))
+                    .is_err()
+                {
+                    self.shared.pending.finish(id);
+                }
+            }
+        }
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// What a key does on the file browser pane.
+#[derive(Clone, Copy)]
+enum FileKey {
+    Toggle,
+    Leave,
+    /// Moves the selection by that many entries
+    Select(isize),
+    Open,
+    Up,
+    Delete,
+    /// Puts `:files <n> <action> ` on the line for the name to be typed
+    Ask(&'static str),
+    Mkdir,
+}
+
+/// Alt-O toggles the file browser pane, acting on the entries `:files`
+/// lists. Up, Down and Esc act whenever it's on, the other keys only on an
+/// empty line, so names can still be typed. Those acting on files take Alt,
+/// letters are always typed.
+struct FilePane {
+    key: FileKey,
+    io_tx: Sender<(Uuid, Request)>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+}
+
+impl FilePane {
+    fn send(&self, label: String, command: files::Command) {
+        let id = self.shared.pending.start(&label);
+
+        if self.io_tx.try_send((id, Request::Files(command))).is_err() {
+            self.shared.pending.finish(id);
+        }
+    }
+}
+
+impl ConditionalEventHandler for FilePane {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if self.shared.screensaver.wake() {
+            return Some(Cmd::Noop);
+        }
+
+        let browser = &self.shared.browser;
+
+        match (self.key, ctx.line().is_empty()) {
+            (FileKey::Toggle, _) => {
+                if browser.toggle_pane() {
+                    self.shared.jog_pad.leave();
+                    let _ = self.notice_tx.try_send(browser.pane_panel());
+
+                    if browser.selected().is_none() {
+                        self.send(":files".to_string(), files::Command::List);
+                    }
+                } else {
+                    let _ = self.notice_tx.try_send("File browser off".to_string());
+                }
+            }
+            _ if !browser.is_pane_on() => return None,
+            (FileKey::Leave, _) => {
+                browser.leave_pane();
+                let _ = self.notice_tx.try_send("File browser off".to_string());
+            }
+            (FileKey::Select(by), _) => browser.select(by),
+            (_, false) => return None,
+            (FileKey::Open, true) => match browser.pane_open() {
+                Some(command @ files::Command::Act(n, _)) => {
+                    self.send(format!(":files {} print", n), command)
+                }
+                Some(command @ files::Command::Open(n)) => {
+                    self.send(format!(":files {}", n), command)
+                }
+                _ => {}
+            },
+            (FileKey::Up, true) => self.send(":files ..".to_string(), files::Command::Up),
+            (FileKey::Delete, true) => {
+                if let Some(n) = browser.selected() {
+                    let command = files::Command::Act(n, files::Action::Delete);
+                    self.send(format!(":files {} delete", n), command);
+                }
+            }
+            (FileKey::Ask(action), true) => {
+                let n = browser.selected()?;
+                return Some(Cmd::Insert(1, format!(":files {} {} ", n, action)));
+            }
+            (FileKey::Mkdir, true) => return Some(Cmd::Insert(1, ":files mkdir ".to_string())),
+        }
+
+        Some(Cmd::Noop)
+    }
+}
+
+/// A key the file browser pane and the jog pad share, only one is ever on.
+struct EitherPane(FilePane, JogPad);
+
+impl ConditionalEventHandler for EitherPane {
+    fn handle(
+        &self,
+        evt: &Event,
+        n: RepeatCount,
+        positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        self.0
+            .handle(evt, n, positive, ctx)
+            .or_else(|| self.1.handle(evt, n, positive, ctx))
+    }
+}
+
+/// Any key counts as activity, the one waking a blank screen is swallowed.
+struct WakeScreen(Screensaver);
+
+impl ConditionalEventHandler for WakeScreen {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        self.0.wake().then_some(Cmd::Noop)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::metadata;
+use crate::{rpc_result, Error, JSON};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+/// Of PLA, in g/cm³, for profiles without a density.
+const DEFAULT_DENSITY: f64 = 1.24;
+
+/// In mm, for profiles without a diameter.
+const DEFAULT_DIAMETER: f64 = 1.75;
+
+/// Filament prices by profile, what jobs cost from the filament they use.
+/// The profile is the filament type the slicer wrote, like `PLA`, matched
+/// regardless of case, or else `default`.
+///
+/// ```toml
+/// [filament]
+/// currency = "EUR"        # shown after the costs
+///
+/// [filament.default]
+/// price = 20.0            # per kg
+///
+/// [filament.petg]
+/// price = 25.0
+/// density = 1.27          # g/cm³, 1.24 by default
+/// diameter = 1.75         # mm, 1.75 by default
+/// ```
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Prices {
+    pub currency: Option<String>,
+    #[serde(flatten)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Profile {
+    /// Per kg
+    pub price: f64,
+    pub density: Option<f64>,
+    pub diameter: Option<f64>,
+}
+
+impl Profile {
+    /// The weight in grams of `length` mm of filament.
+    fn grams(&self, length: f64) -> f64 {
+        let radius = self.diameter.unwrap_or(DEFAULT_DIAMETER) / 2.0;
+        // mm³ to cm³
+        length * PI * radius * radius / 1000.0 * self.density.unwrap_or(DEFAULT_DENSITY)
+    }
+
+    fn cost(&self, grams: f64) -> f64 {
+        grams / 1000.0 * self.price
+    }
+}
+
+impl Prices {
+    /// The profile for the filament type in `metadata`, the first one when
+    /// the slicer lists one per extruder.
+    fn profile(&self, metadata: &JSON) -> Option<(&str, &Profile)> {
+        let filament_type = metadata["filament_type"]
+            .as_str()
+            .and_then(|types| types.split(';').next())
+            .map(str::trim)
+            .unwrap_or_default();
+
+        self.profiles
+            .iter()
+            .find(|(name, _)| !filament_type.is_empty() && name.eq_ignore_ascii_case(filament_type))
+            .or_else(|| self.profiles.get_key_value("default"))
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    /// What `length` mm of the filament of a file with `metadata` costs.
+    pub fn used(&self, metadata: &JSON, length: f64) -> Option<f64> {
+        let (_, profile) = self.profile(metadata)?;
+        Some(profile.cost(profile.grams(length)))
+    }
+
+    /// What the whole file costs, from the weight the slicer wrote or else
+    /// the length.
+    pub fn estimated(&self, metadata: &JSON) -> Option<f64> {
+        let (_, profile) = self.profile(metadata)?;
+        let grams = match metadata["filament_weight_total"].as_f64() {
+            Some(grams) => grams,
+            None => profile.grams(metadata["filament_total"].as_f64()?),
+        };
+
+        Some(profile.cost(grams))
+    }
+
+    pub fn format(&self, cost: f64) -> String {
+        match &self.currency {
+            Some(currency) => format!("{:.2} {}", cost, currency),
+            None => format!("{:.2}", cost),
+        }
+    }
+}
+
+/// `:cost`, what the job printing cost so far and should cost in all.
+pub async fn current(
+    client: &reqwest::Client,
+    url: &str,
+    prices: &Prices,
+) -> Result<String, Error> {
+    if prices.profiles.is_empty() {
+        return Err(Error::Env(
+            "No filament prices, add them under [filament] in the config".to_string(),
+        ));
+    }
+
+    let params =
+        Some(json!({ "objects": { "print_stats": ["state", "filename", "filament_used"] } }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let print_stats = &status["status"]["print_stats"];
+
+    let filename = match print_stats["filename"].as_str() {
+        Some(filename) if !filename.is_empty() => filename,
+        _ => return Err(Error::Env("No job loaded".to_string())),
+    };
+
+    let metadata = metadata::fetch(client, url, filename).await?;
+    let (name, _) = prices.profile(&metadata).ok_or_else(|| {
+        Error::Env(format!(
+            "No price for {}, add it or a default under [filament]",
+            metadata["filament_type"].as_str().unwrap_or("the filament")
+        ))
+    })?;
+
+    let used = print_stats["filament_used"].as_f64().unwrap_or(0.0);
+    let mut lines = vec![format!("{}, priced as {}", filename, name)];
+
+    if let Some(cost) = prices.used(&metadata, used) {
+        lines.push(format!(
+            "So far   {} for {:.2} m",
+            prices.format(cost),
+            used / 1000.0
+        ));
+    }
+
+    if let Some(cost) = prices.estimated(&metadata) {
+        lines.push(format!("In all   {}", prices.format(cost)));
+    }
+
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,430 @@
+use crate::traffic::Traffic;
+use flate2::{Decompress, FlushDecompress, Status};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Asks the printer to compress the messages it sends, which are mostly
+/// status updates repeating the same keys. Ours stay uncompressed, which
+/// the extension allows.
+pub const OFFER: &str = "permessage-deflate";
+
+/// Appended to each compressed message before inflating, RFC 7692 has the
+/// sender strip it.
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Frames and messages longer than this are refused rather than buffered,
+/// the websocket library's own limit for messages.
+const MAX_LENGTH: usize = 64 << 20;
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+
+/// Sits between the websocket and the connection, inflating the messages
+/// the printer compressed with permessage-deflate. The websocket library
+/// doesn't support the extension, so it's handed the frames it would have
+/// got without it. Frames it doesn't have to touch, and every frame when
+/// the printer declines the extension, go through as they are.
+pub struct Inflate<S> {
+    inner: S,
+    traffic: Traffic,
+    /// Whether the handshake response has been handed on, and with it
+    /// whether the printer accepted the extension
+    state: Handshake,
+    /// Read from the connection, not parsed yet
+    raw: Vec<u8>,
+    /// Ready for the websocket to read
+    ready: Vec<u8>,
+    /// Opcode and payload so far of a compressed message sent in fragments
+    message: Option<(u8, Vec<u8>)>,
+    decompress: Decompress,
+}
+
+#[derive(PartialEq)]
+enum Handshake {
+    Pending,
+    Compressed,
+    Plain,
+}
+
+impl<S> Inflate<S> {
+    pub fn new(inner: S, traffic: Traffic) -> Inflate<S> {
+        Inflate {
+            inner,
+            traffic,
+            state: Handshake::Pending,
+            raw: Vec::new(),
+            ready: Vec::new(),
+            message: None,
+            decompress: Decompress::new(false),
+        }
+    }
+
+    /// Moves what's complete in `raw` to `ready`.
+    fn process(&mut self) -> io::Result<()> {
+        if self.state == Handshake::Pending {
+            let Some(end) = self.raw.windows(4).position(|window| window == b"\r\n\r\n") else {
+                return Ok(());
+            };
+
+            let response: Vec<u8> = self.raw.drain(..end + 4).collect();
+            let accepted = String::from_utf8_lossy(&response).lines().any(|line| {
+                line.split_once(':').is_some_and(|(name, value)| {
+                    name.eq_ignore_ascii_case("sec-websocket-extensions")
+                        && value.contains("permessage-deflate")
+                })
+            });
+
+            self.ready.extend(response);
+            self.traffic.set_compressed(accepted);
+            self.state = if accepted {
+                Handshake::Compressed
+            } else {
+                Handshake::Plain
+            };
+        }
+
+        if self.state == Handshake::Plain {
+            self.ready.append(&mut self.raw);
+            return Ok(());
+        }
+
+        while let Some((header, length)) = frame_length(&self.raw)? {
+            let frame: Vec<u8> = self.raw.drain(..header + length).collect();
+            let first = frame[0];
+            let opcode = first & 0x0f;
+
+            // Control frames are never compressed and may come between fragments
+            let compressed = match opcode {
+                0x0 => self.message.is_some(),
+                0x1 | 0x2 => first & RSV1 != 0,
+                _ => false,
+            };
+
+            if !compressed {
+                self.ready.extend(frame);
+                continue;
+            }
+
+            let mut payload = frame[header..].to_vec();
+
+            // Printers don't mask what they send, the RFC has them not to
+            if frame[1] & 0x80 != 0 {
+                let key = &frame[header - 4..header];
+                payload
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(index, byte)| *byte ^= key[index % 4]);
+            }
+
+            let (_, buffered) = self.message.get_or_insert_with(|| (opcode, Vec::new()));
+
+            if buffered.len() + payload.len() > MAX_LENGTH {
+                return Err(too_long());
+            }
+
+            buffered.extend(payload);
+
+            if first & FIN != 0 {
+                let (opcode, deflated) = self.message.take().unwrap_or_default();
+                let inflated = self.inflate(&deflated)?;
+
+                self.traffic.record_inflated(deflated.len(), inflated.len());
+                self.ready.extend(frame_header(opcode, inflated.len()));
+                self.ready.extend(inflated);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn inflate(&mut self, deflated: &[u8]) -> io::Result<Vec<u8>> {
+        let input = [deflated, &TAIL].concat();
+        let mut inflated = Vec::with_capacity(input.len() * 4);
+        let mut consumed = 0;
+
+        loop {
+            let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut inflated, FlushDecompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            consumed += (self.decompress.total_in() - total_in) as usize;
+
+            let done = consumed == input.len() && inflated.len() < inflated.capacity();
+
+            if done || status == Status::StreamEnd {
+                return Ok(inflated);
+            }
+
+            if self.decompress.total_in() == total_in && self.decompress.total_out() == total_out {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated compressed message",
+                ));
+            }
+
+            inflated.reserve(inflated.capacity());
+        }
+    }
+}
+
+/// The header and payload lengths of the frame starting `raw`, none until
+/// it's all there. Lengths over `MAX_LENGTH` are refused, whatever the
+/// printer claims.
+fn frame_length(raw: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let Some(&second) = raw.get(1) else {
+        return Ok(None);
+    };
+    let mask: usize = if second & 0x80 != 0 { 4 } else { 0 };
+
+    let (header, length) = match second & 0x7f {
+        126 => match raw.get(2..4) {
+            Some(bytes) => (4, u16::from_be_bytes([bytes[0], bytes[1]]) as u64),
+            None => return Ok(None),
+        },
+        127 => match raw.get(2..10).and_then(|bytes| bytes.try_into().ok()) {
+            Some(bytes) => (10, u64::from_be_bytes(bytes)),
+            None => return Ok(None),
+        },
+        length => (2, length as u64),
+    };
+
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|length| *length <= MAX_LENGTH)
+        .ok_or_else(too_long)?;
+    let header = header + mask;
+    let total = header.checked_add(length).ok_or_else(too_long)?;
+
+    Ok((raw.len() >= total).then_some((header, length)))
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Websocket message too long")
+}
+
+/// The header of a final, unmasked frame.
+fn frame_header(opcode: u8, length: usize) -> Vec<u8> {
+    let mut header = vec![FIN | opcode];
+
+    match length {
+        0..=125 => header.push(length as u8),
+        126..=0xffff => {
+            header.push(126);
+            header.extend((length as u16).to_be_bytes());
+        }
+        _ => {
+            header.push(127);
+            header.extend((length as u64).to_be_bytes());
+        }
+    }
+
+    header
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Inflate<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        while this.ready.is_empty() {
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(())) if chunk.filled().is_empty() => {
+                    // Closed, what's left can't be a whole frame
+                    this.ready.append(&mut this.raw);
+                    break;
+                }
+                Poll::Ready(Ok(())) => {
+                    this.raw.extend_from_slice(chunk.filled());
+                    this.process()?;
+                }
+                other => return other,
+            }
+        }
+
+        let length = this.ready.len().min(buf.remaining());
+        buf.put_slice(&this.ready[..length]);
+        this.ready.drain(..length);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Inflate<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compress, Compression, FlushCompress};
+
+    /// A message deflated as the printer sends it, without the tail.
+    fn deflate(compress: &mut Compress, text: &str) -> Vec<u8> {
+        let mut deflated = Vec::with_capacity(text.len() + 64);
+        compress
+            .compress_vec(text.as_bytes(), &mut deflated, FlushCompress::Sync)
+            .unwrap();
+        assert!(deflated.ends_with(&TAIL));
+        deflated.truncate(deflated.len() - TAIL.len());
+        deflated
+    }
+
+    fn compressor() -> Compress {
+        Compress::new(Compression::default(), false)
+    }
+
+    fn frame(first: u8, payload: &[u8], key: Option<[u8; 4]>) -> Vec<u8> {
+        let mut frame = frame_header(0, payload.len());
+        frame[0] = first;
+
+        match key {
+            Some(key) => {
+                frame[1] |= 0x80;
+                frame.extend(key);
+                frame.extend(
+                    payload
+                        .iter()
+                        .enumerate()
+                        .map(|(i, byte)| byte ^ key[i % 4]),
+                );
+            }
+            None => frame.extend(payload),
+        }
+
+        frame
+    }
+
+    fn inflate(raw: Vec<u8>) -> Vec<u8> {
+        let mut inflate = Inflate::new((), Traffic::default());
+        inflate.state = Handshake::Compressed;
+        inflate.raw = raw;
+        inflate.process().unwrap();
+        inflate.ready
+    }
+
+    fn text(message: &str) -> Vec<u8> {
+        frame(FIN | 0x1, message.as_bytes(), None)
+    }
+
+    #[test]
+    fn reads_7_bit_lengths() {
+        assert_eq!(
+            frame_length(&[0x81, 5, 1, 2, 3, 4, 5]).unwrap(),
+            Some((2, 5))
+        );
+        assert_eq!(frame_length(&[0x81, 5, 1, 2]).unwrap(), None);
+        assert_eq!(frame_length(&[0x81]).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_16_bit_lengths() {
+        let frame = frame(FIN | 0x1, &[b'a'; 300], None);
+
+        assert_eq!(frame_length(&frame).unwrap(), Some((4, 300)));
+        assert_eq!(frame_length(&frame[..3]).unwrap(), None);
+        assert_eq!(frame_length(&frame[..100]).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_64_bit_lengths() {
+        let frame = frame(FIN | 0x2, &vec![0; 70_000], None);
+
+        assert_eq!(frame_length(&frame).unwrap(), Some((10, 70_000)));
+        assert_eq!(frame_length(&frame[..9]).unwrap(), None);
+    }
+
+    #[test]
+    fn refuses_huge_lengths() {
+        for length in [u64::MAX, 1 << 62, MAX_LENGTH as u64 + 1] {
+            let mut header = vec![0x82, 127];
+            header.extend(length.to_be_bytes());
+
+            assert!(frame_length(&header).is_err(), "{}", length);
+        }
+    }
+
+    #[test]
+    fn reads_masked_lengths() {
+        let frame = frame(FIN | 0x1, b"abc", Some([1, 2, 3, 4]));
+
+        assert_eq!(frame_length(&frame).unwrap(), Some((6, 3)));
+    }
+
+    #[test]
+    fn passes_uncompressed_frames_through() {
+        let raw = [text("plain"), frame(FIN | 0x9, b"ping", None)].concat();
+
+        assert_eq!(inflate(raw.clone()), raw);
+    }
+
+    #[test]
+    fn inflates_compressed_messages() {
+        let message = r#"{"method":"notify_status_update"}"#;
+        let raw = frame(FIN | RSV1 | 0x1, &deflate(&mut compressor(), message), None);
+
+        assert_eq!(inflate(raw), text(message));
+    }
+
+    #[test]
+    fn inflates_masked_messages() {
+        let message = "masked anyway";
+        let raw = frame(
+            FIN | RSV1 | 0x1,
+            &deflate(&mut compressor(), message),
+            Some([9, 8, 7, 6]),
+        );
+
+        assert_eq!(inflate(raw), text(message));
+    }
+
+    #[test]
+    fn inflates_fragmented_messages_around_control_frames() {
+        let message = "a message long enough to be sent in fragments";
+        let deflated = deflate(&mut compressor(), message);
+        let (first, rest) = deflated.split_at(deflated.len() / 2);
+        let ping = frame(FIN | 0x9, b"ping", None);
+
+        let raw = [
+            frame(RSV1 | 0x1, first, None),
+            ping.clone(),
+            frame(FIN, rest, None),
+        ]
+        .concat();
+
+        assert_eq!(inflate(raw), [ping, text(message)].concat());
+    }
+
+    #[test]
+    fn keeps_the_context_across_messages() {
+        let message = "the same keys over and over";
+        let mut compress = compressor();
+        let raw: Vec<u8> = (0..2)
+            .flat_map(|_| frame(FIN | RSV1 | 0x1, &deflate(&mut compress, message), None))
+            .collect();
+
+        assert_eq!(inflate(raw), [text(message), text(message)].concat());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::auth::{self, Auth};
+use crate::cli::Cli;
+use crate::clipboard::Backend;
+use crate::config::{config_dir, Config};
+use crate::filter::Filter;
+use crate::meta::Mode;
+use crate::net;
+use crate::notifications;
+use crate::status::Density;
+use crate::thumbnail::Graphics;
+use crate::tls::Tls;
+use crate::traffic::Traffic;
+use crate::{rpc_result, startup_url, Error, JSON};
+use reqwest::{StatusCode, Url};
+use std::time::Instant;
+
+/// `moonraker-cli doctor`, goes through what connecting takes one step at
+/// a time, from reading the config to opening the websocket, and tells
+/// what the first failing step likely means. Steps needing a failed one
+/// are skipped.
+pub async fn run(cli: &Cli) -> Result<(), Error> {
+    let mut checkup = Checkup::default();
+
+    let Some(config) = checkup.config(cli) else {
+        return checkup.diagnose();
+    };
+
+    let tls = match Tls::new(cli, &config) {
+        Ok(tls) => tls,
+        Err(err) => {
+            checkup.fail("tls", err, "The certificate or key files can't be used");
+            return checkup.diagnose();
+        }
+    };
+
+    let url = match startup_url(cli, &config) {
+        Ok(url) => {
+            checkup.pass("url", url.clone());
+            url
+        }
+        Err(err) => {
+            checkup.fail(
+                "url",
+                err,
+                "Give the printer like --url mainsailos.local:7125",
+            );
+            return checkup.diagnose();
+        }
+    };
+
+    // Normalized already, it parses
+    let parsed = Url::parse(&url).map_err(|err| Error::Env(err.to_string()))?;
+
+    match net::resolve(&parsed).await {
+        Ok(addrs) if !addrs.is_empty() => {
+            let addrs: Vec<String> = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+            checkup.pass("resolve", addrs.join(", "));
+        }
+        result => {
+            let err = result.map_or_else(Error::IO, |_| Error::Env("No address".to_string()));
+            let hint = "The host name doesn't resolve, try the printer's IP address, \
+                        .local names need mDNS set up on this machine";
+            checkup.fail("resolve", err, hint);
+            return checkup.diagnose();
+        }
+    }
+
+    let started = Instant::now();
+
+    match net::connect(&parsed).await {
+        Ok(stream) => {
+            let peer = stream
+                .peer_addr()
+                .map_or("?".to_string(), |addr| addr.to_string());
+            checkup.pass("connect", format!("{} in {}", peer, elapsed(started)));
+        }
+        Err(err) => {
+            let hint = format!(
+                "Nothing answers on port {}, check Moonraker runs and the port, \
+                 7125 unless a proxy serves it on 80 or 443",
+                parsed.port_or_known_default().unwrap_or_default()
+            );
+            checkup.fail("connect", Error::IO(err), &hint);
+            return checkup.diagnose();
+        }
+    }
+
+    let client = tls.client_builder()?.build()?;
+    let auth = Auth::new(cli, &config).for_printer(&url);
+    let username = auth.restore(&client, &url).await;
+
+    let started = Instant::now();
+    let request = auth::authorize(client.get(format!("{}/server/info", url)), &url);
+
+    let info = match request.send().await {
+        Ok(resp)
+            if matches!(
+                resp.status(),
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+            ) =>
+        {
+            let err = Error::Rpc(format!("Moonraker answered {}", resp.status()));
+            let hint = "Moonraker wants credentials, pass --api-key or :login in the console, \
+                        or add this machine to trusted_clients";
+            checkup.fail("auth", err, hint);
+            return checkup.diagnose();
+        }
+        Ok(resp) => match resp.error_for_status() {
+            Ok(resp) => resp.json::<JSON>().await.map_err(Error::Request),
+            Err(err) => Err(Error::Request(err)),
+        },
+        Err(err) => Err(Error::Request(err)),
+    };
+
+    let info = match info {
+        Ok(mut info) => info.get_mut("result").map(JSON::take).unwrap_or_default(),
+        Err(err) => {
+            let hint = "Something answers, but not Moonraker, check the port and the path \
+                        a reverse proxy serves it on";
+            checkup.fail("moonraker", err, hint);
+            return checkup.diagnose();
+        }
+    };
+
+    let version = info["moonraker_version"]
+        .as_str()
+        .unwrap_or("unknown version");
+    checkup.pass("moonraker", format!("{} in {}", version, elapsed(started)));
+
+    let components = strings(&info["components"]);
+
+    if components
+        .iter()
+        .any(|component| component == "authorization")
+    {
+        match rpc_result(&client, &url, "access.get_user", None).await {
+            Ok(user) => {
+                let via = match username {
+                    Some(_) => "logged in",
+                    None if auth::has_api_key(&url) => "API key",
+                    None => "trusted client",
+                };
+                let name = user["username"].as_str().unwrap_or("?");
+                checkup.pass("auth", format!("{} as {}", via, name));
+            }
+            Err(err) => {
+                let hint = "The credentials are refused, check the API key or :login again";
+                checkup.fail("auth", err, hint);
+                return checkup.diagnose();
+            }
+        }
+    } else {
+        checkup.pass(
+            "auth",
+            "not required, no authorization component".to_string(),
+        );
+    }
+
+    checkup.pass("components", components.join(", "));
+
+    let failed = strings(&info["failed_components"]);
+
+    if !failed.is_empty() {
+        let err = Error::Env(format!("Failed to load {}", failed.join(", ")));
+        checkup.fail(
+            "components",
+            err,
+            "Moonraker couldn't load some components, see moonraker.log",
+        );
+    }
+
+    for warning in strings(&info["warnings"]) {
+        checkup.warn("moonraker", warning);
+    }
+
+    match info["klippy_state"].as_str().unwrap_or("unknown") {
+        "ready" => checkup.pass("klippy", "ready".to_string()),
+        state => {
+            let hint = "Moonraker runs but Klippy doesn't, see klippy.log or FIRMWARE_RESTART";
+            checkup.fail("klippy", Error::Klippy(state.to_string()), hint);
+        }
+    }
+
+    let started = Instant::now();
+    let traffic = Traffic::default();
+
+    match notifications::connect(&client, &url, &tls, &traffic).await {
+        Ok(mut socket) => {
+            let compression = if traffic.is_compressed() {
+                "compressed"
+            } else {
+                "uncompressed"
+            };
+            checkup.pass(
+                "websocket",
+                format!("upgraded in {}, {}", elapsed(started), compression),
+            );
+            let _ = socket.close(None).await;
+        }
+        Err(err) => {
+            let hint = "HTTP works but the websocket doesn't, a reverse proxy may not forward \
+                        the Upgrade and Connection headers";
+            checkup.fail("websocket", err, hint);
+        }
+    }
+
+    checkup.diagnose()
+}
+
+/// The steps gone through, printed as they're done, and the first failure.
+#[derive(Default)]
+struct Checkup {
+    failure: Option<(Error, String)>,
+}
+
+impl Checkup {
+    /// Reads the config and checks the settings the console parses later.
+    fn config(&mut self, cli: &Cli) -> Option<Config> {
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| config_dir().map(|dir| dir.join("config.toml")))
+            .filter(|path| cli.config.is_some() || path.exists());
+
+        let config = match Config::load(cli.config.as_deref()) {
+            Ok(config) => config,
+            Err(err) => {
+                self.fail(
+                    "config",
+                    err,
+                    "Fix the config file, or give another with --config",
+                );
+                return None;
+            }
+        };
+
+        let path = path.map_or("none, the defaults".to_string(), |path| {
+            path.display().to_string()
+        });
+        self.pass("config", path);
+
+        let settings = [
+            config
+                .filter
+                .as_deref()
+                .map(|expr| Filter::parse(expr).map(drop)),
+            config
+                .mode
+                .as_deref()
+                .map(|mode| Mode::parse(mode).map(drop)),
+            config
+                .clipboard
+                .as_deref()
+                .map(|backend| Backend::parse(backend).map(drop)),
+            config
+                .density
+                .as_deref()
+                .map(|density| Density::parse(density).map(drop)),
+            config
+                .graphics
+                .as_deref()
+                .map(|graphics| Graphics::parse(graphics).map(drop)),
+        ];
+
+        for err in settings.into_iter().flatten().filter_map(Result::err) {
+            self.fail(
+                "config",
+                Error::Env(err),
+                "Fix the setting in the config file",
+            );
+        }
+
+        Some(config)
+    }
+
+    fn pass(&self, step: &str, detail: String) {
+        println!("ok    {:10}  {}", step, detail);
+    }
+
+    fn warn(&self, step: &str, detail: String) {
+        println!("warn  {:10}  {}", step, detail);
+    }
+
+    fn fail(&mut self, step: &str, err: Error, hint: &str) {
+        println!("FAIL  {:10}  {}", step, err);
+
+        if self.failure.is_none() {
+            self.failure = Some((err, hint.to_string()));
+        }
+    }
+
+    /// What the first failure likely means, failing with its error.
+    fn diagnose(self) -> Result<(), Error> {
+        println!();
+
+        match self.failure {
+            Some((err, hint)) => {
+                println!("{}", hint);
+                Err(err)
+            }
+            None => {
+                println!("Everything looks fine");
+                Ok(())
+            }
+        }
+    }
+}
+
+fn strings(value: &JSON) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.as_str().map(str::to_string))
+        .collect()
+}
+
+fn elapsed(started: Instant) -> String {
+    format!("{} ms", started.elapsed().as_millis())
+}
@@ -0,0 +1,189 @@
+use crate::auth::{self, Auth};
+use crate::cleanup::format_size;
+use crate::tls::Tls;
+use crate::upload::Progress;
+use crate::{Error, JSON};
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// `/server/files/<path>` under `url`, each segment of `path` encoded so
+/// names with spaces, `#` or `?` in them reach the file.
+pub fn file_url(url: &str, path: &str) -> Result<reqwest::Url, Error> {
+    let mut file_url = reqwest::Url::parse(url).map_err(|err| Error::Env(err.to_string()))?;
+    file_url
+        .path_segments_mut()
+        .map_err(|_| Error::Env(format!("{} can't have a path", url)))?
+        .pop_if_empty()
+        .extend(["server", "files"])
+        .extend(path.split('/'));
+
+    Ok(file_url)
+}
+
+/// The roots files can be downloaded from.
+const ROOTS: [&str; 3] = ["gcodes", "config", "logs"];
+
+/// Downloads `path`, like `gcodes/part.gcode`, from `/server/files/<root>/<path>`
+/// to `dest`, a chunk at a time so `progress` follows. Returns the size
+/// written. An existing `dest` is only replaced when `force`d, and only
+/// once the download is complete, a failed one leaves no partial file.
+pub async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    path: &str,
+    dest: &Path,
+    force: bool,
+    progress: &Progress,
+) -> Result<u64, Error> {
+    let path = path.trim_start_matches('/');
+    let name = check_root(path)?;
+
+    if !force && tokio::fs::try_exists(dest).await? {
+        return Err(Error::Env(format!(
+            "{} already exists, not replacing it",
+            dest.display()
+        )));
+    }
+
+    let request = client.get(file_url(url, path)?);
+    let resp = auth::authorize(request, url).send().await?;
+    let status = resp.status();
+
+    if !status.is_success() {
+        let body: JSON = serde_json::from_str(&resp.text().await?).unwrap_or_default();
+        let message = body["error"]["message"].as_str().unwrap_or_default();
+
+        return Err(Error::Rpc(
+            format!("Download failed with {} {}", status, message)
+                .trim()
+                .to_string(),
+        ));
+    }
+
+    progress.start(name, resp.content_length().unwrap_or(0));
+
+    // Next to `dest`, so it's renamed over it on the same file system
+    let partial = partial_path(dest);
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)
+        .await;
+
+    // Nothing to clean up when it couldn't be created, it's not ours
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            progress.finish();
+            return Err(err.into());
+        }
+    };
+
+    let result = save(resp, file, progress).await;
+    progress.finish();
+
+    let result = match result {
+        Ok(written) => tokio::fs::rename(&partial, dest)
+            .await
+            .map(|_| written)
+            .map_err(Error::from),
+        Err(err) => Err(err),
+    };
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+
+    result
+}
+
+/// Like `.part.gcode.1234.download` next to `part.gcode`, the process id
+/// keeping concurrent downloads apart.
+fn partial_path(dest: &Path) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!(".{}.{}.download", name, std::process::id()))
+}
+
+async fn save(
+    resp: reqwest::Response,
+    mut file: tokio::fs::File,
+    progress: &Progress,
+) -> Result<u64, Error> {
+    let mut chunks = resp.bytes_stream();
+    let mut written = 0;
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        progress.advance(chunk.len() as u64);
+    }
+
+    file.flush().await?;
+    Ok(written)
+}
+
+/// The file name in `path`, which must be in one of the roots.
+fn check_root(path: &str) -> Result<&str, Error> {
+    match path.split_once('/') {
+        Some((root, file)) if ROOTS.contains(&root) && !file.is_empty() => {
+            Ok(file.rsplit('/').next().unwrap_or(file))
+        }
+        _ => Err(Error::Env(format!(
+            "Invalid path {}, expected a file under {}",
+            path,
+            ROOTS.join(", ")
+        ))),
+    }
+}
+
+/// Where a file downloaded without a destination goes, the current
+/// directory under its own name.
+pub fn default_dest(path: &str) -> Result<PathBuf, Error> {
+    check_root(path.trim_start_matches('/')).map(PathBuf::from)
+}
+
+/// `moonraker-cli download <root>/<path> [-o <file>] [--force]`, downloads with a
+/// progress bar on stderr when it's a terminal.
+pub async fn run(
+    url: &str,
+    path: &str,
+    dest: Option<&Path>,
+    force: bool,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let dest = match dest {
+        Some(dest) => dest.to_path_buf(),
+        None => default_dest(path)?,
+    };
+
+    if !force && dest.exists() {
+        return Err(Error::Env(format!(
+            "{} already exists, --force replaces it",
+            dest.display()
+        )));
+    }
+
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+
+    let progress = Progress::default();
+    let bar = progress.draw();
+    let written = download(&client, url, path, &dest, force, &progress).await;
+    bar.erase();
+
+    println!("{}", describe(path, &dest, written?));
+    Ok(())
+}
+
+pub fn describe(path: &str, dest: &Path, written: u64) -> String {
+    format!(
+        "Downloaded {} to {}, {}",
+        path,
+        dest.display(),
+        format_size(written)
+    )
+}
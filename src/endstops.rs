@@ -0,0 +1,40 @@
+use crate::{popup, rpc_result, Error};
+
+/// `:endstops`, the state of every endstop as `QUERY_ENDSTOPS` reports it,
+/// queried through Moonraker so it comes back whole rather than as a
+/// G-code response.
+pub async fn query(client: &reqwest::Client, url: &str) -> Result<String, Error> {
+    let result = rpc_result(client, url, "printer.query_endstops.status", None).await?;
+    let endstops = result
+        .as_object()
+        .filter(|endstops| !endstops.is_empty())
+        .ok_or_else(|| Error::Rpc("no endstops reported".to_string()))?;
+
+    let width = endstops
+        .keys()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut triggered = 0;
+
+    let mut lines: Vec<String> = endstops
+        .iter()
+        .map(|(name, state)| {
+            let state = state.as_str().unwrap_or("?");
+
+            if state == "TRIGGERED" {
+                triggered += 1;
+            }
+
+            format!("{:width$}  {}", name, state)
+        })
+        .collect();
+
+    lines.push(String::new());
+    lines.push(match triggered {
+        0 => "All open".to_string(),
+        n => format!("{} triggered", n),
+    });
+
+    Ok(popup::render("Endstops", &lines))
+}
@@ -0,0 +1,175 @@
+use crate::auth::Auth;
+use crate::cli::OutputFormat;
+use crate::output;
+use crate::tls::Tls;
+use crate::{klippy_ready, rpc_result, Error, JSON};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// How many of the latest G-code responses are looked through for the
+/// ones answering a command.
+const STORE_COUNT: u64 = 100;
+
+/// `moonraker-cli exec <gcode>...`, sends each command in turn without the
+/// console and prints the responses it got. With `stdin` the commands are
+/// read from there a line at a time instead, comments skipped. Failures
+/// stop the batch, unless it keeps going, and fail the command.
+pub async fn run(
+    url: &str,
+    commands: &[String],
+    stdin: bool,
+    mut batch: Batch,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+    klippy_ready(&client, url).await?;
+
+    let sent = if stdin || commands == ["-"] {
+        send_stdin(&client, url, &mut batch).await
+    } else {
+        send_all(&client, url, commands, &mut batch).await
+    };
+
+    // Even a batch cut short is reported in the structured formats
+    if let Some(output) = output::render(JSON::Array(batch.results), batch.format)? {
+        println!("{}", output);
+    }
+
+    sent?;
+
+    match batch.failed {
+        0 => Ok(()),
+        failed => Err(Error::Rpc(format!(
+            "{} of {} commands failed",
+            failed, batch.sent
+        ))),
+    }
+}
+
+async fn send_all(
+    client: &reqwest::Client,
+    url: &str,
+    commands: &[String],
+    batch: &mut Batch,
+) -> Result<(), Error> {
+    for script in commands {
+        let result = send(client, url, script).await;
+        batch.report(result, script, script)?;
+    }
+
+    Ok(())
+}
+
+async fn send_stdin(client: &reqwest::Client, url: &str, batch: &mut Batch) -> Result<(), Error> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut number = 0;
+
+    while let Some(line) = lines.next_line().await? {
+        number += 1;
+        let script = line.split(';').next().unwrap_or_default().trim();
+
+        if !script.is_empty() {
+            let result = send(client, url, script).await;
+            batch.report(result, script, &format!("Line {} {}", number, script))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How the commands went, printed as they're sent as text, collected for
+/// the other formats.
+pub struct Batch {
+    keep_going: bool,
+    format: OutputFormat,
+    results: Vec<JSON>,
+    sent: usize,
+    failed: usize,
+}
+
+impl Batch {
+    pub fn new(keep_going: bool, format: OutputFormat) -> Batch {
+        Batch {
+            keep_going,
+            format,
+            results: Vec::new(),
+            sent: 0,
+            failed: 0,
+        }
+    }
+
+    /// Prints the responses, or the error, which ends the batch unless
+    /// keeping going. Only command errors are given `context`, the others
+    /// keep their kind for the exit code.
+    fn report(
+        &mut self,
+        result: Result<Vec<String>, Error>,
+        script: &str,
+        context: &str,
+    ) -> Result<(), Error> {
+        self.sent += 1;
+
+        if self.format != OutputFormat::Text {
+            self.results.push(match &result {
+                Ok(responses) => json!({ "command": script, "responses": responses }),
+                Err(err) => json!({ "command": script, "error": err.to_string() }),
+            });
+        }
+
+        match result {
+            Ok(responses) => {
+                if self.format == OutputFormat::Text {
+                    for response in responses {
+                        println!("{}", response);
+                    }
+                }
+                Ok(())
+            }
+            Err(err) if self.keep_going => {
+                eprintln!("{}: {}", context, err);
+                self.failed += 1;
+                Ok(())
+            }
+            Err(Error::Rpc(err)) => Err(Error::Rpc(format!("{}: {}", context, err))),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Runs `script`, returning the G-code responses it got.
+async fn send(client: &reqwest::Client, url: &str, script: &str) -> Result<Vec<String>, Error> {
+    // The times in the store are the printer host's, the local clock may
+    // be off from it, so the last entry before sending marks where to start
+    let since = store(client, url, 1)
+        .await?
+        .last()
+        .and_then(|entry| entry["time"].as_f64());
+    let params = Some(json!({ "script": script }));
+
+    rpc_result(client, url, "printer.gcode.script", params).await?;
+
+    let responses = store(client, url, STORE_COUNT)
+        .await?
+        .iter()
+        .filter(|entry| entry["type"] == "response")
+        .filter(|entry| {
+            let time = entry["time"].as_f64();
+            since.is_none_or(|since| time.is_some_and(|time| time > since))
+        })
+        .filter_map(|entry| entry["message"].as_str().map(str::to_string))
+        .collect();
+
+    Ok(responses)
+}
+
+/// The last `count` entries of the G-code store, oldest first, Klipper
+/// doesn't return the responses with the script result.
+async fn store(client: &reqwest::Client, url: &str, count: u64) -> Result<Vec<JSON>, Error> {
+    let params = Some(json!({ "count": count }));
+    let store = rpc_result(client, url, "server.gcode_store", params).await?;
+
+    Ok(store["gcode_store"].as_array().cloned().unwrap_or_default())
+}
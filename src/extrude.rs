@@ -0,0 +1,109 @@
+use crate::units::Units;
+use crate::{rpc_result, Error};
+use serde_json::json;
+
+/// Feed rate of extrude and retract moves, in mm/min.
+const FEED_RATE: f64 = 300.0;
+
+/// Klipper's defaults, for configs without `min_extrude_temp` or
+/// `max_extrude_only_distance`.
+const DEFAULT_MIN_EXTRUDE_TEMP: f64 = 170.0;
+const DEFAULT_MAX_EXTRUDE_ONLY_DISTANCE: f64 = 50.0;
+
+/// What `heat` heats a nozzle with no target to, in °C, enough for PLA.
+const LOAD_TEMPERATURE: f64 = 200.0;
+
+/// Parses `<mm> [heat]`, like `50` or `10 heat`.
+pub fn parse(args: &str) -> Option<(f64, bool)> {
+    let (length, heat) = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [length] => (length, false),
+        [length, "heat"] => (length, true),
+        _ => return None,
+    };
+    let length = length.parse::<f64>().ok()?;
+
+    (length.is_finite() && length > 0.0).then_some((length, heat))
+}
+
+/// Pushes `length` mm of filament through the nozzle, or pulls it back when
+/// negative. Refuses to while printing and, instead of letting Klipper
+/// error out, when the nozzle is below `min_extrude_temp` unless `heat`,
+/// which heats it first and waits.
+pub async fn extrude(
+    client: &reqwest::Client,
+    url: &str,
+    length: f64,
+    heat: bool,
+    units: Units,
+) -> Result<String, Error> {
+    let params = Some(json!({
+        "objects": {
+            "extruder": ["temperature", "target"],
+            "print_stats": ["state"],
+            "configfile": ["settings"],
+        }
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let status = &status["status"];
+
+    if let Some(state @ ("printing" | "paused")) = status["print_stats"]["state"].as_str() {
+        return Err(Error::Env(format!("Not while the print is {}", state)));
+    }
+
+    let settings = &status["configfile"]["settings"]["extruder"];
+    let min_temp = settings["min_extrude_temp"]
+        .as_f64()
+        .unwrap_or(DEFAULT_MIN_EXTRUDE_TEMP);
+    let max_distance = settings["max_extrude_only_distance"]
+        .as_f64()
+        .unwrap_or(DEFAULT_MAX_EXTRUDE_ONLY_DISTANCE);
+
+    if length.abs() > max_distance {
+        return Err(Error::Env(format!(
+            "{:.0} mm is over the {:.0} mm max_extrude_only_distance, split it up",
+            length.abs(),
+            max_distance
+        )));
+    }
+
+    let temperature = status["extruder"]["temperature"]
+        .as_f64()
+        .ok_or_else(|| Error::Rpc("extruder temperature unavailable".to_string()))?;
+    let mut script = String::new();
+
+    if temperature < min_temp {
+        if !heat {
+            return Ok(format!(
+                "The nozzle is at {}, below the {} min_extrude_temp, add heat to the command to heat it first",
+                units.format_temperature(temperature),
+                units.format_temperature(min_temp)
+            ));
+        }
+
+        let target = status["extruder"]["target"]
+            .as_f64()
+            .filter(|target| *target >= min_temp)
+            .unwrap_or(LOAD_TEMPERATURE.max(min_temp));
+        script.push_str(&format!("M109 S{:.0}\n", target));
+    }
+
+    // The saved state keeps whatever extrusion mode the user had
+    script.push_str(&format!(
+        "SAVE_GCODE_STATE NAME=moonraker_cli_extrude\nM83\nG1 E{:.2} F{:.0}\nRESTORE_GCODE_STATE NAME=moonraker_cli_extrude",
+        length,
+        FEED_RATE
+    ));
+    rpc_result(
+        client,
+        url,
+        "printer.gcode.script",
+        Some(json!({ "script": script })),
+    )
+    .await?;
+
+    if length > 0.0 {
+        Ok(format!("Extruded {:.1} mm", length))
+    } else {
+        Ok(format!("Retracted {:.1} mm", -length))
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// Percentage points Alt-- and Alt-= move the selected fan by.
+pub const STEP: f64 = 10.0;
+
+/// Cells of the speed sliders.
+const SLIDER_WIDTH: usize = 20;
+
+/// What `:fans` does with the part cooling fan and the `fan_generic` ones,
+/// the fans Klipper lets G-code drive.
+#[derive(Clone, Copy)]
+pub enum Command {
+    List,
+    /// Sets the nth fan listed, from 1, to a percentage and selects it
+    Set(usize, f64),
+    /// Selects the fan after the selected one
+    Next,
+    /// Changes the selected fan, in percentage points
+    By(f64),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        // NaN and inf parse too, the range leaves both out
+        let percent = |percent: &str| {
+            percent
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .ok_or_else(|| format!("Invalid speed {}, expected 0 to 100", percent))
+        };
+
+        match args.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => Ok(Command::List),
+            ["next"] => Ok(Command::Next),
+            [points] if points.starts_with(['+', '-']) => points
+                .parse::<f64>()
+                .ok()
+                .filter(|points| points.is_finite())
+                .map(Command::By)
+                .ok_or_else(|| format!("Invalid change {}, expected like +10", points)),
+            [n, speed] => {
+                let n = n
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid fan {}, expected a number", n))?;
+                Ok(Command::Set(n, percent(speed)?))
+            }
+            _ => Err(format!("Unknown :fans argument {}, try :help", args.trim())),
+        }
+    }
+}
+
+/// The fan the keys adjust, the part cooling one until another is picked.
+#[derive(Clone, Default)]
+pub struct Fans(Arc<Mutex<Option<String>>>);
+
+impl Fans {
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        command: Command,
+    ) -> Result<String, Error> {
+        let fans = query(client, url).await?;
+
+        if fans.is_empty() {
+            return Err(Error::Env(
+                "No [fan] or [fan_generic] in the printer config".to_string(),
+            ));
+        }
+
+        let selected = self.0.lock().unwrap().clone();
+        let selected = fans
+            .iter()
+            .position(|(object, _)| Some(object) == selected.as_ref());
+
+        let (index, percent) = match command {
+            Command::List => return Ok(render(&fans, selected)),
+            Command::Set(n, percent) => {
+                let index = n
+                    .checked_sub(1)
+                    .filter(|index| *index < fans.len())
+                    .ok_or_else(|| {
+                        Error::Env(format!("No fan {}, expected 1 to {}", n, fans.len()))
+                    })?;
+                (index, percent)
+            }
+            Command::Next => {
+                let index = (selected.unwrap_or(0) + 1) % fans.len();
+                *self.0.lock().unwrap() = Some(fans[index].0.clone());
+
+                return Ok(format!(
+                    "{}, Alt-- and Alt-= adjust it",
+                    line(&fans[index].0, fans[index].1)
+                ));
+            }
+            Command::By(points) => {
+                let index = selected.unwrap_or(0);
+                let percent = (fans[index].1 * 100.0 + points).round().clamp(0.0, 100.0);
+                (index, percent)
+            }
+        };
+
+        let object = &fans[index].0;
+        let params = Some(json!({ "script": gcode(object, percent) }));
+        rpc_result(client, url, "printer.gcode.script", params).await?;
+        *self.0.lock().unwrap() = Some(object.clone());
+
+        Ok(line(object, percent / 100.0))
+    }
+}
+
+/// The fans and their speed, from 0 to 1, the part cooling one first.
+async fn query(client: &reqwest::Client, url: &str) -> Result<Vec<(String, f64)>, Error> {
+    let objects = rpc_result(client, url, "printer.objects.list", None).await?;
+    let fans: Vec<&str> = objects["objects"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(JSON::as_str)
+        .filter(|object| *object == "fan" || object.starts_with("fan_generic "))
+        .collect();
+
+    if fans.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let params = Some(json!({
+        "objects": fans
+            .iter()
+            .map(|object| (object.to_string(), json!(["speed"])))
+            .collect::<serde_json::Map<_, _>>()
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+
+    let mut fans: Vec<(String, f64)> = fans
+        .into_iter()
+        .map(|object| {
+            let speed = status["status"][object]["speed"].as_f64().unwrap_or(0.0);
+            (object.to_string(), speed)
+        })
+        .collect();
+    fans.sort_by_key(|(object, _)| object != "fan");
+
+    Ok(fans)
+}
+
+/// `M106` for the part cooling fan, `SET_FAN_SPEED` for the others.
+fn gcode(object: &str, percent: f64) -> String {
+    match object.strip_prefix("fan_generic ") {
+        Some(name) => format!("SET_FAN_SPEED FAN={} SPEED={:.2}", name, percent / 100.0),
+        None => format!("M106 S{:.0}", percent / 100.0 * 255.0),
+    }
+}
+
+fn name(object: &str) -> &str {
+    object.strip_prefix("fan_generic ").unwrap_or("part fan")
+}
+
+/// Like `part fan  ██████░░░░ 60%`.
+fn line(object: &str, speed: f64) -> String {
+    format!("{}  {}", name(object), slider(speed))
+}
+
+fn slider(speed: f64) -> String {
+    let filled = (speed.clamp(0.0, 1.0) * SLIDER_WIDTH as f64).round() as usize;
+
+    format!(
+        "{}{} {:>3.0}%",
+        "█".repeat(filled),
+        "░".repeat(SLIDER_WIDTH - filled),
+        speed * 100.0
+    )
+}
+
+/// The fans numbered with a slider each, the one the keys adjust marked.
+fn render(fans: &[(String, f64)], selected: Option<usize>) -> String {
+    let mut lines = popup::numbered(
+        fans.iter()
+            .enumerate()
+            .map(|(index, (object, speed))| {
+                let marker = if index == selected.unwrap_or(0) {
+                    '*'
+                } else {
+                    ' '
+                };
+
+                (format!("{} {}", marker, name(object)), slider(*speed))
+            })
+            .collect(),
+    );
+
+    lines.push(String::new());
+    lines.push(":fans <n> <percent> sets one".to_string());
+    lines.push("Alt-F picks the one * marks, Alt-- and Alt-= adjust it by 10%".to_string());
+
+    popup::render("Fans", &lines)
+}
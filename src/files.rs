@@ -0,0 +1,820 @@
+use crate::auth::Auth;
+use crate::cleanup::format_size;
+use crate::cli::FilesCommand;
+use crate::ics::format_date;
+use crate::tls::Tls;
+use crate::{download, fuzzy, metadata, popup, precheck, thumbnail};
+use crate::{rpc_result, start_print_retrying, Error, SharedState, JSON};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+
+/// What `:files` does.
+pub enum Command {
+    /// Lists the directory browsed
+    List,
+    /// Enters the nth directory listed or describes the nth file, from 1,
+    /// asking to confirm its print
+    Open(usize),
+    Up,
+    /// Lists the files anywhere under the root matching a query
+    Find(String),
+    /// Browses to a path relative to the root, describing it if it's a file
+    Jump(String),
+    /// Creates a directory in the one browsed
+    Mkdir(String),
+    Act(usize, Action),
+}
+
+pub enum Action {
+    Print,
+    Check,
+    Delete,
+    /// Into the current directory
+    Download,
+    /// To a new name in the same directory
+    Rename(String),
+    /// Into a directory, relative to the root
+    Move(String),
+    /// To a new name in the same directory
+    Copy(String),
+}
+
+/// What `moonraker-cli files` and the browser change on the printer, paths
+/// under a root like `gcodes/part.gcode`.
+pub enum Operation {
+    Delete(String),
+    /// Forced deletes what's inside too
+    DeleteDir(String, bool),
+    Move(String, String),
+    Copy(String, String),
+    Mkdir(String),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        let args = args.trim();
+        let mut words = args.split_whitespace();
+        let (first, action) = (words.next().unwrap_or_default(), words.next());
+        // Names may have spaces, the rest of the line after the action is the name
+        let name = action
+            .map(|action| args[first.len()..].trim_start()[action.len()..].trim())
+            .filter(|name| !name.is_empty());
+
+        match (first, action) {
+            ("", _) => Ok(Command::List),
+            ("..", None) => Ok(Command::Up),
+            ("find", Some(_)) => Ok(Command::Find(args["find".len()..].trim().to_string())),
+            ("mkdir", Some(_)) => Ok(Command::Mkdir(args["mkdir".len()..].trim().to_string())),
+            (n, _) if n.parse::<usize>().is_err() => Ok(Command::Jump(args.trim().to_string())),
+            (n, action) => {
+                let n = n
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid entry {}, expected a number", n))?;
+                let name = |action: &str| {
+                    name.map(str::to_string)
+                        .ok_or_else(|| format!("Missing name for {}, try :help", action))
+                };
+
+                match action {
+                    None => Ok(Command::Open(n)),
+                    Some("print") => Ok(Command::Act(n, Action::Print)),
+                    Some("check") => Ok(Command::Act(n, Action::Check)),
+                    Some("delete") => Ok(Command::Act(n, Action::Delete)),
+                    Some("download") => Ok(Command::Act(n, Action::Download)),
+                    Some("rename") => Ok(Command::Act(n, Action::Rename(name("rename")?))),
+                    Some("move") => Ok(Command::Act(n, Action::Move(name("move")?))),
+                    Some("copy") => Ok(Command::Act(n, Action::Copy(name("copy")?))),
+                    Some(action) => Err(format!(
+                        "Unknown action {}, expected print, check, delete, download, \
+                         rename, move or copy",
+                        action
+                    )),
+                }
+            }
+        }
+    }
+
+    pub fn is_download(&self) -> bool {
+        matches!(self, Command::Act(_, Action::Download))
+    }
+}
+
+/// Every file path under the gcodes root, for `:files` to complete as
+/// typed. Kept up to date by the file list notifications.
+#[derive(Clone, Default)]
+pub struct Index(Arc<Mutex<Vec<String>>>);
+
+impl Index {
+    /// Lists the root again with `server.files.list`, empty if it fails.
+    pub async fn refresh(&self, client: &reqwest::Client, url: &str) {
+        let params = Some(json!({ "root": "gcodes" }));
+        let files = rpc_result(client, url, "server.files.list", params)
+            .await
+            .unwrap_or_default();
+
+        *self.0.lock().unwrap() = files
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|file| Some(file["path"].as_str()?.to_string()))
+            .collect();
+    }
+
+    /// Follows a `notify_filelist_changed`, paths it moves out of the root
+    /// are dropped.
+    pub fn apply(&self, change: &JSON) {
+        let path = |item: &JSON| {
+            (item["root"] == "gcodes")
+                .then(|| item["path"].as_str())
+                .flatten()
+                .map(str::to_string)
+        };
+        let (item, source) = (path(&change["item"]), path(&change["source_item"]));
+        let mut paths = self.0.lock().unwrap();
+        let under = |path: &str, dir: &str| path.starts_with(&format!("{}/", dir));
+
+        match change["action"].as_str().unwrap_or_default() {
+            "create_file" | "modify_file" => {
+                if let Some(item) = item.filter(|item| !paths.contains(item)) {
+                    paths.push(item);
+                }
+            }
+            "delete_file" => paths.retain(|path| Some(path) != item.as_ref()),
+            "move_file" => {
+                paths.retain(|path| Some(path) != source.as_ref());
+                paths.extend(item);
+            }
+            "delete_dir" => {
+                if let Some(dir) = item {
+                    paths.retain(|path| !under(path, &dir));
+                }
+            }
+            "move_dir" => {
+                let Some(source) = source else { return };
+                let moved: Vec<String> = paths
+                    .iter()
+                    .filter(|path| under(path, &source))
+                    .cloned()
+                    .collect();
+                paths.retain(|path| !under(path, &source));
+
+                if let Some(dir) = item {
+                    paths.extend(
+                        moved
+                            .iter()
+                            .map(|path| format!("{}{}", dir, &path[source.len()..])),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The paths matching `query`, best first.
+    pub fn search(&self, query: &str, count: usize) -> Vec<String> {
+        let paths = self.0.lock().unwrap();
+        fuzzy::rank(query, &paths, |path| path)
+            .into_iter()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+}
+
+/// The gcodes root as `:files` browses it, numbered entries and all, so
+/// they can be opened and acted on by number.
+#[derive(Clone, Default)]
+pub struct Browser(Arc<Mutex<Listing>>);
+
+#[derive(Default)]
+struct Listing {
+    /// Relative to the root, empty for the root itself
+    dir: String,
+    /// Search results rather than the directory content
+    query: Option<String>,
+    entries: Vec<Entry>,
+    /// The entry just asked about, acted on by the same `:files <n> print`
+    /// or `:files <n> delete` next
+    confirming: Option<(Confirm, String)>,
+    /// Whether the keys of the pane act on the listing
+    pane: bool,
+    /// The entry the pane keys act on, from 0
+    selected: usize,
+}
+
+#[derive(PartialEq)]
+enum Confirm {
+    Print,
+    Delete,
+    /// Downloading over a local file of the same name
+    Overwrite,
+}
+
+#[derive(Clone)]
+struct Entry {
+    /// Relative to the root
+    path: String,
+    is_dir: bool,
+    size: u64,
+    modified: f64,
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        if self.is_dir {
+            format!("{}/", name)
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+impl Browser {
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        command: Command,
+        shared: &SharedState,
+        notice_tx: &Sender<String>,
+    ) -> Result<String, Error> {
+        match command {
+            Command::List => {
+                let dir = self.0.lock().unwrap().dir.clone();
+                self.browse(client, url, dir).await
+            }
+            Command::Up => {
+                let dir = self.0.lock().unwrap().dir.clone();
+                let parent = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+                self.browse(client, url, parent.to_string()).await
+            }
+            Command::Find(query) => self.find(client, url, query).await,
+            Command::Mkdir(name) => {
+                let dir = self.0.lock().unwrap().dir.clone();
+                let path = format!("gcodes/{}", join(&dir, &name));
+                let done = Operation::Mkdir(path).run(client, url).await?;
+                self.refresh(client, url, done).await
+            }
+            Command::Jump(path) => {
+                let path = path.trim_start_matches("gcodes/").trim_matches('/');
+                let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                self.browse(client, url, parent.to_string()).await?;
+
+                let found = self
+                    .0
+                    .lock()
+                    .unwrap()
+                    .entries
+                    .iter()
+                    .position(|entry| entry.path == path);
+                let n = found.ok_or_else(|| Error::Env(format!("No file {}", path)))? + 1;
+                let entry = self.entry(n)?;
+
+                if entry.is_dir {
+                    self.browse(client, url, entry.path).await
+                } else {
+                    self.describe(client, url, &entry, n, shared).await
+                }
+            }
+            Command::Open(n) => {
+                let entry = self.entry(n)?;
+
+                if entry.is_dir {
+                    self.browse(client, url, entry.path).await
+                } else {
+                    self.describe(client, url, &entry, n, shared).await
+                }
+            }
+            Command::Act(n, action) => {
+                let entry = self.entry(n)?;
+                let confirmed = self.0.lock().unwrap().confirming.take();
+                let path = format!("gcodes/{}", entry.path);
+                let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+
+                let is_file_action =
+                    matches!(action, Action::Print | Action::Check | Action::Download);
+
+                if entry.is_dir && is_file_action {
+                    return Err(Error::Env(format!("{} is a directory", entry.name())));
+                }
+
+                match action {
+                    Action::Print if confirmed == Some((Confirm::Print, entry.path.clone())) => {
+                        start_print_retrying(client, url, &entry.path, false, shared, notice_tx)
+                            .await
+                    }
+                    Action::Print if !is_gcode(&entry.path) => {
+                        Err(Error::Env(format!("{} is not a G-code file", entry.path)))
+                    }
+                    Action::Print => self.describe(client, url, &entry, n, shared).await,
+                    Action::Check => {
+                        let config = &shared.config;
+                        let profile = config.profile(url);
+                        let warnings =
+                            precheck::check(client, url, &entry.path, profile, config.units)
+                                .await?;
+                        Ok(precheck::format(&entry.path, &warnings))
+                    }
+                    Action::Delete if confirmed != Some((Confirm::Delete, entry.path.clone())) => {
+                        self.0.lock().unwrap().confirming =
+                            Some((Confirm::Delete, entry.path.clone()));
+                        let what = if entry.is_dir {
+                            "the empty directory"
+                        } else {
+                            "the file"
+                        };
+                        Ok(format!(
+                            "Delete {} {}? :files {} delete again deletes it",
+                            what, entry.path, n
+                        ))
+                    }
+                    // Directories only when empty, the command line forces it
+                    Action::Delete if entry.is_dir => {
+                        let done = Operation::DeleteDir(path, false).run(client, url).await?;
+                        self.refresh(client, url, done).await
+                    }
+                    Action::Delete => {
+                        let done = Operation::Delete(path).run(client, url).await?;
+                        self.refresh(client, url, done).await
+                    }
+                    Action::Rename(name) => {
+                        let dest = format!("gcodes/{}", join(parent, &name));
+                        let done = Operation::Move(path, dest).run(client, url).await?;
+                        self.refresh(client, url, done).await
+                    }
+                    Action::Move(dir) => {
+                        let dir = dir.trim_matches('/');
+                        let name = entry.path.rsplit('/').next().unwrap_or_default();
+                        let dest = format!("gcodes/{}", join(dir, name));
+                        let done = Operation::Move(path, dest).run(client, url).await?;
+                        self.refresh(client, url, done).await
+                    }
+                    Action::Copy(name) => {
+                        let dest = format!("gcodes/{}", join(parent, &name));
+                        let done = Operation::Copy(path, dest).run(client, url).await?;
+                        self.refresh(client, url, done).await
+                    }
+                    Action::Download => {
+                        let dest = download::default_dest(&path)?;
+                        let overwrite = confirmed == Some((Confirm::Overwrite, entry.path.clone()));
+
+                        if !overwrite && dest.exists() {
+                            self.0.lock().unwrap().confirming =
+                                Some((Confirm::Overwrite, entry.path.clone()));
+                            return Ok(format!(
+                                "{} already exists here, :files {} download again replaces it",
+                                dest.display(),
+                                n
+                            ));
+                        }
+
+                        let written = download::download(
+                            client,
+                            url,
+                            &path,
+                            &dest,
+                            overwrite,
+                            &shared.transfer,
+                        )
+                        .await?;
+                        Ok(download::describe(&path, &dest, written))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets what was browsed, the numbers mean nothing on another printer.
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = Listing::default();
+    }
+
+    /// Turns the pane on or off, answering whether it's on.
+    pub fn toggle_pane(&self) -> bool {
+        let mut listing = self.0.lock().unwrap();
+        listing.pane = !listing.pane;
+        listing.pane
+    }
+
+    /// Turns the pane off, answering whether it was on.
+    pub fn leave_pane(&self) -> bool {
+        std::mem::take(&mut self.0.lock().unwrap().pane)
+    }
+
+    pub fn is_pane_on(&self) -> bool {
+        self.0.lock().unwrap().pane
+    }
+
+    /// The number of the entry the pane keys act on, from 1, if anything is
+    /// listed.
+    pub fn selected(&self) -> Option<usize> {
+        let listing = self.0.lock().unwrap();
+        (!listing.entries.is_empty()).then_some(listing.selected + 1)
+    }
+
+    /// What Enter does on the pane, opening the selected entry or printing
+    /// the file it just described.
+    pub fn pane_open(&self) -> Option<Command> {
+        let listing = self.0.lock().unwrap();
+        let entry = listing.entries.get(listing.selected)?;
+        let n = listing.selected + 1;
+
+        match &listing.confirming {
+            Some((Confirm::Print, path)) if *path == entry.path => {
+                Some(Command::Act(n, Action::Print))
+            }
+            _ => Some(Command::Open(n)),
+        }
+    }
+
+    /// Moves the selection by `by` entries, stopping at either end.
+    pub fn select(&self, by: isize) {
+        let mut listing = self.0.lock().unwrap();
+        let last = listing.entries.len().saturating_sub(1);
+        listing.selected = listing.selected.saturating_add_signed(by).min(last);
+    }
+
+    /// The keys of the pane, shown when it turns on.
+    pub fn pane_panel(&self) -> String {
+        let lines = [
+            "Up, Down          select an entry".to_string(),
+            "Enter             open it, a second time on a file prints it".to_string(),
+            "Backspace         up a directory".to_string(),
+            "Alt-D             delete it, a second time to confirm".to_string(),
+            "Alt-E, Alt-M      rename or move it, the line asks where".to_string(),
+            "Alt-K             copy it, the line asks where".to_string(),
+            "Alt-N             create a directory".to_string(),
+            "Esc, Alt-O        leave".to_string(),
+            String::new(),
+            "Keys other than Up and Down only act on an empty line".to_string(),
+        ];
+
+        popup::render("File browser", &lines)
+    }
+
+    /// Like `files 2/7 part.gcode`, for the status bar while the pane is on.
+    pub fn render_pane(&self) -> Option<String> {
+        let listing = self.0.lock().unwrap();
+
+        if !listing.pane {
+            return None;
+        }
+
+        let Some(entry) = listing.entries.get(listing.selected) else {
+            return Some("files, nothing listed, Esc leaves".to_string());
+        };
+        let name = match listing.query {
+            Some(_) => entry.path.clone(),
+            None => entry.name(),
+        };
+
+        Some(format!(
+            "files {}/{} {}, Esc leaves",
+            listing.selected + 1,
+            listing.entries.len(),
+            name
+        ))
+    }
+
+    /// What was done and the directory browsed again, the numbers shift.
+    async fn refresh(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        done: String,
+    ) -> Result<String, Error> {
+        let dir = self.0.lock().unwrap().dir.clone();
+        let listing = self.browse(client, url, dir).await?;
+        Ok(format!("{}\n{}", done, listing))
+    }
+
+    fn entry(&self, n: usize) -> Result<Entry, Error> {
+        let listing = self.0.lock().unwrap();
+
+        if listing.entries.is_empty() {
+            return Err(Error::Env("Nothing listed yet, try :files".to_string()));
+        }
+
+        n.checked_sub(1)
+            .and_then(|index| listing.entries.get(index))
+            .cloned()
+            .ok_or_else(|| {
+                Error::Env(format!(
+                    "No entry {}, expected 1 to {}",
+                    n,
+                    listing.entries.len()
+                ))
+            })
+    }
+
+    /// Lists `dir` with `server.files.get_directory`, directories first.
+    async fn browse(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        dir: String,
+    ) -> Result<String, Error> {
+        let path = match dir.as_str() {
+            "" => "gcodes".to_string(),
+            dir => format!("gcodes/{}", dir),
+        };
+        let params = Some(json!({ "path": path, "extended": false }));
+        let content = rpc_result(client, url, "server.files.get_directory", params).await?;
+
+        let entries = |key: &str, name_key: &str, is_dir: bool| -> Vec<Entry> {
+            let mut entries: Vec<Entry> = content[key]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| {
+                    let name = entry[name_key].as_str()?;
+                    // Hidden, like the .thumbs directory slicers create
+                    (!name.starts_with('.')).then(|| Entry {
+                        path: join(&dir, name),
+                        is_dir,
+                        size: entry["size"].as_u64().unwrap_or(0),
+                        modified: entry["modified"].as_f64().unwrap_or(0.0),
+                    })
+                })
+                .collect();
+
+            entries.sort_by_key(|entry| entry.path.to_lowercase());
+            entries
+        };
+
+        let mut listed = entries("dirs", "dirname", true);
+        listed.extend(entries("files", "filename", false));
+
+        let free = content["disk_usage"]["free"].as_u64();
+        let mut listing = self.0.lock().unwrap();
+        *listing = Listing {
+            dir,
+            query: None,
+            entries: listed,
+            confirming: None,
+            pane: listing.pane,
+            selected: 0,
+        };
+
+        Ok(listing.render(free))
+    }
+
+    /// What the slicer says about a file, from `server.files.metadata`, under
+    /// its thumbnail. For G-code files it's the dialog confirming the print,
+    /// which the same `:files <n> print` starts next.
+    async fn describe(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        entry: &Entry,
+        n: usize,
+        shared: &SharedState,
+    ) -> Result<String, Error> {
+        let metadata = metadata::fetch(client, url, &entry.path).await?;
+
+        let mut lines = vec![
+            format!("Size           {}", format_size(entry.size)),
+            format!("Modified       {}", format_date(entry.modified)),
+        ];
+        lines.extend(metadata::lines(&metadata, shared.config.units));
+        lines.push(String::new());
+
+        // The details matter more than a missing or broken thumbnail
+        let thumbnail = thumbnail::fetch(client, url, &entry.path, &metadata)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|image| {
+                let width = terminal_size::terminal_size().map_or(0, |(width, _)| width.0);
+                let margin = (width as usize).saturating_sub(image.columns()) / 2;
+                image.render(shared.graphics, margin, &shared.images)
+            })
+            .map(|thumbnail| thumbnail + "\n")
+            .unwrap_or_default();
+
+        if !is_gcode(&entry.path) {
+            lines.push(format!(":files {} download, :files {} delete", n, n));
+            return Ok(thumbnail + &popup::render(&entry.path, &lines));
+        }
+
+        self.0.lock().unwrap().confirming = Some((Confirm::Print, entry.path.clone()));
+        lines.push(format!(":files {} print starts the print", n));
+        lines.push(format!(":files {} check looks for problems first", n));
+        lines.push(format!(":files {} download saves it here", n));
+
+        Ok(thumbnail + &popup::render(&format!("Print {}?", entry.path), &lines))
+    }
+
+    /// Lists the files anywhere under the root matching `query`, with
+    /// `server.files.list`, best matches first.
+    async fn find(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        query: String,
+    ) -> Result<String, Error> {
+        let params = Some(json!({ "root": "gcodes" }));
+        let files = rpc_result(client, url, "server.files.list", params).await?;
+
+        let files: Vec<Entry> = files
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|file| {
+                Some(Entry {
+                    path: file["path"].as_str()?.to_string(),
+                    is_dir: false,
+                    size: file["size"].as_u64().unwrap_or(0),
+                    modified: file["modified"].as_f64().unwrap_or(0.0),
+                })
+            })
+            .collect();
+
+        let found = fuzzy::rank(&query, &files, |file| &file.path)
+            .into_iter()
+            .cloned()
+            .collect();
+
+        let mut listing = self.0.lock().unwrap();
+        listing.query = Some(query);
+        listing.entries = found;
+        listing.confirming = None;
+        listing.selected = 0;
+
+        Ok(listing.render(None))
+    }
+}
+
+impl Listing {
+    fn render(&self, free: Option<u64>) -> String {
+        let title = match &self.query {
+            Some(query) => format!("gcodes matching {}", query),
+            None if self.dir.is_empty() => "gcodes".to_string(),
+            None => format!("gcodes/{}", self.dir),
+        };
+
+        // Search results come from all over the root, they need the path
+        let mut lines = popup::numbered(
+            self.entries
+                .iter()
+                .map(|entry| {
+                    let name = match self.query {
+                        Some(_) => entry.path.clone(),
+                        None => entry.name(),
+                    };
+                    let size = if entry.is_dir {
+                        String::new()
+                    } else {
+                        format_size(entry.size)
+                    };
+
+                    (
+                        name,
+                        format!("{:>10}  {}", size, format_date(entry.modified)),
+                    )
+                })
+                .collect(),
+        );
+
+        if lines.is_empty() {
+            lines.push("No files".to_string());
+        }
+
+        lines.push(String::new());
+
+        if let Some(free) = free {
+            lines.push(format!("{} free", format_size(free)));
+        }
+
+        lines.push(
+            ":files <n> opens, :files <n> print|check|delete|download, :files .. goes up"
+                .to_string(),
+        );
+        lines.push(":files <n> rename|copy <name>, :files <n> move <dir>".to_string());
+        lines.push(":files find <query> searches every directory, :files mkdir <name>".to_string());
+        lines.push(":files <path> jumps to a file, Ctrl-T and Tab complete it".to_string());
+        lines.push("Alt-O browses with the keys".to_string());
+
+        popup::render(&title, &lines)
+    }
+}
+
+impl Operation {
+    /// What was done, for people.
+    pub async fn run(&self, client: &reqwest::Client, url: &str) -> Result<String, Error> {
+        match self {
+            Operation::Delete(path) => {
+                let params = Some(json!({ "path": path }));
+                rpc_result(client, url, "server.files.delete_file", params).await?;
+                Ok(format!("Deleted {}", path))
+            }
+            Operation::DeleteDir(path, force) => {
+                let params = Some(json!({ "path": path, "force": force }));
+                rpc_result(client, url, "server.files.delete_directory", params).await?;
+                Ok(format!("Deleted {}", path))
+            }
+            Operation::Move(source, dest) => {
+                let params = Some(json!({ "source": source, "dest": dest }));
+                rpc_result(client, url, "server.files.move", params).await?;
+                Ok(format!("Moved {} to {}", source, dest))
+            }
+            Operation::Copy(source, dest) => {
+                let params = Some(json!({ "source": source, "dest": dest }));
+                rpc_result(client, url, "server.files.copy", params).await?;
+                Ok(format!("Copied {} to {}", source, dest))
+            }
+            Operation::Mkdir(path) => {
+                let params = Some(json!({ "path": path }));
+                rpc_result(client, url, "server.files.post_directory", params).await?;
+                Ok(format!("Created {}", path))
+            }
+        }
+    }
+}
+
+/// `moonraker-cli files delete|rmdir|move|copy|mkdir`.
+pub async fn run(url: &str, command: &FilesCommand, tls: &Tls, auth: Auth) -> Result<(), Error> {
+    let operation = match command {
+        FilesCommand::Delete { path } => Operation::Delete(path.clone()),
+        FilesCommand::Rmdir { path, force } => Operation::DeleteDir(path.clone(), *force),
+        FilesCommand::Move { source, dest } => Operation::Move(source.clone(), dest.clone()),
+        FilesCommand::Copy { source, dest } => Operation::Copy(source.clone(), dest.clone()),
+        FilesCommand::Mkdir { path } => Operation::Mkdir(path.clone()),
+    };
+
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+
+    println!("{}", operation.run(&client, url).await?);
+    Ok(())
+}
+
+/// `name` in `dir`, relative to the root, empty for the root itself.
+pub fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        dir => format!("{}/{}", dir, name),
+    }
+}
+
+/// Whether Klipper can print the file, going by its extension.
+pub fn is_gcode(path: &str) -> bool {
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+    ["gcode", "g", "gco"]
+        .iter()
+        .any(|gcode| extension.is_some_and(|extension| extension.eq_ignore_ascii_case(gcode)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_browsing_commands() {
+        assert!(matches!(Command::parse(""), Ok(Command::List)));
+        assert!(matches!(Command::parse(" .. "), Ok(Command::Up)));
+        assert!(matches!(Command::parse("3"), Ok(Command::Open(3))));
+        assert!(matches!(
+            Command::parse("find  benchy v2"),
+            Ok(Command::Find(query)) if query == "benchy v2"
+        ));
+        assert!(matches!(
+            Command::parse("mkdir old parts"),
+            Ok(Command::Mkdir(name)) if name == "old parts"
+        ));
+        assert!(matches!(
+            Command::parse("queue/part.gcode"),
+            Ok(Command::Jump(path)) if path == "queue/part.gcode"
+        ));
+    }
+
+    #[test]
+    fn parses_actions_keeping_spaces_in_names() {
+        assert!(matches!(
+            Command::parse("2 print"),
+            Ok(Command::Act(2, Action::Print))
+        ));
+        assert!(matches!(
+            Command::parse("2 download"),
+            Ok(command) if command.is_download()
+        ));
+        assert!(matches!(
+            Command::parse("2 rename  my part.gcode "),
+            Ok(Command::Act(2, Action::Rename(name))) if name == "my part.gcode"
+        ));
+        assert!(matches!(
+            Command::parse("12 move old/parts"),
+            Ok(Command::Act(12, Action::Move(dir))) if dir == "old/parts"
+        ));
+    }
+
+    #[test]
+    fn refuses_unknown_actions_and_missing_names() {
+        assert!(Command::parse("2 burn").is_err());
+        assert!(Command::parse("2 rename").is_err());
+        assert!(Command::parse("2 copy  ").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::JSON;
+use std::fmt;
+
+/// Subset of jq path expressions, e.g. `.result.status["extruder"].temperature`
+/// or `.result.items[-1]`, used to narrow down large responses.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: String,
+    path: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(i64),
+}
+
+impl Filter {
+    pub fn parse(expr: &str) -> Result<Filter, String> {
+        let expr = expr.trim();
+        let chars: Vec<char> = expr.chars().collect();
+        let mut path = Vec::new();
+        let mut pos = 0;
+
+        if chars.first() != Some(&'.') {
+            return Err(format!("filter must start with '.': {}", expr));
+        }
+
+        while pos < chars.len() {
+            match chars[pos] {
+                '.' => {
+                    pos += 1;
+
+                    match chars.get(pos) {
+                        // A lone dot is the identity filter
+                        None if path.is_empty() => break,
+                        Some('[') => continue,
+                        Some('"') => {
+                            let (key, next) = parse_string(&chars, pos)?;
+                            path.push(Segment::Key(key));
+                            pos = next;
+                        }
+                        Some(c) if is_ident_char(*c) => {
+                            let start = pos;
+                            while chars.get(pos).copied().is_some_and(is_ident_char) {
+                                pos += 1;
+                            }
+                            path.push(Segment::Key(chars[start..pos].iter().collect()));
+                        }
+                        _ => return Err(format!("expected key at position {}", pos)),
+                    }
+                }
+                '[' => {
+                    let (segment, next) = parse_brackets(&chars, pos)?;
+                    path.push(segment);
+                    pos = next;
+                }
+                c => return Err(format!("unexpected '{}' at position {}", c, pos)),
+            }
+        }
+
+        Ok(Filter {
+            expr: expr.to_string(),
+            path,
+        })
+    }
+
+    /// Missing keys and out of range indexes yield `null`, like jq does.
+    pub fn apply(&self, value: &JSON) -> JSON {
+        self.path
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => {
+                    let items = value.as_array()?;
+                    let index = if *index < 0 {
+                        items.len().checked_sub(index.unsigned_abs() as usize)?
+                    } else {
+                        *index as usize
+                    };
+                    items.get(index)
+                }
+            })
+            .cloned()
+            .unwrap_or(JSON::Null)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Parses `[0]`, `[-1]` or `["key"]` starting at the opening bracket,
+/// returns the segment and the position right after the closing bracket.
+fn parse_brackets(chars: &[char], start: usize) -> Result<(Segment, usize), String> {
+    let mut pos = start + 1;
+
+    let segment = if chars.get(pos) == Some(&'"') {
+        let (key, next) = parse_string(chars, pos)?;
+        pos = next;
+        Segment::Key(key)
+    } else {
+        let digits_start = pos;
+        while chars
+            .get(pos)
+            .is_some_and(|c| c.is_ascii_digit() || (*c == '-' && pos == digits_start))
+        {
+            pos += 1;
+        }
+        let digits: String = chars[digits_start..pos].iter().collect();
+        let index = digits
+            .parse()
+            .map_err(|_| format!("invalid index at position {}", digits_start))?;
+        Segment::Index(index)
+    };
+
+    if chars.get(pos) != Some(&']') {
+        return Err(format!("expected ']' at position {}", pos));
+    }
+
+    Ok((segment, pos + 1))
+}
+
+/// Parses a double quoted string starting at the opening quote,
+/// returns its content and the position right after the closing quote.
+fn parse_string(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let mut pos = start + 1;
+    let mut content = String::new();
+
+    loop {
+        match chars.get(pos) {
+            Some('"') => return Ok((content, pos + 1)),
+            Some('\\') => {
+                let escaped = chars
+                    .get(pos + 1)
+                    .ok_or_else(|| format!("unterminated string at position {}", start))?;
+                content.push(*escaped);
+                pos += 2;
+            }
+            Some(c) => {
+                content.push(*c);
+                pos += 1;
+            }
+            None => return Err(format!("unterminated string at position {}", start)),
+        }
+    }
+}
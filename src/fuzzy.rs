@@ -0,0 +1,77 @@
+/// Scores how well `query` matches `candidate` as a case insensitive
+/// subsequence, `None` when some query character is missing.
+/// Consecutive matches and matches at word starts score higher,
+/// ties favor shorter candidates.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+
+    for needle in query.chars().filter(|c| !c.is_whitespace()) {
+        let found = (pos..chars.len()).find(|&i| eq_ignore_case(chars[i], needle))?;
+
+        score += 10;
+
+        if last_match.is_some_and(|last| last + 1 == found) {
+            score += 15;
+        }
+
+        if found == 0 || !chars[found - 1].is_alphanumeric() {
+            score += 10;
+        }
+
+        score -= (found - pos) as i64;
+        last_match = Some(found);
+        pos = found + 1;
+    }
+
+    Some(score * 100 - chars.len() as i64)
+}
+
+/// Candidates matching `query`, best first.
+pub fn rank<'a, T>(query: &str, candidates: &'a [T], key: impl Fn(&T) -> &str) -> Vec<&'a T> {
+    let mut matches: Vec<(i64, &T)> = candidates
+        .iter()
+        .filter_map(|candidate| score(query, key(candidate)).map(|score| (score, candidate)))
+        .collect();
+
+    matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_case_insensitive_subsequences() {
+        assert!(score("bmc", "BED_MESH_CALIBRATE").is_some());
+        assert!(score("bed mesh", "BED_MESH_CALIBRATE").is_some());
+        assert!(score("", "G28").is_some());
+        assert_eq!(score("hb", "BED_MESH"), None);
+        assert_eq!(score("g29", "G28"), None);
+    }
+
+    #[test]
+    fn prefers_consecutive_and_word_start_matches() {
+        assert!(score("home", "G28 home") > score("home", "hot meter"));
+        assert!(score("mesh", "BED_MESH") > score("mesh", "some_hash"));
+        assert!(score("g28", "G28") > score("g28", "G28 X Y"));
+    }
+
+    #[test]
+    fn ranks_best_first_dropping_misses() {
+        let candidates = ["PRINT_START", "SAVE_CONFIG", "PAUSE", "PROBE"];
+        let ranked = rank("pr", &candidates, |candidate| candidate);
+
+        assert_eq!(ranked, [&"PROBE", &"PRINT_START"]);
+    }
+}
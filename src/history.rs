@@ -0,0 +1,484 @@
+use crate::cost::Prices;
+use crate::units::Units;
+use crate::{ics, metadata, popup, rpc_result, Error, JSON};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+/// Jobs fetched for `:history`, enough to fill it when filtering.
+const FETCHED_JOBS: usize = 200;
+
+/// Jobs `:history` lists.
+const LISTED_JOBS: usize = 20;
+
+/// Jobs `server.history.list` answers with at most, paged through for
+/// `:stats monthly`.
+const PAGE_SIZE: usize = 1000;
+
+/// How jobs end, or `in_progress`, as Moonraker records them.
+const STATUSES: &[&str] = &[
+    "completed",
+    "cancelled",
+    "error",
+    "klippy_shutdown",
+    "klippy_disconnect",
+    "server_exit",
+    "interrupted",
+    "in_progress",
+];
+
+/// What `:history` does.
+pub enum Command {
+    /// The latest jobs, only those ending this way with a status
+    List(Option<String>),
+    /// The nth job listed, from 1
+    Show(usize),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        match args.trim() {
+            "" => Ok(Command::List(None)),
+            status if STATUSES.contains(&status) => Ok(Command::List(Some(status.to_string()))),
+            n => n.parse::<usize>().map(Command::Show).map_err(|_| {
+                format!(
+                    "Invalid job or status {}, expected a number or {}",
+                    n,
+                    STATUSES.join(", ")
+                )
+            }),
+        }
+    }
+}
+
+/// The job ids `:history` listed last, so `:history <n>` shows the nth.
+#[derive(Clone, Default)]
+pub struct Jobs(Arc<Mutex<Vec<String>>>);
+
+impl Jobs {
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        command: Command,
+        units: Units,
+        prices: &Prices,
+    ) -> Result<String, Error> {
+        match command {
+            Command::List(status) => self.list(client, url, status, prices).await,
+            Command::Show(n) => {
+                let uid = {
+                    let listed = self.0.lock().unwrap();
+
+                    if listed.is_empty() {
+                        return Err(Error::Env("Nothing listed yet, try :history".to_string()));
+                    }
+
+                    n.checked_sub(1)
+                        .and_then(|index| listed.get(index))
+                        .cloned()
+                        .ok_or_else(|| {
+                            Error::Env(format!("No job {}, expected 1 to {}", n, listed.len()))
+                        })?
+                };
+
+                let job = job(client, url, &uid).await?;
+                Ok(describe(&job, units, prices))
+            }
+        }
+    }
+
+    /// Lists the latest jobs with `server.history.list`, newest first.
+    async fn list(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        status: Option<String>,
+        prices: &Prices,
+    ) -> Result<String, Error> {
+        let params = Some(json!({ "limit": FETCHED_JOBS, "order": "desc" }));
+        let history = rpc_result(client, url, "server.history.list", params).await?;
+
+        let jobs: Vec<&JSON> = history["jobs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|job| {
+                status
+                    .as_ref()
+                    .is_none_or(|status| job["status"] == **status)
+            })
+            .take(LISTED_JOBS)
+            .collect();
+
+        *self.0.lock().unwrap() = jobs
+            .iter()
+            .filter_map(|job| job["job_id"].as_str().map(str::to_string))
+            .collect();
+
+        let rows: Vec<Vec<String>> = jobs
+            .iter()
+            .map(|job| {
+                let mut row = vec![
+                    text(&job["job_id"]).unwrap_or_default(),
+                    text(&job["filename"]).unwrap_or_default(),
+                    text(&job["status"]).unwrap_or_default(),
+                    job["start_time"]
+                        .as_f64()
+                        .map_or(String::new(), ics::format_date),
+                    job["print_duration"]
+                        .as_f64()
+                        .map_or(String::new(), format_duration),
+                    job["filament_used"].as_f64().map_or(String::new(), meters),
+                ];
+
+                if !prices.profiles.is_empty() {
+                    row.push(cost(job, prices).unwrap_or_default());
+                }
+
+                row
+            })
+            .collect();
+
+        let columns = rows.first().map_or(0, Vec::len);
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let number_width = rows.len().to_string().len();
+
+        let mut lines: Vec<String> = rows
+            .iter()
+            .enumerate()
+            .map(|(index, row)| {
+                let cells = row
+                    .iter()
+                    .zip(&widths)
+                    .enumerate()
+                    .map(|(column, (cell, &width))| {
+                        // Durations, lengths and costs to the right
+                        if column < 4 {
+                            format!("{:width$}", cell)
+                        } else {
+                            format!("{:>width$}", cell)
+                        }
+                    });
+
+                format!(
+                    "{:>number_width$}  {}",
+                    index + 1,
+                    cells.collect::<Vec<_>>().join("  ")
+                )
+            })
+            .collect();
+
+        if lines.is_empty() {
+            lines.push("No jobs".to_string());
+        }
+
+        lines.push(String::new());
+        lines.push(":history <n> shows a job, :history <status> lists only those".to_string());
+        lines.push(":compare <job> <job> puts two side by side".to_string());
+
+        let title = match status {
+            Some(status) => format!("Print history, {}", status),
+            None => "Print history".to_string(),
+        };
+
+        Ok(popup::render(&title, &lines))
+    }
+}
+
+/// A job in detail, its timings and what the slicer says about the file.
+fn describe(job: &JSON, units: Units, prices: &Prices) -> String {
+    let seconds = |key: &str| job[key].as_f64().map(format_duration);
+    let rows = [
+        ("Job           ", text(&job["job_id"])),
+        ("Status        ", text(&job["status"])),
+        (
+            "Started       ",
+            job["start_time"].as_f64().map(ics::format_date),
+        ),
+        (
+            "Ended         ",
+            job["end_time"].as_f64().map(ics::format_date),
+        ),
+        ("Print time    ", seconds("print_duration")),
+        ("Total time    ", seconds("total_duration")),
+        ("Filament used ", job["filament_used"].as_f64().map(meters)),
+        ("Cost          ", cost(job, prices)),
+    ];
+
+    let mut lines: Vec<String> = rows
+        .into_iter()
+        .filter_map(|(label, value)| Some(format!("{} {}", label, value?)))
+        .collect();
+
+    let details = metadata::lines(&job["metadata"], units);
+
+    if !details.is_empty() {
+        lines.push(String::new());
+        lines.extend(details);
+    }
+
+    let filename = job["filename"].as_str().unwrap_or("Job");
+    popup::render(filename, &lines)
+}
+
+/// Puts two print history jobs side by side: their outcome, durations, time
+/// spent paused, filament used and the slicer settings from the file
+/// metadata, marking the rows that differ with `*`.
+pub async fn compare(
+    client: &reqwest::Client,
+    url: &str,
+    first: &str,
+    second: &str,
+) -> Result<String, Error> {
+    let first = job(client, url, first).await?;
+    let second = job(client, url, second).await?;
+
+    let seconds = |job: &JSON, key: &str| job[key].as_f64().map(format_duration);
+    let paused = |job: &JSON| {
+        let total = job["total_duration"].as_f64()?;
+        let printing = job["print_duration"].as_f64()?;
+        Some(format_duration((total - printing).max(0.0)))
+    };
+    let filament = |job: &JSON| {
+        job["filament_used"]
+            .as_f64()
+            .map(|mm| format!("{:.2} m", mm / 1000.0))
+    };
+
+    let mut rows: Vec<(String, Option<String>, Option<String>)> = vec![
+        (
+            "job".to_string(),
+            text(&first["job_id"]),
+            text(&second["job_id"]),
+        ),
+        (
+            "file".to_string(),
+            text(&first["filename"]),
+            text(&second["filename"]),
+        ),
+        (
+            "status".to_string(),
+            text(&first["status"]),
+            text(&second["status"]),
+        ),
+        (
+            "started".to_string(),
+            first["start_time"].as_f64().map(ics::format_utc),
+            second["start_time"].as_f64().map(ics::format_utc),
+        ),
+        (
+            "print time".to_string(),
+            seconds(&first, "print_duration"),
+            seconds(&second, "print_duration"),
+        ),
+        (
+            "total time".to_string(),
+            seconds(&first, "total_duration"),
+            seconds(&second, "total_duration"),
+        ),
+        ("paused".to_string(), paused(&first), paused(&second)),
+        ("filament".to_string(), filament(&first), filament(&second)),
+    ];
+
+    let keys: BTreeSet<&String> = [&first, &second]
+        .into_iter()
+        .filter_map(|job| job["metadata"].as_object())
+        .flat_map(|metadata| metadata.keys())
+        // Thumbnails are image data, not settings
+        .filter(|key| *key != "thumbnails")
+        .collect();
+
+    for key in keys {
+        rows.push((
+            key.clone(),
+            text(&first["metadata"][key]),
+            text(&second["metadata"][key]),
+        ));
+    }
+
+    let label_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let value_width = rows
+        .iter()
+        .map(|row| row.1.as_deref().unwrap_or("-").len())
+        .max()
+        .unwrap_or(0);
+
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|(label, first, second)| {
+            let marker = if first != second { '*' } else { ' ' };
+            format!(
+                "{} {:label_width$}  {:value_width$}  {}",
+                marker,
+                label,
+                first.as_deref().unwrap_or("-"),
+                second.as_deref().unwrap_or("-")
+            )
+        })
+        .collect();
+
+    Ok(lines.join("\n"))
+}
+
+/// The totals Moonraker keeps over every job, and with `monthly` the jobs,
+/// print time and filament of each month, the whole history paged through.
+pub async fn stats(client: &reqwest::Client, url: &str, monthly: bool) -> Result<String, Error> {
+    let totals = rpc_result(client, url, "server.history.totals", None).await?;
+    let totals = &totals["job_totals"];
+    let seconds = |key: &str| totals[key].as_f64().map(format_duration);
+
+    let rows = [
+        (
+            "Jobs          ",
+            totals["total_jobs"].as_u64().map(|jobs| jobs.to_string()),
+        ),
+        ("Total time    ", seconds("total_time")),
+        ("Print time    ", seconds("total_print_time")),
+        (
+            "Filament used ",
+            totals["total_filament_used"].as_f64().map(meters),
+        ),
+        ("Longest job   ", seconds("longest_job")),
+        ("Longest print ", seconds("longest_print")),
+    ];
+
+    let mut lines: Vec<String> = rows
+        .into_iter()
+        .filter_map(|(label, value)| Some(format!("{} {}", label, value?)))
+        .collect();
+
+    if monthly {
+        lines.push(String::new());
+        lines.extend(months(client, url).await?);
+    } else {
+        lines.push(String::new());
+        lines.push(":stats monthly breaks it down per month".to_string());
+    }
+
+    Ok(popup::render("Print statistics", &lines))
+}
+
+/// A row a month by when jobs started, oldest first: jobs, of which
+/// completed, print time and filament.
+async fn months(client: &reqwest::Client, url: &str) -> Result<Vec<String>, Error> {
+    #[derive(Default)]
+    struct Month {
+        jobs: usize,
+        completed: usize,
+        print_time: f64,
+        filament: f64,
+    }
+
+    let mut months: BTreeMap<String, Month> = BTreeMap::new();
+    let mut start = 0;
+
+    loop {
+        let params = Some(json!({ "limit": PAGE_SIZE, "start": start, "order": "asc" }));
+        let history = rpc_result(client, url, "server.history.list", params).await?;
+        let jobs = history["jobs"].as_array().cloned().unwrap_or_default();
+
+        for job in &jobs {
+            let Some(started) = job["start_time"].as_f64() else {
+                continue;
+            };
+
+            // `2024-05-17 10:30` to `2024-05`
+            let month = months
+                .entry(ics::format_date(started)[..7].to_string())
+                .or_default();
+            month.jobs += 1;
+            month.completed += (job["status"] == "completed") as usize;
+            month.print_time += job["print_duration"].as_f64().unwrap_or(0.0);
+            month.filament += job["filament_used"].as_f64().unwrap_or(0.0);
+        }
+
+        if jobs.len() < PAGE_SIZE {
+            break;
+        }
+
+        start += jobs.len();
+    }
+
+    if months.is_empty() {
+        return Ok(vec!["No jobs".to_string()]);
+    }
+
+    let rows: Vec<[String; 4]> = months
+        .iter()
+        .map(|(name, month)| {
+            [
+                name.clone(),
+                format!("{} jobs, {} completed", month.jobs, month.completed),
+                format_duration(month.print_time),
+                meters(month.filament),
+            ]
+        })
+        .collect();
+
+    let width = |column: usize| {
+        rows.iter()
+            .map(|row| row[column].chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (w1, w2, w3) = (width(1), width(2), width(3));
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            format!(
+                "{}  {:w1$}  {:>w2$}  {:>w3$}",
+                row[0], row[1], row[2], row[3]
+            )
+        })
+        .collect())
+}
+
+/// What the filament the job used cost, priced by the profile for the file.
+fn cost(job: &JSON, prices: &Prices) -> Option<String> {
+    let used = job["filament_used"].as_f64()?;
+    prices
+        .used(&job["metadata"], used)
+        .map(|cost| prices.format(cost))
+}
+
+/// Filament as Moonraker counts it, in millimeters, as meters.
+fn meters(mm: f64) -> String {
+    format!("{:.2} m", mm / 1000.0)
+}
+
+async fn job(client: &reqwest::Client, url: &str, uid: &str) -> Result<JSON, Error> {
+    let params = Some(json!({ "uid": uid }));
+    let mut resp = rpc_result(client, url, "server.history.get_job", params).await?;
+
+    Ok(resp.get_mut("job").map(JSON::take).unwrap_or_default())
+}
+
+/// Strings as they are, other values as JSON, nothing for missing values.
+fn text(value: &JSON) -> Option<String> {
+    match value {
+        JSON::Null => None,
+        JSON::String(text) => Some(text.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Formats seconds as `1h 02m 03s`, leaving out zero hours.
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.max(0.0).round() as u64;
+    let (hours, minutes, secs) = (secs / 3600, secs % 3600 / 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, secs)
+    } else {
+        format!("{}m {:02}s", minutes, secs)
+    }
+}
@@ -0,0 +1,64 @@
+use serde::Deserialize;
+use tokio::process::Command;
+use tokio::sync::mpsc::Sender;
+
+/// Shell commands run on printer events, `--on-complete`, `--on-error` and
+/// `--on-shutdown` or in the config
+///
+/// ```toml
+/// [hooks]
+/// complete = "curl -s -d \"$MOONRAKER_FILENAME done\" https://ntfy.sh/printer"
+/// ```
+///
+/// They get the event, the printer URL and the file printed, if any, in
+/// `$MOONRAKER_EVENT`, `$MOONRAKER_URL` and `$MOONRAKER_FILENAME`.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    pub complete: Option<String>,
+    pub error: Option<String>,
+    pub shutdown: Option<String>,
+}
+
+impl Hooks {
+    /// Runs the command for `event` in the background, if there's one,
+    /// reporting on `notice_tx` when it fails.
+    pub fn run(&self, event: &str, url: &str, filename: Option<&str>, notice_tx: &Sender<String>) {
+        let command = match event {
+            "complete" => &self.complete,
+            "error" => &self.error,
+            "shutdown" => &self.shutdown,
+            _ => &None,
+        };
+
+        let Some(command) = command.clone() else {
+            return;
+        };
+
+        let mut child = Command::new("sh");
+        child
+            .arg("-c")
+            .arg(&command)
+            .env("MOONRAKER_EVENT", event)
+            .env("MOONRAKER_URL", url)
+            .env("MOONRAKER_FILENAME", filename.unwrap_or_default())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+
+        let (event, notice_tx) = (event.to_string(), notice_tx.clone());
+
+        tokio::spawn(async move {
+            let failure = match child.status().await {
+                Ok(status) if status.success() => return,
+                Ok(status) => format!("{}", status),
+                Err(err) => err.to_string(),
+            };
+
+            tracing::warn!(event, command, failure, "hook failed");
+            let _ = notice_tx
+                .send(format!("Hook {} failed: {}", command, failure))
+                .await;
+        });
+    }
+}
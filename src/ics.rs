@@ -0,0 +1,211 @@
+/// Longest content line allowed, in octets, excluding the line break.
+const MAX_LINE: usize = 75;
+
+/// A calendar entry, times are unix timestamps in seconds.
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Renders events as an iCalendar (RFC 5545) document.
+pub fn render(events: &[Event], now: f64) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//moonraker-cli//print queue//EN".to_string(),
+    ];
+
+    for event in events {
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}", event.uid));
+        lines.push(format!("DTSTAMP:{}", format_utc(now)));
+        lines.push(format!("DTSTART:{}", format_utc(event.start)));
+        lines.push(format!("DTEND:{}", format_utc(event.end)));
+        lines.push(format!("SUMMARY:{}", escape(&event.summary)));
+        lines.push(format!("DESCRIPTION:{}", escape(&event.description)));
+        lines.push("END:VEVENT".to_string());
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    // The spec mandates CRLF line endings
+    let lines: Vec<String> = lines.iter().map(|line| fold(line)).collect();
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Splits a content line longer than `MAX_LINE` octets, continuing it on
+/// lines starting with a space. Breaks fall between characters, never
+/// inside a multi-byte UTF-8 sequence.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut length = 0;
+
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE {
+            folded.push_str("\r\n ");
+            // The leading space counts towards the continuation line
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+
+    folded
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Formats a unix timestamp as `YYYYMMDDTHHMMSSZ`.
+pub fn format_utc(timestamp: f64) -> String {
+    let secs = timestamp.max(0.0) as i64;
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Formats a unix timestamp as RFC 3339, like `2024-05-01T12:30:05Z`.
+pub fn format_timestamp(timestamp: f64) -> String {
+    let secs = timestamp.max(0.0) as i64;
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM`, in UTC.
+pub fn format_date(timestamp: f64) -> String {
+    let secs = timestamp.max(0.0) as i64;
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60
+    )
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date,
+/// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(summary: &str) -> Event {
+        Event {
+            uid: "0001@moonraker-cli".to_string(),
+            summary: summary.to_string(),
+            description: "Queued".to_string(),
+            start: 1714566605.0,
+            end: 1714570205.0,
+        }
+    }
+
+    #[test]
+    fn renders_events_with_crlf_line_endings() {
+        let calendar = render(&[event("benchy.gcode")], 1714566600.0);
+        let lines: Vec<&str> = calendar.split_terminator("\r\n").collect();
+
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert!(!calendar.replace("\r\n", "").contains('\n'));
+        assert_eq!(lines.first(), Some(&"BEGIN:VCALENDAR"));
+        assert!(lines.contains(&"UID:0001@moonraker-cli"));
+        assert!(lines.contains(&"DTSTAMP:20240501T123000Z"));
+        assert!(lines.contains(&"DTSTART:20240501T123005Z"));
+        assert!(lines.contains(&"DTEND:20240501T133005Z"));
+        assert!(lines.contains(&"SUMMARY:benchy.gcode"));
+    }
+
+    #[test]
+    fn escapes_text_values() {
+        let calendar = render(&[event("a, b; c\\d\ne")], 0.0);
+
+        assert!(calendar.contains("SUMMARY:a\\, b\\; c\\\\d\\ne\r\n"));
+    }
+
+    #[test]
+    fn renders_an_empty_queue() {
+        let calendar = render(&[], 0.0);
+
+        assert!(!calendar.contains("VEVENT"));
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_utc(0.0), "19700101T000000Z");
+        assert_eq!(format_timestamp(951827696.0), "2000-02-29T12:34:56Z");
+        assert_eq!(format_date(-5.0), "1970-01-01 00:00");
+    }
+
+    #[test]
+    fn short_lines_are_left_alone() {
+        assert_eq!(fold("SUMMARY:benchy"), "SUMMARY:benchy");
+        assert_eq!(fold(&"x".repeat(MAX_LINE)), "x".repeat(MAX_LINE));
+    }
+
+    #[test]
+    fn long_lines_fold_at_75_octets() {
+        let line = "x".repeat(200);
+        let folded = fold(&line);
+
+        for part in folded.split("\r\n") {
+            assert!(part.len() <= MAX_LINE, "{:?}", part);
+        }
+        assert_eq!(folded.split("\r\n").count(), 3);
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+
+    #[test]
+    fn folds_never_split_a_character() {
+        let line = format!("SUMMARY:{}", "é".repeat(100));
+        let folded = fold(&line);
+
+        for part in folded.split("\r\n") {
+            assert!(part.len() <= MAX_LINE, "{:?}", part);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+}
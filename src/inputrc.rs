@@ -0,0 +1,306 @@
+use rustyline::config::{BellStyle, CompletionType, Config, Configurer, EditMode};
+use rustyline::{Anchor, At, Cmd, Event, KeyCode, KeyEvent, Modifiers, Movement, Word};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Name matched by `$if` blocks.
+const APPLICATION: &str = "moonraker-cli";
+
+/// What `~/.inputrc` (or `$INPUTRC`) sets among what the line editor
+/// supports: editing mode, bell, completion and history settings, and key
+/// bindings to the common readline functions. Anything else is ignored.
+pub struct Inputrc {
+    pub config: Config,
+    pub bindings: Vec<(Event, Cmd)>,
+}
+
+pub fn load() -> Inputrc {
+    let content = inputrc_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+
+    parse(&content)
+}
+
+fn inputrc_path() -> Option<PathBuf> {
+    env::var_os("INPUTRC")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".inputrc")))
+}
+
+fn parse(content: &str) -> Inputrc {
+    let mut builder = Config::builder();
+    let mut bindings = Vec::new();
+    let mut vi = false;
+    // Whether each enclosing `$if` block applies, lines are skipped when
+    // any of them doesn't
+    let mut conditions: Vec<bool> = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(test) = line.strip_prefix("$if") {
+            let test = test.trim();
+            let applies = test == APPLICATION || test == if vi { "mode=vi" } else { "mode=emacs" };
+            conditions.push(applies);
+            continue;
+        } else if line.starts_with("$else") {
+            if let Some(applies) = conditions.last_mut() {
+                *applies = !*applies;
+            }
+            continue;
+        } else if line.starts_with("$endif") {
+            conditions.pop();
+            continue;
+        } else if line.starts_with('$') || conditions.contains(&false) {
+            continue;
+        }
+
+        if let Some(variable) = line.strip_prefix("set ") {
+            let mut words = variable.split_whitespace();
+            let (name, value) = (
+                words.next().unwrap_or_default(),
+                words.next().unwrap_or_default(),
+            );
+
+            builder = match (name, value) {
+                ("editing-mode", "vi") => {
+                    vi = true;
+                    builder.edit_mode(EditMode::Vi)
+                }
+                ("editing-mode", "emacs") => {
+                    vi = false;
+                    builder.edit_mode(EditMode::Emacs)
+                }
+                ("bell-style", "none") => builder.bell_style(BellStyle::None),
+                ("bell-style", "visible") => builder.bell_style(BellStyle::Visible),
+                ("bell-style", "audible") => builder.bell_style(BellStyle::Audible),
+                ("show-all-if-ambiguous", "on") => builder.completion_type(CompletionType::List),
+                ("completion-query-items", items) => match items.parse() {
+                    Ok(items) => builder.completion_prompt_limit(items),
+                    Err(_) => builder,
+                },
+                ("keyseq-timeout", millis) => match millis.parse() {
+                    Ok(millis) => builder.keyseq_timeout(Some(millis)),
+                    Err(_) => builder,
+                },
+                ("history-size", size) => {
+                    if let Ok(size) = size.parse() {
+                        let _ = builder.set_max_history_size(size);
+                    }
+                    builder
+                }
+                _ => builder,
+            };
+        } else if let Some((keys, function)) = line.split_once(':') {
+            if let (Some(keys), Some(cmd)) = (parse_keys(keys.trim()), command(function.trim())) {
+                bindings.push((keys, cmd));
+            }
+        }
+    }
+
+    Inputrc {
+        config: builder.build(),
+        bindings,
+    }
+}
+
+/// Either a quoted sequence like `"\C-a"`, `"\M-b"` or `"\e[A"`, or a key
+/// name like `Control-a` or `Meta-b`.
+pub fn parse_keys(keys: &str) -> Option<Event> {
+    let Some(sequence) = keys
+        .strip_prefix('"')
+        .and_then(|keys| keys.strip_suffix('"'))
+    else {
+        return key_name(keys).map(Event::from);
+    };
+
+    let mut events = Vec::new();
+    let mut rest = sequence;
+
+    while !rest.is_empty() {
+        let arrows = [
+            ("\\e[A", KeyCode::Up),
+            ("\\e[B", KeyCode::Down),
+            ("\\e[C", KeyCode::Right),
+            ("\\e[D", KeyCode::Left),
+            ("\\e[H", KeyCode::Home),
+            ("\\e[F", KeyCode::End),
+        ];
+
+        if let Some((escape, code)) = arrows.iter().find(|(escape, _)| rest.starts_with(escape)) {
+            events.push(KeyEvent(*code, Modifiers::NONE));
+            rest = &rest[escape.len()..];
+        } else if let Some(key) = rest.strip_prefix("\\C-") {
+            let c = key.chars().next()?;
+            events.push(KeyEvent::ctrl(c));
+            rest = &key[c.len_utf8()..];
+        } else if let Some(key) = rest
+            .strip_prefix("\\M-")
+            .or_else(|| rest.strip_prefix("\\e"))
+        {
+            let c = key.chars().next()?;
+            events.push(KeyEvent::alt(c));
+            rest = &key[c.len_utf8()..];
+        } else if rest.starts_with('\\') {
+            // Other escapes aren't supported
+            return None;
+        } else {
+            let c = rest.chars().next()?;
+            events.push(KeyEvent::new(c, Modifiers::NONE));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    match events.len() {
+        0 => None,
+        1 => Some(Event::from(events[0])),
+        _ => Some(Event::KeySeq(events)),
+    }
+}
+
+fn key_name(name: &str) -> Option<KeyEvent> {
+    let (modifier, key) = name.rsplit_once('-').unwrap_or(("", name));
+    let c = match key.to_lowercase().as_str() {
+        "space" | "spc" => ' ',
+        "tab" => '\t',
+        key if key.chars().count() == 1 => key.chars().next()?,
+        _ => return None,
+    };
+
+    match modifier.to_lowercase().as_str() {
+        "" => Some(KeyEvent::new(c, Modifiers::NONE)),
+        "control" | "c" => Some(KeyEvent::ctrl(c)),
+        "meta" | "m" => Some(KeyEvent::alt(c)),
+        _ => None,
+    }
+}
+
+/// Maps readline function names to the line editor commands.
+pub fn command(function: &str) -> Option<Cmd> {
+    let cmd = match function {
+        "beginning-of-line" => Cmd::Move(Movement::BeginningOfLine),
+        "end-of-line" => Cmd::Move(Movement::EndOfLine),
+        "forward-char" => Cmd::Move(Movement::ForwardChar(1)),
+        "backward-char" => Cmd::Move(Movement::BackwardChar(1)),
+        "forward-word" => Cmd::Move(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+        "backward-word" => Cmd::Move(Movement::BackwardWord(1, Word::Emacs)),
+        "kill-line" => Cmd::Kill(Movement::EndOfLine),
+        "unix-line-discard" | "backward-kill-line" => Cmd::Kill(Movement::BeginningOfLine),
+        "kill-word" => Cmd::Kill(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+        "backward-kill-word" => Cmd::Kill(Movement::BackwardWord(1, Word::Emacs)),
+        "unix-word-rubout" => Cmd::Kill(Movement::BackwardWord(1, Word::Big)),
+        "delete-char" => Cmd::Kill(Movement::ForwardChar(1)),
+        "backward-delete-char" => Cmd::Kill(Movement::BackwardChar(1)),
+        "previous-history" => Cmd::PreviousHistory,
+        "next-history" => Cmd::NextHistory,
+        "beginning-of-history" => Cmd::BeginningOfHistory,
+        "end-of-history" => Cmd::EndOfHistory,
+        "history-search-backward" => Cmd::HistorySearchBackward,
+        "history-search-forward" => Cmd::HistorySearchForward,
+        "reverse-search-history" => Cmd::ReverseSearchHistory,
+        "forward-search-history" => Cmd::ForwardSearchHistory,
+        "transpose-chars" => Cmd::TransposeChars,
+        "transpose-words" => Cmd::TransposeWords(1),
+        "upcase-word" => Cmd::UpcaseWord,
+        "downcase-word" => Cmd::DowncaseWord,
+        "capitalize-word" => Cmd::CapitalizeWord,
+        "yank" => Cmd::Yank(1, Anchor::Before),
+        "yank-pop" => Cmd::YankPop,
+        "undo" => Cmd::Undo(1),
+        "complete" => Cmd::Complete,
+        "clear-screen" => Cmd::ClearScreen,
+        "accept-line" => Cmd::AcceptLine,
+        _ => return None,
+    };
+
+    Some(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_sequences_and_names() {
+        assert_eq!(
+            parse_keys("\"\\C-x\""),
+            Some(Event::from(KeyEvent::ctrl('x')))
+        );
+        assert_eq!(
+            parse_keys("\"\\M-b\""),
+            Some(Event::from(KeyEvent::alt('b')))
+        );
+        assert_eq!(
+            parse_keys("\"\\e[A\""),
+            Some(Event::from(KeyEvent(KeyCode::Up, Modifiers::NONE)))
+        );
+        assert_eq!(
+            parse_keys("\"\\C-xa\""),
+            Some(Event::KeySeq(vec![
+                KeyEvent::ctrl('x'),
+                KeyEvent::new('a', Modifiers::NONE)
+            ]))
+        );
+        assert_eq!(
+            parse_keys("Control-a"),
+            Some(Event::from(KeyEvent::ctrl('a')))
+        );
+        assert_eq!(
+            parse_keys("Meta-Space"),
+            Some(Event::from(KeyEvent::alt(' ')))
+        );
+        assert_eq!(parse_keys("\"\\t\""), None);
+        assert_eq!(parse_keys("Hyper-a"), None);
+    }
+
+    #[test]
+    fn reads_settings_and_bindings() {
+        let inputrc = parse(
+            "# comment\n\
+             set editing-mode vi\n\
+             set bell-style none\n\
+             set history-size 50\n\
+             \"\\C-u\": unix-line-discard\n\
+             \"\\C-o\": operate-and-get-next\n",
+        );
+
+        assert_eq!(inputrc.config.edit_mode(), EditMode::Vi);
+        assert_eq!(inputrc.config.bell_style(), BellStyle::None);
+        assert_eq!(inputrc.config.max_history_size(), 50);
+        assert_eq!(
+            inputrc.bindings,
+            [(
+                Event::from(KeyEvent::ctrl('u')),
+                Cmd::Kill(Movement::BeginningOfLine)
+            )]
+        );
+    }
+
+    #[test]
+    fn skips_conditional_blocks_not_applying() {
+        let inputrc = parse(
+            "$if Bash\n\
+             set editing-mode vi\n\
+             $else\n\
+             set bell-style none\n\
+             $endif\n\
+             $if moonraker-cli\n\
+             \"\\C-t\": transpose-chars\n\
+             $endif\n\
+             $if mode=vi\n\
+             \"\\C-y\": yank\n\
+             $endif\n",
+        );
+
+        assert_eq!(inputrc.config.edit_mode(), EditMode::Emacs);
+        assert_eq!(inputrc.config.bell_style(), BellStyle::None);
+        assert_eq!(
+            inputrc.bindings,
+            [(Event::from(KeyEvent::ctrl('t')), Cmd::TransposeChars)]
+        );
+    }
+}
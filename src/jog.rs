@@ -0,0 +1,281 @@
+use crate::keepout::KeepOut;
+use crate::popup;
+use crate::units::Units;
+use crate::{rpc_result, Error, JSON};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// Feed rate of jog moves on X and Y, in mm/min.
+const XY_FEED_RATE: f64 = 6000.0;
+
+/// Feed rate of jog moves on Z, in mm/min.
+const Z_FEED_RATE: f64 = 600.0;
+
+/// The steps 1 to 4 pick on the jog pad, in the display unit.
+pub const PAD_STEPS: [f64; 4] = [0.1, 1.0, 10.0, 100.0];
+
+/// The step the jog pad starts with.
+const DEFAULT_PAD_STEP: f64 = 1.0;
+
+/// Moves `axis` by `distance` in the display unit, stopping short of the
+/// axis limits, which Klipper derives from the `position_min` and
+/// `position_max` of the steppers in the config, and of the keep-out zones.
+/// An axis that is not homed isn't moved, the jog offers to home it and
+/// jogging it again homes it first.
+pub async fn jog(
+    client: &reqwest::Client,
+    url: &str,
+    keepout: &KeepOut,
+    homing: &Homing,
+    axis: char,
+    distance: f64,
+    units: Units,
+) -> Result<String, Error> {
+    let distance = units.to_mm(distance);
+    let name = axis.to_ascii_uppercase();
+    let offered = homing.take(url, axis);
+
+    let mut status = query_toolhead(client, url).await?;
+    let homed = status["status"]["toolhead"]["homed_axes"]
+        .as_str()
+        .unwrap_or_default();
+    let missing = unhomed(axis, homed);
+
+    if !missing.is_empty() {
+        let gcode = format!("G28 {}", missing.join(" "));
+
+        if !offered {
+            homing.offer(url, axis);
+            let (verb, pronoun) = if missing.len() == 1 {
+                ("is", "it")
+            } else {
+                ("are", "them")
+            };
+            return Ok(format!(
+                "{} {} not homed, jog {} again to home {} with {} first",
+                list(&missing),
+                verb,
+                name,
+                pronoun,
+                gcode
+            ));
+        }
+
+        let params = Some(json!({ "script": gcode }));
+        rpc_result(client, url, "printer.gcode.script", params).await?;
+        status = query_toolhead(client, url).await?;
+    }
+
+    let toolhead = &status["status"]["toolhead"];
+
+    // Positions are [x, y, z, e] arrays
+    let index = match axis {
+        'x' => 0,
+        'y' => 1,
+        _ => 2,
+    };
+    let coordinate = |key: &str| toolhead[key][index].as_f64();
+
+    let (Some(position), Some(min), Some(max)) = (
+        coordinate("position"),
+        coordinate("axis_minimum"),
+        coordinate("axis_maximum"),
+    ) else {
+        return Err(Error::Rpc("toolhead position unavailable".to_string()));
+    };
+
+    let mut target = (position + distance).clamp(min, max);
+
+    if axis != 'z' {
+        let xy = |index: usize| toolhead["position"][index].as_f64().unwrap_or_default();
+        let from = (xy(0), xy(1));
+        let to = if axis == 'x' {
+            (target, from.1)
+        } else {
+            (from.0, target)
+        };
+        let limited = keepout.limit(from, to);
+        target = if axis == 'x' { limited.0 } else { limited.1 };
+    }
+
+    let clamped = target - position;
+
+    if clamped.abs() < f64::EPSILON {
+        return Ok(format!(
+            "{} is already at a limit, {}",
+            name,
+            units.format_length(target)
+        ));
+    }
+
+    let feed_rate = if axis == 'z' {
+        Z_FEED_RATE
+    } else {
+        XY_FEED_RATE
+    };
+
+    // Relative moves are independent of G-code offsets, the saved state
+    // keeps whatever positioning mode the user had
+    let script = format!(
+        "SAVE_GCODE_STATE NAME=moonraker_cli_jog\nG91\nG1 {}{:.3} F{:.0}\nRESTORE_GCODE_STATE NAME=moonraker_cli_jog",
+        name,
+        clamped,
+        feed_rate
+    );
+    rpc_result(
+        client,
+        url,
+        "printer.gcode.script",
+        Some(json!({ "script": script })),
+    )
+    .await?;
+
+    if (clamped - distance).abs() > f64::EPSILON {
+        Ok(format!(
+            "{} at {}, stopped at the axis limit or a keep-out zone",
+            name,
+            units.format_length(target)
+        ))
+    } else {
+        Ok(format!("{} at {}", name, units.format_length(target)))
+    }
+}
+
+async fn query_toolhead(client: &reqwest::Client, url: &str) -> Result<JSON, Error> {
+    let params = Some(json!({
+        "objects": { "toolhead": ["homed_axes", "position", "axis_minimum", "axis_maximum"] }
+    }));
+    rpc_result(client, url, "printer.objects.query", params).await
+}
+
+/// The axes to home before jogging `axis`, uppercase. Homing Z takes X and
+/// Y homed too, `safe_z_home` refuses otherwise.
+fn unhomed(axis: char, homed: &str) -> Vec<String> {
+    let needed = if axis == 'z' {
+        "xyz"
+    } else {
+        &axis.to_string()
+    };
+
+    needed
+        .chars()
+        .filter(|needed| !homed.contains(*needed))
+        .map(|axis| axis.to_ascii_uppercase().to_string())
+        .collect()
+}
+
+/// Like `X`, `X and Z` or `X, Y and Z`.
+fn list(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// The axis a jog offered to home, on the printer at `url`. Any jog takes
+/// the offer back, jogging the same axis right after accepts it.
+#[derive(Clone, Default)]
+pub struct Homing(Arc<Mutex<Option<(String, char)>>>);
+
+impl Homing {
+    fn offer(&self, url: &str, axis: char) {
+        *self.0.lock().unwrap() = Some((url.to_string(), axis));
+    }
+
+    /// Whether homing was offered for `axis` on `url`, clearing the offer.
+    fn take(&self, url: &str, axis: char) -> bool {
+        self.0.lock().unwrap().take() == Some((url.to_string(), axis))
+    }
+}
+
+/// Parses `<axis> <distance>`, like `x 10` or `z -0.1`.
+pub fn parse(args: &str) -> Option<(char, f64)> {
+    let (axis, distance) = args.split_once(char::is_whitespace)?;
+    let axis = match axis.to_ascii_lowercase().as_str() {
+        "x" => 'x',
+        "y" => 'y',
+        "z" => 'z',
+        _ => return None,
+    };
+    let distance = distance.trim().parse::<f64>().ok()?;
+
+    distance.is_finite().then_some((axis, distance))
+}
+
+/// The jog pad, toggled with `:jog` or Alt-J. While it's on the arrows jog
+/// X and Y and Page Up and Page Down jog Z by its step.
+#[derive(Clone, Default)]
+pub struct Pad(Arc<Mutex<PadState>>);
+
+#[derive(Default)]
+struct PadState {
+    on: bool,
+    /// Kept when the pad is off, in the display unit
+    step: Option<f64>,
+}
+
+impl Pad {
+    /// The step when the pad is on.
+    pub fn active(&self) -> Option<f64> {
+        let state = self.0.lock().unwrap();
+        state.on.then(|| state.step.unwrap_or(DEFAULT_PAD_STEP))
+    }
+
+    /// Turns the pad on or off, answering whether it's on.
+    pub fn toggle(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.on = !state.on;
+        state.on
+    }
+
+    /// Turns the pad off, answering whether it was on.
+    pub fn leave(&self) -> bool {
+        std::mem::take(&mut self.0.lock().unwrap().on)
+    }
+
+    /// Turns the pad on with `step`.
+    pub fn set_step(&self, step: f64) {
+        let mut state = self.0.lock().unwrap();
+        state.on = true;
+        state.step = Some(step);
+    }
+
+    /// The keys of the pad, shown when it turns on.
+    pub fn panel(&self, units: Units) -> String {
+        let step = self.active().unwrap_or(DEFAULT_PAD_STEP);
+        let steps: Vec<String> = PAD_STEPS
+            .iter()
+            .map(|pad_step| {
+                if *pad_step == step {
+                    format!("[{}]", pad_step)
+                } else {
+                    pad_step.to_string()
+                }
+            })
+            .collect();
+        let lines = [
+            "Left, Right       X -/+".to_string(),
+            "Down, Up          Y -/+".to_string(),
+            "Page Down, Up     Z -/+".to_string(),
+            format!("1 to 4            step {}", steps.join(" ")),
+            "Esc, Alt-J        leave".to_string(),
+            String::new(),
+            format!(
+                "Jogging by {}, twice on an unhomed axis homes it",
+                units.format_length(units.to_mm(step))
+            ),
+        ];
+
+        popup::render("Jog pad", &lines)
+    }
+
+    /// Like `jog ±1.00 mm`, for the status bar while the pad is on.
+    pub fn render(&self, units: Units) -> Option<String> {
+        let step = self.active()?;
+        Some(format!(
+            "jog ±{}, 1-4 step, Esc leaves",
+            units.format_length(units.to_mm(step))
+        ))
+    }
+}
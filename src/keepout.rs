@@ -0,0 +1,310 @@
+use crate::config::config_dir;
+use crate::units::Units;
+use crate::{rpc_result, Error};
+use serde_json::json;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+/// Jogs stop this far from a zone edge, in millimeters.
+const MARGIN: f64 = 0.5;
+
+/// A rectangle on the XY plane the toolhead must stay out of, like the
+/// area around a fixed probe dock.
+#[derive(Clone, Copy)]
+pub struct Zone {
+    x_min: f64,
+    y_min: f64,
+    x_max: f64,
+    y_max: f64,
+}
+
+impl Zone {
+    /// Parses two opposite corners, `<x1> <y1> <x2> <y2>`, in whatever unit
+    /// they're written in.
+    pub fn parse(args: &str) -> Option<Zone> {
+        let coordinates: Vec<f64> = args
+            .split_whitespace()
+            .map(|value| value.parse::<f64>().ok().filter(|value| value.is_finite()))
+            .collect::<Option<_>>()?;
+
+        let [x1, y1, x2, y2] = coordinates[..] else {
+            return None;
+        };
+
+        Some(Zone {
+            x_min: x1.min(x2),
+            y_min: y1.min(y2),
+            x_max: x1.max(x2),
+            y_max: y1.max(y2),
+        })
+    }
+
+    /// The zone typed in the display unit, in millimeters.
+    pub fn to_mm(self, units: Units) -> Zone {
+        Zone {
+            x_min: units.to_mm(self.x_min),
+            y_min: units.to_mm(self.y_min),
+            x_max: units.to_mm(self.x_max),
+            y_max: units.to_mm(self.y_max),
+        }
+    }
+
+    /// Like `X 0.00..30.00 Y 200.00..220.00 mm`, in the display unit.
+    pub fn format(&self, units: Units) -> String {
+        format!(
+            "X {}..{} Y {}..{} {}",
+            units.format_length_value(self.x_min),
+            units.format_length_value(self.x_max),
+            units.format_length_value(self.y_min),
+            units.format_length_value(self.y_max),
+            units.length_symbol()
+        )
+    }
+
+    fn contains(&self, (x, y): (f64, f64)) -> bool {
+        (self.x_min..=self.x_max).contains(&x) && (self.y_min..=self.y_max).contains(&y)
+    }
+
+    /// Where along the move `from` -> `to` it enters the zone, from 0 to 1,
+    /// see https://en.wikipedia.org/wiki/Liang%E2%80%93Barsky_algorithm
+    fn entry(&self, from: (f64, f64), to: (f64, f64)) -> Option<f64> {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let (mut enter, mut exit) = (0.0_f64, 1.0_f64);
+
+        let edges = [
+            (-dx, from.0 - self.x_min),
+            (dx, self.x_max - from.0),
+            (-dy, from.1 - self.y_min),
+            (dy, self.y_max - from.1),
+        ];
+
+        for (p, q) in edges {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                enter = enter.max(q / p);
+            } else {
+                exit = exit.min(q / p);
+            }
+        }
+
+        (enter <= exit).then_some(enter)
+    }
+}
+
+/// Keep-out zones, in millimeters, read at startup from the `keepout` file
+/// in the config directory, one `<x1> <y1> <x2> <y2>` zone in millimeters
+/// per line, and changed with `:keepout` for the session.
+#[derive(Clone, Default)]
+pub struct KeepOut(Arc<Mutex<Vec<Zone>>>);
+
+impl KeepOut {
+    pub fn load() -> KeepOut {
+        let zones = config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join("keepout")).ok())
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .filter_map(Zone::parse)
+            .collect();
+
+        KeepOut(Arc::new(Mutex::new(zones)))
+    }
+
+    pub fn add(&self, zone: Zone) {
+        self.0.lock().unwrap().push(zone);
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    pub fn zones(&self) -> Vec<Zone> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// How far towards `to` a jog from `from` may go, stopping short of the
+    /// first zone on the way. Moves starting inside a zone are let through,
+    /// so the toolhead can leave it.
+    pub fn limit(&self, from: (f64, f64), to: (f64, f64)) -> (f64, f64) {
+        let length = (to.0 - from.0).hypot(to.1 - from.1);
+        let zones = self.0.lock().unwrap();
+
+        let entry = zones
+            .iter()
+            .filter(|zone| !zone.contains(from))
+            .filter_map(|zone| zone.entry(from, to))
+            .fold(None, |first: Option<f64>, entry| {
+                Some(first.map_or(entry, |first| first.min(entry)))
+            });
+
+        match entry {
+            Some(entry) if length > 0.0 => {
+                let t = (entry - MARGIN / length).max(0.0);
+                (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+            }
+            _ => to,
+        }
+    }
+
+    fn crossed(&self, from: (f64, f64), to: (f64, f64)) -> Option<Zone> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|zone| zone.entry(from, to).is_some())
+            .copied()
+    }
+
+    /// A warning if the `G0`/`G1` moves in `script` would cross a zone,
+    /// followed from the current G-code position. Stops looking at the first
+    /// command it can't follow, like homing.
+    pub async fn check_gcode(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        script: &str,
+        units: Units,
+    ) -> Result<Option<String>, Error> {
+        let moves: Vec<&str> = script
+            .lines()
+            .map(|line| line.split(';').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        let has_moves = moves.iter().any(|line| is_move(line));
+
+        if self.0.lock().unwrap().is_empty() || !has_moves {
+            return Ok(None);
+        }
+
+        let params = Some(json!({
+            "objects": { "gcode_move": ["gcode_position", "homing_origin", "absolute_coordinates"] }
+        }));
+        let status = rpc_result(client, url, "printer.objects.query", params).await?;
+        let gcode_move = &status["status"]["gcode_move"];
+        let coordinate = |key: &str, index: usize| gcode_move[key][index].as_f64();
+
+        let (Some(x), Some(y), Some(x_origin), Some(y_origin)) = (
+            coordinate("gcode_position", 0),
+            coordinate("gcode_position", 1),
+            coordinate("homing_origin", 0),
+            coordinate("homing_origin", 1),
+        ) else {
+            return Ok(None);
+        };
+
+        let mut absolute = gcode_move["absolute_coordinates"].as_bool().unwrap_or(true);
+        let mut position = (x + x_origin, y + y_origin);
+
+        for line in moves {
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or_default().to_uppercase();
+
+            match command.as_str() {
+                "G90" => absolute = true,
+                "G91" => absolute = false,
+                "G0" | "G1" => {
+                    let mut target = position;
+
+                    for word in words {
+                        let mut chars = word.chars();
+                        let axis = chars.next().map(|axis| axis.to_ascii_uppercase());
+                        let Ok(value) = chars.as_str().parse::<f64>() else {
+                            continue;
+                        };
+
+                        match (axis, absolute) {
+                            (Some('X'), true) => target.0 = value + x_origin,
+                            (Some('X'), false) => target.0 += value,
+                            (Some('Y'), true) => target.1 = value + y_origin,
+                            (Some('Y'), false) => target.1 += value,
+                            _ => {}
+                        }
+                    }
+
+                    if let Some(zone) = self.crossed(position, target) {
+                        return Ok(Some(format!(
+                            "Warning: {} crosses the keep-out zone {}",
+                            line,
+                            zone.format(units)
+                        )));
+                    }
+
+                    position = target;
+                }
+                command if command.starts_with('G') || command.starts_with("SET_") => break,
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn is_move(line: &str) -> bool {
+    let command = line.split_whitespace().next().unwrap_or_default();
+    command.eq_ignore_ascii_case("G0") || command.eq_ignore_ascii_case("G1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keepout(zone: &str) -> KeepOut {
+        let keepout = KeepOut::default();
+        keepout.add(Zone::parse(zone).unwrap());
+        keepout
+    }
+
+    fn assert_near((x, y): (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (x - expected.0).abs() < 1e-9 && (y - expected.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            (x, y),
+            expected
+        );
+    }
+
+    #[test]
+    fn parses_zones_from_any_corners() {
+        let zone = Zone::parse("30 220 0 200").unwrap();
+
+        assert_eq!(
+            (zone.x_min, zone.y_min, zone.x_max, zone.y_max),
+            (0.0, 200.0, 30.0, 220.0)
+        );
+        assert!(Zone::parse("0 0 10").is_none());
+        assert!(Zone::parse("0 0 10 x").is_none());
+        assert!(Zone::parse("0 0 10 inf").is_none());
+    }
+
+    #[test]
+    fn stops_jogs_short_of_a_zone() {
+        let keepout = keepout("50 0 60 100");
+
+        assert_near(keepout.limit((0.0, 10.0), (100.0, 10.0)), (49.5, 10.0));
+        assert_near(keepout.limit((100.0, 10.0), (0.0, 10.0)), (60.5, 10.0));
+    }
+
+    #[test]
+    fn lets_jogs_missing_or_leaving_a_zone_through() {
+        let keepout = keepout("50 0 60 100");
+
+        assert_near(keepout.limit((0.0, 10.0), (40.0, 10.0)), (40.0, 10.0));
+        assert_near(keepout.limit((0.0, 150.0), (100.0, 150.0)), (100.0, 150.0));
+        assert_near(keepout.limit((55.0, 10.0), (100.0, 10.0)), (100.0, 10.0));
+    }
+
+    #[test]
+    fn finds_moves_crossing_a_zone() {
+        let keepout = keepout("50 0 60 100");
+
+        assert!(keepout.crossed((0.0, 10.0), (100.0, 90.0)).is_some());
+        assert!(keepout.crossed((0.0, 10.0), (0.0, 90.0)).is_none());
+        assert!(is_move("g1 X10") && is_move("G0"));
+        assert!(!is_move("G10") && !is_move("M104 S0"));
+    }
+}
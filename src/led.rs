@@ -0,0 +1,272 @@
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+
+/// The LED objects of Klipper `SET_LED` drives.
+const KLIPPER_LEDS: &[&str] = &["neopixel", "dotstar", "led", "pca9533", "pca9632"];
+
+/// The colors the picker offers besides `#rrggbb`, in RGB from 0 to 1.
+const COLORS: &[(&str, [f64; 3])] = &[
+    ("white", [1.0, 1.0, 1.0]),
+    ("warm", [1.0, 0.6, 0.25]),
+    ("red", [1.0, 0.0, 0.0]),
+    ("orange", [1.0, 0.4, 0.0]),
+    ("yellow", [1.0, 1.0, 0.0]),
+    ("green", [0.0, 1.0, 0.0]),
+    ("cyan", [0.0, 1.0, 1.0]),
+    ("blue", [0.0, 0.0, 1.0]),
+    ("purple", [0.5, 0.0, 1.0]),
+    ("magenta", [1.0, 0.0, 1.0]),
+];
+
+/// What `:leds` does with the WLED strips Moonraker controls and the LEDs
+/// in the Klipper config.
+pub enum Command {
+    List,
+    /// The nth LED listed, from 1, on, off or toggled
+    Power(usize, &'static str),
+    /// A WLED preset on the nth LED
+    Preset(usize, u64),
+    /// A color on the nth LED, a Klipper one
+    Color(usize, [f64; 3]),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let n = match words.first() {
+            None => return Ok(Command::List),
+            Some(n) => n
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid LED {}, expected a number", n))?,
+        };
+
+        match words[1..] {
+            ["on"] => Ok(Command::Power(n, "on")),
+            ["off"] => Ok(Command::Power(n, "off")),
+            ["toggle"] => Ok(Command::Power(n, "toggle")),
+            ["preset", preset] => preset
+                .parse()
+                .map(|preset| Command::Preset(n, preset))
+                .map_err(|_| format!("Invalid preset {}, expected a number", preset)),
+            [color] => parse_color(color)
+                .map(|color| Command::Color(n, color))
+                .ok_or_else(|| format!("Invalid color {}, expected a name or #rrggbb", color)),
+            _ => Err(format!("Unknown :leds argument {}, try :help", args.trim())),
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Option<[f64; 3]> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)?;
+        let channel = |shift: u32| ((value >> shift) & 0xff) as f64 / 255.0;
+        return Some([channel(16), channel(8), channel(0)]);
+    }
+
+    COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(color))
+        .map(|(_, rgb)| *rgb)
+}
+
+/// A WLED strip or a Klipper LED, with what it shows.
+enum Led {
+    Wled(JSON),
+    /// The object, like `neopixel sb_leds`, and the color of its first LED
+    Klipper(String, [f64; 3]),
+}
+
+impl Led {
+    fn name(&self) -> &str {
+        match self {
+            Led::Wled(strip) => strip["strip"].as_str().unwrap_or_default(),
+            Led::Klipper(object, _) => object.split_whitespace().last().unwrap_or_default(),
+        }
+    }
+
+    fn is_on(&self) -> bool {
+        match self {
+            Led::Wled(strip) => strip["status"] == "on",
+            Led::Klipper(_, color) => color.iter().any(|channel| *channel > 0.0),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Led::Wled(strip) => {
+                let mut parts = vec![
+                    "WLED".to_string(),
+                    strip["status"].as_str().unwrap_or("unknown").to_string(),
+                ];
+
+                if let Some(preset) = strip["preset"].as_i64().filter(|preset| *preset >= 0) {
+                    parts.push(format!("preset {}", preset));
+                }
+
+                if let Some(error) = strip["error"].as_str() {
+                    parts.push(error.to_string());
+                }
+
+                parts.join(", ")
+            }
+            Led::Klipper(object, color) => {
+                let kind = object.split_whitespace().next().unwrap_or_default();
+                let state = if self.is_on() { "on" } else { "off" };
+                format!("{}, {} {}", kind, state, hex(*color))
+            }
+        }
+    }
+}
+
+pub async fn run(client: &reqwest::Client, url: &str, command: Command) -> Result<String, Error> {
+    let leds = query(client, url).await?;
+
+    if leds.is_empty() {
+        return Err(Error::Env(
+            "No LEDs, configure [wled] in Moonraker or a neopixel in Klipper".to_string(),
+        ));
+    }
+
+    match command {
+        Command::List => Ok(render(&leds)),
+        Command::Power(n, action) => match nth(&leds, n)? {
+            led @ Led::Wled(_) => {
+                let params = Some(json!({ "strip": led.name(), "action": action }));
+                rpc_result(client, url, "machine.wled.post_strip", params).await?;
+                Ok(format!("{} {}", led.name(), action))
+            }
+            led @ Led::Klipper(..) => {
+                let on = match action {
+                    "toggle" => !led.is_on(),
+                    action => action == "on",
+                };
+                let color = if on { [1.0; 3] } else { [0.0; 3] };
+                set_led(client, url, led.name(), color).await?;
+                Ok(format!("{} {}", led.name(), if on { "on" } else { "off" }))
+            }
+        },
+        Command::Preset(n, preset) => match nth(&leds, n)? {
+            led @ Led::Wled(_) => {
+                let params = Some(json!({
+                    "strip": led.name(),
+                    "action": "control",
+                    "preset": preset,
+                }));
+                rpc_result(client, url, "machine.wled.post_strip", params).await?;
+                Ok(format!("{} on preset {}", led.name(), preset))
+            }
+            led @ Led::Klipper(..) => Err(Error::Env(format!(
+                "{} has no presets, give it a color instead",
+                led.name()
+            ))),
+        },
+        Command::Color(n, color) => match nth(&leds, n)? {
+            led @ Led::Wled(_) => Err(Error::Env(format!(
+                "{} is a WLED strip, pick a preset of it with :leds {} preset <p>",
+                led.name(),
+                n
+            ))),
+            led @ Led::Klipper(..) => {
+                set_led(client, url, led.name(), color).await?;
+                Ok(format!("{} {}", led.name(), hex(color)))
+            }
+        },
+    }
+}
+
+fn nth(leds: &[Led], n: usize) -> Result<&Led, Error> {
+    n.checked_sub(1)
+        .and_then(|index| leds.get(index))
+        .ok_or_else(|| Error::Env(format!("No LED {}, expected 1 to {}", n, leds.len())))
+}
+
+async fn set_led(
+    client: &reqwest::Client,
+    url: &str,
+    name: &str,
+    [red, green, blue]: [f64; 3],
+) -> Result<(), Error> {
+    let script = format!(
+        "SET_LED LED={} RED={:.3} GREEN={:.3} BLUE={:.3}",
+        name, red, green, blue
+    );
+    rpc_result(
+        client,
+        url,
+        "printer.gcode.script",
+        Some(json!({ "script": script })),
+    )
+    .await?;
+    Ok(())
+}
+
+/// The WLED strips first, then the Klipper LEDs.
+async fn query(client: &reqwest::Client, url: &str) -> Result<Vec<Led>, Error> {
+    // Without [wled] in the Moonraker config the method is missing
+    let strips = rpc_result(client, url, "machine.wled.strips", None)
+        .await
+        .unwrap_or_default();
+    let mut leds: Vec<Led> = strips["strips"]
+        .as_object()
+        .into_iter()
+        .flat_map(|strips| strips.values().cloned())
+        .map(Led::Wled)
+        .collect();
+
+    let objects = rpc_result(client, url, "printer.objects.list", None).await?;
+    let objects: Vec<&str> = objects["objects"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(JSON::as_str)
+        .filter(|object| {
+            object
+                .split_once(' ')
+                .is_some_and(|(kind, _)| KLIPPER_LEDS.contains(&kind))
+        })
+        .collect();
+
+    if !objects.is_empty() {
+        let params = Some(json!({
+            "objects": objects
+                .iter()
+                .map(|object| (object.to_string(), json!(["color_data"])))
+                .collect::<serde_json::Map<_, _>>()
+        }));
+        let status = rpc_result(client, url, "printer.objects.query", params).await?;
+
+        leds.extend(objects.into_iter().map(|object| {
+            let first = &status["status"][object]["color_data"][0];
+            let channel = |index: usize| first[index].as_f64().unwrap_or(0.0);
+            Led::Klipper(object.to_string(), [channel(0), channel(1), channel(2)])
+        }));
+    }
+
+    Ok(leds)
+}
+
+fn hex(color: [f64; 3]) -> String {
+    let [red, green, blue] = color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{:02x}{:02x}{:02x}", red, green, blue)
+}
+
+/// The LEDs numbered, followed by the colors to pick from.
+fn render(leds: &[Led]) -> String {
+    let mut lines = popup::numbered(
+        leds.iter()
+            .map(|led| (led.name().to_string(), led.describe()))
+            .collect(),
+    );
+
+    let colors: Vec<&str> = COLORS.iter().map(|(name, _)| *name).collect();
+
+    lines.push(String::new());
+    lines.push(":leds <n> on|off|toggle".to_string());
+    lines.push(":leds <n> preset <p> for WLED strips".to_string());
+    lines.push(":leds <n> <color> for the others, #rrggbb or".to_string());
+    lines.push(format!("  {}", colors.join(" ")));
+
+    popup::render("LEDs", &lines)
+}
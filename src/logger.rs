@@ -0,0 +1,44 @@
+use crate::Error;
+use std::env;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::prelude::*;
+
+/// Starts writing the events and spans of this crate at `level` to
+/// `moonraker-cli.log` in the state directory, the terminal belongs to the
+/// console. Nothing is written with `Off`.
+pub fn init(level: LevelFilter) -> Result<(), Error> {
+    if level == LevelFilter::OFF {
+        return Ok(());
+    }
+
+    let dir = state_dir()
+        .ok_or_else(|| Error::Env("No state directory to write the log to".to_string()))?;
+    fs::create_dir_all(&dir)?;
+
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(dir.join("moonraker-cli.log"))?;
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .with_thread_names(true);
+
+    tracing_subscriber::registry()
+        .with(layer.with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), level)))
+        .try_init()
+        .map_err(|err| Error::Env(err.to_string()))
+}
+
+/// `$XDG_STATE_HOME/moonraker-cli`, defaulting to `~/.local/state/moonraker-cli`.
+fn state_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+
+    Some(base.join("moonraker-cli"))
+}
@@ -1,10 +1,109 @@
+mod alerts;
+mod api;
+mod auth;
+mod broadcast;
+mod bundle;
+mod calc;
+mod calibrate;
+mod cleanup;
+mod cli;
+mod clipboard;
+mod config;
+mod console;
+mod cost;
+mod deflate;
+mod doctor;
+mod download;
+mod endstops;
+mod exec;
+mod extrude;
+mod fan;
+mod files;
+mod filter;
+mod fuzzy;
+mod history;
+mod hooks;
+mod ics;
+mod inputrc;
+mod jog;
+mod keepout;
+mod led;
+mod logger;
+mod mesh;
+mod meta;
+mod metadata;
+mod net;
+mod notifications;
+mod objects;
+mod offline;
+mod output;
+mod pager;
+mod palette;
+mod pending;
+mod pins;
+mod plot;
+mod popup;
+mod power;
+mod precheck;
+mod preheat;
+mod prompt;
+mod queue;
+mod screensaver;
+mod screws;
+mod split;
+mod spoolman;
+mod status;
+mod statusline;
+mod summary;
+mod sync;
+mod system;
+mod template;
+mod thumbnail;
+mod tls;
+mod traffic;
+mod transcript;
+mod tune;
+mod units;
+mod upload;
+mod wait;
+mod webhook;
+
+use alerts::Alerts;
+use auth::Auth;
+use cli::{Cli, Command, OutputFormat, PrinterCommand};
+use clipboard::Backend;
+use config::Config;
+use exec::Batch;
+use filter::Filter;
+use keepout::KeepOut;
+use meta::Mode;
+use notifications::LocalActions;
+use offline::OfflineQueue;
+use pager::Pager;
+use pending::Pending;
+use prompt::Prompts;
+use screensaver::Screensaver;
 use serde::Serialize;
 use serde_json::json;
-use std::env;
-use std::io::{self, IsTerminal, Write};
+use status::{Density, Status};
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thumbnail::Graphics;
+use tls::Tls;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tracing::Instrument;
+use traffic::Traffic;
 use uuid::Uuid;
+use webhook::Webhooks;
 
+#[allow(clippy::upper_case_acronyms)]
 type JSON = serde_json::value::Value;
 
 #[allow(unused)]
@@ -13,9 +112,49 @@ enum Error {
     Request(reqwest::Error),
     Serde(serde_json::Error),
     JoinError(tokio::task::JoinError),
-    SendError(mpsc::error::SendError<String>),
+    Channel(String),
     IO(io::Error),
     Env(String),
+    Rpc(String),
+    /// Klippy is not ready to run commands, with its state
+    Klippy(String),
+    Timeout(Duration),
+    Readline(rustyline::error::ReadlineError),
+    Websocket(tokio_tungstenite::tungstenite::Error),
+    Tls(native_tls::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Request(err) => write!(f, "{}", err),
+            Error::Serde(err) => write!(f, "{}", err),
+            Error::JoinError(err) => write!(f, "{}", err),
+            Error::Channel(err) => write!(f, "{}", err),
+            Error::IO(err) => write!(f, "{}", err),
+            Error::Env(err) => write!(f, "{}", err),
+            Error::Rpc(err) => write!(f, "{}", err),
+            Error::Klippy(state) => write!(f, "Klippy is {}", state),
+            Error::Timeout(timeout) => write!(f, "Timed out after {:?}", timeout),
+            Error::Readline(err) => write!(f, "{}", err),
+            Error::Websocket(err) => write!(f, "{}", err),
+            Error::Tls(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error {
+    /// The process exit code, distinct for each kind of failure scripts
+    /// may want to handle. Usage errors exit with 2, from clap.
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::Request(_) | Error::Websocket(_) => ExitCode::from(3),
+            Error::Rpc(_) => ExitCode::from(4),
+            Error::Klippy(_) => ExitCode::from(5),
+            Error::Timeout(_) => ExitCode::from(6),
+            _ => ExitCode::FAILURE,
+        }
+    }
 }
 
 impl From<reqwest::Error> for Error {
@@ -24,18 +163,55 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Serde(err)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
         Error::IO(err)
     }
 }
 
-impl From<mpsc::error::SendError<String>> for Error {
-    fn from(err: mpsc::error::SendError<String>) -> Self {
-        Error::SendError(err)
+impl From<rustyline::error::ReadlineError> for Error {
+    fn from(err: rustyline::error::ReadlineError) -> Self {
+        Error::Readline(err)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::Websocket(err)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Self {
+        Error::Tls(err)
+    }
+}
+
+impl<T> From<mpsc::error::SendError<T>> for Error {
+    fn from(err: mpsc::error::SendError<T>) -> Self {
+        Error::Channel(err.to_string())
     }
 }
 
+/// Clock difference, in seconds, above which the printer host is reported as out of sync.
+const MAX_CLOCK_DRIFT: f64 = 5.0;
+
+/// How long printer requests may take before they're reported as timed out,
+/// generous enough for `M190` waiting on a cold bed.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How many times a print start is attempted while Klippy is starting up.
+const PRINT_START_ATTEMPTS: u32 = 5;
+
+/// Delay before retrying a print start, doubled after each attempt.
+const PRINT_START_BACKOFF: Duration = Duration::from_secs(2);
+
 #[derive(Serialize)]
 struct MoonrakerRPC<'a> {
     jsonrpc: &'a str,
@@ -46,77 +222,1560 @@ struct MoonrakerRPC<'a> {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            err.exit_code()
+        }
+    }
+}
+
+async fn run() -> Result<(), Error> {
+    let cli = Cli::parse_args();
+    logger::init(cli.log_level.into())?;
+
+    let command = match &cli.command {
+        // Before the config is loaded, checking it is part of the job
+        Some(Command::Doctor) => return doctor::run(&cli).await,
+        Some(Command::Printer(command)) => Some(command),
+        None => None,
+    };
+
+    let mut config = Config::load(cli.config.as_deref())?;
+    let hooks = &mut config.hooks;
+    hooks.complete = cli.on_complete.clone().or(hooks.complete.take());
+    hooks.error = cli.on_error.clone().or(hooks.error.take());
+    hooks.shutdown = cli.on_shutdown.clone().or(hooks.shutdown.take());
+
+    let tls = Tls::new(&cli, &config)?;
+    let auth = Auth::new(&cli, &config);
+    let url = startup_url(&cli, &config)?;
 
-    if !stdin.is_terminal() {
+    if cli.save_api_key {
+        auth.save_api_key(&url)?;
+    }
+
+    match command {
+        Some(PrinterCommand::Statusline {
+            format,
+            positional_url,
+        }) => {
+            let url = http_url(positional_url.as_ref().unwrap_or(&url)).map_err(Error::Env)?;
+            return statusline::run(&url, format, &tls, auth, config.units).await;
+        }
+        Some(PrinterCommand::Exec {
+            commands,
+            stdin,
+            keep_going,
+            output,
+        }) => {
+            let batch = Batch::new(*keep_going, *output);
+            return exec::run(&url, commands, *stdin, batch, &tls, auth).await;
+        }
+        Some(PrinterCommand::Status { json, output }) => {
+            let output = if *json { OutputFormat::Json } else { *output };
+            return summary::run(&url, output, &tls, auth, config.units).await;
+        }
+        Some(PrinterCommand::Wait { until, timeout }) => {
+            return wait::run(&url, *until, *timeout, &tls, auth).await;
+        }
+        Some(PrinterCommand::Api {
+            method,
+            path,
+            data,
+            output,
+        }) => {
+            return api::run(&url, method, path, data.as_deref(), *output, &tls, auth).await;
+        }
+        Some(PrinterCommand::Upload {
+            file,
+            print,
+            output,
+        }) => {
+            return upload::run(&url, file, *print, *output, &tls, auth).await;
+        }
+        Some(PrinterCommand::Download { path, dest, force }) => {
+            return download::run(&url, path, dest.as_deref(), *force, &tls, auth).await;
+        }
+        Some(PrinterCommand::Sync {
+            local,
+            remote,
+            checksum,
+            dry_run,
+        }) => {
+            return sync::run(&url, local, remote, *checksum, *dry_run, &tls, auth).await;
+        }
+        Some(PrinterCommand::Queue { command, output }) => {
+            return queue::run(&url, command.as_ref(), *output, &tls, auth).await;
+        }
+        Some(PrinterCommand::Files { command }) => {
+            return files::run(&url, command, &tls, auth).await;
+        }
+        None => {}
+    }
+
+    if !cli.no_tui && !io::stdin().is_terminal() {
         return Err::<(), Error>(Error::Env(
             "Input device must be a TTY in interactive mode".to_string(),
         ));
     }
 
-    let (io_tx, io_rx) = mpsc::channel::<String>(2);
-    let (network_tx, mut network_rx) = mpsc::channel::<String>(2);
+    let url = &url;
+    let auth = auth.for_printer(url);
+    let client = tls.client_builder()?.build()?;
 
-    let io_thread = tokio::task::spawn_blocking(move || -> Result<(), Error> {
-        loop {
-            stdout.write_all(b"> ")?;
-            stdout.flush()?;
+    if let Some(username) = auth.restore(&client, url).await {
+        println!("Logged in as {}", username);
+    }
 
-            let mut buffer = String::new();
-            stdin.read_line(&mut buffer)?;
+    // An unreachable printer is reported by the network loop, no need to fail here
+    let _ = check_clock_drift(&client, url).await;
 
-            io_tx.blocking_send(buffer)?;
+    let (io_tx, io_rx) = mpsc::channel::<(Uuid, Request)>(16);
+    let (network_tx, network_rx) = mpsc::channel::<(Uuid, String)>(16);
+    let (notice_tx, notice_rx) = mpsc::channel::<String>(16);
+    let (url_tx, url_rx) = watch::channel(url.clone());
 
-            network_rx
-                .blocking_recv()
-                .map(|resp| stdout.write_fmt(format_args!("{}\n", resp)))
-                .transpose()?;
+    let filter = match &config.filter {
+        Some(expr) => Some(Filter::parse(expr).map_err(Error::Env)?),
+        None => None,
+    };
+    let broadcast = match cli.printers {
+        Some(names) => {
+            for name in &names {
+                config.printer(name).map_err(Error::Env)?;
+            }
+            names
         }
+        None => config.printers.keys().cloned().collect(),
+    };
+    let mode = match &config.mode {
+        Some(mode) => Mode::parse(mode).map_err(Error::Env)?,
+        None => Mode::Gcode,
+    };
+    let clipboard = match &config.clipboard {
+        Some(backend) => Backend::parse(backend).map_err(Error::Env)?,
+        None => Backend::Auto,
+    };
+    let graphics = match &config.graphics {
+        Some(graphics) => Graphics::parse(graphics).map_err(Error::Env)?,
+        None => Graphics::Auto,
+    };
+    let density = match &config.density {
+        Some(density) => Density::parse(density).map_err(Error::Env)?,
+        None => Density::Compact,
+    };
+
+    let shared = SharedState {
+        tls,
+        auth,
+        keepout: KeepOut::load(),
+        mode,
+        density: Arc::new(Mutex::new(density)),
+        clipboard,
+        graphics,
+        plain: cli.no_tui,
+        printer: cli.printer,
+        broadcast,
+        config: Arc::new(config),
+        ..SharedState::default()
+    };
+    shared.screensaver.set_timeout(
+        shared
+            .config
+            .screensaver
+            .filter(|minutes| *minutes > 0.0 && !shared.plain)
+            .map(|minutes| Duration::from_secs_f64(minutes * 60.0)),
+    );
+    if let Some(minutes) = shared
+        .config
+        .alert_interval
+        .filter(|minutes| *minutes > 0.0)
+    {
+        shared
+            .alerts
+            .set_interval(Duration::from_secs_f64(minutes * 60.0));
+    }
+    shared.status.set_url(url);
+
+    if !shared.plain {
+        status::make_room()?;
+    }
+
+    let console_shared = shared.clone();
+    let console_notice_tx = notice_tx.clone();
+
+    let io_thread = tokio::task::spawn_blocking(move || {
+        console::run(
+            io_tx,
+            network_rx,
+            console_notice_tx,
+            notice_rx,
+            console_shared,
+        )
     });
 
-    let args: Vec<String> = env::args().collect();
-    let default_url = "http://localhost:7125".to_string();
-    let url = args.get(1).unwrap_or(&default_url);
+    let bell = alerts::run(shared.alerts.clone(), notice_tx.clone());
+    let status_bar = status::run(shared.clone());
+    let screensaver = screensaver::run(shared.screensaver.clone());
+    let notifications =
+        notifications::run(client.clone(), url_rx, notice_tx.clone(), shared.clone());
+    let login_refresh = auth::run(client.clone(), notice_tx.clone());
+    let network = network_loop(client, url_tx, network_tx, notice_tx, io_rx, filter, shared);
 
     tokio::select! {
         io_res = io_thread =>  { io_res.map_err(Error::JoinError).and_then(|res| res) }
-        network_res = network_loop(url, network_tx, io_rx) => { network_res }
+        network_res = network => { network_res }
+        notifications_res = notifications => { notifications_res }
+        bell_res = bell => { bell_res }
+        status_res = status_bar => { status_res }
+        screensaver_res = screensaver => { screensaver_res }
+        login_res = login_refresh => { login_res }
+    }
+}
+
+/// Handles shared by the console, the network loop and the notification
+/// listener, cloning is cheap.
+#[derive(Clone, Default)]
+struct SharedState {
+    /// Printer macros discovered by the network loop, offered by the command palette
+    macros: Arc<Mutex<Vec<String>>>,
+    /// The G-code files, for the finder to complete
+    files: files::Index,
+    local_actions: LocalActions,
+    alerts: Alerts,
+    webhooks: Webhooks,
+    status: Status,
+    screensaver: Screensaver,
+    pending: Pending,
+    offline_queue: OfflineQueue,
+    /// Status of the printer followed with `:watch`, if any
+    watched: Arc<Mutex<Option<Status>>>,
+    tls: Tls,
+    auth: Auth,
+    /// Bytes the websocket moved this session, not the HTTP requests
+    traffic: Traffic,
+    /// The `:upload` or `:files` download going on, for the status bar
+    transfer: upload::Progress,
+    keepout: KeepOut,
+    jog_pad: jog::Pad,
+    /// The axes a refused jog offered to home
+    homing: jog::Homing,
+    /// The directory `:files` browses, with the pane Alt-O toggles
+    browser: files::Browser,
+    /// The watched printer beside the console, with `:split`
+    split: split::Split,
+    /// The power device `:power <n> off-after` turns off after the print
+    power_off: power::PowerOff,
+    /// The dialog macros opened with action prompts
+    prompts: Prompts,
+    density: Arc<Mutex<Density>>,
+    pager: Pager,
+    clipboard: Backend,
+    graphics: Graphics,
+    /// Thumbnails waiting for their reply to be printed
+    images: thumbnail::Images,
+    /// `--no-tui`, a line-oriented console without the status bar
+    plain: bool,
+    /// How input is sent at startup
+    mode: Mode,
+    /// The printer from the config connected at startup, if any
+    printer: Option<String>,
+    /// The printers from the config `:all` sends to
+    broadcast: Vec<String>,
+    config: Arc<Config>,
+}
+
+/// What `:pause`, `:resume`, `:cancel` and their keys do to the print.
+#[derive(Clone, Copy)]
+enum Control {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+impl Control {
+    fn method(self) -> &'static str {
+        match self {
+            Control::Pause => "printer.print.pause",
+            Control::Resume => "printer.print.resume",
+            Control::Cancel => "printer.print.cancel",
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Control::Pause => "pause",
+            Control::Resume => "resume",
+            Control::Cancel => "cancel",
+        }
     }
+
+    fn done(self) -> &'static str {
+        match self {
+            Control::Pause => "paused",
+            Control::Resume => "resumed",
+            Control::Cancel => "cancelled",
+        }
+    }
+
+    /// Whether it applies to a print in `state`, paused prints resume and
+    /// printing ones pause.
+    fn applies(self, state: &str) -> bool {
+        match self {
+            Control::Pause => state == "printing",
+            Control::Resume => state == "paused",
+            Control::Cancel => state == "printing" || state == "paused",
+        }
+    }
+}
+
+/// Work the console delegates to the network loop, each request is sent
+/// with an id and answered with exactly one message carrying the same id.
+enum Request {
+    Gcode(String),
+    Connect(String),
+    Filter(Option<Filter>),
+    ExportQueue(String),
+    Rpc(String, Option<JSON>),
+    /// Carries the console transcript, which only the console has
+    DebugBundle(String),
+    Timeout(Option<Duration>),
+    Watch(Option<String>),
+    Swap,
+    Compare(String, String),
+    Plot(String, usize),
+    Jog(char, f64),
+    /// In mm, retracting when negative, heating first if asked
+    Extrude(f64, bool),
+    Check(String),
+    /// Starts a print, unless the file fails the checks and it's not forced
+    Print(String, bool),
+    Control(Control),
+    Objects(objects::Command),
+    Calibrate(calibrate::Command),
+    Preheat(preheat::Command),
+    Fans(fan::Command),
+    Leds(led::Command),
+    Power(power::Command),
+    Pins(pins::Command),
+    Endstops,
+    /// The loaded bed mesh, or a saved profile
+    Mesh(Option<String>),
+    Screws(screws::Command),
+    Tune(tune::Factor, tune::Adjust),
+    /// Username and password
+    Login(String, String),
+    Logout,
+    Cleanup(cleanup::Command),
+    Files(files::Command),
+    Queue(queue::Command),
+    History(history::Command),
+    /// Print totals, broken down per month
+    Stats(bool),
+    Cost,
+    Spool(spoolman::Command),
+    Upload(String, bool),
+    /// Shows the host OS state, or upgrades its packages
+    System(system::Command),
+    /// Switches to a printer from the config
+    Printer(String),
+    /// G-code for every printer `:all` sends to
+    All(String),
+    DebugState,
 }
 
+impl Request {
+    /// What the request does, for the log, which doesn't get the content.
+    fn kind(&self) -> &'static str {
+        match self {
+            Request::Gcode(_) => "gcode",
+            Request::Connect(_) => "connect",
+            Request::Filter(_) => "filter",
+            Request::ExportQueue(_) => "export-queue",
+            Request::Rpc(..) => "rpc",
+            Request::DebugBundle(_) => "debug-bundle",
+            Request::Timeout(_) => "timeout",
+            Request::Watch(_) => "watch",
+            Request::Swap => "swap",
+            Request::Compare(..) => "compare",
+            Request::Plot(..) => "plot",
+            Request::Jog(..) => "jog",
+            Request::Extrude(..) => "extrude",
+            Request::Check(_) => "check",
+            Request::Print(..) => "print",
+            Request::Control(_) => "control",
+            Request::Objects(_) => "objects",
+            Request::Calibrate(_) => "calibrate",
+            Request::Preheat(_) => "preheat",
+            Request::Fans(_) => "fans",
+            Request::Leds(_) => "leds",
+            Request::Power(_) => "power",
+            Request::Pins(_) => "pins",
+            Request::Endstops => "endstops",
+            Request::Mesh(_) => "mesh",
+            Request::Screws(_) => "screws",
+            Request::Tune(..) => "tune",
+            Request::Login(..) => "login",
+            Request::Logout => "logout",
+            Request::Cleanup(_) => "cleanup",
+            Request::Files(_) => "files",
+            Request::Queue(_) => "queue",
+            Request::History(_) => "history",
+            Request::Stats(_) => "stats",
+            Request::Cost => "cost",
+            Request::Spool(_) => "spool",
+            Request::Upload(..) => "upload",
+            Request::System(_) => "system",
+            Request::Printer(_) => "printer",
+            Request::All(_) => "all",
+            Request::DebugState => "debug-state",
+        }
+    }
+}
+
+/// Serves console requests against the printer, `url_tx` holds the URL
+/// of the Moonraker instance currently connected. Replies may come back
+/// in a different order than requests, matched by their id.
 async fn network_loop(
-    url: &String,
-    network_tx: Sender<String>,
-    mut io_rx: Receiver<String>,
+    client: reqwest::Client,
+    url_tx: watch::Sender<String>,
+    network_tx: Sender<(Uuid, String)>,
+    notice_tx: Sender<String>,
+    mut io_rx: Receiver<(Uuid, Request)>,
+    mut filter: Option<Filter>,
+    shared: SharedState,
 ) -> Result<(), Error> {
-    let client = reqwest::Client::new();
+    let mut url = url_tx.borrow().clone();
+    let mut watcher: Option<Watcher> = None;
+    let mut timeout = match shared.config.timeout {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => Some(DEFAULT_TIMEOUT),
+    };
+    let cleanup_candidates = cleanup::Candidates::default();
+    let browser = shared.browser.clone();
+    let jobs = history::Jobs::default();
+    let prices = shared.config.filament.clone();
+    let spools = spoolman::Spools::default();
+    let fans = fan::Fans::default();
+    let units = shared.config.units;
+
+    // What was listed or armed on one printer means nothing on another
+    let forget_printer = || {
+        cleanup_candidates.clear();
+        browser.clear();
+
+        if let Some(device) = shared.power_off.disarm() {
+            let notice = format!("{} no longer turns off after the print", device);
+            let _ = notice_tx.try_send(notice);
+        }
+    };
+
+    *shared.macros.lock().unwrap() = discover_macros(&client, &url).await;
+    shared.files.refresh(&client, &url).await;
+
+    while let Some((id, request)) = io_rx.recv().await {
+        tracing::debug!(%id, kind = request.kind(), "request");
+
+        let resp = match request {
+            Request::Gcode(script) => {
+                shared.local_actions.record_gcode(&script);
+                let pid_heater = plot::pid_calibrate_heater(&script);
+                let params = Some(json!({ "script": script.clone() }));
+                let (client, url, filter, shared) =
+                    (client.clone(), url.clone(), filter.clone(), shared.clone());
+
+                // A tuning run takes minutes, longer than any reply timeout
+                let timeout = if pid_heater.is_some() { None } else { timeout };
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    // Only a warning, the move is sent anyway
+                    let warning = shared
+                        .keepout
+                        .check_gcode(&client, &url, &script, units)
+                        .await
+                        .ok()
+                        .flatten();
+                    let sent = Instant::now();
+                    let resp =
+                        timed_call(&client, &url, id, "printer.gcode.script", params, &shared)
+                            .await
+                            .and_then(|(value, latency)| {
+                                format_response(value, filter.as_ref(), latency)
+                            })
+                            .unwrap_or_else(|err| format!("Request failed: {}", err));
+
+                    let resp = match warning {
+                        Some(warning) => format!("{}\n{}", warning, resp),
+                        None => resp,
+                    };
+
+                    let Some(heater) = pid_heater else {
+                        return resp;
+                    };
+
+                    // The tuning run is over, show how the heater responded during it
+                    let seconds = sent.elapsed().as_secs() as usize + 10;
+                    let plot = plot::temperature(&client, &url, &heater, seconds, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to plot {}: {}", heater, err));
+
+                    format!("{}\n{}", resp, plot)
+                });
+                continue;
+            }
+            Request::Jog(axis, distance) => {
+                let (client, url, keepout) = (client.clone(), url.clone(), shared.keepout.clone());
+                let homing = shared.homing.clone();
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    jog::jog(&client, &url, &keepout, &homing, axis, distance, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to jog: {}", err))
+                });
+                continue;
+            }
+            Request::Extrude(length, heat) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    extrude::extrude(&client, &url, length, heat, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to extrude: {}", err))
+                });
+                continue;
+            }
+            Request::Check(filename) => {
+                let (client, url) = (client.clone(), url.clone());
+                let profile = shared.config.profile(&url).map(str::to_string);
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    precheck::check(&client, &url, &filename, profile.as_deref(), units)
+                        .await
+                        .map(|warnings| precheck::format(&filename, &warnings))
+                        .unwrap_or_else(|err| format!("Unable to check {}: {}", filename, err))
+                });
+                continue;
+            }
+            Request::Control(control) => {
+                shared.local_actions.record_method(control.method());
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    match rpc_result(&client, &url, control.method(), None).await {
+                        Ok(_) => format!("Print {}", control.done()),
+                        Err(err) => format!("Unable to {} the print: {}", control.verb(), err),
+                    }
+                });
+                continue;
+            }
+            Request::Objects(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    objects::run(&client, &url, command, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to manage objects: {}", err))
+                });
+                continue;
+            }
+            Request::Calibrate(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    calibrate::run(&client, &url, command)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to calibrate: {}", err))
+                });
+                continue;
+            }
+            Request::Preheat(command) => {
+                let (client, url, config) = (client.clone(), url.clone(), shared.config.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    preheat::run(&client, &url, command, &config.presets, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to set the heaters: {}", err))
+                });
+                continue;
+            }
+            Request::Fans(command) => {
+                let (client, url, fans) = (client.clone(), url.clone(), fans.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    fans.run(&client, &url, command)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to set the fans: {}", err))
+                });
+                continue;
+            }
+            Request::Leds(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    led::run(&client, &url, command)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to set the LEDs: {}", err))
+                });
+                continue;
+            }
+            Request::Power(command) => {
+                let (client, url, power_off) =
+                    (client.clone(), url.clone(), shared.power_off.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    power_off
+                        .run(&client, &url, command, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to switch the power: {}", err))
+                });
+                continue;
+            }
+            Request::Pins(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    pins::run(&client, &url, command)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to set the pin: {}", err))
+                });
+                continue;
+            }
+            Request::Endstops => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    endstops::query(&client, &url)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to query the endstops: {}", err))
+                });
+                continue;
+            }
+            Request::Mesh(profile) => {
+                let (client, url, graphics) = (client.clone(), url.clone(), shared.graphics);
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    mesh::show(&client, &url, profile.as_deref(), units, graphics)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to show the bed mesh: {}", err))
+                });
+                continue;
+            }
+            Request::Screws(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    screws::run(&client, &url, command, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to measure the bed screws: {}", err))
+                });
+                continue;
+            }
+            Request::Tune(factor, adjust) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    tune::run(&client, &url, factor, adjust)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to adjust the factor: {}", err))
+                });
+                continue;
+            }
+            Request::Print(filename, force) => {
+                let (client, url, shared, notice_tx) = (
+                    client.clone(),
+                    url.clone(),
+                    shared.clone(),
+                    notice_tx.clone(),
+                );
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    start_print_retrying(&client, &url, &filename, force, &shared, &notice_tx)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to print {}: {}", filename, err))
+                });
+                continue;
+            }
+            Request::Login(username, password) => {
+                let (client, url, auth) = (client.clone(), url.clone(), shared.auth.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    auth.login(&client, &url, &username, &password)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to log in: {}", err))
+                });
+                continue;
+            }
+            Request::Logout => {
+                let (client, url, auth) = (client.clone(), url.clone(), shared.auth.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    auth.logout(&client, &url)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to log out: {}", err))
+                });
+                continue;
+            }
+            Request::Cleanup(command) => {
+                let (client, url, candidates) =
+                    (client.clone(), url.clone(), cleanup_candidates.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    candidates
+                        .run(&client, &url, command)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to clean up: {}", err))
+                });
+                continue;
+            }
+            Request::Files(command) => {
+                let (client, url, browser, shared, notice_tx) = (
+                    client.clone(),
+                    url.clone(),
+                    browser.clone(),
+                    shared.clone(),
+                    notice_tx.clone(),
+                );
+
+                // Downloads take longer than any reply timeout
+                let timeout = if command.is_download() { None } else { timeout };
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    browser
+                        .run(&client, &url, command, &shared, &notice_tx)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to browse the files: {}", err))
+                });
+                continue;
+            }
+            Request::Queue(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    queue::apply(&client, &url, &command)
+                        .await
+                        .map(|queue| queue::render(&queue))
+                        .unwrap_or_else(|err| format!("Unable to manage the queue: {}", err))
+                });
+                continue;
+            }
+            Request::History(command) => {
+                let (client, url, jobs, prices) =
+                    (client.clone(), url.clone(), jobs.clone(), prices.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    jobs.run(&client, &url, command, units, &prices)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to browse the history: {}", err))
+                });
+                continue;
+            }
+            Request::Stats(monthly) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    history::stats(&client, &url, monthly)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to get the statistics: {}", err))
+                });
+                continue;
+            }
+            Request::Cost => {
+                let (client, url, prices) = (client.clone(), url.clone(), prices.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    cost::current(&client, &url, &prices)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to estimate the cost: {}", err))
+                });
+                continue;
+            }
+            Request::Spool(command) => {
+                let (client, url, spools) = (client.clone(), url.clone(), spools.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    spools
+                        .run(&client, &url, command)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to manage spools: {}", err))
+                });
+                continue;
+            }
+            Request::Upload(path, print) => {
+                let (client, url, shared) = (client.clone(), url.clone(), shared.clone());
+
+                if print {
+                    shared.local_actions.record_method("printer.print.start");
+                }
+
+                // Large files take longer than any reply timeout
+                spawn_reply(&network_tx, id, None, async move {
+                    upload::upload(&client, &url, Path::new(&path), "", print, &shared.transfer)
+                        .await
+                        .map(|resp| upload::describe(&resp))
+                        .unwrap_or_else(|err| format!("Unable to upload {}: {}", path, err))
+                });
+                continue;
+            }
+            Request::System(command) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    command
+                        .run(&client, &url)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to query the host: {}", err))
+                });
+                continue;
+            }
+            Request::All(script) => {
+                let printers: Vec<(String, String)> = shared
+                    .broadcast
+                    .iter()
+                    .filter_map(|name| {
+                        let printer = shared.config.printer(name).ok()?;
+                        Some((name.clone(), printer.url.clone()))
+                    })
+                    .collect();
+
+                for (_, url) in &printers {
+                    shared.auth.register(url);
+                }
+
+                let (client, notice_tx) = (client.clone(), notice_tx.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    broadcast::send(&client, &printers, &script, &notice_tx).await
+                });
+                continue;
+            }
+            Request::Plot(heater, seconds) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    plot::temperature(&client, &url, &heater, seconds, units)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to plot {}: {}", heater, err))
+                });
+                continue;
+            }
+            Request::Rpc(method, params) => {
+                shared.local_actions.record_method(&method);
+                let (client, url, filter, shared) =
+                    (client.clone(), url.clone(), filter.clone(), shared.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    timed_call(&client, &url, id, &method, params, &shared)
+                        .await
+                        .and_then(|(value, latency)| {
+                            format_response(value, filter.as_ref(), latency)
+                        })
+                        .unwrap_or_else(|err| format!("{} failed: {}", method, err))
+                });
+                continue;
+            }
+            Request::ExportQueue(path) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    export_queue(&client, &url, &path)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to export queue: {}", err))
+                });
+                continue;
+            }
+            Request::Compare(first, second) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    history::compare(&client, &url, &first, &second)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to compare jobs: {}", err))
+                });
+                continue;
+            }
+            Request::DebugBundle(transcript) => {
+                let (client, url) = (client.clone(), url.clone());
+
+                spawn_reply(&network_tx, id, timeout, async move {
+                    bundle::collect(&client, &url, &transcript)
+                        .await
+                        .unwrap_or_else(|err| format!("Unable to collect debug bundle: {}", err))
+                });
+                continue;
+            }
+            // Answered in order, as they change how the following requests are served
+            Request::Connect(new_url) => match http_url(&new_url) {
+                Ok(new_url) => match connect(&client, &new_url, &shared).await {
+                    Ok(()) => {
+                        url = new_url;
+                        url_tx.send_replace(url.clone());
+                        forget_printer();
+                        format!("Connected to {}", url)
+                    }
+                    Err(err) => format!("Unable to connect to {}: {}", new_url, err),
+                },
+                Err(err) => err,
+            },
+            Request::Printer(name) => match shared.config.printer(&name) {
+                Ok(printer) => {
+                    url = printer.url.clone();
+                    url_tx.send_replace(url.clone());
+                    forget_printer();
+
+                    // Switched to anyway, commands queue until it's back
+                    match connect(&client, &url, &shared).await {
+                        Ok(()) => format!("Switched to {} at {}", name, url),
+                        Err(err) => {
+                            shared.status.set_url(&url);
+                            shared.macros.lock().unwrap().clear();
+                            format!("Switched to {} at {}, unreachable: {}", name, url, err)
+                        }
+                    }
+                }
+                Err(err) => err,
+            },
+            Request::Watch(Some(watched_url)) => match http_url(&watched_url) {
+                Ok(watched_url) => {
+                    let resp = format!("Watching {}, :swap switches to it", watched_url);
+                    // The previous watcher clears the watched status when dropped
+                    drop(watcher.take());
+                    watcher = Some(Watcher::spawn(&client, &watched_url, &notice_tx, &shared));
+                    resp
+                }
+                Err(err) => err,
+            },
+            Request::Watch(None) => match watcher.take() {
+                Some(watcher) => format!("Stopped watching {}", watcher.url),
+                None => "Not watching any printer".to_string(),
+            },
+            Request::Swap => match watcher.take() {
+                Some(watched) => match connect(&client, &watched.url, &shared).await {
+                    Ok(()) => {
+                        let previous = std::mem::replace(&mut url, watched.url.clone());
+                        drop(watched);
+                        url_tx.send_replace(url.clone());
+                        forget_printer();
+                        watcher = Some(Watcher::spawn(&client, &previous, &notice_tx, &shared));
+                        format!("Connected to {}, watching {}", url, previous)
+                    }
+                    Err(err) => {
+                        let resp = format!("Unable to connect to {}: {}", watched.url, err);
+                        watcher = Some(watched);
+                        resp
+                    }
+                },
+                None => "Not watching any printer, try :watch <url>".to_string(),
+            },
+            Request::Filter(Some(new_filter)) => {
+                let resp = format!("Filtering responses through {}", new_filter);
+                filter = Some(new_filter);
+                resp
+            }
+            Request::Filter(None) => {
+                filter = None;
+                "Filter cleared".to_string()
+            }
+            Request::DebugState => {
+                let pending: Vec<String> = shared
+                    .pending
+                    .list()
+                    .iter()
+                    .map(|(command, waiting)| {
+                        format!("{} ({:.1}s)", command, waiting.as_secs_f64())
+                    })
+                    .collect();
+
+                let state = [
+                    format!("connection: {}", shared.status.describe()),
+                    format!("websocket traffic: {}", shared.traffic.describe()),
+                    format!(
+                        "watching: {}",
+                        watcher
+                            .as_ref()
+                            .map_or("none".to_string(), |watcher| watcher.url.clone())
+                    ),
+                    format!("subscriptions: {}", notifications::subscriptions()),
+                    format!("macros: {}", shared.macros.lock().unwrap().len()),
+                    format!("pending: {}", pending.join(", ")),
+                    format!("offline queue: {}", shared.offline_queue.len()),
+                    format!(
+                        "filter: {}",
+                        filter
+                            .as_ref()
+                            .map_or("none".to_string(), ToString::to_string)
+                    ),
+                    format!("timeout: {:?}", timeout),
+                    format!(
+                        "channels: requests {}/{}, replies {}, notices {}",
+                        io_rx.len(),
+                        io_rx.max_capacity(),
+                        channel_depth(&network_tx),
+                        channel_depth(&notice_tx)
+                    ),
+                ]
+                .join("\n");
+
+                tracing::info!("state dump\n{}", state);
+                state
+            }
+            Request::Timeout(new_timeout) => {
+                timeout = new_timeout;
+
+                match timeout {
+                    Some(timeout) => {
+                        format!("Requests time out after {} seconds", timeout.as_secs())
+                    }
+                    None => "Request timeout disabled".to_string(),
+                }
+            }
+        };
+
+        network_tx.send((id, resp)).await?;
+    }
+
+    Ok(())
+}
+
+/// The Moonraker base URL from the forms people type, like
+/// `mainsailos.local`, `192.168.1.50:7125/` or `ws://voron.local/websocket`.
+/// No scheme means `http://`, `ws://` and `wss://` map to `http://` and
+/// `https://`. Anything else that can't be a base URL is refused.
+fn http_url(url: &str) -> Result<String, String> {
+    let (scheme, rest) = url.trim().split_once("://").unwrap_or(("http", url.trim()));
+    let rest = rest.trim_end_matches('/');
+    let rest = rest.strip_suffix("/websocket").unwrap_or(rest);
+    let rest = rest.trim_end_matches('/');
+
+    let normalized = match scheme.to_ascii_lowercase().as_str() {
+        "http" | "ws" => format!("http://{}", rest),
+        "https" | "wss" => format!("https://{}", rest),
+        _ => {
+            return Err(format!(
+                "Unsupported scheme {} in {}, expected http(s) or ws(s)",
+                scheme, url
+            ))
+        }
+    };
+
+    let parsed =
+        reqwest::Url::parse(&normalized).map_err(|err| format!("Invalid URL {}: {}", url, err))?;
+
+    if parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(format!(
+            "Invalid URL {}, expected no query or fragment",
+            url
+        ));
+    }
+
+    Ok(normalized)
+}
+
+/// Like `start_print`, but retries with exponential backoff while Klippy
+/// is starting up, as after a firmware restart, instead of failing.
+async fn start_print_retrying(
+    client: &reqwest::Client,
+    url: &str,
+    filename: &str,
+    force: bool,
+    shared: &SharedState,
+    notice_tx: &Sender<String>,
+) -> Result<String, Error> {
+    let mut delay = PRINT_START_BACKOFF;
+    let mut attempt = 1;
 
     loop {
-        let input = io_rx.recv().await;
+        match start_print(client, url, filename, force, shared).await {
+            Err(_) if attempt < PRINT_START_ATTEMPTS && klippy_starting(client, url).await => {
+                let notice = format!(
+                    "Klippy is starting up, retrying {} in {} seconds ({}/{})",
+                    filename,
+                    delay.as_secs(),
+                    attempt,
+                    PRINT_START_ATTEMPTS - 1
+                );
+                notice_tx.send(notice).await?;
+
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            resp => return resp,
+        }
+    }
+}
+
+async fn klippy_starting(client: &reqwest::Client, url: &str) -> bool {
+    rpc_result(client, url, "server.info", None)
+        .await
+        .is_ok_and(|info| info["klippy_state"] == "startup")
+}
+
+/// Fails with `Error::Klippy` unless Klippy is ready to run commands.
+async fn klippy_ready(client: &reqwest::Client, url: &str) -> Result<(), Error> {
+    let info = rpc_result(client, url, "server.info", None).await?;
+
+    match info["klippy_state"].as_str() {
+        Some("ready") => Ok(()),
+        state => Err(Error::Klippy(state.unwrap_or("unknown").to_string())),
+    }
+}
+
+/// Starts printing `filename` if it passes the pre-print checks, or anyway
+/// when forced, showing the warnings and what the slicer says about it.
+async fn start_print(
+    client: &reqwest::Client,
+    url: &str,
+    filename: &str,
+    force: bool,
+    shared: &SharedState,
+) -> Result<String, Error> {
+    let profile = shared.config.profile(url);
+    let warnings = precheck::check(client, url, filename, profile, shared.config.units).await?;
+
+    if !warnings.is_empty() && !force {
+        return Ok(format!(
+            "{}\nNot started, :print --force {} starts it anyway",
+            precheck::format(filename, &warnings),
+            filename
+        ));
+    }
+
+    shared.local_actions.record_method("printer.print.start");
+    let params = Some(json!({ "filename": filename }));
+    rpc_result(client, url, "printer.print.start", params).await?;
+
+    // The print started already, the details are not worth failing over
+    let details = metadata::fetch(client, url, filename)
+        .await
+        .map(|metadata| metadata::lines(&metadata, shared.config.units))
+        .unwrap_or_default()
+        .iter()
+        .map(|line| format!("\n  {}", line))
+        .collect::<String>();
 
-        let req = MoonrakerRPC {
-            jsonrpc: "2.0",
-            id: uuid::Uuid::new_v4(),
-            method: "printer.gcode.script",
-            params: Some(json!({ "script": input })),
+    if warnings.is_empty() {
+        Ok(format!("Printing {}{}", filename, details))
+    } else {
+        Ok(format!(
+            "{}\nPrinting {} anyway{}",
+            precheck::format(filename, &warnings),
+            filename,
+            details
+        ))
+    }
+}
+
+/// Messages queued in a channel over its capacity.
+fn channel_depth<T>(tx: &Sender<T>) -> String {
+    format!(
+        "{}/{}",
+        tx.max_capacity() - tx.capacity(),
+        tx.max_capacity()
+    )
+}
+
+/// Makes `url` the printer console requests go to, if it answers.
+async fn connect(client: &reqwest::Client, url: &str, shared: &SharedState) -> Result<(), Error> {
+    shared.auth.register(url);
+    rpc_call(client, url, "server.info", None).await?;
+
+    let _ = check_clock_drift(client, url).await;
+    *shared.macros.lock().unwrap() = discover_macros(client, url).await;
+    shared.files.refresh(client, url).await;
+    shared.status.set_url(url);
+
+    Ok(())
+}
+
+/// Follows a second printer next to the connected one, for the notices
+/// only: they're prefixed with its host, or shown in the `:split` pane, and
+/// its errors ring the bell too. Dropping it stops following.
+struct Watcher {
+    url: String,
+    task: tokio::task::JoinHandle<()>,
+    watched: Arc<Mutex<Option<Status>>>,
+}
+
+impl Watcher {
+    fn spawn(
+        client: &reqwest::Client,
+        url: &str,
+        notice_tx: &Sender<String>,
+        shared: &SharedState,
+    ) -> Watcher {
+        let status = Status::default();
+        status.set_url(url);
+        *shared.watched.lock().unwrap() = Some(status.clone());
+        shared.auth.register(url);
+        shared.split.clear();
+
+        let watched_shared = SharedState {
+            status,
+            alerts: shared.alerts.clone(),
+            tls: shared.tls.clone(),
+            auth: shared.auth.clone(),
+            ..SharedState::default()
         };
+        let (client, notice_tx, split) = (client.clone(), notice_tx.clone(), shared.split.clone());
+        let (url_tx, url_rx) = watch::channel(url.to_string());
+        let (watched_tx, mut watched_rx) = mpsc::channel::<String>(16);
+        let host = status::host(url).to_string();
 
-        let resp = client
-            .post(format!("{}/server/jsonrpc", url))
-            .json(&req)
-            .send()
-            .await?
-            .json::<JSON>()
-            .await
-            .map_err(Error::Request)
-            .and_then(format_json)?;
+        let span = tracing::info_span!("watch", url);
+        let task = tokio::spawn(
+            async move {
+                let _url_tx = url_tx;
+                let forward = async {
+                    while let Some(notice) = watched_rx.recv().await {
+                        if split.push(&notice) {
+                            continue;
+                        }
+
+                        if notice_tx
+                            .send(format!("[{}] {}", host, notice))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                };
+
+                let _ = tokio::join!(
+                    notifications::run(client, url_rx, watched_tx, watched_shared),
+                    forward
+                );
+            }
+            .instrument(span),
+        );
+
+        Watcher {
+            url: url.to_string(),
+            task,
+            watched: shared.watched.clone(),
+        }
+    }
+}
 
-        network_tx.send(resp).await?;
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.task.abort();
+        *self.watched.lock().unwrap() = None;
     }
 }
 
+/// Answers request `id` from its own task, so a slow command like `G28`
+/// doesn't hold back the quick queries sent after it.
+fn spawn_reply<F>(network_tx: &Sender<(Uuid, String)>, id: Uuid, timeout: Option<Duration>, resp: F)
+where
+    F: Future<Output = String> + Send + 'static,
+{
+    let network_tx = network_tx.clone();
+    let span = tracing::info_span!("reply", %id);
+
+    tokio::spawn(
+        async move {
+            let resp = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, resp)
+                    .await
+                    .unwrap_or_else(|_| {
+                        tracing::warn!(?timeout, "timed out");
+                        format!(
+                            "Timed out after {} seconds, the printer may still run it",
+                            timeout.as_secs()
+                        )
+                    }),
+                None => resp.await,
+            };
+
+            let _ = network_tx.send((id, resp)).await;
+        }
+        .instrument(span),
+    );
+}
+
+async fn rpc_call(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    params: Option<JSON>,
+) -> Result<JSON, Error> {
+    rpc_call_with_id(client, url, Uuid::new_v4(), method, params).await
+}
+
+/// The printer to connect to, given with `--url` or `--printer`, or else
+/// the one in the config, or else a local Moonraker.
+fn startup_url(cli: &Cli, config: &Config) -> Result<String, Error> {
+    let url = match (cli.url(), &cli.printer) {
+        (Some(url), _) => url.clone(),
+        (None, Some(name)) => config.printer(name).map_err(Error::Env)?.url.clone(),
+        (None, None) => config
+            .url
+            .clone()
+            .unwrap_or_else(|| "http://localhost:7125".to_string()),
+    };
+
+    http_url(&url).map_err(Error::Env)
+}
+
+/// Sends a JSON-RPC request with the given id, failing if the response
+/// belongs to another request.
+async fn rpc_call_with_id(
+    client: &reqwest::Client,
+    url: &str,
+    id: Uuid,
+    method: &str,
+    params: Option<JSON>,
+) -> Result<JSON, Error> {
+    let req = MoonrakerRPC {
+        jsonrpc: "2.0",
+        id,
+        method,
+        params,
+    };
+
+    tracing::debug!(url, %id, method, "rpc call");
+
+    let request = client.post(format!("{}/server/jsonrpc", url));
+    let resp = auth::authorize(request, url)
+        .json(&req)
+        .send()
+        .await?
+        .json::<JSON>()
+        .await?;
+
+    if resp["id"] != id.to_string() {
+        return Err(Error::Rpc(format!(
+            "{} answered with id {}",
+            method, resp["id"]
+        )));
+    }
+
+    Ok(resp)
+}
+
+/// Like `rpc_call` but also measures the round trip, which feeds the
+/// average latency shown in the status bar.
+async fn timed_call(
+    client: &reqwest::Client,
+    url: &str,
+    id: Uuid,
+    method: &str,
+    params: Option<JSON>,
+    shared: &SharedState,
+) -> Result<(JSON, Duration), Error> {
+    let sent = Instant::now();
+    let value = rpc_call_with_id(client, url, id, method, params).await?;
+    let latency = sent.elapsed();
+
+    shared.status.record_latency(latency);
+
+    Ok((value, latency))
+}
+
+/// Like `rpc_call` but unwraps the `result` member, turning JSON-RPC
+/// errors into `Error::Rpc`.
+async fn rpc_result(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    params: Option<JSON>,
+) -> Result<JSON, Error> {
+    let mut resp = rpc_call(client, url, method, params).await?;
+
+    if let Some(err) = resp.get("error") {
+        let message = err
+            .get("message")
+            .and_then(JSON::as_str)
+            .unwrap_or("unknown error");
+        return Err(Error::Rpc(format!("{} failed: {}", method, message)));
+    }
+
+    Ok(resp.get_mut("result").map(JSON::take).unwrap_or(JSON::Null))
+}
+
+/// Writes the queued jobs as an iCalendar file, one event per job, scheduled
+/// back to back after the current print using the slicer time estimates.
+async fn export_queue(client: &reqwest::Client, url: &str, path: &str) -> Result<String, Error> {
+    let queue = rpc_result(client, url, "server.job_queue.status", None).await?;
+    let jobs = queue
+        .get("queued_jobs")
+        .and_then(JSON::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    let now = unix_time();
+    let mut start = now;
+
+    let params =
+        Some(json!({ "objects": { "print_stats": ["state", "filename", "print_duration"] } }));
+    let stats = rpc_result(client, url, "printer.objects.query", params).await?;
+    let print_stats = &stats["status"]["print_stats"];
+
+    if print_stats["state"] == "printing" || print_stats["state"] == "paused" {
+        if let Some(filename) = print_stats["filename"].as_str() {
+            let elapsed = print_stats["print_duration"].as_f64().unwrap_or(0.0);
+            let estimate = estimated_time(client, url, filename).await.unwrap_or(0.0);
+            start += (estimate - elapsed).max(0.0);
+        }
+    }
+
+    let mut events = Vec::new();
+
+    for job in &jobs {
+        let filename = job["filename"].as_str().unwrap_or("unknown");
+        let estimate = estimated_time(client, url, filename).await;
+        let duration = estimate.unwrap_or(0.0);
+
+        let description = match estimate {
+            Some(secs) => format!("{}, estimated {:.0} minutes", filename, secs / 60.0),
+            None => format!("{}, estimated duration unknown", filename),
+        };
+
+        events.push(ics::Event {
+            uid: format!(
+                "{}@moonraker-cli",
+                job["job_id"].as_str().unwrap_or(filename)
+            ),
+            summary: format!("Print {}", filename),
+            description,
+            start,
+            end: start + duration,
+        });
+
+        start += duration;
+    }
+
+    fs::write(path, ics::render(&events, now))?;
+
+    Ok(format!("Exported {} queued jobs to {}", events.len(), path))
+}
+
+/// Names of the `gcode_macro` objects defined in the printer config.
+async fn discover_macros(client: &reqwest::Client, url: &str) -> Vec<String> {
+    let objects = rpc_result(client, url, "printer.objects.list", None)
+        .await
+        .unwrap_or_default();
+
+    objects["objects"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|object| object.as_str()?.strip_prefix("gcode_macro "))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Slicer estimated print time in seconds, from the file metadata.
+async fn estimated_time(client: &reqwest::Client, url: &str, filename: &str) -> Option<f64> {
+    let params = Some(json!({ "filename": filename }));
+    let metadata = rpc_result(client, url, "server.files.metadata", params)
+        .await
+        .ok()?;
+
+    metadata.get("estimated_time").and_then(JSON::as_f64)
+}
+
+/// Warns when the printer host clock differs from the local one, as drift
+/// breaks job history timestamps and TLS certificate validation.
+async fn check_clock_drift(client: &reqwest::Client, url: &str) -> Result<(), Error> {
+    let resp = rpc_call(client, url, "machine.proc_stats", None).await?;
+
+    // Moonraker samples its own process stats every second, the last sample
+    // carries the most recent host timestamp
+    let host_time = resp
+        .pointer("/result/moonraker_stats")
+        .and_then(JSON::as_array)
+        .and_then(|stats| stats.last())
+        .and_then(|sample| sample.get("time"))
+        .and_then(JSON::as_f64);
+
+    if let Some(host_time) = host_time {
+        let drift = host_time - unix_time();
+
+        if drift.abs() > MAX_CLOCK_DRIFT {
+            let direction = if drift > 0.0 { "ahead of" } else { "behind" };
+
+            eprintln!(
+                "warning: printer host clock is {:.1}s {} local time\n\
+                 hint: enable NTP on the host, e.g. `sudo timedatectl set-ntp true`",
+                drift.abs(),
+                direction
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// The filtered response followed by how long it took to arrive.
+fn format_response(
+    value: JSON,
+    filter: Option<&Filter>,
+    latency: Duration,
+) -> Result<String, Error> {
+    let value = match filter {
+        Some(filter) => filter.apply(&value),
+        None => value,
+    };
+
+    Ok(format!(
+        "{}\n({} ms)",
+        format_json(value)?,
+        latency.as_millis()
+    ))
+}
+
 fn format_json(value: JSON) -> Result<String, Error> {
     serde_json::to_string_pretty(&value).map_err(Error::Serde)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_printer_urls() {
+        let cases = [
+            ("mainsailos.local", "http://mainsailos.local"),
+            (" 192.168.1.50:7125/ ", "http://192.168.1.50:7125"),
+            ("ws://voron.local/websocket", "http://voron.local"),
+            (
+                "WSS://voron.local:7130/websocket/",
+                "https://voron.local:7130",
+            ),
+            (
+                "https://voron.local/moonraker",
+                "https://voron.local/moonraker",
+            ),
+        ];
+
+        for (url, expected) in cases {
+            assert_eq!(http_url(url).as_deref(), Ok(expected), "{}", url);
+        }
+    }
+
+    #[test]
+    fn refuses_urls_that_cannot_be_a_base() {
+        for url in [
+            "ftp://voron.local",
+            "http://",
+            "voron.local?x=1",
+            "voron.local#top",
+            "http://voron local",
+        ] {
+            assert!(http_url(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::thumbnail::Graphics;
+use crate::units::Units;
+use crate::{rpc_result, Error, JSON};
+use serde_json::json;
+
+/// Cells of heatmap a probed point takes, wide enough to look square.
+const CELL_WIDTH: usize = 4;
+
+/// The gradient from the lowest point to the highest, blue to green to red.
+const GRADIENT: [[f64; 3]; 3] = [
+    [40.0, 90.0, 220.0],
+    [60.0, 200.0, 90.0],
+    [230.0, 60.0, 50.0],
+];
+
+/// A probed mesh with where it was probed, in mm.
+struct Mesh {
+    name: String,
+    /// From the front row, each from the left
+    points: Vec<Vec<f64>>,
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+impl Mesh {
+    /// Where the point at `row` and `column` was probed.
+    fn position(&self, row: usize, column: usize) -> (f64, f64) {
+        let step = |min: f64, max: f64, count: usize, index: usize| {
+            if count > 1 {
+                min + (max - min) * index as f64 / (count - 1) as f64
+            } else {
+                min
+            }
+        };
+        let columns = self.points[row].len();
+
+        (
+            step(self.min.0, self.max.0, columns, column),
+            step(self.min.1, self.max.1, self.points.len(), row),
+        )
+    }
+}
+
+/// `:mesh [profile]`, the loaded bed mesh or a saved profile as a heatmap
+/// seen from above, the back of the bed on top, with the probed values and
+/// how flat it is.
+pub async fn show(
+    client: &reqwest::Client,
+    url: &str,
+    profile: Option<&str>,
+    units: Units,
+    graphics: Graphics,
+) -> Result<String, Error> {
+    let params = Some(json!({
+        "objects": { "bed_mesh": ["profile_name", "mesh_min", "mesh_max", "probed_matrix", "profiles"] }
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let bed_mesh = &status["status"]["bed_mesh"];
+
+    if bed_mesh.is_null() {
+        return Err(Error::Env(
+            "No [bed_mesh] in the printer config".to_string(),
+        ));
+    }
+
+    let profiles: Vec<&str> = bed_mesh["profiles"]
+        .as_object()
+        .map(|profiles| profiles.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+
+    let mesh = match profile {
+        Some(name) => saved(bed_mesh, name).ok_or_else(|| {
+            Error::Env(format!(
+                "No mesh profile {}, expected one of {}",
+                name,
+                profiles.join(", ")
+            ))
+        })?,
+        None => match loaded(bed_mesh) {
+            Some(mesh) => mesh,
+            None if profiles.is_empty() => {
+                return Ok("No bed mesh, :calibrate probes one".to_string());
+            }
+            None => {
+                return Ok(format!(
+                    "No bed mesh loaded, :mesh <profile> shows a saved one of {}",
+                    profiles.join(", ")
+                ));
+            }
+        },
+    };
+
+    Ok(render(&mesh, units, graphics))
+}
+
+fn coordinates(pair: &JSON) -> Option<(f64, f64)> {
+    Some((pair[0].as_f64()?, pair[1].as_f64()?))
+}
+
+fn matrix(points: &JSON) -> Vec<Vec<f64>> {
+    points
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|row| {
+            row.as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(JSON::as_f64)
+                .collect::<Vec<_>>()
+        })
+        .filter(|row| !row.is_empty())
+        .collect()
+}
+
+fn loaded(bed_mesh: &JSON) -> Option<Mesh> {
+    let points = matrix(&bed_mesh["probed_matrix"]);
+
+    if points.is_empty() {
+        return None;
+    }
+
+    Some(Mesh {
+        name: bed_mesh["profile_name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        points,
+        min: coordinates(&bed_mesh["mesh_min"])?,
+        max: coordinates(&bed_mesh["mesh_max"])?,
+    })
+}
+
+fn saved(bed_mesh: &JSON, name: &str) -> Option<Mesh> {
+    let profile = bed_mesh["profiles"].get(name)?;
+    let params = &profile["mesh_params"];
+    let points = matrix(&profile["points"]);
+
+    if points.is_empty() {
+        return None;
+    }
+
+    Some(Mesh {
+        name: name.to_string(),
+        points,
+        min: (params["min_x"].as_f64()?, params["min_y"].as_f64()?),
+        max: (params["max_x"].as_f64()?, params["max_y"].as_f64()?),
+    })
+}
+
+/// The color of `value` along the gradient from `low` to `high`.
+fn color(value: f64, low: f64, high: f64) -> [u8; 3] {
+    let ratio = if high > low {
+        ((value - low) / (high - low)).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    let (from, to, ratio) = if ratio < 0.5 {
+        (GRADIENT[0], GRADIENT[1], ratio * 2.0)
+    } else {
+        (GRADIENT[1], GRADIENT[2], ratio * 2.0 - 1.0)
+    };
+
+    [0, 1, 2].map(|channel| (from[channel] + (to[channel] - from[channel]) * ratio).round() as u8)
+}
+
+fn block(value: f64, low: f64, high: f64, width: usize) -> String {
+    let [red, green, blue] = color(value, low, high);
+    format!(
+        "\x1b[38;2;{};{};{}m{}\x1b[0m",
+        red,
+        green,
+        blue,
+        "█".repeat(width)
+    )
+}
+
+fn render(mesh: &Mesh, units: Units, graphics: Graphics) -> String {
+    let values = || mesh.points.iter().flatten().copied();
+    let low = values().fold(f64::INFINITY, f64::min);
+    let high = values().fold(f64::NEG_INFINITY, f64::max);
+    let mean = values().sum::<f64>() / values().count() as f64;
+
+    // Where the extremes are, as (row, column)
+    let find = |target: f64| {
+        mesh.points.iter().enumerate().find_map(|(row, points)| {
+            points
+                .iter()
+                .position(|value| *value == target)
+                .map(|column| (row, column))
+        })
+    };
+    let at = |(row, column): (usize, usize)| {
+        let (x, y) = mesh.position(row, column);
+        format!(
+            "X {} Y {}",
+            units.format_length_value(x),
+            units.format_length_value(y)
+        )
+    };
+
+    let columns = mesh.points.iter().map(Vec::len).max().unwrap_or(0);
+    let mut lines = vec![format!(
+        "Bed mesh {}, {}×{} from X {} Y {} to X {} Y {} {}",
+        mesh.name,
+        columns,
+        mesh.points.len(),
+        units.format_length_value(mesh.min.0),
+        units.format_length_value(mesh.min.1),
+        units.format_length_value(mesh.max.0),
+        units.format_length_value(mesh.max.1),
+        units.length_symbol()
+    )];
+    lines.push(String::new());
+
+    let color = graphics.has_color();
+
+    // The back of the bed on top, as seen standing in front of it
+    for points in mesh.points.iter().rev() {
+        let mut line = String::from("  ");
+
+        if color {
+            for value in points {
+                line.push_str(&block(*value, low, high, CELL_WIDTH));
+            }
+            line.push_str("   ");
+        }
+
+        let numbers: Vec<String> = points
+            .iter()
+            .map(|value| format!("{:>9}", units.format_deviation(*value)))
+            .collect();
+        line.push_str(&numbers.join(""));
+        lines.push(line);
+    }
+
+    lines.push(String::new());
+
+    if color {
+        let steps = columns * CELL_WIDTH;
+        let legend: String = (0..steps)
+            .map(|step| {
+                let value = low + (high - low) * step as f64 / (steps.max(2) - 1) as f64;
+                block(value, low, high, 1)
+            })
+            .collect();
+        lines.push(format!(
+            "  {} {} to {}",
+            legend,
+            units.format_deviation(low),
+            units.format_deviation(high)
+        ));
+    }
+
+    let lowest = find(low).map(at).unwrap_or_default();
+    let highest = find(high).map(at).unwrap_or_default();
+    lines.push(format!(
+        "  Lowest   {} at {}",
+        units.format_deviation(low),
+        lowest
+    ));
+    lines.push(format!(
+        "  Highest  {} at {}",
+        units.format_deviation(high),
+        highest
+    ));
+    lines.push(format!(
+        "  Range    {}, mean {}",
+        units.format_deviation(high - low).trim_start_matches('+'),
+        units.format_deviation(mean)
+    ));
+
+    lines.join("\n")
+}
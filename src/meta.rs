@@ -0,0 +1,648 @@
+use crate::calc;
+use crate::calibrate;
+use crate::cleanup;
+use crate::extrude;
+use crate::fan;
+use crate::files;
+use crate::filter::Filter;
+use crate::history;
+use crate::jog;
+use crate::keepout::Zone;
+use crate::led;
+use crate::objects;
+use crate::pins;
+use crate::plot;
+use crate::power;
+use crate::preheat;
+use crate::queue;
+use crate::screws;
+use crate::spoolman;
+use crate::status::Density;
+use crate::system;
+use crate::transcript::Export;
+use crate::tune::{Adjust, Factor};
+use crate::webhook::Webhook;
+use crate::JSON;
+use serde_json::json;
+use std::time::Duration;
+
+/// Usage and description of every meta-command, shown by `:help`.
+pub const COMMANDS: &[(&str, &str)] = &[
+    (":connect <url>", "switch to another Moonraker instance"),
+    (":clear", "clear the screen"),
+    (":save <file>", "save the session transcript to a file"),
+    (
+        ":export [--since 10m] [--until 5m] [--grep text] <file>",
+        "save the transcript lines shown in a time range or matching, timestamped",
+    ),
+    (
+        ":filter [expr]",
+        "pipe responses through a jq-style path, no expr clears it",
+    ),
+    (
+        ":export-queue <file.ics>",
+        "export queued jobs with estimated durations as iCalendar",
+    ),
+    (
+        ":rpc <method> [params]",
+        "call a Moonraker method, params as a JSON object",
+    ),
+    (
+        ":palette [query]",
+        "fuzzy search commands, methods and macros (Ctrl-P)",
+    ),
+    (
+        ":webhook <url> [--snapshot]",
+        "notify a URL when prints end, :webhook off removes it",
+    ),
+    (
+        ":screensaver <minutes>|off",
+        "blank the screen when idle, keys and printer events wake it",
+    ),
+    (
+        ":timeout <seconds>|off",
+        "give up waiting for replies after this long, 300 by default",
+    ),
+    (
+        ":debug-bundle",
+        "archive logs, printer.cfg, the last job and this transcript",
+    ),
+    (
+        ":mode gcode|rpc|db",
+        "change what plain input is sent as, see below",
+    ),
+    (
+        ":watch <url>|off",
+        "follow a second printer's notices and state in the status bar",
+    ),
+    (
+        ":swap",
+        "send input to the watched printer, watching this one",
+    ),
+    (
+        ":split",
+        "show the watched printer beside the console, :swap moves the input over",
+    ),
+    (
+        ":replay, :discard",
+        "send or drop the commands queued while offline",
+    ),
+    (
+        ":compare <job> <job>",
+        "compare two print history jobs, durations and settings",
+    ),
+    (
+        ":plot <heater> [minutes]",
+        "plot a heater's temperature against its target, 5 minutes by default",
+    ),
+    (
+        ":jog <axis> <distance>",
+        "move an axis relative to its position, in the display unit, within its limits, offering to home it",
+    ),
+    (
+        ":jog [step <n>]",
+        "toggle the jog pad, or turn it on jogging by n in the display unit",
+    ),
+    (
+        ":extrude <mm> [heat]",
+        "push filament through the nozzle, heat heats it first when too cold to extrude",
+    ),
+    (":retract <mm> [heat]", "pull filament back from the nozzle"),
+    (
+        ":keepout [x1 y1 x2 y2|clear]",
+        "list, add or clear XY zones jogs stop at and moves warn about",
+    ),
+    (
+        ":check <file>",
+        "look for missing end G-code, unsafe temperatures or profile mismatches",
+    ),
+    (
+        ":print [--force] <file>",
+        "start a print if the file passes :check, --force starts it anyway",
+    ),
+    (
+        ":pause, :resume, :cancel",
+        "pause, resume or cancel the print, Alt-P, Alt-R and Alt-X too",
+    ),
+    (
+        ":objects [exclude <n>]",
+        "list the objects of the print, exclude the nth to stop printing it",
+    ),
+    (
+        ":calibrate [n]",
+        "list the calibrations the printer supports, run the nth",
+    ),
+    (
+        ":preheat [n|name]",
+        "list the temperature presets, set the heater targets of one",
+    ),
+    (":cooldown", "turn every heater off"),
+    (
+        ":fans [n percent|next|+n|-n]",
+        "show the fans G-code drives, set one, pick the one the keys adjust or adjust it",
+    ),
+    (
+        ":leds [n on|off|toggle|preset <p>|<color>]",
+        "list the WLED strips and Klipper LEDs with the colors to pick, set one",
+    ),
+    (
+        ":power [n on|off|toggle|off-after|keep]",
+        "list the power devices, switch one, turn it off once the print completes and cools",
+    ),
+    (
+        ":pins [n on|off|toggle|value]",
+        "list the output pins, set one, a value between only for pwm pins",
+    ),
+    (":endstops", "show which endstops are triggered"),
+    (
+        ":mesh [profile]",
+        "show the loaded bed mesh or a saved profile as a heatmap, with how flat it is",
+    ),
+    (
+        ":screws [last]",
+        "probe the bed screws and show which way and how far to turn each",
+    ),
+    (
+        ":speed [+n|-n|n], :flow [+n|-n|n]",
+        "show or set the speed or flow factor in percent, like Alt-, . and Alt-{ }",
+    ),
+    (
+        ":login [user], :logout",
+        "log in to Moonraker, the password is asked for",
+    ),
+    (
+        ":files [n [action]|<path>|..|find <query>|mkdir <name>]",
+        "browse the G-code files, open or act on the nth one listed",
+    ),
+    (
+        ":history [status|n]",
+        "list past jobs, only those ending with status, or show the nth listed",
+    ),
+    (
+        ":cost",
+        "what the current job cost so far and will in all, from [filament] prices",
+    ),
+    (
+        ":spool [list|n|clear]",
+        "show the active Spoolman spool, list spools or make the nth listed active",
+    ),
+    (
+        ":stats [monthly]",
+        "print totals over the whole history, monthly breaks them down",
+    ),
+    (
+        ":queue [add <file>...|remove <n>...|clear|pause|start]",
+        "show or change the job queue, jobs by their number listed",
+    ),
+    (
+        ":upload [--print] <file>",
+        "upload a G-code file, --print starts printing it once uploaded",
+    ),
+    (
+        ":cleanup <days>|delete [n ...]|confirm",
+        "list files not printed in this many days, pick some or all, then confirm deleting them",
+    ),
+    (
+        ":system [update [confirm]]",
+        "show the host kernel and pending OS upgrades, or upgrade them once confirmed",
+    ),
+    (
+        ":printer <name>",
+        "switch to a printer from the config, each keeps its own history",
+    ),
+    (
+        ":copy [lines]",
+        "copy the last reply, or the last lines of the console, to the clipboard",
+    ),
+    (
+        ":density [compact|detailed]",
+        "status bar on one row or three, no argument switches",
+    ),
+    (
+        ":page",
+        "show the last reply in $PAGER, replies over pager-lines only there",
+    ),
+    (
+        ":prompt [n|close]",
+        "show the dialog a macro opened, press its nth button or close it",
+    ),
+    (
+        ":all <gcode>",
+        "send G-code to every printer in the config, or those in --printers",
+    ),
+    (":ack", "silence the pending printer error alert"),
+    (":help", "show this help"),
+];
+
+pub fn help() -> String {
+    let width = COMMANDS
+        .iter()
+        .map(|(usage, _)| usage.len())
+        .max()
+        .unwrap_or(0);
+    let mut help: Vec<String> = COMMANDS
+        .iter()
+        .map(|(usage, description)| format!("{:width$}  {}", usage, description))
+        .collect();
+
+    help.push(String::new());
+    help.push("Anything not starting with ':' is sent according to the mode:".to_string());
+    help.push(
+        "  gcode  a G-code script, the default, arithmetic in braces like Z{2.5+0.2} is evaluated"
+            .to_string(),
+    );
+    help.push("  rpc    <method> [params], like :rpc".to_string());
+    help.push(
+        "  db     list, get <namespace> [key], set <namespace> <key> <json>, delete <namespace> <key>"
+            .to_string(),
+    );
+    help.join("\n")
+}
+
+/// A line typed in the console, either a client-side command or G-code.
+pub enum Input {
+    Meta(MetaCommand),
+    Gcode(String),
+}
+
+/// How input not starting with ':' is interpreted.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Gcode,
+    Rpc,
+    Db,
+}
+
+impl Mode {
+    pub fn parse(mode: &str) -> Result<Mode, String> {
+        match mode {
+            "gcode" => Ok(Mode::Gcode),
+            "rpc" => Ok(Mode::Rpc),
+            "db" => Ok(Mode::Db),
+            mode => Err(format!("Unknown mode {}, expected gcode, rpc or db", mode)),
+        }
+    }
+
+    pub fn prompt(self) -> &'static str {
+        match self {
+            Mode::Gcode => "> ",
+            Mode::Rpc => "rpc> ",
+            Mode::Db => "db> ",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Gcode => "G-code",
+            Mode::Rpc => "RPC method calls",
+            Mode::Db => "database queries",
+        }
+    }
+}
+
+/// Client-side commands, prefixed with ':' so they never reach the printer.
+pub enum MetaCommand {
+    Connect(String),
+    Clear,
+    Save(String),
+    Export(Export),
+    Filter(Option<Filter>),
+    ExportQueue(String),
+    Rpc(String, Option<JSON>),
+    Palette(String),
+    Webhook(Option<Webhook>),
+    Screensaver(Option<Duration>),
+    DebugBundle,
+    /// Not in the help, it's for bug reports
+    DebugState,
+    Timeout(Option<Duration>),
+    Mode(Mode),
+    Watch(Option<String>),
+    Swap,
+    /// Toggles the watched printer beside the console
+    Split,
+    Replay,
+    Discard,
+    Compare(String, String),
+    Plot(String, usize),
+    Jog(char, f64),
+    /// Turns the jog pad on with a step, toggles it without one
+    JogPad(Option<f64>),
+    /// In mm, retracting when negative, heating first if asked
+    Extrude(f64, bool),
+    /// Adds a zone, lists them without one
+    KeepOut(Option<Zone>),
+    KeepOutClear,
+    Check(String),
+    Print(String, bool),
+    Pause,
+    Resume,
+    Cancel,
+    Objects(objects::Command),
+    Calibrate(calibrate::Command),
+    Preheat(preheat::Command),
+    Fans(fan::Command),
+    Leds(led::Command),
+    Power(power::Command),
+    Pins(pins::Command),
+    Endstops,
+    /// A saved profile rather than the loaded mesh
+    Mesh(Option<String>),
+    Screws(screws::Command),
+    Tune(Factor, Adjust),
+    /// The username, asked for when missing
+    Login(Option<String>),
+    Logout,
+    Cleanup(cleanup::Command),
+    Files(files::Command),
+    Queue(queue::Command),
+    History(history::Command),
+    /// Broken down per month
+    Stats(bool),
+    Cost,
+    Spool(spoolman::Command),
+    /// The local file, and whether to print it
+    Upload(String, bool),
+    System(system::Command),
+    /// A printer from the config
+    Printer(String),
+    /// G-code sent to several printers
+    All(String),
+    Page,
+    /// The last reply, or this many console lines
+    Copy(Option<usize>),
+    /// Switches between the two without one
+    Density(Option<Density>),
+    /// Shows the macro dialog, or presses its nth button
+    Prompt(Option<usize>),
+    PromptClose,
+    Ack,
+    Help,
+}
+
+impl Input {
+    pub fn parse(line: &str, mode: Mode) -> Result<Input, String> {
+        let line = line.trim();
+
+        match (line.strip_prefix(':'), mode) {
+            (Some(command), _) => MetaCommand::parse(command).map(Input::Meta),
+            (None, Mode::Gcode) => calc::expand(line).map(Input::Gcode),
+            (None, Mode::Rpc) => parse_rpc(line).map(Input::Meta),
+            (None, Mode::Db) => parse_db(line).map(Input::Meta),
+        }
+    }
+}
+
+impl MetaCommand {
+    fn parse(command: &str) -> Result<MetaCommand, String> {
+        let (name, args) = command
+            .split_once(char::is_whitespace)
+            .map(|(name, args)| (name, args.trim()))
+            .unwrap_or((command, ""));
+
+        match name {
+            "connect" => required(name, args).map(|url| MetaCommand::Connect(url.to_string())),
+            "clear" => Ok(MetaCommand::Clear),
+            "save" => required(name, args).map(|path| MetaCommand::Save(path.to_string())),
+            "export" => Export::parse(args).map(MetaCommand::Export),
+            "filter" if args.is_empty() => Ok(MetaCommand::Filter(None)),
+            "filter" => Filter::parse(args).map(|filter| MetaCommand::Filter(Some(filter))),
+            "export-queue" => {
+                required(name, args).map(|path| MetaCommand::ExportQueue(path.to_string()))
+            }
+            "rpc" => parse_rpc(args),
+            "palette" => Ok(MetaCommand::Palette(args.to_string())),
+            "webhook" => parse_webhook(args),
+            "screensaver" => parse_screensaver(args),
+            "debug-bundle" => Ok(MetaCommand::DebugBundle),
+            "debug-state" => Ok(MetaCommand::DebugState),
+            "timeout" => parse_timeout(args),
+            "mode" => Mode::parse(required(name, args)?).map(MetaCommand::Mode),
+            "watch" => match required(name, args)? {
+                "off" => Ok(MetaCommand::Watch(None)),
+                url => Ok(MetaCommand::Watch(Some(url.to_string()))),
+            },
+            "swap" => Ok(MetaCommand::Swap),
+            "split" => Ok(MetaCommand::Split),
+            "replay" => Ok(MetaCommand::Replay),
+            "discard" => Ok(MetaCommand::Discard),
+            "compare" => match required(name, args)?.split_whitespace().collect::<Vec<_>>()[..] {
+                [first, second] => Ok(MetaCommand::Compare(first.to_string(), second.to_string())),
+                _ => Err("Expected two job ids, try :help".to_string()),
+            },
+            "plot" => parse_plot(args),
+            "jog" => match args.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => Ok(MetaCommand::JogPad(None)),
+                ["step", step] => step
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|step| step.is_finite() && *step > 0.0)
+                    .map(|step| MetaCommand::JogPad(Some(step)))
+                    .ok_or_else(|| format!("Invalid jog step {}, expected like 0.1", step)),
+                _ => jog::parse(args)
+                    .map(|(axis, distance)| MetaCommand::Jog(axis, distance))
+                    .ok_or_else(|| format!("Invalid jog {}, expected like :jog x 10", args)),
+            },
+            "extrude" => extrude::parse(args)
+                .map(|(length, heat)| MetaCommand::Extrude(length, heat))
+                .ok_or_else(|| format!("Invalid extrude {}, expected like :extrude 50", args)),
+            "retract" => extrude::parse(args)
+                .map(|(length, heat)| MetaCommand::Extrude(-length, heat))
+                .ok_or_else(|| format!("Invalid retract {}, expected like :retract 50", args)),
+            "keepout" => match args {
+                "" => Ok(MetaCommand::KeepOut(None)),
+                "clear" => Ok(MetaCommand::KeepOutClear),
+                corners => Zone::parse(corners)
+                    .map(|zone| MetaCommand::KeepOut(Some(zone)))
+                    .ok_or_else(|| format!("Invalid zone {}, expected x1 y1 x2 y2", corners)),
+            },
+            "check" => required(name, args).map(|file| MetaCommand::Check(file.to_string())),
+            "print" => match required(name, args)?.strip_prefix("--force") {
+                Some(file) if !file.trim().is_empty() => {
+                    Ok(MetaCommand::Print(file.trim().to_string(), true))
+                }
+                Some(_) => Err("Missing file for :print, try :help".to_string()),
+                None => Ok(MetaCommand::Print(args.to_string(), false)),
+            },
+            "login" if args.is_empty() => Ok(MetaCommand::Login(None)),
+            "login" => Ok(MetaCommand::Login(Some(args.to_string()))),
+            "logout" => Ok(MetaCommand::Logout),
+            "cleanup" => cleanup::Command::parse(args).map(MetaCommand::Cleanup),
+            "files" => files::Command::parse(args).map(MetaCommand::Files),
+            "queue" => queue::Command::parse(args).map(MetaCommand::Queue),
+            "history" => history::Command::parse(args).map(MetaCommand::History),
+            "cost" => Ok(MetaCommand::Cost),
+            "spool" => spoolman::Command::parse(args).map(MetaCommand::Spool),
+            "stats" => match args.trim() {
+                "" => Ok(MetaCommand::Stats(false)),
+                "monthly" => Ok(MetaCommand::Stats(true)),
+                _ => Err(format!("Unknown :stats argument {}, try :help", args)),
+            },
+            "upload" => match required(name, args)?.strip_prefix("--print") {
+                Some(file) if !file.trim().is_empty() => {
+                    Ok(MetaCommand::Upload(file.trim().to_string(), true))
+                }
+                Some(_) => Err("Missing file for :upload, try :help".to_string()),
+                None => Ok(MetaCommand::Upload(args.to_string(), false)),
+            },
+            "system" => system::Command::parse(args).map(MetaCommand::System),
+            "printer" => Ok(MetaCommand::Printer(required(name, args)?.to_string())),
+            "page" => Ok(MetaCommand::Page),
+            "copy" if args.is_empty() => Ok(MetaCommand::Copy(None)),
+            "copy" => args
+                .parse::<usize>()
+                .ok()
+                .filter(|lines| *lines > 0)
+                .map(|lines| MetaCommand::Copy(Some(lines)))
+                .ok_or_else(|| format!("Invalid line count {}", args)),
+            "density" if args.is_empty() => Ok(MetaCommand::Density(None)),
+            "density" => Density::parse(args).map(|density| MetaCommand::Density(Some(density))),
+            "prompt" => match args {
+                "" => Ok(MetaCommand::Prompt(None)),
+                "close" => Ok(MetaCommand::PromptClose),
+                n => n
+                    .parse::<usize>()
+                    .map(|n| MetaCommand::Prompt(Some(n)))
+                    .map_err(|_| format!("Invalid button {}, expected a number or close", n)),
+            },
+            "all" => calc::expand(required(name, args)?).map(MetaCommand::All),
+            "ack" => Ok(MetaCommand::Ack),
+            "pause" => Ok(MetaCommand::Pause),
+            "resume" => Ok(MetaCommand::Resume),
+            "cancel" => Ok(MetaCommand::Cancel),
+            "objects" => objects::Command::parse(args).map(MetaCommand::Objects),
+            "calibrate" => calibrate::Command::parse(args).map(MetaCommand::Calibrate),
+            "preheat" => Ok(MetaCommand::Preheat(preheat::Command::parse(args))),
+            "cooldown" => Ok(MetaCommand::Preheat(preheat::Command::Cooldown)),
+            "fans" => fan::Command::parse(args).map(MetaCommand::Fans),
+            "leds" => led::Command::parse(args).map(MetaCommand::Leds),
+            "power" => power::Command::parse(args).map(MetaCommand::Power),
+            "pins" => pins::Command::parse(args).map(MetaCommand::Pins),
+            "endstops" => Ok(MetaCommand::Endstops),
+            "mesh" if args.is_empty() => Ok(MetaCommand::Mesh(None)),
+            "mesh" => Ok(MetaCommand::Mesh(Some(args.to_string()))),
+            "screws" => screws::Command::parse(args).map(MetaCommand::Screws),
+            "speed" => Adjust::parse(args).map(|adjust| MetaCommand::Tune(Factor::Speed, adjust)),
+            "flow" => Adjust::parse(args).map(|adjust| MetaCommand::Tune(Factor::Flow, adjust)),
+            "help" => Ok(MetaCommand::Help),
+            _ => Err(format!("Unknown command :{}, try :help", name)),
+        }
+    }
+}
+
+fn required<'a>(name: &str, args: &'a str) -> Result<&'a str, String> {
+    if args.is_empty() {
+        Err(format!("Missing argument for :{}, try :help", name))
+    } else {
+        Ok(args)
+    }
+}
+
+fn parse_rpc(args: &str) -> Result<MetaCommand, String> {
+    let (method, params) = required("rpc", args)?
+        .split_once(char::is_whitespace)
+        .map(|(method, params)| (method, params.trim()))
+        .unwrap_or((args, ""));
+
+    if params.is_empty() {
+        return Ok(MetaCommand::Rpc(method.to_string(), None));
+    }
+
+    serde_json::from_str(params)
+        .map(|params| MetaCommand::Rpc(method.to_string(), Some(params)))
+        .map_err(|err| format!("Invalid params for {}: {}", method, err))
+}
+
+fn parse_webhook(args: &str) -> Result<MetaCommand, String> {
+    let mut words = required("webhook", args)?.split_whitespace();
+    let url = words.next().unwrap_or_default();
+    let snapshot = words.next() == Some("--snapshot");
+
+    if url == "off" {
+        return Ok(MetaCommand::Webhook(None));
+    }
+
+    Ok(MetaCommand::Webhook(Some(Webhook {
+        url: url.to_string(),
+        snapshot,
+    })))
+}
+
+fn parse_screensaver(args: &str) -> Result<MetaCommand, String> {
+    match required("screensaver", args)? {
+        "off" => Ok(MetaCommand::Screensaver(None)),
+        minutes => minutes
+            .parse::<f64>()
+            .ok()
+            .filter(|minutes| *minutes > 0.0)
+            .map(|minutes| MetaCommand::Screensaver(Some(Duration::from_secs_f64(minutes * 60.0))))
+            .ok_or_else(|| format!("Invalid timeout {}, expected minutes or off", minutes)),
+    }
+}
+
+fn parse_timeout(args: &str) -> Result<MetaCommand, String> {
+    match required("timeout", args)? {
+        "off" => Ok(MetaCommand::Timeout(None)),
+        secs => secs
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(|secs| MetaCommand::Timeout(Some(Duration::from_secs(secs))))
+            .ok_or_else(|| format!("Invalid timeout {}, expected seconds or off", secs)),
+    }
+}
+
+fn parse_plot(args: &str) -> Result<MetaCommand, String> {
+    let (heater, minutes) = required("plot", args)?
+        .split_once(char::is_whitespace)
+        .map(|(heater, minutes)| (heater, minutes.trim()))
+        .unwrap_or((args, "5"));
+
+    // Temperature store keys are lowercase, like `heater_generic chamber`
+    let heater = heater.replace(':', " ").to_lowercase();
+
+    minutes
+        .parse::<f64>()
+        .ok()
+        .filter(|minutes| *minutes > 0.0)
+        .map(|minutes| {
+            let seconds = (minutes * 60.0) as usize;
+            MetaCommand::Plot(heater, seconds.min(plot::STORE_SECONDS))
+        })
+        .ok_or_else(|| format!("Invalid duration {}, expected minutes", minutes))
+}
+
+/// Database queries in `:mode db`, mapped to the `server.database` methods.
+fn parse_db(line: &str) -> Result<MetaCommand, String> {
+    let mut words = line.splitn(4, char::is_whitespace);
+    let query = words.next().unwrap_or_default();
+    let (namespace, key, value) = (words.next(), words.next(), words.next());
+
+    let (method, params) = match (query, namespace, key, value) {
+        ("list", None, _, _) => ("list", None),
+        ("get", Some(namespace), None, _) => ("get_item", Some(json!({ "namespace": namespace }))),
+        ("get", Some(namespace), Some(key), None) => (
+            "get_item",
+            Some(json!({ "namespace": namespace, "key": key })),
+        ),
+        ("set", Some(namespace), Some(key), Some(value)) => {
+            let value: JSON = serde_json::from_str(value.trim())
+                .map_err(|err| format!("Invalid value for {}: {}", key, err))?;
+            (
+                "post_item",
+                Some(json!({ "namespace": namespace, "key": key, "value": value })),
+            )
+        }
+        ("delete", Some(namespace), Some(key), None) => (
+            "delete_item",
+            Some(json!({ "namespace": namespace, "key": key })),
+        ),
+        _ => return Err(format!("Invalid database query {}, try :help", line)),
+    };
+
+    Ok(MetaCommand::Rpc(
+        format!("server.database.{}", method),
+        params,
+    ))
+}
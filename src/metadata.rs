@@ -0,0 +1,69 @@
+use crate::history::format_duration;
+use crate::units::Units;
+use crate::{rpc_result, Error, JSON};
+use serde_json::json;
+
+/// What the slicer says about `filename`, from `server.files.metadata`.
+pub async fn fetch(client: &reqwest::Client, url: &str, filename: &str) -> Result<JSON, Error> {
+    let params = Some(json!({ "filename": filename }));
+    rpc_result(client, url, "server.files.metadata", params).await
+}
+
+/// The slicer, the estimated time, the filament, the layer heights and the
+/// first layer temperatures, a line each for those the slicer wrote.
+pub fn lines(metadata: &JSON, units: Units) -> Vec<String> {
+    let number = |key: &str| metadata[key].as_f64();
+    let mut lines = Vec::new();
+
+    if let Some(slicer) = metadata["slicer"].as_str() {
+        let version = metadata["slicer_version"].as_str().unwrap_or_default();
+        lines.push(format!("Slicer         {} {}", slicer, version));
+    }
+
+    if let Some(estimated) = number("estimated_time") {
+        lines.push(format!("Estimated time {}", format_duration(estimated)));
+    }
+
+    match (number("filament_total"), number("filament_weight_total")) {
+        (Some(length), Some(weight)) => lines.push(format!(
+            "Filament       {:.2} m, {:.1} g",
+            length / 1000.0,
+            weight
+        )),
+        (Some(length), None) => lines.push(format!("Filament       {:.2} m", length / 1000.0)),
+        (None, Some(weight)) => lines.push(format!("Filament       {:.1} g", weight)),
+        (None, None) => {}
+    }
+
+    match (number("layer_height"), number("first_layer_height")) {
+        (Some(height), Some(first)) => lines.push(format!(
+            "Layer height   {}, first {}",
+            units.format_length(height),
+            units.format_length(first)
+        )),
+        (Some(height), None) => {
+            lines.push(format!("Layer height   {}", units.format_length(height)))
+        }
+        _ => {}
+    }
+
+    if let Some(height) = number("object_height") {
+        lines.push(format!("Object height  {}", units.format_length(height)));
+    }
+
+    let temperatures: Vec<String> = [
+        ("first_layer_extr_temp", "extruder"),
+        ("first_layer_bed_temp", "bed"),
+    ]
+    .iter()
+    .filter_map(|(key, heater)| {
+        number(key).map(|temp| format!("{} {}", heater, units.format_temperature(temp)))
+    })
+    .collect();
+
+    if !temperatures.is_empty() {
+        lines.push(format!("First layer    {}", temperatures.join(", ")));
+    }
+
+    lines
+}
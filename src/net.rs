@@ -0,0 +1,125 @@
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use reqwest::Url;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{self, TcpStream};
+
+/// How long an address is tried alone before the next one is tried
+/// alongside, as recommended for happy eyeballs by RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// A route dropping packets would otherwise hang for the system timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connects to the host of `url`, trying every address it resolves to,
+/// IPv6 and IPv4 alternated, so a broken route to one of them doesn't
+/// fail the connection. HTTP requests get the same from reqwest.
+#[tracing::instrument(skip_all, fields(host = url.host_str()))]
+pub async fn connect(url: &Url) -> io::Result<TcpStream> {
+    let mut addrs = interleave(resolve(url).await?).into_iter().peekable();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No address for the host");
+
+    loop {
+        if let Some(addr) = addrs.next() {
+            tracing::debug!(%addr, "connecting");
+            attempts.push(async move {
+                tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+                    .await
+                    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+                    .map_err(|err| (addr, err))
+            });
+        }
+
+        if attempts.is_empty() {
+            return Err(last_err);
+        }
+
+        tokio::select! {
+            Some(attempt) = attempts.next() => match attempt {
+                Ok(stream) => return Ok(stream),
+                Err((addr, err)) => {
+                    tracing::info!(%addr, %err, "connection failed");
+                    last_err = err;
+                }
+            },
+            // Until the attempts pending fail, only when there's another address
+            _ = tokio::time::sleep(ATTEMPT_DELAY), if addrs.peek().is_some() => {}
+        }
+    }
+}
+
+/// The addresses the host of `url` resolves to, with its port.
+pub async fn resolve(url: &Url) -> io::Result<Vec<SocketAddr>> {
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No port in the URL"))?;
+
+    // IPv6 literals come in brackets, which the resolver doesn't expect
+    let host = url.host_str().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok(net::lookup_host((host, port)).await?.collect())
+}
+
+/// The addresses alternating between IPv6 and IPv4, starting with the
+/// family the resolver put first.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first().copied() else {
+        return addrs;
+    };
+
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first.is_ipv6());
+    let mut interleaved = Vec::new();
+
+    preferred.reverse();
+    other.reverse();
+
+    while let Some(addr) = preferred.pop() {
+        interleaved.push(addr);
+        interleaved.extend(other.pop());
+    }
+
+    interleaved.extend(other.into_iter().rev());
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn alternates_families_starting_with_the_first() {
+        let resolved = addrs(&["[::1]:7125", "[::2]:7125", "10.0.0.1:7125", "10.0.0.2:7125"]);
+
+        assert_eq!(
+            interleave(resolved),
+            addrs(&["[::1]:7125", "10.0.0.1:7125", "[::2]:7125", "10.0.0.2:7125"])
+        );
+
+        let resolved = addrs(&["10.0.0.1:7125", "[::1]:7125", "10.0.0.2:7125"]);
+
+        assert_eq!(
+            interleave(resolved),
+            addrs(&["10.0.0.1:7125", "[::1]:7125", "10.0.0.2:7125"])
+        );
+    }
+
+    #[test]
+    fn keeps_the_order_of_the_leftovers() {
+        let resolved = addrs(&["10.0.0.1:7125", "[::1]:7125", "[::2]:7125", "[::3]:7125"]);
+
+        assert_eq!(
+            interleave(resolved),
+            addrs(&["10.0.0.1:7125", "[::1]:7125", "[::2]:7125", "[::3]:7125"])
+        );
+        assert_eq!(interleave(Vec::new()), Vec::new());
+    }
+}
@@ -0,0 +1,463 @@
+use crate::auth;
+use crate::deflate::{self, Inflate};
+use crate::net;
+use crate::power;
+use crate::prompt;
+use crate::tls::Tls;
+use crate::traffic::{Metered, Traffic};
+use crate::webhook;
+use crate::{rpc_result, Error, SharedState, JSON};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::error::TlsError;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::Instrument;
+
+pub type Socket = WebSocketStream<Inflate<MaybeTlsStream<Metered<TcpStream>>>>;
+
+/// State changes happening this long after one of our print control commands
+/// are attributed to this client.
+const ATTRIBUTION_WINDOW: Duration = Duration::from_secs(10);
+
+/// How often the websocket is pinged, without any message for two intervals
+/// the printer is considered unreachable.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Delay between attempts to reopen a dropped websocket.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// G-code commands that start, pause, resume or cancel a print.
+const PRINT_CONTROL_GCODES: &[&str] = &[
+    "PAUSE",
+    "RESUME",
+    "CANCEL_PRINT",
+    "SDCARD_PRINT_FILE",
+    "M24",
+    "M25",
+];
+
+/// When this client last sent a print control command, used to tell our own
+/// print state changes apart from those issued by other Moonraker clients.
+#[derive(Clone, Default)]
+pub struct LocalActions(Arc<Mutex<Option<Instant>>>);
+
+impl LocalActions {
+    pub fn record_gcode(&self, script: &str) {
+        let is_print_control = script.lines().any(|line| {
+            line.split_whitespace().next().is_some_and(|command| {
+                PRINT_CONTROL_GCODES.contains(&command.to_uppercase().as_str())
+            })
+        });
+
+        if is_print_control {
+            *self.0.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    pub fn record_method(&self, method: &str) {
+        if method.starts_with("printer.print.") {
+            *self.0.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    fn is_recent(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .is_some_and(|sent| sent.elapsed() < ATTRIBUTION_WINDOW)
+    }
+}
+
+/// Keeps a websocket open to the Moonraker instance currently selected in
+/// `url_rx`, reporting G-code responses and print state changes to the
+/// console, raising alerts on printer errors and firing the webhook when
+/// prints end.
+pub async fn run(
+    client: reqwest::Client,
+    mut url_rx: watch::Receiver<String>,
+    notice_tx: Sender<String>,
+    shared: SharedState,
+) -> Result<(), Error> {
+    loop {
+        let url = url_rx.borrow_and_update().clone();
+
+        tokio::select! {
+            res = listen(&client, &url, &notice_tx, &shared).instrument(tracing::info_span!("websocket", url)) => {
+                shared.status.set_online(false);
+
+                match res {
+                    Ok(()) => tracing::info!("websocket closed"),
+                    Err(err) => tracing::warn!(%err, "websocket failed"),
+                }
+
+                // Connection refused or dropped, retry unless we switched printer meanwhile
+                tokio::select! {
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                    changed = url_rx.changed() => if changed.is_err() { return Ok(()) }
+                }
+            }
+            changed = url_rx.changed() => if changed.is_err() { return Ok(()) }
+        }
+    }
+}
+
+/// The printer objects and fields the websocket subscribes to.
+pub fn subscriptions() -> JSON {
+    json!({
+        "print_stats": ["state", "filename"],
+        "gcode_move": ["homing_origin", "speed_factor", "extrude_factor", "gcode_position", "speed"],
+        "toolhead": ["homed_axes"],
+    })
+}
+
+/// Opens the websocket of the Moonraker instance at `url`, with the
+/// credentials registered for it, counting what goes through in `traffic`.
+pub async fn connect(
+    client: &reqwest::Client,
+    url: &str,
+    tls: &Tls,
+    traffic: &Traffic,
+) -> Result<Socket, Error> {
+    let ws_url = match auth::oneshot_token(client, url).await? {
+        Some(token) => format!("{}?token={}", websocket_url(url), token),
+        None => websocket_url(url),
+    };
+    let mut upgrade = ws_url.as_str().into_client_request()?;
+    upgrade.headers_mut().extend(auth::headers(url)?);
+    upgrade.headers_mut().insert(
+        "Sec-WebSocket-Extensions",
+        HeaderValue::from_static(deflate::OFFER),
+    );
+
+    let parsed = reqwest::Url::parse(&ws_url).map_err(|err| Error::Env(err.to_string()))?;
+    let stream = Metered::new(net::connect(&parsed).await?, traffic.clone());
+
+    // TLS is set up here rather than by the websocket library, the
+    // compressed messages are only readable above it
+    let stream = match parsed.scheme() {
+        "wss" => {
+            let connector = tokio_native_tls::TlsConnector::from(tls.connector()?);
+            let host = parsed.host_str().unwrap_or_default();
+            let stream = connector.connect(host, stream).await.map_err(|err| {
+                tokio_tungstenite::tungstenite::Error::Tls(TlsError::Native(Box::new(err)))
+            })?;
+            MaybeTlsStream::NativeTls(stream)
+        }
+        _ => MaybeTlsStream::Plain(stream),
+    };
+
+    let stream = Inflate::new(stream, traffic.clone());
+    let (socket, _) = tokio_tungstenite::client_async_with_config(upgrade, stream, None).await?;
+
+    Ok(socket)
+}
+
+async fn listen(
+    client: &reqwest::Client,
+    url: &str,
+    notice_tx: &Sender<String>,
+    shared: &SharedState,
+) -> Result<(), Error> {
+    let mut socket = connect(client, url, &shared.tls, &shared.traffic).await?;
+    shared.status.set_online(true);
+
+    let queued = shared.offline_queue.len();
+
+    if queued > 0 {
+        let notice = format!(
+            "Printer is back, :replay sends the {} queued commands, :discard drops them",
+            queued
+        );
+        notice_tx.send(notice).await?;
+    }
+
+    let mut identify = json!({
+        "client_name": "moonraker-cli",
+        "version": env!("CARGO_PKG_VERSION"),
+        "type": "other",
+        "url": "https://github.com/emilianobovetti/moonraker-cli",
+    });
+    auth::identify(url, &mut identify);
+    let subscribe = json!({ "objects": subscriptions() });
+
+    socket
+        .send(request(1, "server.connection.identify", identify))
+        .await?;
+    socket
+        .send(request(2, "printer.objects.subscribe", subscribe))
+        .await?;
+
+    // Moonraker counts our own websocket too
+    let server_info = rpc_result(client, url, "server.info", None).await?;
+
+    if let Some(klippy_state) = server_info["klippy_state"].as_str() {
+        shared.status.set_klippy_state(klippy_state);
+    }
+
+    let other_clients = server_info["websocket_count"]
+        .as_u64()
+        .unwrap_or(1)
+        .saturating_sub(1);
+
+    if other_clients > 0 {
+        let notice = format!("{} other clients connected to Moonraker", other_clients);
+        notice_tx.send(notice).await?;
+    }
+
+    let mut state: Option<String> = None;
+    let mut filename: Option<String> = None;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    loop {
+        let message = tokio::select! {
+            message = socket.next() => message,
+            _ = heartbeat.tick() => {
+                // A dead host or network doesn't close the socket, silence does
+                if last_seen.elapsed() > HEARTBEAT_INTERVAL * 2 {
+                    notice_tx.send("Moonraker stopped answering, reconnecting".to_string()).await?;
+                    return Ok(());
+                }
+
+                socket.send(Message::Ping(Default::default())).await?;
+                continue;
+            }
+        };
+
+        let Some(message) = message else {
+            break;
+        };
+
+        last_seen = Instant::now();
+
+        let Ok(value) = serde_json::from_str::<JSON>(message?.to_text()?) else {
+            continue;
+        };
+
+        match value["method"].as_str() {
+            Some("notify_klippy_ready") => shared.status.set_klippy_state("ready"),
+            Some("notify_klippy_disconnected") => shared.status.set_klippy_state("disconnected"),
+            Some("notify_klippy_shutdown") => {
+                shared.status.set_klippy_state("shutdown");
+                shared.alerts.raise("Klippy shut down");
+                let filename = filename.as_deref();
+                shared
+                    .config
+                    .hooks
+                    .run("shutdown", url, filename, notice_tx);
+            }
+            Some("notify_filelist_changed") => shared.files.apply(&value["params"][0]),
+            Some("notify_gcode_response") => match value["params"][0].as_str() {
+                Some(response) if prompt::is_action(response) => {
+                    if let Some(notice) = shared.prompts.handle(response) {
+                        notice_tx.send(notice).await?;
+                    }
+                }
+                Some(response) => match host_action(response, shared) {
+                    Some(notice) => notice_tx.send(notice).await?,
+                    None => notice_tx.send(response.to_string()).await?,
+                },
+                None => {}
+            },
+            _ => {}
+        }
+
+        // The subscription response carries the initial status, later changes
+        // come as notifications
+        let status = if value["method"] == "notify_status_update" {
+            &value["params"][0]
+        } else {
+            &value["result"]["status"]
+        };
+
+        if let Some(new_filename) = status["print_stats"]["filename"].as_str() {
+            filename = Some(new_filename.to_string()).filter(|name| !name.is_empty());
+        }
+
+        // The Z of the G-code offset, what babystepping adjusts
+        if let Some(z_offset) = status["gcode_move"]["homing_origin"][2].as_f64() {
+            shared.status.set_z_offset(z_offset);
+        }
+
+        if let Some(speed) = status["gcode_move"]["speed_factor"].as_f64() {
+            shared.status.set_speed_factor(speed);
+        }
+
+        if let Some(flow) = status["gcode_move"]["extrude_factor"].as_f64() {
+            shared.status.set_flow_factor(flow);
+        }
+
+        if let Some(position) = status["gcode_move"]["gcode_position"].as_array() {
+            let coordinate = |index: usize| position.get(index).and_then(JSON::as_f64);
+
+            if let (Some(x), Some(y), Some(z), Some(e)) =
+                (coordinate(0), coordinate(1), coordinate(2), coordinate(3))
+            {
+                shared.status.set_position([x, y, z, e]);
+            }
+        }
+
+        if let Some(speed) = status["gcode_move"]["speed"].as_f64() {
+            shared.status.set_speed(speed);
+        }
+
+        if let Some(homed_axes) = status["toolhead"]["homed_axes"].as_str() {
+            shared.status.set_homed_axes(homed_axes);
+        }
+
+        if let Some(new_state) = status["print_stats"]["state"].as_str() {
+            shared.status.set_print_state(new_state);
+            let is_local = shared.local_actions.is_recent();
+            let notice = state
+                .as_deref()
+                .and_then(|old_state| describe(old_state, new_state, is_local));
+
+            if let Some(notice) = notice {
+                notice_tx.send(notice).await?;
+            }
+
+            // Not for a print that had already failed before connecting
+            if new_state == "error" && state.as_deref().is_some_and(|old| old != "error") {
+                shared.alerts.raise("Print failed");
+            }
+
+            if state.is_some() {
+                let hooks = &shared.config.hooks;
+                hooks.run(new_state, url, filename.as_deref(), notice_tx);
+            }
+
+            // Only a completed print powers off, after a failed one
+            // someone may want to look at the printer first
+            let armed = match new_state {
+                "complete" | "error" | "cancelled" if state.is_some() => shared.power_off.take(url),
+                _ => None,
+            };
+
+            match (armed, new_state) {
+                (Some(device), "complete") => {
+                    tokio::spawn(power::off_when_cool(
+                        client.clone(),
+                        url.to_string(),
+                        device,
+                        shared.config.units,
+                        notice_tx.clone(),
+                    ));
+                }
+                (Some(device), "error") => {
+                    let notice = format!("Print failed, {} stays on", device);
+                    notice_tx.send(notice).await?;
+                }
+                (Some(device), _) => {
+                    let notice = format!("Print cancelled, {} stays on", device);
+                    notice_tx.send(notice).await?;
+                }
+                (None, _) => {}
+            }
+
+            let webhook = shared.webhooks.get();
+            let message = match new_state {
+                "complete" => Some("Print complete"),
+                "error" => Some("Print failed"),
+                _ => None,
+            };
+
+            if let (Some(webhook), Some(message), true) = (webhook, message, state.is_some()) {
+                let (client, url, notice_tx) = (client.clone(), url.to_string(), notice_tx.clone());
+                let (event, filename) = (new_state.to_string(), filename.clone());
+
+                // Snapshots and slow endpoints must not hold back notifications
+                tokio::spawn(async move {
+                    let fired = webhook::fire(
+                        &client,
+                        &url,
+                        &webhook,
+                        &event,
+                        filename.as_deref(),
+                        message,
+                    )
+                    .await;
+
+                    if let Err(err) = fired {
+                        let _ = notice_tx
+                            .send(format!("Webhook {} failed: {}", webhook.url, err))
+                            .await;
+                    }
+                });
+            }
+
+            state = Some(new_state.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Follows the `// action:` messages macros send to the host, so a pause
+/// or cancel shows up even before the print state changes. Returns the
+/// notice replacing the response, none for responses of other kinds.
+fn host_action(response: &str, shared: &SharedState) -> Option<String> {
+    let action = response.strip_prefix("// action:")?;
+    let (action, args) = action.split_once(' ').unwrap_or((action, ""));
+
+    match action {
+        "paused" => {
+            shared.status.set_print_state("paused");
+            // Filament runouts pause this way, someone has to come over
+            shared.alerts.raise("Print paused by the printer");
+            Some("Print paused by the printer".to_string())
+        }
+        "resumed" => {
+            shared.status.set_print_state("printing");
+            Some("Print resumed by the printer".to_string())
+        }
+        "cancel" => {
+            shared.status.set_print_state("cancelled");
+            Some("Print cancelled by the printer".to_string())
+        }
+        "notification" => Some(format!("Printer: {}", args.trim())),
+        _ => None,
+    }
+}
+
+fn describe(old_state: &str, new_state: &str, is_local: bool) -> Option<String> {
+    let action = match (old_state, new_state) {
+        ("paused", "printing") => "resumed",
+        (_, "printing") => "started",
+        ("printing", "paused") => "paused",
+        (_, "cancelled") => "cancelled",
+        (_, "complete") => return Some("Print complete".to_string()),
+        _ => return None,
+    };
+
+    let origin = if is_local {
+        "this client"
+    } else {
+        "another client"
+    };
+
+    Some(format!("Print {} by {}", action, origin))
+}
+
+pub fn request(id: u64, method: &str, params: JSON) -> Message {
+    let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+
+    Message::text(request.to_string())
+}
+
+/// Maps the HTTP base URL to the websocket endpoint, `http` to `ws` and
+/// `https` to `wss`.
+fn websocket_url(url: &str) -> String {
+    let url = url
+        .strip_prefix("http")
+        .map_or(url.to_string(), |rest| format!("ws{}", rest));
+
+    format!("{}/websocket", url)
+}
@@ -0,0 +1,124 @@
+use crate::units::Units;
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+
+/// What `:objects` does with the objects of the file printing, as labeled
+/// by the slicer for Klipper's `[exclude_object]`.
+pub enum Command {
+    List,
+    /// Stops printing the nth object listed, from 1
+    Exclude(usize),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        match args.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => Ok(Command::List),
+            ["exclude", n] => n
+                .parse::<usize>()
+                .map(Command::Exclude)
+                .map_err(|_| format!("Invalid object {}, expected a number", n)),
+            ["exclude"] => Err("Missing object for :objects exclude, try :help".to_string()),
+            _ => Err(format!(
+                "Unknown :objects argument {}, try :help",
+                args.trim()
+            )),
+        }
+    }
+}
+
+pub async fn run(
+    client: &reqwest::Client,
+    url: &str,
+    command: Command,
+    units: Units,
+) -> Result<String, Error> {
+    let status = query(client, url).await?;
+
+    match command {
+        Command::List => Ok(render(&status, units)),
+        Command::Exclude(n) => {
+            let objects = objects(&status);
+            let name = n
+                .checked_sub(1)
+                .and_then(|index| objects.get(index))
+                .and_then(|object| object["name"].as_str())
+                .ok_or_else(|| {
+                    Error::Env(format!("No object {}, expected 1 to {}", n, objects.len()))
+                })?;
+
+            if is_excluded(&status, name) {
+                return Err(Error::Env(format!("{} is excluded already", name)));
+            }
+
+            let params = Some(json!({ "script": format!("EXCLUDE_OBJECT NAME={}", name) }));
+            rpc_result(client, url, "printer.gcode.script", params).await?;
+
+            Ok(format!("Excluded {}, the rest goes on printing", name))
+        }
+    }
+}
+
+async fn query(client: &reqwest::Client, url: &str) -> Result<JSON, Error> {
+    let params = Some(json!({
+        "objects": { "exclude_object": ["objects", "excluded_objects", "current_object"] }
+    }));
+    let mut result = rpc_result(client, url, "printer.objects.query", params).await?;
+
+    Ok(result["status"]
+        .get_mut("exclude_object")
+        .map(JSON::take)
+        .unwrap_or_default())
+}
+
+fn objects(status: &JSON) -> Vec<JSON> {
+    status["objects"].as_array().cloned().unwrap_or_default()
+}
+
+fn is_excluded(status: &JSON, name: &str) -> bool {
+    status["excluded_objects"]
+        .as_array()
+        .is_some_and(|excluded| excluded.iter().any(|excluded| excluded == name))
+}
+
+/// The objects numbered with their center, the one printing and the
+/// excluded ones marked.
+fn render(status: &JSON, units: Units) -> String {
+    let objects = objects(status);
+
+    if objects.is_empty() {
+        return "No objects, the slicer must label them and Klipper needs [exclude_object]"
+            .to_string();
+    }
+
+    let rows = objects
+        .iter()
+        .map(|object| {
+            let name = object["name"].as_str().unwrap_or_default();
+            let marker = if is_excluded(status, name) {
+                "excluded"
+            } else if status["current_object"] == name {
+                "printing"
+            } else {
+                ""
+            };
+            let center = match (object["center"][0].as_f64(), object["center"][1].as_f64()) {
+                (Some(x), Some(y)) => {
+                    format!("at {}, {}", units.format_length(x), units.format_length(y))
+                }
+                _ => String::new(),
+            };
+
+            (name.to_string(), format!("{:8}  {}", marker, center))
+        })
+        .collect();
+    let mut lines: Vec<String> = popup::numbered(rows)
+        .into_iter()
+        .map(|line| line.trim_end().to_string())
+        .collect();
+
+    lines.push(String::new());
+    lines.push(":objects exclude <n> stops printing it, there's no undo".to_string());
+
+    popup::render("Objects", &lines)
+}
@@ -0,0 +1,24 @@
+use crate::Request;
+use std::sync::{Arc, Mutex};
+
+/// Commands typed while the printer was unreachable, kept with the line
+/// they were typed as until they're replayed or discarded.
+#[derive(Clone, Default)]
+pub struct OfflineQueue(Arc<Mutex<Vec<(String, Request)>>>);
+
+impl OfflineQueue {
+    /// Queues a request, returning how many are queued now.
+    pub fn push(&self, line: &str, request: Request) -> usize {
+        let mut queue = self.0.lock().unwrap();
+        queue.push((line.to_string(), request));
+        queue.len()
+    }
+
+    pub fn take(&self) -> Vec<(String, Request)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
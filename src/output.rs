@@ -0,0 +1,159 @@
+use crate::cli::OutputFormat;
+use crate::{format_json, Error, JSON};
+
+/// `value` as the machine readable formats or a table, `None` for plain
+/// text, which each subcommand writes its own way.
+pub fn render(value: JSON, format: OutputFormat) -> Result<Option<String>, Error> {
+    match format {
+        OutputFormat::Text => Ok(None),
+        OutputFormat::Json => format_json(value).map(Some),
+        OutputFormat::Yaml => serde_norway::to_string(&value)
+            .map(|yaml| Some(yaml.trim_end().to_string()))
+            .map_err(|err| Error::Env(err.to_string())),
+        OutputFormat::Table => Ok(Some(table(&value))),
+    }
+}
+
+/// A list of objects has a column for each key, an object is listed as
+/// key and value. Nested objects are flattened, keys joined with dots.
+fn table(value: &JSON) -> String {
+    match value {
+        JSON::Array(items) if items.iter().all(JSON::is_object) && !items.is_empty() => {
+            let rows: Vec<Vec<(String, String)>> = items.iter().map(flatten).collect();
+            let mut header: Vec<String> = Vec::new();
+
+            for (key, _) in rows.iter().flatten() {
+                if !header.contains(key) {
+                    header.push(key.clone());
+                }
+            }
+
+            let cells = rows
+                .iter()
+                .map(|row| {
+                    header
+                        .iter()
+                        .map(|key| {
+                            row.iter()
+                                .find(|(name, _)| name == key)
+                                .map(|(_, value)| value.clone())
+                                .unwrap_or_default()
+                        })
+                        .collect()
+                })
+                .collect::<Vec<Vec<String>>>();
+
+            columns(&header, &cells)
+        }
+        JSON::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join("\n"),
+        JSON::Object(_) => {
+            let cells = flatten(value)
+                .into_iter()
+                .map(|(key, value)| vec![key, value])
+                .collect::<Vec<_>>();
+
+            columns(&["key".to_string(), "value".to_string()], &cells)
+        }
+        value => cell(value),
+    }
+}
+
+fn flatten(value: &JSON) -> Vec<(String, String)> {
+    let mut flat = Vec::new();
+
+    if let JSON::Object(map) = value {
+        for (key, value) in map {
+            match value {
+                JSON::Object(map) if !map.is_empty() => {
+                    for (path, value) in flatten(value) {
+                        flat.push((format!("{}.{}", key, path), value));
+                    }
+                }
+                value => flat.push((key.clone(), cell(value))),
+            }
+        }
+    }
+
+    flat
+}
+
+/// Strings without quotes, anything else as JSON.
+fn cell(value: &JSON) -> String {
+    match value {
+        JSON::String(string) => string.clone(),
+        JSON::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Left aligned columns, as wide as their widest cell.
+fn columns(header: &[String], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            rows.iter()
+                .map(|row| row[index].chars().count())
+                .chain(std::iter::once(name.chars().count()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let line = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    std::iter::once(line(header))
+        .chain(rows.iter().map(|row| line(row)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lists_objects_in_columns() {
+        let value = json!([
+            { "filename": "benchy.gcode", "size": 1024 },
+            { "filename": "cube.gcode", "job": { "id": "0002" } },
+        ]);
+
+        assert_eq!(
+            table(&value),
+            "filename      size  job.id\n\
+             benchy.gcode  1024\n\
+             cube.gcode          0002"
+        );
+    }
+
+    #[test]
+    fn lists_an_object_as_keys_and_values() {
+        let value = json!({ "state": "ready", "toolhead": { "homed_axes": "xyz" }, "error": null });
+
+        assert_eq!(
+            table(&value),
+            "key                  value\n\
+             error\n\
+             state                ready\n\
+             toolhead.homed_axes  xyz"
+        );
+    }
+
+    #[test]
+    fn prints_scalars_and_other_lists_as_is() {
+        assert_eq!(table(&json!("ok")), "ok");
+        assert_eq!(table(&json!([1, "two", [3]])), "1\ntwo\n[3]");
+        assert_eq!(table(&json!([])), "");
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Used when `$PAGER` is not set.
+const DEFAULT_PAGER: &str = "less -R";
+
+/// Keeps the last reply for `:copy` and `:page`, which shows it in
+/// `$PAGER` while the status bar stands aside.
+#[derive(Clone, Default)]
+pub struct Pager(Arc<Mutex<State>>);
+
+#[derive(Default)]
+struct State {
+    last: Option<String>,
+    open: bool,
+}
+
+impl Pager {
+    /// Remembers `reply` and returns what to print of it, a notice instead
+    /// when it's longer than `max_lines`.
+    pub fn keep(&self, reply: String, max_lines: Option<usize>) -> String {
+        let lines = reply.lines().count();
+        let folded = max_lines
+            .filter(|max_lines| lines > *max_lines)
+            .map(|_| format!("{} lines, :page shows them", lines));
+
+        let mut state = self.0.lock().unwrap();
+        state.last = Some(reply.clone());
+
+        folded.unwrap_or(reply)
+    }
+
+    /// Whether the pager has the terminal, nothing else should draw on it.
+    pub fn is_open(&self) -> bool {
+        self.0.lock().unwrap().open
+    }
+
+    /// The last reply, for `:copy`.
+    pub fn last(&self) -> Option<String> {
+        self.0.lock().unwrap().last.clone()
+    }
+
+    /// Pipes the last reply into the pager and waits for it to quit.
+    pub fn show(&self) -> Result<(), String> {
+        let Some(reply) = self.last() else {
+            return Err("No reply to page yet".to_string());
+        };
+
+        self.0.lock().unwrap().open = true;
+        let shown = run_pager(&reply);
+        self.0.lock().unwrap().open = false;
+
+        shown.map_err(|err| format!("Unable to run the pager: {}", err))
+    }
+}
+
+fn run_pager(text: &str) -> io::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+
+    // The whole screen is the pager's, the status bar sets its row aside
+    // again once it's closed
+    let mut stdout = io::stdout();
+    stdout.write_all(b"\x1b[r")?;
+    stdout.flush()?;
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting early closes the pipe, which is not an error
+        let _ = stdin.write_all(text.as_bytes());
+    }
+
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use crate::fuzzy;
+use crate::meta;
+
+/// Moonraker methods worth reaching from the palette, each one runs via `:rpc`.
+const METHODS: &[(&str, &str)] = &[
+    ("printer.info", "Klippy host information"),
+    ("printer.restart", "restart Klippy"),
+    (
+        "printer.firmware_restart",
+        "restart Klippy and the MCU firmware",
+    ),
+    ("printer.emergency_stop", "halt the printer immediately"),
+    ("printer.objects.list", "list available printer objects"),
+    ("printer.query_endstops.status", "query endstops state"),
+    ("printer.print.pause", "pause the current print"),
+    ("printer.print.resume", "resume a paused print"),
+    ("printer.print.cancel", "cancel the current print"),
+    ("server.info", "Moonraker server information"),
+    ("server.config", "Moonraker configuration"),
+    ("server.restart", "restart Moonraker"),
+    ("server.files.list", "list gcode files"),
+    ("server.history.list", "list past jobs"),
+    ("server.history.totals", "aggregate print statistics"),
+    ("server.job_queue.status", "show the job queue"),
+    ("server.gcode_store", "recent G-code responses"),
+    ("machine.system_info", "printer host system information"),
+    ("machine.proc_stats", "printer host process statistics"),
+    ("machine.reboot", "reboot the printer host"),
+    ("machine.shutdown", "shut down the printer host"),
+];
+
+/// Something the palette can put on the input line.
+pub struct Entry {
+    pub command: String,
+    pub description: String,
+}
+
+/// Meta-commands, Moonraker methods and the printer G-code macros.
+pub fn entries(macros: &[String]) -> Vec<Entry> {
+    let commands = meta::COMMANDS.iter().map(|(usage, description)| Entry {
+        // Leave room for the arguments after the command name
+        command: match usage.split_once(' ') {
+            Some((name, _)) => format!("{} ", name),
+            None => usage.to_string(),
+        },
+        description: description.to_string(),
+    });
+
+    let methods = METHODS.iter().map(|(method, description)| Entry {
+        command: format!(":rpc {}", method),
+        description: description.to_string(),
+    });
+
+    let macros = macros.iter().map(|name| Entry {
+        command: name.clone(),
+        description: "G-code macro".to_string(),
+    });
+
+    commands.chain(methods).chain(macros).collect()
+}
+
+pub fn search<'a>(query: &str, entries: &'a [Entry]) -> Vec<&'a Entry> {
+    fuzzy::rank(query, entries, |entry| &entry.command)
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Commands sent to the network loop and still waiting for a reply, oldest
+/// first. Replies are matched back to their command through the request id.
+#[derive(Clone, Default)]
+pub struct Pending(Arc<Mutex<Vec<InFlight>>>);
+
+pub struct InFlight {
+    pub id: Uuid,
+    pub command: String,
+    pub sent: Instant,
+}
+
+impl Pending {
+    /// Tracks a new command, returning the id its request is sent with.
+    pub fn start(&self, command: &str) -> Uuid {
+        let id = Uuid::new_v4();
+
+        self.0.lock().unwrap().push(InFlight {
+            id,
+            command: command.to_string(),
+            sent: Instant::now(),
+        });
+
+        id
+    }
+
+    pub fn finish(&self, id: Uuid) -> Option<InFlight> {
+        let mut pending = self.0.lock().unwrap();
+        let index = pending.iter().position(|request| request.id == id)?;
+
+        Some(pending.remove(index))
+    }
+
+    /// Stops tracking the most recent command, its reply will be dropped.
+    pub fn abandon_latest(&self) -> Option<InFlight> {
+        self.0.lock().unwrap().pop()
+    }
+
+    /// Every pending command with how long it has been waiting, oldest first.
+    pub fn list(&self) -> Vec<(String, Duration)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| (request.command.clone(), request.sent.elapsed()))
+            .collect()
+    }
+
+    /// The command waiting the longest, how long it has been waiting and
+    /// how many others are pending.
+    pub fn oldest(&self) -> Option<(String, Duration, usize)> {
+        let pending = self.0.lock().unwrap();
+
+        pending.first().map(|request| {
+            (
+                request.command.clone(),
+                request.sent.elapsed(),
+                pending.len() - 1,
+            )
+        })
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+
+/// What `:pins` does with the `output_pin` objects, the lights, fans and
+/// relays wired to a pin rather than set up as power devices.
+pub enum Command {
+    List,
+    /// Sets the nth pin listed, from 1
+    Set(usize, Value),
+}
+
+pub enum Value {
+    /// The full `scale`
+    On,
+    Off,
+    Toggle,
+    /// In the unit of the `scale`, only pwm pins take what's in between
+    Level(f64),
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let n = match words.first() {
+            None => return Ok(Command::List),
+            Some(n) => n
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid pin {}, expected a number", n))?,
+        };
+
+        let value = match words[1..] {
+            ["on"] => Value::On,
+            ["off"] => Value::Off,
+            ["toggle"] => Value::Toggle,
+            [value] => value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| *value >= 0.0)
+                .map(Value::Level)
+                .ok_or_else(|| format!("Invalid value {}, expected on, off or a number", value))?,
+            _ => return Err(format!("Unknown :pins argument {}, try :help", args.trim())),
+        };
+
+        Ok(Command::Set(n, value))
+    }
+}
+
+/// An `output_pin` with its settings from the Klipper config.
+struct Pin {
+    name: String,
+    /// From 0 to 1
+    value: f64,
+    pwm: bool,
+    /// What `SET_PIN` values are relative to, 1 unless configured
+    scale: f64,
+}
+
+impl Pin {
+    fn describe(&self) -> String {
+        match (self.pwm, self.value > 0.0) {
+            (true, _) => format!("pwm      {:.2} of {}", self.value * self.scale, self.scale),
+            (false, true) => "digital  on".to_string(),
+            (false, false) => "digital  off".to_string(),
+        }
+    }
+}
+
+pub async fn run(client: &reqwest::Client, url: &str, command: Command) -> Result<String, Error> {
+    let pins = query(client, url).await?;
+
+    if pins.is_empty() {
+        return Err(Error::Env(
+            "No [output_pin] in the printer config".to_string(),
+        ));
+    }
+
+    let (n, value) = match command {
+        Command::List => return Ok(render(&pins)),
+        Command::Set(n, value) => (n, value),
+    };
+    let pin = n
+        .checked_sub(1)
+        .and_then(|index| pins.get(index))
+        .ok_or_else(|| Error::Env(format!("No pin {}, expected 1 to {}", n, pins.len())))?;
+
+    let value = match value {
+        Value::On => pin.scale,
+        Value::Off => 0.0,
+        Value::Toggle if pin.value > 0.0 => 0.0,
+        Value::Toggle => pin.scale,
+        Value::Level(value) if value > pin.scale => {
+            return Err(Error::Env(format!(
+                "{} is over the scale of {}, {}",
+                value, pin.name, pin.scale
+            )))
+        }
+        Value::Level(value) if !pin.pwm && value != 0.0 && value != pin.scale => {
+            return Err(Error::Env(format!(
+                "{} is not a pwm pin, it's either on or off",
+                pin.name
+            )))
+        }
+        Value::Level(value) => value,
+    };
+
+    let script = format!("SET_PIN PIN={} VALUE={}", pin.name, value);
+    rpc_result(
+        client,
+        url,
+        "printer.gcode.script",
+        Some(json!({ "script": script })),
+    )
+    .await?;
+
+    match (pin.pwm, value > 0.0) {
+        (true, _) => Ok(format!("{} at {:.2} of {}", pin.name, value, pin.scale)),
+        (false, true) => Ok(format!("{} on", pin.name)),
+        (false, false) => Ok(format!("{} off", pin.name)),
+    }
+}
+
+/// The pins with their value and settings, by name.
+async fn query(client: &reqwest::Client, url: &str) -> Result<Vec<Pin>, Error> {
+    let objects = rpc_result(client, url, "printer.objects.list", None).await?;
+    let mut objects: Vec<&str> = objects["objects"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(JSON::as_str)
+        .filter(|object| object.starts_with("output_pin "))
+        .collect();
+    objects.sort_unstable();
+
+    if objects.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut queried: serde_json::Map<String, JSON> = objects
+        .iter()
+        .map(|object| (object.to_string(), json!(["value"])))
+        .collect();
+    queried.insert("configfile".to_string(), json!(["settings"]));
+    let params = Some(json!({ "objects": queried }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let status = &status["status"];
+
+    Ok(objects
+        .into_iter()
+        .map(|object| {
+            // Settings are keyed by the lowercased section name
+            let settings = &status["configfile"]["settings"][object.to_lowercase()];
+
+            Pin {
+                name: object["output_pin ".len()..].to_string(),
+                value: status[object]["value"].as_f64().unwrap_or(0.0),
+                pwm: settings["pwm"].as_bool().unwrap_or(false),
+                scale: settings["scale"].as_f64().unwrap_or(1.0),
+            }
+        })
+        .collect())
+}
+
+fn render(pins: &[Pin]) -> String {
+    let mut lines = popup::numbered(
+        pins.iter()
+            .map(|pin| (pin.name.clone(), pin.describe()))
+            .collect(),
+    );
+
+    lines.push(String::new());
+    lines.push(":pins <n> on|off|toggle, or a value for pwm pins".to_string());
+
+    popup::render("Output pins", &lines)
+}
@@ -0,0 +1,250 @@
+use crate::status::truncate;
+use crate::units::Units;
+use crate::{rpc_result, Error, JSON};
+
+/// Rows of the plot area, the axis labels and summary come on top.
+const PLOT_HEIGHT: usize = 12;
+
+/// Columns taken by the labels and the axis left of the plot area.
+const AXIS_WIDTH: usize = 7;
+
+/// The plot area is no wider than this however wide the terminal.
+const MAX_COLUMNS: usize = 200;
+
+/// Moonraker keeps this many seconds of one sample per second.
+pub const STORE_SECONDS: usize = 1200;
+
+/// The heater tuned by a `PID_CALIBRATE HEATER=<heater>` script, if any.
+pub fn pid_calibrate_heater(script: &str) -> Option<String> {
+    script.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+
+        if !words.next()?.eq_ignore_ascii_case("PID_CALIBRATE") {
+            return None;
+        }
+
+        words.find_map(|word| {
+            let (param, value) = word.split_once('=')?;
+            param
+                .eq_ignore_ascii_case("HEATER")
+                .then(|| value.to_lowercase())
+        })
+    })
+}
+
+/// Plots the last `seconds` of `heater` temperatures from Moonraker's
+/// temperature store against the target, with the peak and overshoot, to
+/// judge a PID tuning run before saving its constants.
+pub async fn temperature(
+    client: &reqwest::Client,
+    url: &str,
+    heater: &str,
+    seconds: usize,
+    units: Units,
+) -> Result<String, Error> {
+    let store = rpc_result(client, url, "server.temperature_store", None).await?;
+    let Some((heater, samples)) = store_entry(&store, heater) else {
+        return Err(Error::Rpc(format!(
+            "no temperatures recorded for {}",
+            heater
+        )));
+    };
+
+    let series = |key: &str| -> Vec<f64> {
+        let values: Vec<f64> = samples[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(JSON::as_f64)
+            .collect();
+        let skip = values.len().saturating_sub(seconds);
+
+        values[skip..].to_vec()
+    };
+
+    // A target of 0 is off, whatever the display unit
+    let temperatures: Vec<f64> = series("temperatures")
+        .into_iter()
+        .map(|temp| units.temperature(temp))
+        .collect();
+    let targets: Vec<f64> = series("targets")
+        .into_iter()
+        .map(|target| {
+            if target > 0.0 {
+                units.temperature(target)
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    if temperatures.is_empty() {
+        return Err(Error::Rpc(format!(
+            "no temperatures recorded for {}",
+            heater
+        )));
+    }
+
+    let width = terminal_size::terminal_size()
+        .map(|(width, _)| width.0 as usize)
+        .filter(|width| *width > 0)
+        .unwrap_or(80);
+
+    Ok(render(
+        heater,
+        &temperatures,
+        &targets,
+        units.temperature_symbol(),
+        width,
+    ))
+}
+
+/// The samples of `heater` in the temperature store with its key there.
+/// `PID_CALIBRATE` takes the bare name of a generic heater, like `chamber`
+/// for the `heater_generic chamber` the store has.
+fn store_entry<'a>(store: &'a JSON, heater: &str) -> Option<(&'a str, &'a JSON)> {
+    let sensors = store.as_object()?;
+
+    sensors
+        .get_key_value(heater)
+        .or_else(|| {
+            let generic = format!("heater_generic {}", heater);
+            sensors
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(&generic))
+        })
+        .map(|(key, samples)| (key.as_str(), samples))
+}
+
+/// Temperatures drawn with `*` and the target with `-`, one column per
+/// bucket of samples and time running left to right, in `width` columns
+/// with the axis. Values are already in the display unit, `symbol`.
+fn render(
+    heater: &str,
+    temperatures: &[f64],
+    targets: &[f64],
+    symbol: &str,
+    width: usize,
+) -> String {
+    let columns = width
+        .saturating_sub(AXIS_WIDTH)
+        .min(MAX_COLUMNS)
+        .min(temperatures.len());
+    let bucket = |values: &[f64], column: usize| -> Option<f64> {
+        let start = column * values.len() / columns;
+        let end = ((column + 1) * values.len() / columns).max(start + 1);
+        let bucket = values.get(start..end.min(values.len()))?;
+
+        Some(bucket.iter().sum::<f64>() / bucket.len() as f64)
+    };
+
+    let temperature_points: Vec<f64> = (0..columns)
+        .filter_map(|column| bucket(temperatures, column))
+        .collect();
+    let target_points: Vec<Option<f64>> = (0..columns)
+        .map(|column| bucket(targets, column).filter(|target| *target > 0.0))
+        .collect();
+
+    let values = temperature_points
+        .iter()
+        .chain(target_points.iter().flatten());
+    let min = values
+        .clone()
+        .fold(f64::INFINITY, |min, value| min.min(*value));
+    let max = values.fold(f64::NEG_INFINITY, |max, value| max.max(*value));
+    let span = (max - min).max(1.0);
+    let row = |value: f64| ((max - value) / span * (PLOT_HEIGHT - 1) as f64).round() as usize;
+
+    let mut grid = vec![vec![' '; columns]; PLOT_HEIGHT];
+
+    for (column, target) in target_points.iter().enumerate() {
+        if let Some(target) = target {
+            grid[row(*target)][column] = '-';
+        }
+    }
+
+    for (column, temperature) in temperature_points.iter().enumerate() {
+        grid[row(*temperature)][column] = '*';
+    }
+
+    let peak = temperatures
+        .iter()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let target = targets.iter().copied().fold(0.0, f64::max);
+    let mut lines = vec![if target > 0.0 {
+        format!(
+            "{}: peak {:.1}{}, overshoot {:.1}{} over the {:.0}{} target, last {}s",
+            heater,
+            peak,
+            symbol,
+            peak - target,
+            symbol,
+            target,
+            symbol,
+            temperatures.len()
+        )
+    } else {
+        format!(
+            "{}: peak {:.1}{}, last {}s",
+            heater,
+            peak,
+            symbol,
+            temperatures.len()
+        )
+    }];
+
+    for (index, cells) in grid.into_iter().enumerate() {
+        let label = match index {
+            0 => format!("{:5.0}", max),
+            index if index == PLOT_HEIGHT - 1 => format!("{:5.0}", min),
+            _ => " ".repeat(5),
+        };
+        lines.push(format!(
+            "{} │{}",
+            label,
+            cells.into_iter().collect::<String>()
+        ));
+    }
+
+    lines.push(format!("{} └{}", " ".repeat(5), "─".repeat(columns)));
+
+    lines
+        .iter()
+        .map(|line| truncate(line, width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_tiny_terminals() {
+        let temperatures = vec![20.0, 60.0, 120.0, 200.0, 215.0, 210.0];
+        let targets = vec![210.0; 6];
+
+        for width in [0, 1, 2, 8, 20] {
+            let plot = render("extruder", &temperatures, &targets, "°C", width);
+
+            for line in plot.lines() {
+                assert!(
+                    line.chars().count() <= width,
+                    "{} columns: {:?}",
+                    width,
+                    line
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn takes_a_column_per_sample_when_there_is_room() {
+        let temperatures = vec![20.0, 40.0, 60.0];
+        let plot = render("bed", &temperatures, &[0.0; 3], "°C", 80);
+        let axis = plot.lines().last().unwrap();
+
+        assert_eq!(axis.chars().filter(|c| *c == '─').count(), 3);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::status::truncate;
+
+/// Draws `lines` in a box with `title` on its top border, horizontally
+/// centered in the terminal. Lines too wide for the terminal are cut.
+pub fn render(title: &str, lines: &[String]) -> String {
+    // Terminals without a size set report 0 columns
+    let terminal_width = terminal_size::terminal_size()
+        .map(|(width, _)| width.0 as usize)
+        .filter(|width| *width > 0);
+    render_width(title, lines, terminal_width)
+}
+
+/// Numbered lines for a popup listing things to pick with `:<command> <n>`,
+/// each a name and what to say about it, the numbers and the names aligned.
+pub fn numbered(rows: Vec<(String, String)>) -> Vec<String> {
+    let number_width = rows.len().to_string().len();
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    rows.into_iter()
+        .enumerate()
+        .map(|(index, (name, details))| {
+            format!("{:>number_width$}  {:width$}  {}", index + 1, name, details)
+        })
+        .collect()
+}
+
+/// Like `render`, in a terminal `terminal_width` columns wide, unknown when
+/// `None`. Terminals narrower than the borders get them cut too.
+fn render_width(title: &str, lines: &[String], terminal_width: Option<usize>) -> String {
+    // Borders and padding take four columns
+    let max_width = match terminal_width {
+        None => usize::MAX,
+        Some(width) => width.saturating_sub(4),
+    };
+
+    let content_width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .chain([title.chars().count() + 2])
+        .max()
+        .unwrap_or(0)
+        .min(max_width);
+
+    let title = truncate(title, content_width.saturating_sub(2));
+    let margin = " ".repeat(
+        terminal_width
+            .unwrap_or(0)
+            .saturating_sub(content_width + 4)
+            / 2,
+    );
+
+    let mut popup = vec![format!(
+        "{}┌─ {} {}┐",
+        margin,
+        title,
+        "─".repeat(content_width.saturating_sub(title.chars().count() + 1))
+    )];
+
+    for line in lines {
+        let line = truncate(line, content_width);
+        let padding = content_width - line.chars().count();
+        popup.push(format!("{}│ {}{} │", margin, line, " ".repeat(padding)));
+    }
+
+    popup.push(format!("{}└{}┘", margin, "─".repeat(content_width + 2)));
+
+    // Only the borders are left to cut
+    if let Some(width) = terminal_width {
+        popup = popup
+            .iter()
+            .map(|line| line.chars().take(width).collect())
+            .collect();
+    }
+
+    popup.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn widest(popup: &str) -> usize {
+        popup
+            .lines()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn fits_tiny_terminals() {
+        let lines = vec!["A line wider than the terminal".to_string()];
+
+        for width in [0, 1, 2, 3, 4, 5, 10] {
+            let popup = render_width("Title", &lines, Some(width));
+            assert!(widest(&popup) <= width, "{} columns: {:?}", width, popup);
+        }
+    }
+
+    #[test]
+    fn fits_the_content_in_unknown_terminals() {
+        let lines = vec!["Content".to_string()];
+        let popup = render_width("Title", &lines, None);
+
+        assert_eq!(widest(&popup), "Content".len() + 4);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::units::Units;
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+
+/// The nozzle temperature in °C below which a device armed with
+/// `:power <n> off-after` is turned off, so the hotend fan can cool it.
+const COOL_TEMPERATURE: f64 = 50.0;
+
+/// How often the nozzle temperature is checked while waiting to power off.
+const COOL_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for the nozzle to cool down, the heaters are off after
+/// a print so it has by then even if the temperature can't be read.
+const COOL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// What `:power` does with the power devices configured in Moonraker.
+pub enum Command {
+    List,
+    /// The nth device listed, from 1, on, off or toggled
+    Set(usize, &'static str),
+    /// Turns the nth device off once the print completes and the nozzle
+    /// cools down
+    OffAfter(usize),
+    /// Leaves the device armed with `OffAfter` on
+    Keep,
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let n = match words[..] {
+            [] => return Ok(Command::List),
+            ["keep"] => return Ok(Command::Keep),
+            [n, ..] => n
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid device {}, expected a number", n))?,
+        };
+
+        match words[1..] {
+            ["on"] => Ok(Command::Set(n, "on")),
+            ["off"] => Ok(Command::Set(n, "off")),
+            ["toggle"] => Ok(Command::Set(n, "toggle")),
+            ["off-after"] => Ok(Command::OffAfter(n)),
+            _ => Err(format!(
+                "Unknown :power argument {}, try :help",
+                args.trim()
+            )),
+        }
+    }
+}
+
+/// The device to turn off once the print completes, if any, with the URL
+/// of the Moonraker instance it belongs to.
+#[derive(Clone, Default)]
+pub struct PowerOff(Arc<Mutex<Option<(String, String)>>>);
+
+impl PowerOff {
+    /// The device armed on the printer at `url`, disarming it.
+    pub fn take(&self, url: &str) -> Option<String> {
+        let mut armed = self.0.lock().unwrap();
+
+        match &*armed {
+            Some((armed_url, _)) if armed_url == url => armed.take().map(|(_, device)| device),
+            _ => None,
+        }
+    }
+
+    /// Disarms whatever device was armed, on switching printers.
+    pub fn disarm(&self) -> Option<String> {
+        self.0.lock().unwrap().take().map(|(_, device)| device)
+    }
+
+    /// Like `psu off after the print`, for the status bar.
+    pub fn render(&self) -> Option<String> {
+        let (_, device) = self.0.lock().unwrap().clone()?;
+        Some(format!("{} off after the print", device))
+    }
+
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        command: Command,
+        units: Units,
+    ) -> Result<String, Error> {
+        let result = rpc_result(client, url, "machine.device_power.devices", None).await?;
+        let devices = result["devices"].as_array().cloned().unwrap_or_default();
+        let nth = |n: usize| {
+            n.checked_sub(1)
+                .and_then(|index| devices.get(index))
+                .and_then(|device| device["device"].as_str())
+                .ok_or_else(|| {
+                    Error::Env(format!("No device {}, expected 1 to {}", n, devices.len()))
+                })
+        };
+
+        match command {
+            Command::List => Ok(self.render_devices(&devices)),
+            Command::Set(n, action) => {
+                let device = nth(n)?;
+                let params = Some(json!({ "device": device, "action": action }));
+                let result =
+                    rpc_result(client, url, "machine.device_power.post_device", params).await?;
+
+                Ok(format!(
+                    "{} {}",
+                    device,
+                    result[device].as_str().unwrap_or(action)
+                ))
+            }
+            Command::OffAfter(n) => {
+                let device = nth(n)?;
+                let params = Some(json!({ "objects": { "print_stats": ["state"] } }));
+                let status = rpc_result(client, url, "printer.objects.query", params).await?;
+
+                match status["status"]["print_stats"]["state"].as_str() {
+                    Some("printing" | "paused") => {}
+                    _ => return Err(Error::Env("No print to wait for".to_string())),
+                }
+
+                *self.0.lock().unwrap() = Some((url.to_string(), device.to_string()));
+
+                Ok(format!(
+                    "{} turns off once the print completes and the nozzle is below {}, :power keep leaves it on",
+                    device,
+                    units.format_temperature(COOL_TEMPERATURE)
+                ))
+            }
+            Command::Keep => match self.take(url) {
+                Some(device) => Ok(format!("{} stays on after the print", device)),
+                None => Ok("No device to turn off after the print".to_string()),
+            },
+        }
+    }
+
+    fn render_devices(&self, devices: &[JSON]) -> String {
+        if devices.is_empty() {
+            return "No power devices, add them under [power] in the Moonraker config".to_string();
+        }
+
+        let name = |device: &JSON| device["device"].as_str().unwrap_or_default().to_string();
+        let mut lines = popup::numbered(
+            devices
+                .iter()
+                .map(|device| {
+                    let mut details = vec![device["type"].as_str().unwrap_or_default().to_string()];
+
+                    if device["locked_while_printing"] == true {
+                        details.push("locked while printing".to_string());
+                    }
+
+                    let status = device["status"].as_str().unwrap_or("?");
+                    (
+                        name(device),
+                        format!("{:5}  {}", status, details.join(", ")),
+                    )
+                })
+                .collect(),
+        );
+
+        lines.push(String::new());
+
+        if let Some(armed) = self.render() {
+            lines.push(armed);
+        }
+
+        lines.push(":power <n> on|off|toggle, :power <n> off-after".to_string());
+        lines.push("turns it off once the print completes".to_string());
+
+        popup::render("Power", &lines)
+    }
+}
+
+/// Turns `device` off once the nozzle is below `COOL_TEMPERATURE`, or
+/// after `COOL_TIMEOUT`, unless another print starts meanwhile.
+pub async fn off_when_cool(
+    client: reqwest::Client,
+    url: String,
+    device: String,
+    units: Units,
+    notice_tx: Sender<String>,
+) {
+    let _ = notice_tx
+        .send(format!(
+            "Turning {} off once the nozzle is below {}",
+            device,
+            units.format_temperature(COOL_TEMPERATURE)
+        ))
+        .await;
+
+    let started = Instant::now();
+
+    while started.elapsed() < COOL_TIMEOUT {
+        let params =
+            Some(json!({ "objects": { "extruder": ["temperature"], "print_stats": ["state"] } }));
+
+        // Unreadable temperatures count as hot, until the timeout
+        let status = rpc_result(&client, &url, "printer.objects.query", params)
+            .await
+            .unwrap_or_default();
+        let status = &status["status"];
+
+        if status["print_stats"]["state"] == "printing" {
+            let _ = notice_tx
+                .send(format!("Another print started, {} stays on", device))
+                .await;
+            return;
+        }
+
+        match status["extruder"]["temperature"].as_f64() {
+            Some(temperature) if temperature < COOL_TEMPERATURE => break,
+            _ => tokio::time::sleep(COOL_POLL_INTERVAL).await,
+        }
+    }
+
+    let params = Some(json!({ "device": device, "action": "off" }));
+    let notice = match rpc_result(&client, &url, "machine.device_power.post_device", params).await {
+        Ok(_) => format!("Turned {} off", device),
+        Err(err) => format!("Unable to turn {} off: {}", device, err),
+    };
+    let _ = notice_tx.send(notice).await;
+}
@@ -0,0 +1,186 @@
+use crate::auth;
+use crate::download::file_url;
+use crate::spoolman;
+use crate::units::Units;
+use crate::{rpc_result, Error};
+use reqwest::header::RANGE;
+use serde_json::json;
+
+/// How much of the start of the file is scanned, where the start G-code is.
+const HEAD_BYTES: usize = 16 * 1024;
+
+/// How much of the end of the file is scanned, where the end G-code and the
+/// slicer settings comments are.
+const TAIL_BYTES: usize = 64 * 1024;
+
+/// Commands turning the heaters off, one of them is expected at the end.
+const END_GCODES: &[&str] = &[
+    "TURN_OFF_HEATERS",
+    "END_PRINT",
+    "PRINT_END",
+    "M104 S0",
+    "M140 S0",
+];
+
+/// Comments slicers write the printer profile name in.
+const PROFILE_COMMENTS: &[&str] = &["printer_settings_id", "printer_model", "PRINTER"];
+
+/// Looks for what could ruin a print or worse before it starts: a file with
+/// no end G-code leaving the heaters on, extrusion enabled on a cold
+/// hotend, temperatures beyond the configured limits, an object too tall for
+/// the printer, a file sliced for another printer profile or taking more
+/// filament than the active Spoolman spool has left. The expected profile,
+/// if any, comes from the printer's section in the config, see
+/// `Config::profile`. Returns the warnings, empty if the file looks fine.
+pub async fn check(
+    client: &reqwest::Client,
+    url: &str,
+    filename: &str,
+    expected_profile: Option<&str>,
+    units: Units,
+) -> Result<Vec<String>, Error> {
+    let params = Some(json!({ "filename": filename }));
+    let metadata = rpc_result(client, url, "server.files.metadata", params).await?;
+
+    let params = Some(json!({
+        "objects": { "configfile": ["settings"], "toolhead": ["axis_maximum"] }
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let settings = &status["status"]["configfile"]["settings"];
+
+    let head = download(
+        client,
+        url,
+        filename,
+        &format!("bytes=0-{}", HEAD_BYTES - 1),
+    )
+    .await?;
+    let tail = download(client, url, filename, &format!("bytes=-{}", TAIL_BYTES)).await?;
+
+    let mut warnings = Vec::new();
+
+    if !commands(&tail).any(|line| END_GCODES.iter().any(|end| line.starts_with(end))) {
+        warnings.push("No end G-code turning off the heaters found".to_string());
+    }
+
+    if commands(&head)
+        .chain(commands(&tail))
+        .any(|line| line.starts_with("M302"))
+    {
+        warnings.push("M302 allows extruding with a cold hotend".to_string());
+    }
+
+    let limits = [
+        ("extruder", ["M104", "M109"]),
+        ("heater_bed", ["M140", "M190"]),
+    ];
+
+    for (heater, gcodes) in limits {
+        let Some(max_temp) = settings[heater]["max_temp"].as_f64() else {
+            continue;
+        };
+
+        let over = commands(&head)
+            .chain(commands(&tail))
+            .filter(|line| gcodes.iter().any(|gcode| line.starts_with(gcode)))
+            .filter_map(|line| parameter(&line, 'S'))
+            .find(|temp| *temp > max_temp);
+
+        if let Some(temp) = over {
+            warnings.push(format!(
+                "{} set to {}, over its {} max_temp",
+                heater,
+                units.format_temperature(temp),
+                units.format_temperature(max_temp)
+            ));
+        }
+    }
+
+    let height = metadata["object_height"].as_f64();
+    let max_z = status["status"]["toolhead"]["axis_maximum"][2].as_f64();
+
+    if let (Some(height), Some(max_z)) = (height, max_z) {
+        if height > max_z {
+            warnings.push(format!(
+                "Object is {} tall, the printer reaches {}",
+                units.format_length(height),
+                units.format_length(max_z)
+            ));
+        }
+    }
+
+    let expected = expected_profile.filter(|profile| !profile.is_empty());
+
+    if let (Some(expected), Some(profile)) = (expected, profile(&head, &tail)) {
+        if profile != expected {
+            warnings.push(format!(
+                "Sliced for the {} printer profile, expected {}",
+                profile, expected
+            ));
+        }
+    }
+
+    // Without Spoolman set up in Moonraker there is nothing to check
+    if let Ok(Some(warning)) = spoolman::check(client, url, &metadata).await {
+        warnings.push(warning);
+    }
+
+    Ok(warnings)
+}
+
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    filename: &str,
+    range: &str,
+) -> Result<String, Error> {
+    let request = client.get(file_url(url, &format!("gcodes/{}", filename))?);
+    let resp = auth::authorize(request, url)
+        .header(RANGE, range)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(String::from_utf8_lossy(&resp.bytes().await?).into_owned())
+}
+
+/// Uppercase G-code commands without comments, skipping empty lines.
+fn commands(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_uppercase)
+}
+
+/// The value of a G-code parameter, like `S` in `M104 S210`.
+fn parameter(line: &str, name: char) -> Option<f64> {
+    line.split_whitespace()
+        .skip(1)
+        .find_map(|word| word.strip_prefix(name)?.parse().ok())
+}
+
+/// The printer profile in the slicer comments, like `; printer_model = MK3S`
+/// or Cura's `;PRINTER:Ender-3`.
+fn profile(head: &str, tail: &str) -> Option<String> {
+    head.lines().chain(tail.lines()).find_map(|line| {
+        let comment = line.trim().strip_prefix(';')?.trim();
+        let (key, value) = comment.split_once(['=', ':'])?;
+
+        PROFILE_COMMENTS
+            .contains(&key.trim())
+            .then(|| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    })
+}
+
+/// Warnings as shown in the console, a file with none is reported as fine.
+pub fn format(filename: &str, warnings: &[String]) -> String {
+    if warnings.is_empty() {
+        return format!("{} looks fine", filename);
+    }
+
+    let mut lines = vec![format!("{} may not print safely:", filename)];
+    lines.extend(warnings.iter().map(|warning| format!("  {}", warning)));
+    lines.join("\n")
+}
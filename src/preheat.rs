@@ -0,0 +1,175 @@
+use crate::units::Units;
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// Heater targets in °C by heater, named as in the Klipper config like
+/// `extruder`, `heater_bed` or `chamber` for a `heater_generic chamber`.
+pub type Preset = BTreeMap<String, f64>;
+
+/// The presets when the config has none.
+const DEFAULT_PRESETS: &[(&str, f64, f64)] = &[
+    ("abs", 245.0, 100.0),
+    ("petg", 235.0, 80.0),
+    ("pla", 200.0, 60.0),
+];
+
+/// What `:preheat` and `:cooldown` do with the heater targets.
+pub enum Command {
+    List,
+    /// Applies the preset by name or number, from 1
+    Apply(String),
+    /// Every heater off
+    Cooldown,
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Command {
+        match args.trim() {
+            "" => Command::List,
+            preset => Command::Apply(preset.to_string()),
+        }
+    }
+}
+
+/// The presets from the config, or else the defaults, by name.
+fn presets(configured: &BTreeMap<String, Preset>) -> Vec<(String, Preset)> {
+    if !configured.is_empty() {
+        return configured
+            .iter()
+            .map(|(name, preset)| (name.clone(), preset.clone()))
+            .collect();
+    }
+
+    DEFAULT_PRESETS
+        .iter()
+        .map(|(name, extruder, bed)| {
+            let preset = [
+                ("extruder".to_string(), *extruder),
+                ("heater_bed".to_string(), *bed),
+            ];
+            (name.to_string(), preset.into_iter().collect())
+        })
+        .collect()
+}
+
+pub async fn run(
+    client: &reqwest::Client,
+    url: &str,
+    command: Command,
+    configured: &BTreeMap<String, Preset>,
+    units: Units,
+) -> Result<String, Error> {
+    let presets = presets(configured);
+
+    let (name, targets) = match command {
+        Command::List => return Ok(render(&presets, units)),
+        Command::Apply(preset) => {
+            let (name, targets) = match preset.parse::<usize>() {
+                Ok(n) => n
+                    .checked_sub(1)
+                    .and_then(|index| presets.get(index))
+                    .ok_or_else(|| {
+                        Error::Env(format!("No preset {}, expected 1 to {}", n, presets.len()))
+                    })?,
+                Err(_) => presets
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&preset))
+                    .ok_or_else(|| {
+                        Error::Env(format!("No preset {}, :preheat lists them", preset))
+                    })?,
+            };
+            (name.to_uppercase(), targets.clone())
+        }
+        Command::Cooldown => ("Cooldown".to_string(), Preset::new()),
+    };
+
+    let params = Some(json!({
+        "objects": { "heaters": ["available_heaters"], "print_stats": ["state"] }
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+
+    if status["status"]["print_stats"]["state"] == "printing" {
+        return Err(Error::Env("Not while printing".to_string()));
+    }
+
+    let heaters = heaters(&status["status"]["heaters"]);
+
+    // Cooling down turns off every heater, not only those of the presets
+    let targets: Vec<(String, f64)> = if targets.is_empty() {
+        heaters.iter().map(|heater| (heater.clone(), 0.0)).collect()
+    } else {
+        targets.into_iter().collect()
+    };
+
+    if let Some((heater, _)) = targets.iter().find(|(heater, _)| !heaters.contains(heater)) {
+        return Err(Error::Env(format!(
+            "No heater {} on the printer, expected one of {}",
+            heater,
+            heaters.join(", ")
+        )));
+    }
+
+    let script = targets
+        .iter()
+        .map(|(heater, target)| {
+            format!(
+                "SET_HEATER_TEMPERATURE HEATER={} TARGET={:.0}",
+                heater, target
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    rpc_result(
+        client,
+        url,
+        "printer.gcode.script",
+        Some(json!({ "script": script })),
+    )
+    .await?;
+
+    let summary = targets
+        .iter()
+        .map(|(heater, target)| format!("{} {}", heater, units.format_temperature(*target)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(format!("{}, {}", name, summary))
+}
+
+/// The heaters `SET_HEATER_TEMPERATURE` takes, `heater_generic chamber`
+/// as `chamber`.
+fn heaters(status: &JSON) -> Vec<String> {
+    status["available_heaters"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(JSON::as_str)
+        .filter_map(|heater| heater.split_whitespace().last())
+        .map(str::to_string)
+        .collect()
+}
+
+fn render(presets: &[(String, Preset)], units: Units) -> String {
+    let mut lines = popup::numbered(
+        presets
+            .iter()
+            .map(|(name, targets)| {
+                let targets = targets
+                    .iter()
+                    .map(|(heater, target)| {
+                        format!("{} {}", heater, units.format_temperature(*target))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                (name.to_uppercase(), targets)
+            })
+            .collect(),
+    );
+
+    lines.push(String::new());
+    lines.push(":preheat <n|name> heats, :cooldown or Alt-C turns all off".to_string());
+
+    popup::render("Presets", &lines)
+}
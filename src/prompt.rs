@@ -0,0 +1,120 @@
+use crate::popup;
+use std::sync::{Arc, Mutex};
+
+const ACTION_PREFIX: &str = "// action:prompt_";
+
+/// G-code closing the dialog on the printer side too, as Mainsail does.
+pub const CLOSE_GCODE: &str = "RESPOND TYPE=command MSG=action:prompt_end";
+
+/// Dialogs macros open with Klipper's action prompts, `RESPOND` commands
+/// answering `// action:prompt_begin <title>`, then the text and buttons,
+/// up to `// action:prompt_show`. See
+/// https://docs.mainsail.xyz/overview/features/macro-prompts
+#[derive(Clone, Default)]
+pub struct Prompts(Arc<Mutex<State>>);
+
+#[derive(Default)]
+struct State {
+    /// The dialog being described, not shown yet
+    building: Option<Dialog>,
+    shown: Option<Dialog>,
+}
+
+#[derive(Clone, Default)]
+struct Dialog {
+    title: String,
+    text: Vec<String>,
+    /// Label and G-code, the footer buttons last
+    buttons: Vec<(String, String)>,
+}
+
+impl Dialog {
+    fn render(&self) -> String {
+        let mut lines = self.text.clone();
+
+        if !self.buttons.is_empty() {
+            lines.push(String::new());
+        }
+
+        for (index, (label, _)) in self.buttons.iter().enumerate() {
+            lines.push(format!("[{}] {}", index + 1, label));
+        }
+
+        lines.push(String::new());
+        lines.push(":prompt <n> presses a button, :prompt close dismisses".to_string());
+
+        popup::render(&self.title, &lines)
+    }
+}
+
+/// Whether a G-code response is part of a prompt, rather than for the user.
+pub fn is_action(response: &str) -> bool {
+    response.starts_with(ACTION_PREFIX)
+}
+
+impl Prompts {
+    /// Follows a prompt action, returning the dialog to show once complete
+    /// or a notice when the macro closes it.
+    pub fn handle(&self, response: &str) -> Option<String> {
+        let action = response.strip_prefix(ACTION_PREFIX)?;
+        let (action, args) = action.split_once(' ').unwrap_or((action, ""));
+        let args = args.trim();
+        let mut state = self.0.lock().unwrap();
+
+        match action {
+            "begin" => {
+                state.building = Some(Dialog {
+                    title: args.to_string(),
+                    ..Dialog::default()
+                });
+            }
+            "text" => state.building.as_mut()?.text.push(args.to_string()),
+            "button" | "footer_button" => {
+                // <label>|<gcode>|<color>, the label is sent when there's no G-code
+                let mut parts = args.split('|');
+                let label = parts.next().unwrap_or_default().to_string();
+                let gcode = parts
+                    .next()
+                    .filter(|gcode| !gcode.is_empty())
+                    .map_or(label.clone(), str::to_string);
+
+                state.building.as_mut()?.buttons.push((label, gcode));
+            }
+            "show" => {
+                let dialog = state.building.take()?;
+                let rendered = dialog.render();
+                state.shown = Some(dialog);
+                return Some(rendered);
+            }
+            "end" => {
+                state.building = None;
+                let dialog = state.shown.take()?;
+                return Some(format!("{} closed", dialog.title));
+            }
+            // Button groups only matter to graphical clients
+            _ => {}
+        }
+
+        None
+    }
+
+    /// The dialog shown, if any, drawn again.
+    pub fn current(&self) -> Option<String> {
+        self.0.lock().unwrap().shown.as_ref().map(Dialog::render)
+    }
+
+    /// The G-code of the `n`th button of the dialog shown, from 1.
+    pub fn button(&self, n: usize) -> Option<String> {
+        let state = self.0.lock().unwrap();
+        let buttons = &state.shown.as_ref()?.buttons;
+
+        n.checked_sub(1)
+            .and_then(|index| buttons.get(index))
+            .map(|(_, gcode)| gcode.clone())
+    }
+
+    /// Forgets the dialog shown, returning whether there was one.
+    pub fn close(&self) -> bool {
+        self.0.lock().unwrap().shown.take().is_some()
+    }
+}
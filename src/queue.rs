@@ -0,0 +1,168 @@
+use crate::auth::Auth;
+use crate::cli::{OutputFormat, QueueCommand};
+use crate::history::format_duration;
+use crate::ics::format_date;
+use crate::output;
+use crate::popup;
+use crate::tls::Tls;
+use crate::{rpc_result, Error, JSON};
+use serde_json::json;
+
+/// What `:queue` and `moonraker-cli queue` do with Moonraker's job queue.
+pub enum Command {
+    List,
+    /// Files under the gcodes root, queued in this order
+    Add(Vec<String>),
+    /// Jobs by their number in the list, from 1
+    Remove(Vec<usize>),
+    Clear,
+    Pause,
+    /// Starts the next job when the printer is idle, or when it's done
+    Start,
+}
+
+impl Command {
+    /// `[add <file>...|remove <n>...|clear|pause|start]`.
+    pub fn parse(args: &str) -> Result<Command, String> {
+        let mut words = args.split_whitespace();
+        let rest: Vec<&str> = words.clone().skip(1).collect();
+
+        match words.next() {
+            None => Ok(Command::List),
+            Some("add") if !rest.is_empty() => Ok(Command::Add(
+                rest.iter().map(|file| file.to_string()).collect(),
+            )),
+            Some("remove") if !rest.is_empty() => rest
+                .iter()
+                .map(|n| {
+                    n.parse::<usize>()
+                        .map_err(|_| format!("Invalid job {}, expected a number", n))
+                })
+                .collect::<Result<_, _>>()
+                .map(Command::Remove),
+            Some("add") => Err("Missing files for :queue add, try :help".to_string()),
+            Some("remove") => Err("Missing jobs for :queue remove, try :help".to_string()),
+            Some("clear") => Ok(Command::Clear),
+            Some("pause") => Ok(Command::Pause),
+            Some("start") => Ok(Command::Start),
+            Some(command) => Err(format!(
+                "Unknown queue command {}, expected add, remove, clear, pause or start",
+                command
+            )),
+        }
+    }
+}
+
+/// Runs `command`, answering with the queue as it is after it.
+pub async fn apply(client: &reqwest::Client, url: &str, command: &Command) -> Result<JSON, Error> {
+    match command {
+        Command::List => rpc_result(client, url, "server.job_queue.status", None).await,
+        Command::Add(files) => {
+            let params = Some(json!({ "filenames": files }));
+            rpc_result(client, url, "server.job_queue.post_job", params).await
+        }
+        Command::Remove(numbers) => {
+            let queue = rpc_result(client, url, "server.job_queue.status", None).await?;
+            let jobs = jobs(&queue);
+            let job_ids = numbers
+                .iter()
+                .map(|&n| {
+                    n.checked_sub(1)
+                        .and_then(|index| jobs.get(index))
+                        .and_then(|job| job["job_id"].as_str())
+                        .ok_or_else(|| {
+                            Error::Env(format!("No job {}, expected 1 to {}", n, jobs.len()))
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let params = Some(json!({ "job_ids": job_ids }));
+            rpc_result(client, url, "server.job_queue.delete_job", params).await
+        }
+        Command::Clear => {
+            let params = Some(json!({ "all": true }));
+            rpc_result(client, url, "server.job_queue.delete_job", params).await
+        }
+        Command::Pause => rpc_result(client, url, "server.job_queue.pause", None).await,
+        Command::Start => rpc_result(client, url, "server.job_queue.start", None).await,
+    }
+}
+
+fn jobs(queue: &JSON) -> Vec<JSON> {
+    queue["queued_jobs"].as_array().cloned().unwrap_or_default()
+}
+
+/// The queued jobs numbered, with when they were queued, under the queue
+/// state.
+fn lines(queue: &JSON) -> Vec<String> {
+    let jobs = jobs(queue);
+    let mut lines = vec![format!(
+        "Queue {}",
+        queue["queue_state"].as_str().unwrap_or("unknown")
+    )];
+
+    lines.extend(popup::numbered(
+        jobs.iter()
+            .map(|job| {
+                let queued = format!(
+                    "{}  waiting {}",
+                    format_date(job["time_added"].as_f64().unwrap_or(0.0)),
+                    format_duration(job["time_in_queue"].as_f64().unwrap_or(0.0))
+                );
+
+                (
+                    job["filename"].as_str().unwrap_or_default().to_string(),
+                    queued,
+                )
+            })
+            .collect(),
+    ));
+
+    if jobs.is_empty() {
+        lines.push("No jobs queued".to_string());
+    }
+
+    lines
+}
+
+/// The queue as a popup, for `:queue`.
+pub fn render(queue: &JSON) -> String {
+    let mut lines = lines(queue);
+    lines.push(String::new());
+    lines.push(":queue add <file>..., :queue remove <n>..., :queue clear".to_string());
+    lines.push(":queue pause, :queue start".to_string());
+
+    popup::render("Job queue", &lines)
+}
+
+/// `moonraker-cli queue [list|add|remove|clear|pause|start]`, prints the
+/// queue after the change, as is or in `format`.
+pub async fn run(
+    url: &str,
+    command: Option<&QueueCommand>,
+    format: OutputFormat,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let command = match command {
+        None | Some(QueueCommand::List) => Command::List,
+        Some(QueueCommand::Add { files }) => Command::Add(files.clone()),
+        Some(QueueCommand::Remove { jobs }) => Command::Remove(jobs.clone()),
+        Some(QueueCommand::Clear) => Command::Clear,
+        Some(QueueCommand::Pause) => Command::Pause,
+        Some(QueueCommand::Start) => Command::Start,
+    };
+
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+
+    let queue = apply(&client, url, &command).await?;
+
+    match output::render(queue.clone(), format)? {
+        Some(output) => println!("{}", output),
+        None => println!("{}", lines(&queue).join("\n")),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::Error;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Blanks the terminal after a period without keypresses or printer events,
+/// sparing always-on displays from burn-in.
+#[derive(Clone, Default)]
+pub struct Screensaver(Arc<Mutex<State>>);
+
+struct State {
+    timeout: Option<Duration>,
+    last_activity: Instant,
+    blank: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            timeout: None,
+            last_activity: Instant::now(),
+            blank: false,
+        }
+    }
+}
+
+impl Screensaver {
+    /// `None` disables the screensaver.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        self.0.lock().unwrap().timeout = timeout;
+    }
+
+    /// Records user or printer activity, restoring the screen if blank.
+    /// Returns whether the screen was blank.
+    pub fn wake(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        let was_blank = state.blank;
+
+        state.last_activity = Instant::now();
+        state.blank = false;
+
+        if was_blank {
+            // Back to the main screen, content and cursor are restored
+            let _ = write_now(b"\x1b[?25h\x1b[?1049l");
+        }
+
+        was_blank
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.0.lock().unwrap().blank
+    }
+
+    fn blank_if_idle(&self) -> io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        let idle = state
+            .timeout
+            .is_some_and(|timeout| state.last_activity.elapsed() >= timeout);
+
+        if idle && !state.blank {
+            state.blank = true;
+            // An empty alternate screen with the cursor hidden
+            write_now(b"\x1b[?1049h\x1b[2J\x1b[?25l")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_now(bytes: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(bytes)?;
+    stdout.flush()
+}
+
+pub async fn run(screensaver: Screensaver) -> Result<(), Error> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        ticker.tick().await;
+        screensaver.blank_if_idle()?;
+    }
+}
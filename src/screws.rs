@@ -0,0 +1,269 @@
+use crate::units::Units;
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+
+/// Columns of the diagram the screws are placed in by where they are.
+const DIAGRAM_WIDTH: usize = 40;
+
+/// Rows of the diagram, the back of the bed on top.
+const DIAGRAM_HEIGHT: usize = 5;
+
+/// What `:screws` does with `[screws_tilt_adjust]`.
+pub enum Command {
+    /// Probes the screws with `SCREWS_TILT_CALCULATE` first
+    Measure,
+    /// The results of the last measurement
+    Last,
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        match args.trim() {
+            "" => Ok(Command::Measure),
+            "last" => Ok(Command::Last),
+            args => Err(format!("Unknown :screws argument {}, try :help", args)),
+        }
+    }
+}
+
+/// A probed screw with how to turn it, as Klipper reports it.
+struct Screw {
+    name: String,
+    position: Option<(f64, f64)>,
+    z: f64,
+    base: bool,
+    /// `CW` or `CCW`
+    sign: String,
+    /// Full turns and minutes of a clock face, like `01:15`
+    adjust: String,
+}
+
+impl Screw {
+    /// The turns as a number, `01:15` being one and a quarter.
+    fn turns(&self) -> f64 {
+        let (turns, minutes) = self.adjust.split_once(':').unwrap_or(("0", "0"));
+        turns.parse().unwrap_or(0.0) + minutes.parse::<f64>().unwrap_or(0.0) / 60.0
+    }
+
+    fn arrow(&self) -> char {
+        if self.sign == "CCW" {
+            '↺'
+        } else {
+            '↻'
+        }
+    }
+
+    /// Like `↻ 01:15` for the diagram.
+    fn label(&self) -> String {
+        match (self.base, self.turns() > 0.0) {
+            (true, _) => "● base".to_string(),
+            (false, true) => format!("{} {}", self.arrow(), self.adjust),
+            (false, false) => "✓ level".to_string(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (self.base, self.turns()) {
+            (true, _) => "base, leave it".to_string(),
+            (false, turns) if turns > 0.0 => format!(
+                "{} {:3} {}  {:.2} turns",
+                self.arrow(),
+                self.sign,
+                self.adjust,
+                turns
+            ),
+            (false, _) => "level".to_string(),
+        }
+    }
+}
+
+/// `:screws [last]`, which way and how far to turn each bed screw so the bed
+/// is level with the base one, as a diagram of the bed seen from above and a
+/// table rather than the lines `SCREWS_TILT_CALCULATE` prints.
+pub async fn run(
+    client: &reqwest::Client,
+    url: &str,
+    command: Command,
+    units: Units,
+) -> Result<String, Error> {
+    let params = Some(json!({
+        "objects": { "screws_tilt_adjust": null, "print_stats": ["state"] }
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+
+    if status["status"]["screws_tilt_adjust"].is_null() {
+        return Err(Error::Env(
+            "No [screws_tilt_adjust] in the printer config".to_string(),
+        ));
+    }
+
+    let measure = matches!(command, Command::Measure);
+
+    if measure {
+        if let Some(state @ ("printing" | "paused")) =
+            status["status"]["print_stats"]["state"].as_str()
+        {
+            return Err(Error::Env(format!("Not while the print is {}", state)));
+        }
+
+        let params = Some(json!({ "script": "SCREWS_TILT_CALCULATE" }));
+        rpc_result(client, url, "printer.gcode.script", params).await?;
+    }
+
+    let params = Some(json!({
+        "objects": { "screws_tilt_adjust": null, "configfile": ["settings"] }
+    }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let tilt = &status["status"]["screws_tilt_adjust"];
+    let settings = &status["status"]["configfile"]["settings"]["screws_tilt_adjust"];
+    let screws = screws(&tilt["results"], settings);
+
+    if screws.is_empty() {
+        if measure {
+            return Err(Error::Rpc("no screws reported".to_string()));
+        }
+
+        return Ok("No screws measured yet, :screws probes them".to_string());
+    }
+
+    let mut lines = diagram(&screws);
+
+    if !lines.is_empty() {
+        lines.push(String::new());
+    }
+
+    lines.extend(table(&screws, units));
+    lines.push(String::new());
+
+    if tilt["error"] == true {
+        lines.push(format!(
+            "Over the max deviation of {}, adjust before printing",
+            units.format_length(tilt["max_deviation"].as_f64().unwrap_or(0.0))
+        ));
+    }
+
+    if let Some(thread) = settings["screw_thread"].as_str() {
+        lines.push(format!(
+            "For {} screws, 01:15 is a turn and a quarter",
+            thread
+        ));
+    }
+
+    lines.push(":screws probes again, :screws last shows these".to_string());
+
+    Ok(popup::render("Bed screws", &lines))
+}
+
+/// The screws in the order of the config, `screw1` first, with the names
+/// and positions it gives them.
+fn screws(results: &JSON, settings: &JSON) -> Vec<Screw> {
+    let mut screws: Vec<(usize, Screw)> = results
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(key, result)| {
+            let index = key
+                .strip_prefix("screw")
+                .and_then(|index| index.parse().ok())
+                .unwrap_or(0);
+            let name = result["name"]
+                .as_str()
+                .or_else(|| settings[format!("{}_name", key)].as_str())
+                .unwrap_or(key);
+
+            let screw = Screw {
+                name: name.to_string(),
+                position: position(&settings[key]),
+                z: result["z"].as_f64().unwrap_or(0.0),
+                base: result["is_base"] == true,
+                sign: result["sign"].as_str().unwrap_or("CW").to_string(),
+                adjust: result["adjust"].as_str().unwrap_or("00:00").to_string(),
+            };
+
+            (index, screw)
+        })
+        .collect();
+    screws.sort_by_key(|(index, _)| *index);
+
+    screws.into_iter().map(|(_, screw)| screw).collect()
+}
+
+/// A screw position from the config, a list or the `x, y` it was written as.
+fn position(setting: &JSON) -> Option<(f64, f64)> {
+    if let Some(text) = setting.as_str() {
+        let (x, y) = text.split_once(',')?;
+        return Some((x.trim().parse().ok()?, y.trim().parse().ok()?));
+    }
+
+    Some((setting[0].as_f64()?, setting[1].as_f64()?))
+}
+
+/// The screws placed where they are on the bed, nothing when the config
+/// doesn't tell where.
+fn diagram(screws: &[Screw]) -> Vec<String> {
+    let positions: Option<Vec<(f64, f64)>> = screws.iter().map(|screw| screw.position).collect();
+    let Some(positions) = positions else {
+        return Vec::new();
+    };
+
+    let bounds = |values: Vec<f64>| {
+        let low = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (low, high)
+    };
+    let (min_x, max_x) = bounds(positions.iter().map(|(x, _)| *x).collect());
+    let (min_y, max_y) = bounds(positions.iter().map(|(_, y)| *y).collect());
+    let scale = |value: f64, low: f64, high: f64, cells: usize| {
+        if high > low {
+            ((value - low) / (high - low) * cells as f64).round() as usize
+        } else {
+            cells / 2
+        }
+    };
+
+    let mut grid = vec![vec![' '; DIAGRAM_WIDTH]; DIAGRAM_HEIGHT];
+
+    for (screw, (x, y)) in screws.iter().zip(positions) {
+        let label: Vec<char> = screw.label().chars().collect();
+        let column = scale(x, min_x, max_x, DIAGRAM_WIDTH.saturating_sub(label.len()));
+        let row = DIAGRAM_HEIGHT - 1 - scale(y, min_y, max_y, DIAGRAM_HEIGHT - 1);
+
+        for (offset, c) in label.into_iter().enumerate() {
+            if let Some(cell) = grid[row].get_mut(column + offset) {
+                *cell = c;
+            }
+        }
+    }
+
+    let mut lines: Vec<String> = grid
+        .into_iter()
+        .map(|row| String::from_iter(row).trim_end().to_string())
+        .collect();
+    lines.push(format!("{:^DIAGRAM_WIDTH$}", "front"));
+
+    lines
+}
+
+fn table(screws: &[Screw], units: Units) -> Vec<String> {
+    let width = screws
+        .iter()
+        .map(|screw| screw.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let base = screws
+        .iter()
+        .find(|screw| screw.base)
+        .map_or(0.0, |screw| screw.z);
+
+    screws
+        .iter()
+        .map(|screw| {
+            format!(
+                "{:width$}  {:>8}  {}",
+                screw.name,
+                units.format_deviation(screw.z - base),
+                screw.describe()
+            )
+        })
+        .collect()
+}
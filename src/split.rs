@@ -0,0 +1,141 @@
+use crate::status::truncate;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Lines of the watched printer kept for the pane, more than any terminal
+/// is tall.
+const KEPT_LINES: usize = 500;
+
+/// Narrower than this the console and the pane would both be unreadable,
+/// the console takes the whole width again.
+const MIN_SPLIT_WIDTH: usize = 60;
+
+/// `:split`, the console area shared side by side: the printer input goes
+/// to on the left, the one `:watch` follows on the right. `:swap` moves the
+/// input over, and the printers change sides.
+#[derive(Clone, Default)]
+pub struct Split(Arc<Mutex<SplitState>>);
+
+#[derive(Default)]
+struct SplitState {
+    on: bool,
+    /// What the watched printer printed, oldest first
+    lines: VecDeque<String>,
+}
+
+impl Split {
+    /// Turns the split on or off, answering whether it's on.
+    pub fn toggle(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.on = !state.on;
+        state.on
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.0.lock().unwrap().on
+    }
+
+    /// Keeps a notice of the watched printer for the pane, answering
+    /// whether it went there. It's for the console when the split is off.
+    pub fn push(&self, notice: &str) -> bool {
+        let mut state = self.0.lock().unwrap();
+
+        if !state.on {
+            return false;
+        }
+
+        state.lines.extend(notice.lines().map(str::to_string));
+        let excess = state.lines.len().saturating_sub(KEPT_LINES);
+        state.lines.drain(..excess);
+        true
+    }
+
+    /// Forgets what the pane showed, another printer is watched.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().lines.clear();
+    }
+
+    /// The columns the console takes in a terminal `width` wide, all of
+    /// them unless split.
+    pub fn console_width(&self, width: usize) -> usize {
+        if self.is_on() && width >= MIN_SPLIT_WIDTH {
+            (width - 1) / 2
+        } else {
+            width
+        }
+    }
+
+    /// Console output wrapped to the left side while split, popups cut
+    /// instead and without the margin they're centered with. Lines drawing
+    /// with escape sequences, like thumbnails, can't be cut safely and are
+    /// left whole.
+    pub fn fit(&self, output: String) -> String {
+        let Some((width, _)) = terminal_size::terminal_size() else {
+            return output;
+        };
+        let width = width.0 as usize;
+        let console_width = self.console_width(width);
+
+        if console_width == width || output.contains('\x1b') {
+            return output;
+        }
+
+        let is_popup = |line: &str| line.trim_start().starts_with(['┌', '│', '└']);
+        let margin = output
+            .lines()
+            .filter(|line| is_popup(line))
+            .map(|line| line.len() - line.trim_start_matches(' ').len())
+            .min()
+            .unwrap_or(0);
+
+        let mut lines = Vec::new();
+
+        for line in output.lines() {
+            if is_popup(line) {
+                let line: String = line.chars().skip(margin).collect();
+                lines.push(truncate(&line, console_width));
+                continue;
+            }
+
+            let chars: Vec<char> = line.chars().collect();
+
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+
+            for chunk in chars.chunks(console_width) {
+                lines.push(chunk.iter().collect());
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// The rows of the pane, `rows` tall in a terminal `width` wide, under
+    /// `title`. Each starts with the border, to be drawn right of the
+    /// console, nothing unless split.
+    pub fn render(&self, title: &str, width: usize, rows: usize) -> Option<Vec<String>> {
+        let console_width = self.console_width(width);
+
+        if console_width == width || rows == 0 {
+            return None;
+        }
+
+        // The border and a space
+        let pane_width = width - console_width - 2;
+        let state = self.0.lock().unwrap();
+        let shown = state.lines.len().min(rows - 1);
+
+        let mut pane = vec![format!("│ {}", truncate(title, pane_width))];
+        pane.extend(
+            state
+                .lines
+                .iter()
+                .skip(state.lines.len() - shown)
+                .map(|line| format!("│ {}", truncate(line, pane_width))),
+        );
+        pane.resize(rows, "│".to_string());
+
+        Some(pane)
+    }
+}
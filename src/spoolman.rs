@@ -0,0 +1,242 @@
+use crate::{popup, rpc_result, Error, JSON};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// What `:spool` does with the spools Moonraker tracks through Spoolman.
+pub enum Command {
+    /// The active spool
+    Show,
+    /// The spools in Spoolman, numbered to pick one
+    List,
+    /// Makes the nth spool listed active, from 1
+    Set(usize),
+    /// No spool active
+    Clear,
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        match args.trim() {
+            "" => Ok(Command::Show),
+            "list" => Ok(Command::List),
+            "clear" => Ok(Command::Clear),
+            n => n
+                .parse::<usize>()
+                .map(Command::Set)
+                .map_err(|_| format!("Invalid spool {}, expected a number, list or clear", n)),
+        }
+    }
+}
+
+/// The spool ids `:spool list` listed last, so `:spool <n>` picks the nth.
+#[derive(Clone, Default)]
+pub struct Spools(Arc<Mutex<Vec<u64>>>);
+
+impl Spools {
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        command: Command,
+    ) -> Result<String, Error> {
+        match command {
+            Command::Show => match active(client, url).await? {
+                Some(spool) => Ok(popup::render("Active spool", &lines(&spool))),
+                None => Ok("No spool active, :spool list to pick one".to_string()),
+            },
+            Command::List => self.list(client, url).await,
+            Command::Set(n) => {
+                let id = {
+                    let listed = self.0.lock().unwrap();
+
+                    if listed.is_empty() {
+                        return Err(Error::Env(
+                            "Nothing listed yet, try :spool list".to_string(),
+                        ));
+                    }
+
+                    n.checked_sub(1)
+                        .and_then(|index| listed.get(index))
+                        .copied()
+                        .ok_or_else(|| {
+                            Error::Env(format!("No spool {}, expected 1 to {}", n, listed.len()))
+                        })?
+                };
+
+                let params = Some(json!({ "spool_id": id }));
+                rpc_result(client, url, "server.spoolman.post_spool_id", params).await?;
+
+                let spool = proxy(client, url, &format!("/v1/spool/{}", id)).await?;
+                Ok(format!("Active spool {}", name(&spool)))
+            }
+            Command::Clear => {
+                let params = Some(json!({ "spool_id": null }));
+                rpc_result(client, url, "server.spoolman.post_spool_id", params).await?;
+                Ok("No spool active".to_string())
+            }
+        }
+    }
+
+    async fn list(&self, client: &reqwest::Client, url: &str) -> Result<String, Error> {
+        let spools = proxy(client, url, "/v1/spool").await?;
+        let spools = spools.as_array().cloned().unwrap_or_default();
+        let active = active_id(client, url).await?;
+
+        *self.0.lock().unwrap() = spools
+            .iter()
+            .filter_map(|spool| spool["id"].as_u64())
+            .collect();
+
+        let mut lines = popup::numbered(
+            spools
+                .iter()
+                .map(|spool| {
+                    let marker = if spool["id"].as_u64() == active {
+                        '*'
+                    } else {
+                        ' '
+                    };
+
+                    (
+                        format!("{} {}", marker, name(spool)),
+                        remaining(spool).unwrap_or_default(),
+                    )
+                })
+                .collect(),
+        );
+
+        if lines.is_empty() {
+            lines.push("No spools in Spoolman".to_string());
+        }
+
+        lines.push(String::new());
+        lines.push(":spool <n> makes the nth active, :spool clear none".to_string());
+
+        Ok(popup::render("Spools", &lines))
+    }
+}
+
+/// A request to the Spoolman API through Moonraker, answering with its
+/// response.
+async fn proxy(client: &reqwest::Client, url: &str, path: &str) -> Result<JSON, Error> {
+    let params = Some(json!({
+        "request_method": "GET",
+        "path": path,
+        "use_v2_response": true,
+    }));
+    let mut result = rpc_result(client, url, "server.spoolman.proxy", params).await?;
+
+    match result["error"].as_object() {
+        Some(error) => Err(Error::Rpc(format!(
+            "Spoolman answered {}",
+            error
+                .get("message")
+                .and_then(JSON::as_str)
+                .unwrap_or("with an error")
+        ))),
+        None => Ok(result
+            .get_mut("response")
+            .map(JSON::take)
+            .unwrap_or_default()),
+    }
+}
+
+async fn active_id(client: &reqwest::Client, url: &str) -> Result<Option<u64>, Error> {
+    let result = rpc_result(client, url, "server.spoolman.get_spool_id", None).await?;
+    Ok(result["spool_id"].as_u64())
+}
+
+/// The active spool as Spoolman has it, if any.
+pub async fn active(client: &reqwest::Client, url: &str) -> Result<Option<JSON>, Error> {
+    match active_id(client, url).await? {
+        Some(id) => proxy(client, url, &format!("/v1/spool/{}", id))
+            .await
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Warns when the active spool has less filament left than `metadata` says
+/// the file takes, by weight when both are known or else by length.
+pub async fn check(
+    client: &reqwest::Client,
+    url: &str,
+    metadata: &JSON,
+) -> Result<Option<String>, Error> {
+    let Some(spool) = active(client, url).await? else {
+        return Ok(None);
+    };
+
+    let short = match (
+        spool["remaining_weight"].as_f64(),
+        metadata["filament_weight_total"].as_f64(),
+        spool["remaining_length"].as_f64(),
+        metadata["filament_total"].as_f64(),
+    ) {
+        (Some(left), Some(needed), ..) if left < needed => Some(format!(
+            "{:.1} g left, the file takes {:.1} g",
+            left, needed
+        )),
+        (_, None, Some(left), Some(needed)) if left < needed => Some(format!(
+            "{:.2} m left, the file takes {:.2} m",
+            left / 1000.0,
+            needed / 1000.0
+        )),
+        _ => None,
+    };
+
+    Ok(short.map(|short| format!("Spool {} is running low, {}", name(&spool), short)))
+}
+
+/// Like `#12 Prusament PLA Galaxy Black`.
+fn name(spool: &JSON) -> String {
+    let filament = &spool["filament"];
+    let parts: Vec<&str> = [
+        &filament["vendor"]["name"],
+        &filament["material"],
+        &filament["name"],
+    ]
+    .iter()
+    .filter_map(|value| value.as_str())
+    .collect();
+
+    format!("#{} {}", spool["id"], parts.join(" "))
+}
+
+/// Like `734.2 g, 245.60 m left`.
+fn remaining(spool: &JSON) -> Option<String> {
+    let weight = spool["remaining_weight"].as_f64();
+    let length = spool["remaining_length"].as_f64();
+
+    match (weight, length) {
+        (Some(weight), Some(length)) => {
+            Some(format!("{:.1} g, {:.2} m left", weight, length / 1000.0))
+        }
+        (Some(weight), None) => Some(format!("{:.1} g left", weight)),
+        (None, Some(length)) => Some(format!("{:.2} m left", length / 1000.0)),
+        (None, None) => None,
+    }
+}
+
+fn lines(spool: &JSON) -> Vec<String> {
+    let rows = [
+        ("Spool     ", Some(name(spool))),
+        ("Remaining ", remaining(spool)),
+        (
+            "Used      ",
+            spool["used_weight"]
+                .as_f64()
+                .map(|weight| format!("{:.1} g", weight)),
+        ),
+        ("Location  ", spool["location"].as_str().map(str::to_string)),
+        ("Lot       ", spool["lot_nr"].as_str().map(str::to_string)),
+        ("Comment   ", spool["comment"].as_str().map(str::to_string)),
+    ];
+
+    rows.into_iter()
+        .filter_map(|(label, value)| {
+            let value = value.filter(|value| !value.is_empty())?;
+            Some(format!("{} {}", label, value))
+        })
+        .collect()
+}
@@ -0,0 +1,694 @@
+use crate::units::Units;
+use crate::{Error, SharedState};
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many of the latest round trips the average latency covers.
+const LATENCY_SAMPLES: usize = 10;
+
+/// Frames of the spinner shown while commands await replies.
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+/// How often the status bar is redrawn, it also catches terminal resizes.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// With fewer rows there's no room for the status bar, the line edited and
+/// the output, the terminal shows a placeholder screen until it grows.
+const MIN_HEIGHT: usize = 3;
+
+/// Narrower terminals show the placeholder screen too.
+const MIN_WIDTH: usize = 20;
+
+/// Rows of the detailed status bar: connection, print, watch and queue.
+const DETAILED_ROWS: usize = 3;
+
+/// How much room the status bar takes, switched with `:density`.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Density {
+    /// A single row, for small terminals
+    #[default]
+    Compact,
+    Detailed,
+}
+
+impl Density {
+    pub fn parse(density: &str) -> Result<Density, String> {
+        match density {
+            "compact" => Ok(Density::Compact),
+            "detailed" => Ok(Density::Detailed),
+            density => Err(format!(
+                "Unknown density {}, expected compact or detailed",
+                density
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Density::Compact => "compact",
+            Density::Detailed => "detailed",
+        }
+    }
+}
+
+/// What the status bar shows, updated by the network loop and the
+/// notification listener.
+#[derive(Clone, Default)]
+pub struct Status(Arc<Mutex<Fields>>);
+
+#[derive(Clone, Default, PartialEq)]
+struct Fields {
+    url: String,
+    klippy_state: Option<String>,
+    print_state: Option<String>,
+    /// Live Z offset in mm
+    z_offset: Option<f64>,
+    /// Ratios, 1 for what the file says
+    speed_factor: Option<f64>,
+    flow_factor: Option<f64>,
+    /// X, Y, Z and E in G-code coordinates, in mm
+    position: Option<[f64; 4]>,
+    homed_axes: Option<String>,
+    /// Of the G-code moves, in mm/s
+    speed: Option<f64>,
+    online: bool,
+    /// Online at least once since connecting to the URL
+    seen_online: bool,
+    latencies: VecDeque<Duration>,
+}
+
+impl Status {
+    pub fn set_url(&self, url: &str) {
+        let mut fields = self.0.lock().unwrap();
+        *fields = Fields {
+            url: url.to_string(),
+            ..Fields::default()
+        };
+    }
+
+    pub fn set_klippy_state(&self, state: &str) {
+        self.0.lock().unwrap().klippy_state = Some(state.to_string());
+    }
+
+    pub fn set_print_state(&self, state: &str) {
+        self.0.lock().unwrap().print_state = Some(state.to_string());
+    }
+
+    pub fn set_z_offset(&self, z_offset: f64) {
+        self.0.lock().unwrap().z_offset = Some(z_offset);
+    }
+
+    pub fn set_speed_factor(&self, speed_factor: f64) {
+        self.0.lock().unwrap().speed_factor = Some(speed_factor);
+    }
+
+    pub fn set_flow_factor(&self, flow_factor: f64) {
+        self.0.lock().unwrap().flow_factor = Some(flow_factor);
+    }
+
+    pub fn set_position(&self, position: [f64; 4]) {
+        self.0.lock().unwrap().position = Some(position);
+    }
+
+    pub fn set_homed_axes(&self, homed_axes: &str) {
+        self.0.lock().unwrap().homed_axes = Some(homed_axes.to_string());
+    }
+
+    pub fn set_speed(&self, speed: f64) {
+        self.0.lock().unwrap().speed = Some(speed);
+    }
+
+    pub fn set_online(&self, online: bool) {
+        let mut fields = self.0.lock().unwrap();
+        fields.online = online;
+        fields.seen_online |= online;
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.0.lock().unwrap().online
+    }
+
+    /// Whether the printer went offline after being online, rather than
+    /// not being reached yet since connecting.
+    pub fn is_lost(&self) -> bool {
+        let fields = self.0.lock().unwrap();
+        fields.seen_online && !fields.online
+    }
+
+    pub fn print_state(&self) -> Option<String> {
+        self.0.lock().unwrap().print_state.clone()
+    }
+
+    /// Whether a print is going on, paused or not.
+    pub fn has_print(&self) -> bool {
+        matches!(
+            self.0.lock().unwrap().print_state.as_deref(),
+            Some("printing" | "paused")
+        )
+    }
+
+    /// The keys controlling the print, the live Z offset, speed and flow,
+    /// while there's a print.
+    fn render_controls(&self, units: Units) -> Option<String> {
+        let fields = self.0.lock().unwrap();
+        let keys = match fields.print_state.as_deref() {
+            Some("printing") => "Alt-P pause, Alt-X cancel",
+            Some("paused") => "Alt-R resume, Alt-X cancel",
+            _ => return None,
+        };
+        let mut controls = format!(" {} ", keys);
+
+        if let Some(z_offset) = fields.z_offset {
+            controls.push_str(&format!("│ Z {} ", units.format_length(z_offset)));
+        }
+
+        let percent = |ratio: f64| (ratio * 100.0).round();
+
+        if let (Some(speed), Some(flow)) = (fields.speed_factor, fields.flow_factor) {
+            controls.push_str(&format!(
+                "│ speed {}% flow {}% ",
+                percent(speed),
+                percent(flow)
+            ));
+        }
+
+        Some(controls)
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        let mut fields = self.0.lock().unwrap();
+
+        if fields.latencies.len() == LATENCY_SAMPLES {
+            fields.latencies.pop_front();
+        }
+
+        fields.latencies.push_back(latency);
+    }
+
+    /// Host and print state, for the printer followed with `:watch`.
+    pub fn summary(&self) -> String {
+        let fields = self.0.lock().unwrap();
+        let state = match (&fields.print_state, fields.online) {
+            (Some(state), true) => state.as_str(),
+            (None, true) => "?",
+            (_, false) => "offline",
+        };
+
+        format!("{} {}", host(&fields.url), state)
+    }
+
+    /// Every field, for `:debug-state`.
+    pub fn describe(&self) -> String {
+        let fields = self.0.lock().unwrap();
+
+        format!(
+            "url {}, klippy {}, print {}, online {}, latencies {:?}",
+            fields.url,
+            fields.klippy_state.as_deref().unwrap_or("unknown"),
+            fields.print_state.as_deref().unwrap_or("unknown"),
+            fields.online,
+            fields.latencies
+        )
+    }
+
+    /// Host, Klippy and connection, for the detailed status bar.
+    fn render_connection(&self) -> String {
+        let fields = self.0.lock().unwrap().clone();
+        let health = if fields.online { "online" } else { "offline" };
+
+        let mut line = format!(
+            " {} │ {} │ Klippy {} ",
+            host(&fields.url),
+            health,
+            fields.klippy_state.as_deref().unwrap_or("?")
+        );
+
+        if let Some(average) = fields.average_latency() {
+            line.push_str(&format!("│ {} ms avg ", average.as_millis()));
+        }
+
+        line
+    }
+
+    /// Like `X 10.00 Y 20.00 Z 0.30 E 12.50 mm, homed xyz, 50 mm/s`, once
+    /// the position is known.
+    fn render_position(&self, units: Units) -> Option<String> {
+        let fields = self.0.lock().unwrap();
+        let position = fields.position?;
+        let coordinates: Vec<String> = ['X', 'Y', 'Z', 'E']
+            .iter()
+            .zip(position)
+            .map(|(axis, mm)| format!("{} {}", axis, units.format_length_value(mm)))
+            .collect();
+        let mut line = format!(" {} {}", coordinates.join(" "), units.length_symbol());
+
+        match fields.homed_axes.as_deref() {
+            Some("") => line.push_str(", not homed"),
+            Some(homed_axes) => line.push_str(&format!(", homed {}", homed_axes)),
+            None => {}
+        }
+
+        if let Some(speed) = fields.speed {
+            line.push_str(&format!(
+                ", {:.0} {}/s",
+                units.length(speed),
+                units.length_symbol()
+            ));
+        }
+
+        line.push(' ');
+        Some(line)
+    }
+
+    fn render_print(&self) -> String {
+        let fields = self.0.lock().unwrap();
+        format!(" Print {} ", fields.print_state.as_deref().unwrap_or("?"))
+    }
+
+    fn render(&self) -> String {
+        let fields = self.0.lock().unwrap().clone();
+        let unknown = || "?".to_string();
+
+        let host = host(&fields.url);
+        let klippy_state = fields.klippy_state.clone().unwrap_or_else(unknown);
+        let print_state = fields.print_state.clone().unwrap_or_else(unknown);
+        let health = if fields.online { "online" } else { "offline" };
+
+        let mut line = format!(
+            " {} │ Klippy {} │ {} │ {} ",
+            host, klippy_state, print_state, health
+        );
+
+        if let Some(average) = fields.average_latency() {
+            line.push_str(&format!("│ {} ms avg ", average.as_millis()));
+        }
+
+        line
+    }
+}
+
+impl Fields {
+    fn average_latency(&self) -> Option<Duration> {
+        let total: Duration = self.latencies.iter().sum();
+        (!self.latencies.is_empty()).then(|| total / self.latencies.len() as u32)
+    }
+}
+
+/// The URL without its scheme.
+pub fn host(url: &str) -> &str {
+    url.split_once("://").map_or(url, |(_, host)| host)
+}
+
+/// Moves the cursor off the last row, where the bar will be drawn.
+/// Must run before the console prints its first prompt.
+pub fn make_room() -> io::Result<()> {
+    if io::stdout().is_terminal() {
+        io::stdout().write_all(b"\n\x1b[A")?;
+        io::stdout().flush()?;
+    }
+
+    Ok(())
+}
+
+/// Keeps the status bar drawn on the last terminal rows, which are taken
+/// out of the scrolling region so console output never overwrites them.
+pub async fn run(shared: SharedState) -> Result<(), Error> {
+    if !io::stdout().is_terminal() || shared.plain {
+        return std::future::pending().await;
+    }
+
+    let mut reserved = ReservedRows {
+        rows: 1,
+        placeholder: None,
+    };
+    let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+    let mut height = 0;
+    // The column the `:split` pane is drawn from, while it is
+    let mut split_column = None;
+
+    loop {
+        ticker.tick().await;
+
+        if shared.screensaver.is_blank() || shared.pager.is_open() {
+            // Redraw everything once the main screen is back
+            height = 0;
+            reserved.placeholder = None;
+            continue;
+        }
+
+        let Some((width, new_height)) = terminal_size() else {
+            continue;
+        };
+
+        if !fits(width, new_height) {
+            if reserved.placeholder != Some((width, new_height)) {
+                let mut output = String::new();
+
+                // The whole scrolling region back, then the alternate screen
+                if reserved.placeholder.is_none() {
+                    output.push_str("\x1b7\x1b[r\x1b8\x1b[?1049h\x1b[?25l");
+                }
+
+                output.push_str("\x1b[2J");
+
+                for (index, line) in placeholder(width, new_height).iter().enumerate() {
+                    output.push_str(&format!("\x1b[{};1H{}", index + 1, line));
+                }
+
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(output.as_bytes())?;
+                stdout.flush()?;
+                reserved.placeholder = Some((width, new_height));
+            }
+
+            height = 0;
+            continue;
+        }
+
+        if reserved.placeholder.take().is_some() {
+            // The main screen as it was, the status bar is drawn again below
+            io::stdout().write_all(b"\x1b[?25h\x1b[?1049l")?;
+            io::stdout().flush()?;
+        }
+
+        // The detailed bar falls back to a single row when it would leave
+        // the console less room than the minimum
+        let density = match *shared.density.lock().unwrap() {
+            Density::Detailed if new_height >= MIN_HEIGHT + DETAILED_ROWS - 1 => Density::Detailed,
+            _ => Density::Compact,
+        };
+        let lines = render(&shared, density);
+
+        let mut output = String::from("\x1b7");
+
+        if new_height != height || lines.len() != reserved.rows {
+            output.push_str(&reserve(new_height, reserved.rows, lines.len()));
+            height = new_height;
+            reserved.rows = lines.len();
+        }
+
+        for (index, line) in lines.iter().enumerate() {
+            output.push_str(&format!(
+                "\x1b[{};1H\x1b[2K\x1b[{}m{}\x1b[0m",
+                height - lines.len() + index + 1,
+                shared.config.theme.status_bar(),
+                truncate(line, width)
+            ));
+        }
+
+        // The pane beside the console takes every row but the prompt's, and
+        // is cleared once when the split goes off
+        let rows = height.saturating_sub(reserved.rows + 1);
+        let title = match shared.watched.lock().unwrap().as_ref() {
+            Some(watched) => watched.summary(),
+            None => "Not watching a printer, try :watch <url>".to_string(),
+        };
+
+        match shared.split.render(&title, width, rows) {
+            Some(pane) => {
+                let column = shared.split.console_width(width) + 1;
+
+                for (index, line) in pane.iter().enumerate() {
+                    output.push_str(&format!("\x1b[{};{}H{}\x1b[K", index + 1, column, line));
+                }
+
+                split_column = Some(column);
+            }
+            None => {
+                if let Some(column) = split_column.take() {
+                    for row in 1..=rows {
+                        output.push_str(&format!("\x1b[{};{}H\x1b[K", row, column));
+                    }
+                }
+            }
+        }
+
+        output.push_str("\x1b8");
+
+        // A single write, so it doesn't interleave with the line editor output
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()?;
+    }
+}
+
+/// Escape sequences taking the last `rows` rows out of the scrolling
+/// region, `reserved` of them before. Rows taken are emptied by scrolling
+/// the console up, so neither its output nor the cursor end up under the
+/// status bar, and rows given back are cleared. Expects the cursor saved.
+fn reserve(height: usize, reserved: usize, rows: usize) -> String {
+    let mut output = String::new();
+
+    if rows > reserved {
+        let added = rows - reserved;
+        output.push_str(&format!(
+            "\x1b[r\x1b[{};1H{}\x1b8\x1b[{}A\x1b7",
+            height,
+            "\n".repeat(added),
+            added
+        ));
+    }
+
+    for row in height.saturating_sub(reserved) + 1..=height.saturating_sub(rows) {
+        output.push_str(&format!("\x1b[{};1H\x1b[2K", row));
+    }
+
+    output.push_str(&format!("\x1b[1;{}r", height.saturating_sub(rows).max(1)));
+    output
+}
+
+/// Whether the console and the status bar fit in the terminal.
+fn fits(width: usize, height: usize) -> bool {
+    width >= MIN_WIDTH && height >= MIN_HEIGHT
+}
+
+/// The rows of the placeholder screen shown while the terminal is too small,
+/// the message in the middle and cut to the width.
+fn placeholder(width: usize, height: usize) -> Vec<String> {
+    let message = [
+        "Terminal too small".to_string(),
+        format!("{}×{} needed", MIN_WIDTH, MIN_HEIGHT),
+    ];
+    let message = &message[..message.len().min(height)];
+    let top = (height - message.len()) / 2;
+
+    (0..height)
+        .map(|row| {
+            let Some(line) = row.checked_sub(top).and_then(|index| message.get(index)) else {
+                return String::new();
+            };
+            let margin = width.saturating_sub(line.chars().count()) / 2;
+
+            truncate(&format!("{}{}", " ".repeat(margin), line), width)
+        })
+        .collect()
+}
+
+/// The status bar rows, a single line with the printer status followed by
+/// the pending commands, the watched printer and the offline queue when
+/// there are any, or a row each for the connection, the print and the rest.
+fn render(shared: &SharedState, density: Density) -> Vec<String> {
+    let pending = shared.pending.oldest().map(|(command, waiting, others)| {
+        let frame =
+            SPINNER[(waiting.as_millis() / REFRESH_INTERVAL.as_millis()) as usize % SPINNER.len()];
+
+        match others {
+            0 => format!("{} {} ", frame, command),
+            others => format!("{} {} +{} ", frame, command, others),
+        }
+    });
+    let watched = shared.watched.lock().unwrap().as_ref().map(Status::summary);
+    let queued = shared.offline_queue.len();
+    let transfer = shared.transfer.render();
+    let jog_pad = shared.jog_pad.render(shared.config.units);
+    let browser = shared.browser.render_pane();
+    let power_off = shared.power_off.render();
+    let position = shared.status.render_position(shared.config.units);
+
+    if density == Density::Detailed {
+        let mut print = shared.status.render_print();
+
+        if let Some(controls) = shared.status.render_controls(shared.config.units) {
+            print.push_str(&format!("│{}", controls));
+        }
+
+        if let Some(jog_pad) = jog_pad {
+            print.push_str(&format!("│ {} ", jog_pad));
+        }
+
+        if let Some(browser) = browser {
+            print.push_str(&format!("│ {} ", browser));
+        }
+
+        if let Some(power_off) = power_off {
+            print.push_str(&format!("│ {} ", power_off));
+        }
+
+        if let Some(pending) = pending {
+            print.push_str(&format!("│ {}", pending));
+        }
+
+        if let Some(transfer) = transfer {
+            print.push_str(&format!("│ {} ", transfer));
+        }
+
+        let connection = format!(
+            "{}│ {} ",
+            shared.status.render_connection(),
+            shared.traffic.summary()
+        );
+
+        let mut rest = format!(
+            " watching {} │ {} queued offline ",
+            watched.as_deref().unwrap_or("nothing"),
+            queued
+        );
+
+        if let Some(position) = position {
+            rest = format!("{}│{}", position, rest);
+        }
+
+        return vec![connection, print, rest];
+    }
+
+    let mut line = shared.status.render();
+
+    if let Some(controls) = shared.status.render_controls(shared.config.units) {
+        line.push_str(&format!("│{}", controls));
+    }
+
+    if let Some(position) = position {
+        line.push_str(&format!("│{}", position));
+    }
+
+    if let Some(jog_pad) = jog_pad {
+        line.push_str(&format!("│ {} ", jog_pad));
+    }
+
+    if let Some(browser) = browser {
+        line.push_str(&format!("│ {} ", browser));
+    }
+
+    if let Some(power_off) = power_off {
+        line.push_str(&format!("│ {} ", power_off));
+    }
+
+    if let Some(pending) = pending {
+        line.push_str(&format!("│ {}", pending));
+    }
+
+    if let Some(transfer) = transfer {
+        line.push_str(&format!("│ {} ", transfer));
+    }
+
+    if let Some(watched) = watched {
+        line.push_str(&format!("│ watching {} ", watched));
+    }
+
+    if queued > 0 {
+        line.push_str(&format!("│ {} queued ", queued));
+    }
+
+    vec![line]
+}
+
+/// At most `width` characters, ending with an ellipsis when cut.
+pub fn truncate(line: &str, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line.to_string();
+    }
+
+    let mut truncated: String = line.chars().take(width.saturating_sub(1)).collect();
+
+    if width > 0 {
+        truncated.push('…');
+    }
+
+    truncated
+}
+
+fn terminal_size() -> Option<(usize, usize)> {
+    terminal_size::terminal_size().map(|(width, height)| (width.0 as usize, height.0 as usize))
+}
+
+/// Gives the rows of the status bar back to the terminal on exit, or the
+/// main screen when it exits on the placeholder one.
+struct ReservedRows {
+    rows: usize,
+    /// The terminal size the placeholder screen was drawn for, if shown
+    placeholder: Option<(usize, usize)>,
+}
+
+impl Drop for ReservedRows {
+    fn drop(&mut self) {
+        if self.placeholder.is_some() {
+            let _ = io::stdout().write_all(b"\x1b[?25h\x1b[?1049l");
+            let _ = io::stdout().flush();
+            return;
+        }
+
+        if let Some((_, height)) = terminal_size() {
+            let mut reset = String::from("\x1b7\x1b[r");
+
+            for row in height.saturating_sub(self.rows) + 1..=height {
+                reset.push_str(&format!("\x1b[{};1H\x1b[2K", row));
+            }
+
+            reset.push_str("\x1b8");
+            let _ = io::stdout().write_all(reset.as_bytes());
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_to_tiny_widths() {
+        for width in [0, 1, 2] {
+            let line = truncate("connected │ printing", width);
+            assert!(
+                line.chars().count() <= width,
+                "{} columns: {:?}",
+                width,
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn tiny_terminals_get_the_placeholder() {
+        for (width, height) in [(0, 24), (1, 24), (2, 24), (80, 1), (80, 2), (1, 1)] {
+            assert!(!fits(width, height), "{}x{}", width, height);
+        }
+
+        assert!(fits(MIN_WIDTH, MIN_HEIGHT));
+    }
+
+    #[test]
+    fn placeholder_fits_the_terminal() {
+        for width in [0, 1, 2, 80] {
+            for height in [0, 1, 2, 24] {
+                let rows = placeholder(width, height);
+
+                assert_eq!(rows.len(), height, "{}x{}", width, height);
+                assert!(rows.iter().all(|row| row.chars().count() <= width));
+            }
+        }
+
+        assert!(placeholder(80, 1)[0].contains("Terminal too small"));
+    }
+
+    #[test]
+    fn reserves_rows_in_short_terminals() {
+        for height in [1, 2] {
+            for rows in [1, DETAILED_ROWS] {
+                reserve(height, 1, rows);
+            }
+        }
+    }
+}
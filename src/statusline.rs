@@ -0,0 +1,162 @@
+use crate::auth::Auth;
+use crate::config::cache_dir;
+use crate::tls::Tls;
+use crate::units::Units;
+use crate::{klippy_ready, rpc_result, Error, JSON};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+/// Used when `--format` is not given.
+pub const DEFAULT_FORMAT: &str = "{state} {progress}% {nozzle}/{bed}";
+
+/// Status bars run the command every few seconds, within this window the
+/// printer status is read from the cache instead of asking Moonraker again.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// A status bar must not hang on an unreachable printer.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `moonraker-cli statusline [--format <format>]`, prints a single line
+/// for tmux, waybar or polybar and exits. The format placeholders are
+/// `{state}`, `{filename}`, `{progress}`, `{nozzle}`, `{nozzle_target}`,
+/// `{bed}` and `{bed_target}`, temperatures in the configured unit, targets
+/// `off` when the heater is. The line is printed even when the printer
+/// can't be queried, the command fails afterwards.
+pub async fn run(
+    url: &str,
+    format: &str,
+    tls: &Tls,
+    auth: Auth,
+    units: Units,
+) -> Result<(), Error> {
+    let status = match read_cache(url) {
+        Some(status) => Ok(status),
+        None => {
+            auth.for_printer(url);
+            query(url, tls.client_builder()?).await
+        }
+    };
+
+    println!("{}", render(format, status.as_ref().ok(), units));
+
+    status.map(|_| ())
+}
+
+async fn query(url: &str, client_builder: reqwest::ClientBuilder) -> Result<JSON, Error> {
+    let client = client_builder.timeout(TIMEOUT).build()?;
+    let params = Some(json!({
+        "objects": {
+            "print_stats": ["state", "filename"],
+            "virtual_sdcard": ["progress"],
+            "extruder": ["temperature", "target"],
+            "heater_bed": ["temperature", "target"],
+        }
+    }));
+
+    let mut result = match rpc_result(&client, url, "printer.objects.query", params).await {
+        Ok(result) => result,
+        // Most likely Klippy isn't ready, which is worth telling apart
+        Err(Error::Rpc(err)) => {
+            klippy_ready(&client, url).await?;
+            return Err(Error::Rpc(err));
+        }
+        Err(err) => return Err(err),
+    };
+    let status = result.get_mut("status").map(JSON::take).unwrap_or_default();
+
+    // Failing to cache only costs a request next time
+    let _ = write_cache(url, &status);
+
+    Ok(status)
+}
+
+fn read_cache(url: &str) -> Option<JSON> {
+    let path = cache_path(url)?;
+    let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+
+    if age > CACHE_TTL {
+        return None;
+    }
+
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Written next to the cache and renamed over it, so status bars running
+/// the command at once never read it half written.
+fn write_cache(url: &str, status: &JSON) -> io::Result<()> {
+    let Some(path) = cache_path(url) else {
+        return Ok(());
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let partial = path.with_extension(format!("json.{}", process::id()));
+    fs::write(&partial, status.to_string())?;
+    fs::rename(&partial, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+}
+
+/// One cache file per printer, in the user cache directory, none without
+/// a home directory.
+fn cache_path(url: &str) -> Option<PathBuf> {
+    let name: String = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    Some(cache_dir()?.join(format!("statusline-{}.json", name)))
+}
+
+/// Fills in the placeholders, an unreachable printer shows as `offline`
+/// with `?` for every value.
+fn render(format: &str, status: Option<&JSON>, units: Units) -> String {
+    let null = JSON::Null;
+    let state = match status {
+        Some(status) => status["print_stats"]["state"].as_str(),
+        None => Some("offline"),
+    };
+    let status = status.unwrap_or(&null);
+    let temperature = |value: &JSON| {
+        value
+            .as_f64()
+            .map(|temp| format!("{:.0}", units.temperature(temp)))
+    };
+    // Off rather than 0°C, which isn't even 0 in Fahrenheit
+    let target = |value: &JSON| match value.as_f64() {
+        Some(temp) if temp <= 0.0 => Some("off".to_string()),
+        _ => temperature(value),
+    };
+
+    let placeholders = [
+        ("{state}", state.map(str::to_string)),
+        (
+            "{filename}",
+            status["print_stats"]["filename"]
+                .as_str()
+                .map(str::to_string),
+        ),
+        (
+            "{progress}",
+            status["virtual_sdcard"]["progress"]
+                .as_f64()
+                .map(|progress| format!("{:.0}", progress * 100.0)),
+        ),
+        ("{nozzle}", temperature(&status["extruder"]["temperature"])),
+        ("{nozzle_target}", target(&status["extruder"]["target"])),
+        ("{bed}", temperature(&status["heater_bed"]["temperature"])),
+        ("{bed_target}", target(&status["heater_bed"]["target"])),
+    ];
+
+    placeholders
+        .into_iter()
+        .fold(format.to_string(), |line, (placeholder, value)| {
+            line.replace(placeholder, value.as_deref().unwrap_or("?"))
+        })
+}
@@ -0,0 +1,130 @@
+use crate::auth::Auth;
+use crate::cli::OutputFormat;
+use crate::output;
+use crate::tls::Tls;
+use crate::units::Units;
+use crate::{klippy_ready, rpc_result, Error, JSON};
+use serde_json::json;
+
+/// `moonraker-cli status`, prints the print state, the toolhead and every
+/// heater, either as a few lines or as the status objects.
+pub async fn run(
+    url: &str,
+    format: OutputFormat,
+    tls: &Tls,
+    auth: Auth,
+    units: Units,
+) -> Result<(), Error> {
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+    klippy_ready(&client, url).await?;
+
+    let status = query(&client, url).await?;
+    let text = render(&status, units);
+
+    match output::render(status, format)? {
+        Some(output) => println!("{}", output),
+        None => println!("{}", text),
+    }
+
+    Ok(())
+}
+
+/// The objects summarized, heaters found through `heaters`.
+async fn query(client: &reqwest::Client, url: &str) -> Result<JSON, Error> {
+    let params = Some(json!({ "objects": { "heaters": ["available_heaters"] } }));
+    let result = rpc_result(client, url, "printer.objects.query", params).await?;
+    let heaters = result["status"]["heaters"]["available_heaters"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+
+    let mut objects = json!({
+        "print_stats": ["state", "filename", "print_duration", "message"],
+        "virtual_sdcard": ["progress"],
+        "toolhead": ["position", "homed_axes"],
+    });
+
+    for heater in heaters.iter().filter_map(JSON::as_str) {
+        objects[heater] = json!(["temperature", "target"]);
+    }
+
+    let params = Some(json!({ "objects": objects }));
+    let mut result = rpc_result(client, url, "printer.objects.query", params).await?;
+
+    Ok(result.get_mut("status").map(JSON::take).unwrap_or_default())
+}
+
+fn render(status: &JSON, units: Units) -> String {
+    let print_stats = &status["print_stats"];
+    let mut lines = Vec::new();
+
+    let mut print = format!("State: {}", print_stats["state"].as_str().unwrap_or("?"));
+
+    if let Some(filename) = print_stats["filename"]
+        .as_str()
+        .filter(|name| !name.is_empty())
+    {
+        let progress = status["virtual_sdcard"]["progress"].as_f64().unwrap_or(0.0);
+        let duration = print_stats["print_duration"].as_f64().unwrap_or(0.0) as u64;
+
+        print.push_str(&format!(
+            ", {} {:.0}% after {}h{:02}m",
+            filename,
+            progress * 100.0,
+            duration / 3600,
+            duration / 60 % 60
+        ));
+    }
+
+    lines.push(print);
+
+    if let Some(message) = print_stats["message"]
+        .as_str()
+        .filter(|message| !message.is_empty())
+    {
+        lines.push(format!("Message: {}", message));
+    }
+
+    let toolhead = &status["toolhead"];
+    let position = toolhead["position"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let axes = ["X", "Y", "Z"]
+        .iter()
+        .zip(position)
+        .filter_map(|(axis, mm)| Some(format!("{} {}", axis, units.format_length(mm.as_f64()?))))
+        .collect::<Vec<String>>();
+    let homed = match toolhead["homed_axes"].as_str().unwrap_or_default() {
+        "" => "not homed".to_string(),
+        axes => format!("{} homed", axes.to_uppercase()),
+    };
+
+    lines.push(format!("Toolhead: {}, {}", axes.join(" "), homed));
+
+    for (name, heater) in status.as_object().into_iter().flatten() {
+        let (Some(temperature), Some(target)) =
+            (heater["temperature"].as_f64(), heater["target"].as_f64())
+        else {
+            continue;
+        };
+
+        // Off rather than 0°C, which isn't even 0 in Fahrenheit
+        let target = if target > 0.0 {
+            units.format_temperature(target)
+        } else {
+            "off".to_string()
+        };
+
+        lines.push(format!(
+            "{}: {} / {}",
+            name,
+            units.format_temperature(temperature),
+            target
+        ));
+    }
+
+    lines.join("\n")
+}
@@ -0,0 +1,189 @@
+use crate::auth::{self, Auth};
+use crate::download::file_url;
+use crate::files::{is_gcode, join};
+use crate::tls::Tls;
+use crate::upload::{self, Progress};
+use crate::{rpc_result, Error};
+use futures_util::StreamExt;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// A file on either side, the path relative to the directory synced.
+struct File {
+    size: u64,
+    modified: f64,
+}
+
+/// `moonraker-cli sync <local dir> <gcodes dir>`, uploads the G-code files
+/// in the local directory and below that are new or changed since last
+/// uploaded, skipping the rest. Files of the same size are changed when
+/// modified after the upload, or with `checksum` when their SHA-256
+/// differs, the printer copy downloaded to hash it. Never deletes anything
+/// on the printer.
+pub async fn run(
+    url: &str,
+    local: &Path,
+    remote: &str,
+    checksum: bool,
+    dry_run: bool,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let remote = remote.trim_matches('/');
+    let dir = match remote.split_once('/') {
+        Some(("gcodes", dir)) => dir,
+        None if remote == "gcodes" => "",
+        _ => {
+            return Err(Error::Env(format!(
+                "Invalid directory {}, expected one under gcodes",
+                remote
+            )))
+        }
+    };
+
+    let local_files = walk(local)?;
+
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+
+    let remote_files = list(&client, url, dir).await?;
+    let (mut uploaded, mut unchanged) = (0, 0);
+
+    for (path, file) in &local_files {
+        let source = local.join(path);
+        let target = join(dir, path);
+        let changed = match remote_files.get(path) {
+            None => true,
+            Some(remote) if remote.size != file.size => true,
+            Some(_) if checksum => {
+                upload::checksum(&source).await? != remote_checksum(&client, url, &target).await?
+            }
+            Some(remote) => file.modified > remote.modified,
+        };
+
+        if !changed {
+            unchanged += 1;
+            continue;
+        }
+
+        if dry_run {
+            println!("Would upload {}", target);
+            uploaded += 1;
+            continue;
+        }
+
+        // Into the directory of the file, under the one synced
+        let parent = target.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let progress = Progress::default();
+        let bar = progress.draw();
+        let resp = upload::upload(&client, url, &source, parent, false, &progress).await;
+        bar.erase();
+
+        resp?;
+        println!("Uploaded {}", target);
+        uploaded += 1;
+    }
+
+    let verb = if dry_run {
+        "would be uploaded"
+    } else {
+        "uploaded"
+    };
+    println!("{} {}, {} unchanged", uploaded, verb, unchanged);
+    Ok(())
+}
+
+/// The G-code files under `root`, by path relative to it with `/` separators.
+fn walk(root: &Path) -> Result<BTreeMap<String, File>, Error> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let relative = dir.join(entry.file_name());
+            let metadata = entry.metadata()?;
+
+            if metadata.is_dir() {
+                dirs.push(relative);
+                continue;
+            }
+
+            let path = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if !is_gcode(&path) {
+                continue;
+            }
+
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |time| time.as_secs_f64());
+
+            files.insert(
+                path,
+                File {
+                    size: metadata.len(),
+                    modified,
+                },
+            );
+        }
+    }
+
+    Ok(files)
+}
+
+/// The files under `dir` on the printer, by path relative to it.
+async fn list(
+    client: &reqwest::Client,
+    url: &str,
+    dir: &str,
+) -> Result<HashMap<String, File>, Error> {
+    let params = Some(json!({ "root": "gcodes" }));
+    let files = rpc_result(client, url, "server.files.list", params).await?;
+    let prefix = match dir {
+        "" => String::new(),
+        dir => format!("{}/", dir),
+    };
+
+    Ok(files
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|file| {
+            let path = file["path"].as_str()?.strip_prefix(&prefix)?;
+            let file = File {
+                size: file["size"].as_u64().unwrap_or(0),
+                modified: file["modified"].as_f64().unwrap_or(0.0),
+            };
+
+            Some((path.to_string(), file))
+        })
+        .collect())
+}
+
+/// The SHA-256 of a file on the printer as hex, hashed as it downloads.
+async fn remote_checksum(client: &reqwest::Client, url: &str, path: &str) -> Result<String, Error> {
+    let request = client.get(file_url(url, &format!("gcodes/{}", path))?);
+    let resp = auth::authorize(request, url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut chunks = resp.bytes_stream();
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = chunks.next().await {
+        hasher.update(chunk?);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
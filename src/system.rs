@@ -0,0 +1,92 @@
+use crate::{rpc_result, Error};
+use serde_json::json;
+
+/// What `:system` does.
+pub enum Command {
+    /// Shows the host OS and the packages waiting for an upgrade
+    Info,
+    /// Asks to confirm upgrading the packages
+    Update,
+    /// Upgrades the packages
+    Confirm,
+}
+
+impl Command {
+    pub fn parse(args: &str) -> Result<Command, String> {
+        match args.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => Ok(Command::Info),
+            ["update"] => Ok(Command::Update),
+            ["update", "confirm"] => Ok(Command::Confirm),
+            _ => Err(format!("Unknown :system argument {}, try :help", args)),
+        }
+    }
+
+    pub async fn run(&self, client: &reqwest::Client, url: &str) -> Result<String, Error> {
+        match self {
+            Command::Info => info(client, url).await,
+            Command::Update => confirm_update(client, url).await,
+            Command::Confirm => update(client, url).await,
+        }
+    }
+}
+
+/// Host distribution, kernel and the OS packages waiting for an upgrade, as
+/// known to the update manager. Doesn't refresh the package lists, which
+/// Moonraker does on its own schedule.
+pub async fn info(client: &reqwest::Client, url: &str) -> Result<String, Error> {
+    let info = rpc_result(client, url, "machine.system_info", None).await?;
+    let distribution = &info["system_info"]["distribution"];
+
+    let mut lines = vec![
+        format!(
+            "Distribution  {}",
+            distribution["name"].as_str().unwrap_or("unknown")
+        ),
+        format!(
+            "Kernel        {}",
+            distribution["kernel_version"].as_str().unwrap_or("unknown")
+        ),
+    ];
+
+    match package_count(client, url).await {
+        Ok(count) => {
+            lines.push(format!("Packages      {} to upgrade", count));
+
+            if count > 0 {
+                lines.push(":system update upgrades them".to_string());
+            }
+        }
+        Err(err) => lines.push(format!("Packages      unknown, {}", err)),
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// The OS packages waiting for an upgrade, without refreshing the lists.
+async fn package_count(client: &reqwest::Client, url: &str) -> Result<u64, Error> {
+    let params = Some(json!({ "refresh": false }));
+    let status = rpc_result(client, url, "machine.update.status", params).await?;
+
+    Ok(status["version_info"]["system"]["package_count"]
+        .as_u64()
+        .unwrap_or(0))
+}
+
+/// Asks before upgrading, the upgrade can restart services the printer
+/// depends on and takes a while.
+async fn confirm_update(client: &reqwest::Client, url: &str) -> Result<String, Error> {
+    match package_count(client, url).await? {
+        0 => Ok("No packages to upgrade".to_string()),
+        count => Ok(format!(
+            "Upgrade {} packages on the host? :system update confirm upgrades them",
+            count
+        )),
+    }
+}
+
+/// Upgrades the host OS packages through the update manager.
+async fn update(client: &reqwest::Client, url: &str) -> Result<String, Error> {
+    rpc_result(client, url, "machine.update.system", None).await?;
+
+    Ok("System packages upgraded".to_string())
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+/// The template named by the first word of `line`, if any, and the rest of
+/// the line holding its values.
+pub fn find<'a>(
+    templates: &'a BTreeMap<String, String>,
+    line: &'a str,
+) -> Option<(&'a str, &'a str)> {
+    let line = line.trim();
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+    templates
+        .iter()
+        .find(|(template, _)| template.eq_ignore_ascii_case(name))
+        .map(|(_, body)| (body.as_str(), args.trim()))
+}
+
+/// The uppercase names in braces, like `LENGTH` in `G1 E{LENGTH} F300`, in
+/// the order they first appear.
+pub fn parameters(body: &str) -> Vec<String> {
+    let mut parameters: Vec<String> = Vec::new();
+
+    for expr in braces(body) {
+        for name in names(expr) {
+            if !parameters.iter().any(|parameter| parameter == name) {
+                parameters.push(name.to_string());
+            }
+        }
+    }
+
+    parameters
+}
+
+/// The values typed after the template name, either in the order of the
+/// parameters or as `NAME=value`. Those missing are left out.
+pub fn values(parameters: &[String], args: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut values = BTreeMap::new();
+    let mut positional = Vec::new();
+
+    for arg in args.split_whitespace() {
+        match arg.split_once('=') {
+            Some((name, value)) if parameters.contains(&name.to_uppercase()) => {
+                values.insert(name.to_uppercase(), value.to_string());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut unset = parameters
+        .iter()
+        .filter(|parameter| !values.contains_key(*parameter))
+        .cloned()
+        .collect::<Vec<String>>()
+        .into_iter();
+
+    for value in positional {
+        let Some(parameter) = unset.next() else {
+            return Err(format!(
+                "Too many values, the template takes {}",
+                parameters.join(" ")
+            ));
+        };
+        values.insert(parameter, value.to_string());
+    }
+
+    Ok(values)
+}
+
+/// The template with its parameters replaced by their values. A brace
+/// holding only a parameter becomes the value, other braces keep theirs
+/// for the arithmetic to be evaluated.
+pub fn fill(body: &str, values: &BTreeMap<String, String>) -> String {
+    let mut filled = String::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        let expr = &rest[start + 1..end];
+        filled.push_str(&rest[..start]);
+
+        match values.get(expr.trim()) {
+            Some(value) => filled.push_str(value),
+            None => filled.push_str(&format!("{{{}}}", substitute(expr, values))),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    filled.push_str(rest);
+    filled
+}
+
+/// `expr` with each parameter replaced by its value in parentheses, so
+/// `{LENGTH*2}` with a length of `10+5` is `{(10+5)*2}`.
+fn substitute(expr: &str, values: &BTreeMap<String, String>) -> String {
+    let mut substituted = String::new();
+    let mut name = String::new();
+
+    for c in expr.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_uppercase() || c == '_' || (c.is_ascii_digit() && !name.is_empty()) {
+            name.push(c);
+            continue;
+        }
+
+        match values.get(&name) {
+            Some(value) => substituted.push_str(&format!("({})", value)),
+            None => substituted.push_str(&name),
+        }
+
+        name.clear();
+        substituted.push(c);
+    }
+
+    substituted.pop();
+    substituted
+}
+
+/// What's inside each pair of braces.
+fn braces(body: &str) -> Vec<&str> {
+    let mut braces = Vec::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        braces.push(&rest[start + 1..end]);
+        rest = &rest[end + 1..];
+    }
+
+    braces
+}
+
+/// Uppercase identifiers, like `LENGTH` or `Z_HOP`.
+fn names(expr: &str) -> impl Iterator<Item = &str> {
+    expr.split(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+        .filter(|word| word.starts_with(|c: char| c.is_ascii_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_templates_by_first_word() {
+        let templates = map(&[("purge", "G1 E{LENGTH} F300")]);
+
+        assert_eq!(
+            find(&templates, " PURGE 10 "),
+            Some(("G1 E{LENGTH} F300", "10"))
+        );
+        assert_eq!(find(&templates, "purge"), Some(("G1 E{LENGTH} F300", "")));
+        assert_eq!(find(&templates, "purger 10"), None);
+    }
+
+    #[test]
+    fn lists_parameters_once_in_order() {
+        assert_eq!(
+            parameters("G1 Z{Z_HOP} E{LENGTH*2}\nG1 Z{-Z_HOP}"),
+            ["Z_HOP", "LENGTH"]
+        );
+        assert!(parameters("G28\nRESPOND MSG={printer.name}").is_empty());
+    }
+
+    #[test]
+    fn takes_values_by_position_or_name() {
+        let parameters = ["LENGTH".to_string(), "SPEED".to_string()];
+
+        assert_eq!(
+            values(&parameters, "10 300").unwrap(),
+            map(&[("LENGTH", "10"), ("SPEED", "300")])
+        );
+        assert_eq!(
+            values(&parameters, "speed=300 10").unwrap(),
+            map(&[("LENGTH", "10"), ("SPEED", "300")])
+        );
+        assert_eq!(
+            values(&parameters, "SPEED=300").unwrap(),
+            map(&[("SPEED", "300")])
+        );
+        assert!(values(&parameters, "1 2 3").is_err());
+    }
+
+    #[test]
+    fn fills_parameters_leaving_arithmetic() {
+        let values = map(&[("LENGTH", "10+5")]);
+
+        assert_eq!(fill("G1 E{LENGTH} F300", &values), "G1 E10+5 F300");
+        assert_eq!(fill("G1 E{LENGTH*2}", &values), "G1 E{(10+5)*2}");
+        assert_eq!(fill("G1 E{SPEED}", &values), "G1 E{SPEED}");
+        assert_eq!(fill("G1 X{", &values), "G1 X{");
+    }
+}
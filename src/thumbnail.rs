@@ -0,0 +1,410 @@
+use crate::auth;
+use crate::download::file_url;
+use crate::{Error, JSON};
+use base64::Engine;
+use std::env;
+use std::io::{Cursor, IsTerminal};
+use std::sync::{Arc, Mutex};
+
+/// Columns of the half-block rendering, two pixels a row.
+const BLOCK_COLUMNS: usize = 32;
+
+/// Slicers embed several sizes, the largest up to this wide is drawn.
+const MAX_WIDTH: u64 = 400;
+
+/// Base64 sent per kitty graphics escape, the protocol's limit.
+const KITTY_CHUNK: usize = 4096;
+
+/// Levels of each channel in the sixel palette, 6×6×6 colors.
+const SIXEL_LEVELS: usize = 6;
+
+/// How thumbnails are drawn, `graphics` in the config. The automatic choice
+/// goes by what the terminal advertises in its environment, half-blocks
+/// working anywhere with true color.
+#[derive(Clone, Copy, Default)]
+pub enum Graphics {
+    #[default]
+    Auto,
+    Kitty,
+    Sixel,
+    Blocks,
+    None,
+}
+
+impl Graphics {
+    pub fn parse(graphics: &str) -> Result<Graphics, String> {
+        match graphics {
+            "auto" => Ok(Graphics::Auto),
+            "kitty" => Ok(Graphics::Kitty),
+            "sixel" => Ok(Graphics::Sixel),
+            "blocks" => Ok(Graphics::Blocks),
+            "none" => Ok(Graphics::None),
+            graphics => Err(format!(
+                "Unknown graphics {}, expected auto, kitty, sixel, blocks or none",
+                graphics
+            )),
+        }
+    }
+
+    /// Whether the terminal takes true color, anything but `none`.
+    pub fn has_color(self) -> bool {
+        !matches!(self.resolve(), Graphics::None)
+    }
+
+    fn resolve(self) -> Graphics {
+        let Graphics::Auto = self else {
+            return self;
+        };
+
+        let var = |name: &str| env::var(name).unwrap_or_default();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+
+        if !std::io::stdout().is_terminal() {
+            Graphics::None
+        } else if env::var_os("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || ["WezTerm", "ghostty"].contains(&program.as_str())
+        {
+            Graphics::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || program == "iTerm.app"
+        {
+            Graphics::Sixel
+        } else {
+            Graphics::Blocks
+        }
+    }
+}
+
+/// Kitty and sixel images, each with the placeholder line standing for it
+/// in the reply. Only text goes in replies, as they're kept by the pager,
+/// the transcript and `:save`, the image is drawn in place of its
+/// placeholder when the reply reaches the terminal.
+#[derive(Clone, Default)]
+pub struct Images(Arc<Mutex<Vec<(String, String)>>>);
+
+impl Images {
+    /// `output` for the terminal, the placeholder lines replaced by the
+    /// images they stand for. Each is drawn once, later showings of the
+    /// same output keep the placeholder.
+    pub fn draw(&self, output: String) -> String {
+        let mut images = self.0.lock().unwrap();
+
+        if images.is_empty() {
+            return output;
+        }
+
+        output
+            .lines()
+            .map(|line| {
+                let placeholder = line.trim_start();
+                let Some(index) = images.iter().position(|(kept, _)| kept == placeholder) else {
+                    return line.to_string();
+                };
+                let (_, escapes) = images.remove(index);
+                let margin = &line[..line.len() - placeholder.len()];
+
+                format!("{}{}", margin, escapes)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// A decoded thumbnail, kept encoded too for the kitty protocol.
+pub struct Image {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    png: Vec<u8>,
+}
+
+/// Downloads the thumbnail of `filename` the slicer embedded, as listed in
+/// its `metadata`, if any.
+pub async fn fetch(
+    client: &reqwest::Client,
+    url: &str,
+    filename: &str,
+    metadata: &JSON,
+) -> Result<Option<Image>, Error> {
+    let thumbnails = metadata["thumbnails"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let width = |thumbnail: &JSON| thumbnail["width"].as_u64().unwrap_or(0);
+
+    let chosen = thumbnails
+        .iter()
+        .filter(|thumbnail| width(thumbnail) <= MAX_WIDTH)
+        .max_by_key(|thumbnail| width(thumbnail))
+        .or_else(|| thumbnails.iter().min_by_key(|thumbnail| width(thumbnail)));
+
+    let Some(path) = chosen.and_then(|thumbnail| thumbnail["relative_path"].as_str()) else {
+        return Ok(None);
+    };
+
+    // Relative to the directory of the file
+    let path = match filename.rsplit_once('/') {
+        Some((dir, _)) => format!("{}/{}", dir, path),
+        None => path.to_string(),
+    };
+
+    let request = client.get(file_url(url, &format!("gcodes/{}", path))?);
+    let png = auth::authorize(request, url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec();
+
+    decode(png).map(Some)
+}
+
+fn decode(png: Vec<u8>) -> Result<Image, Error> {
+    let invalid = |err: png::DecodingError| Error::Env(format!("Invalid thumbnail: {}", err));
+
+    let mut decoder = png::Decoder::new(Cursor::new(&png));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(invalid)?;
+    pixels.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        // Expanded by the transformations
+        png::ColorType::Indexed => return Err(Error::Env("Invalid thumbnail".to_string())),
+    };
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        rgba,
+        png,
+    })
+}
+
+impl Image {
+    /// Draws the thumbnail with `graphics`, `margin` columns from the left.
+    /// Kitty and sixel images are kept in `images`, a placeholder is drawn
+    /// instead.
+    pub fn render(&self, graphics: Graphics, margin: usize, images: &Images) -> Option<String> {
+        let margin = " ".repeat(margin);
+        let placeholder = format!("[thumbnail {}×{}]", self.width, self.height);
+
+        match graphics.resolve() {
+            Graphics::Kitty => {
+                images
+                    .0
+                    .lock()
+                    .unwrap()
+                    .push((placeholder.clone(), self.kitty()));
+                Some(format!("{}{}", margin, placeholder))
+            }
+            Graphics::Sixel => {
+                images
+                    .0
+                    .lock()
+                    .unwrap()
+                    .push((placeholder.clone(), self.sixel()));
+                Some(format!("{}{}", margin, placeholder))
+            }
+            Graphics::Blocks => Some(
+                self.blocks()
+                    .iter()
+                    .map(|line| format!("{}{}", margin, line))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            Graphics::Auto | Graphics::None => None,
+        }
+    }
+
+    /// How many columns the half-block rendering takes.
+    pub fn columns(&self) -> usize {
+        self.width.min(BLOCK_COLUMNS)
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.rgba[offset..offset + 4]);
+        pixel
+    }
+
+    /// Averages the pixels the scaled down pixel covers, in `scale` units.
+    fn sample(&self, x: usize, y: usize, scale: f64) -> [u8; 4] {
+        let x0 = ((x as f64 * scale) as usize).min(self.width - 1);
+        let y0 = ((y as f64 * scale) as usize).min(self.height - 1);
+        let x1 = (((x + 1) as f64 * scale) as usize).clamp(x0 + 1, self.width);
+        let y1 = (((y + 1) as f64 * scale) as usize).clamp(y0 + 1, self.height);
+
+        let mut sum = [0u32; 4];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                for (total, channel) in sum.iter_mut().zip(self.pixel(x, y)) {
+                    *total += channel as u32;
+                }
+            }
+        }
+
+        let count = ((x1 - x0) * (y1 - y0)) as u32;
+        sum.map(|total| (total / count) as u8)
+    }
+
+    /// Two pixels a cell, the upper half block in the foreground color over
+    /// the background one. Transparent pixels are left to the terminal.
+    fn blocks(&self) -> Vec<String> {
+        let columns = self.columns();
+        let scale = self.width as f64 / columns as f64;
+        let rows = ((self.height as f64 / scale).round() as usize).max(1);
+        let opaque = |pixel: [u8; 4]| pixel[3] >= 128;
+
+        (0..rows.div_ceil(2))
+            .map(|row| {
+                let mut line = String::new();
+
+                for x in 0..columns {
+                    let top = self.sample(x, row * 2, scale);
+                    let bottom = if row * 2 + 1 < rows {
+                        self.sample(x, row * 2 + 1, scale)
+                    } else {
+                        [0; 4]
+                    };
+
+                    line.push_str(&match (opaque(top), opaque(bottom)) {
+                        (true, true) => format!(
+                            "\x1b[38;2;{};{};{};48;2;{};{};{}m▀",
+                            top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                        ),
+                        (true, false) => {
+                            format!("\x1b[0;38;2;{};{};{}m▀", top[0], top[1], top[2])
+                        }
+                        (false, true) => {
+                            format!("\x1b[0;38;2;{};{};{}m▄", bottom[0], bottom[1], bottom[2])
+                        }
+                        (false, false) => "\x1b[0m ".to_string(),
+                    });
+                }
+
+                line.push_str("\x1b[0m");
+                line
+            })
+            .collect()
+    }
+
+    /// The PNG as is, the terminal decodes it.
+    fn kitty(&self) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&self.png);
+        let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+        let mut escapes = String::new();
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let more = (index + 1 < chunks.len()) as u8;
+            let chunk = String::from_utf8_lossy(chunk);
+
+            if index == 0 {
+                escapes.push_str(&format!("\x1b_Gf=100,a=T,m={};{}\x1b\\", more, chunk));
+            } else {
+                escapes.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+            }
+        }
+
+        escapes
+    }
+
+    /// Six rows of pixels a band, each color of the band drawn in turn over
+    /// it. Colors are rounded to a fixed palette, transparent pixels left
+    /// unchanged.
+    fn sixel(&self) -> String {
+        let level = |channel: u8| (channel as usize * (SIXEL_LEVELS - 1) + 127) / 255;
+        let index = |pixel: [u8; 4]| {
+            (pixel[3] >= 128).then(|| {
+                (level(pixel[0]) * SIXEL_LEVELS + level(pixel[1])) * SIXEL_LEVELS + level(pixel[2])
+            })
+        };
+
+        let mut sixel = format!("\x1bP0;1;0q\"1;1;{};{}", self.width, self.height);
+
+        for color in 0..SIXEL_LEVELS.pow(3) {
+            let percent = |level: usize| level * 100 / (SIXEL_LEVELS - 1);
+            sixel.push_str(&format!(
+                "#{};2;{};{};{}",
+                color,
+                percent(color / (SIXEL_LEVELS * SIXEL_LEVELS)),
+                percent(color / SIXEL_LEVELS % SIXEL_LEVELS),
+                percent(color % SIXEL_LEVELS)
+            ));
+        }
+
+        for band in (0..self.height).step_by(6) {
+            let mut columns = vec![[None; 6]; self.width];
+
+            for (x, column) in columns.iter_mut().enumerate() {
+                for (dy, cell) in column.iter_mut().enumerate() {
+                    if band + dy < self.height {
+                        *cell = index(self.pixel(x, band + dy));
+                    }
+                }
+            }
+
+            let mut colors: Vec<usize> = columns.iter().flatten().flatten().copied().collect();
+            colors.sort_unstable();
+            colors.dedup();
+
+            for color in colors {
+                let chars: Vec<char> = columns
+                    .iter()
+                    .map(|column| {
+                        let bits = column
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, cell)| **cell == Some(color))
+                            .fold(0, |bits, (dy, _)| bits | 1 << dy);
+                        (63 + bits) as u8 as char
+                    })
+                    .collect();
+
+                sixel.push_str(&format!("#{}{}$", color, run_length(&chars)));
+            }
+
+            sixel.push('-');
+        }
+
+        sixel.push_str("\x1b\\");
+        sixel
+    }
+}
+
+/// Repeats of a sixel as `!<count><sixel>`.
+fn run_length(chars: &[char]) -> String {
+    let mut encoded = String::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let char = chars[index];
+        let count = chars[index..].iter().take_while(|&&c| c == char).count();
+
+        if count > 3 {
+            encoded.push_str(&format!("!{}{}", count, char));
+        } else {
+            encoded.extend(std::iter::repeat_n(char, count));
+        }
+
+        index += count;
+    }
+
+    encoded
+}
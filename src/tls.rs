@@ -0,0 +1,91 @@
+use crate::cli::Cli;
+use crate::config::Config;
+use crate::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How `https://` and `wss://` printers are verified, set from the command
+/// line. Printers behind a reverse proxy often use a self-signed certificate,
+/// which `--ca-cert` trusts explicitly and `--insecure` skips checking at all.
+/// Proxies requiring mutual TLS get the `--client-cert` and `--client-key` pair.
+#[derive(Clone, Default)]
+pub struct Tls {
+    /// PEM encoded certificate trusted on top of the system ones
+    ca_cert: Option<Vec<u8>>,
+    insecure: bool,
+    /// PEM encoded certificate chain and PKCS#8 private key
+    client_identity: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Tls {
+    /// The TLS options from the command line, or else from the config.
+    pub fn new(cli: &Cli, config: &Config) -> Result<Tls, Error> {
+        let read = |flag: &Option<PathBuf>, setting: &Option<PathBuf>, what| {
+            flag.as_ref()
+                .or(setting.as_ref())
+                .map(|path| read_pem(path, what))
+                .transpose()
+        };
+
+        let client_identity = match (
+            read(&cli.client_cert, &config.client_cert, "client certificate")?,
+            read(&cli.client_key, &config.client_key, "client key")?,
+        ) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => {
+                return Err(Error::Env(
+                    "client-cert and client-key must be given together".to_string(),
+                ))
+            }
+        };
+
+        Ok(Tls {
+            ca_cert: read(&cli.ca_cert, &config.ca_cert, "CA certificate")?,
+            insecure: cli.insecure || config.insecure.unwrap_or(false),
+            client_identity,
+        })
+    }
+
+    /// A client builder set up to reach the printer.
+    pub fn client_builder(&self) -> Result<reqwest::ClientBuilder, Error> {
+        let mut builder = reqwest::Client::builder().danger_accept_invalid_certs(self.insecure);
+
+        if let Some(pem) = &self.ca_cert {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(pem)?);
+        }
+
+        if let Some((cert, key)) = &self.client_identity {
+            builder = builder.identity(reqwest::Identity::from_pkcs8_pem(cert, key)?);
+        }
+
+        Ok(builder)
+    }
+
+    /// The same settings for the websocket.
+    pub fn connector(&self) -> Result<native_tls::TlsConnector, Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder.danger_accept_invalid_certs(self.insecure);
+
+        if let Some(pem) = &self.ca_cert {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(pem)?);
+        }
+
+        if let Some((cert, key)) = &self.client_identity {
+            builder.identity(native_tls::Identity::from_pkcs8(cert, key)?);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+fn read_pem(path: &Path, what: &str) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|err| {
+        Error::Env(format!(
+            "Unable to read {} {}: {}",
+            what,
+            path.display(),
+            err
+        ))
+    })
+}
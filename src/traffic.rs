@@ -0,0 +1,131 @@
+use crate::cleanup::format_size;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes the websocket moved this session, across reconnects and printer
+/// switches, as they went over the wire, TLS included. Commands go over
+/// HTTP and aren't counted, so it's labelled as the websocket's.
+#[derive(Clone, Default)]
+pub struct Traffic(Arc<Counters>);
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    received: AtomicU64,
+    /// What the compressed messages received took once inflated
+    inflated: AtomicU64,
+    /// Those messages as received
+    deflated: AtomicU64,
+    /// Whether the printer agreed to compress its messages
+    compressed: AtomicBool,
+}
+
+impl Traffic {
+    pub fn record_inflated(&self, deflated: usize, inflated: usize) {
+        self.0
+            .deflated
+            .fetch_add(deflated as u64, Ordering::Relaxed);
+        self.0
+            .inflated
+            .fetch_add(inflated as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_compressed(&self, compressed: bool) {
+        self.0.compressed.store(compressed, Ordering::Relaxed);
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.0.compressed.load(Ordering::Relaxed)
+    }
+
+    /// Sent and received, for the status bar.
+    pub fn summary(&self) -> String {
+        format!(
+            "ws ↑ {} ↓ {}",
+            format_size(self.0.sent.load(Ordering::Relaxed)),
+            format_size(self.0.received.load(Ordering::Relaxed))
+        )
+    }
+
+    /// Every counter, for `:debug-state`.
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "sent {}, received {}",
+            format_size(self.0.sent.load(Ordering::Relaxed)),
+            format_size(self.0.received.load(Ordering::Relaxed))
+        );
+
+        if self.0.compressed.load(Ordering::Relaxed) {
+            description.push_str(&format!(
+                ", permessage-deflate {} inflated to {}",
+                format_size(self.0.deflated.load(Ordering::Relaxed)),
+                format_size(self.0.inflated.load(Ordering::Relaxed))
+            ));
+        } else {
+            description.push_str(", uncompressed");
+        }
+
+        description
+    }
+}
+
+/// A stream counting the bytes read and written through it.
+pub struct Metered<S> {
+    inner: S,
+    traffic: Traffic,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, traffic: Traffic) -> Metered<S> {
+        Metered { inner, traffic }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+
+        self.traffic
+            .0
+            .received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            self.traffic
+                .0
+                .sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
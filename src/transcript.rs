@@ -0,0 +1,187 @@
+use crate::cli::parse_duration;
+use crate::ics::format_timestamp;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Everything the console showed this session, commands, replies and
+/// notices, each with when it was shown.
+#[derive(Clone, Default)]
+pub struct Transcript(Arc<Mutex<Vec<Entry>>>);
+
+struct Entry {
+    time: SystemTime,
+    text: String,
+}
+
+/// What `:export` writes out, entries shown in a time range and lines
+/// matching a pattern.
+pub struct Export {
+    since: Option<Duration>,
+    until: Option<Duration>,
+    /// Matched ignoring case
+    grep: Option<String>,
+    path: String,
+}
+
+impl Export {
+    /// `[--since <duration>] [--until <duration>] [--grep <pattern>] <file>`,
+    /// durations counting back from now.
+    pub fn parse(args: &str) -> Result<Export, String> {
+        let mut export = Export {
+            since: None,
+            until: None,
+            grep: None,
+            path: String::new(),
+        };
+        let mut words = args.split_whitespace();
+
+        while let Some(word) = words.next() {
+            let mut value = |flag: &str| {
+                words
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}", flag))
+            };
+
+            match word {
+                "--since" => export.since = Some(parse_duration(value(word)?)?),
+                "--until" => export.until = Some(parse_duration(value(word)?)?),
+                "--grep" => export.grep = Some(value(word)?.to_lowercase()),
+                flag if flag.starts_with("--") => {
+                    return Err(format!(
+                        "Unknown option {}, expected --since, --until or --grep",
+                        flag
+                    ))
+                }
+                path if export.path.is_empty() => export.path = path.to_string(),
+                _ => return Err("Expected a single file, try :help".to_string()),
+            }
+        }
+
+        if export.path.is_empty() {
+            return Err("Missing file for :export, try :help".to_string());
+        }
+
+        Ok(export)
+    }
+}
+
+impl Transcript {
+    pub fn push(&self, text: String) {
+        self.0.lock().unwrap().push(Entry {
+            time: SystemTime::now(),
+            text,
+        });
+    }
+
+    /// Replaces the last entry, temperature reports overwrite each other.
+    pub fn replace_last(&self, text: String) {
+        let mut entries = self.0.lock().unwrap();
+        entries.pop();
+        entries.push(Entry {
+            time: SystemTime::now(),
+            text,
+        });
+    }
+
+    /// The whole transcript as shown, for `:save` and the debug bundle.
+    pub fn text(&self) -> String {
+        let entries = self.0.lock().unwrap();
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_str()).collect();
+
+        texts.join("\n") + "\n"
+    }
+
+    /// The last `count` lines printed, leaving out the command asking for them.
+    pub fn last_lines(&self, count: usize) -> String {
+        let entries = self.0.lock().unwrap();
+        let entries = &entries[..entries.len().saturating_sub(1)];
+        let lines: Vec<&str> = entries
+            .iter()
+            .flat_map(|entry| entry.text.lines())
+            .collect();
+
+        lines[lines.len().saturating_sub(count)..].join("\n")
+    }
+
+    /// Writes the lines `export` selects, each headed by when it was shown,
+    /// leaving out the command asking for them.
+    pub fn export(&self, export: &Export) -> Result<String, String> {
+        let now = SystemTime::now();
+        let since = export.since.and_then(|since| now.checked_sub(since));
+        let until = export.until.and_then(|until| now.checked_sub(until));
+
+        let entries = self.0.lock().unwrap();
+        let entries = &entries[..entries.len().saturating_sub(1)];
+
+        let lines: Vec<String> = entries
+            .iter()
+            .filter(|entry| since.is_none_or(|since| entry.time >= since))
+            .filter(|entry| until.is_none_or(|until| entry.time <= until))
+            .flat_map(|entry| {
+                let timestamp = entry
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0.0, |time| time.as_secs_f64());
+
+                entry
+                    .text
+                    .lines()
+                    .filter(|line| {
+                        export
+                            .grep
+                            .as_ref()
+                            .is_none_or(|grep| line.to_lowercase().contains(grep))
+                    })
+                    .map(move |line| format!("{}  {}", format_timestamp(timestamp), line))
+            })
+            .collect();
+
+        let mut content = lines.join("\n");
+
+        if !lines.is_empty() {
+            content.push('\n');
+        }
+
+        fs::write(&export.path, content)
+            .map(|()| format!("Exported {} lines to {}", lines.len(), export.path))
+            .map_err(|err| format!("Unable to export to {}: {}", export.path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options_in_any_order() {
+        let export = Export::parse("--grep Error log.txt --since 2h --until 30m").unwrap();
+
+        assert_eq!(export.path, "log.txt");
+        assert_eq!(export.since, Some(Duration::from_secs(7200)));
+        assert_eq!(export.until, Some(Duration::from_secs(1800)));
+        assert_eq!(export.grep.as_deref(), Some("error"));
+    }
+
+    #[test]
+    fn exports_everything_by_default() {
+        let export = Export::parse("log.txt").unwrap();
+
+        assert_eq!(export.path, "log.txt");
+        assert!(export.since.is_none() && export.until.is_none() && export.grep.is_none());
+    }
+
+    #[test]
+    fn refuses_invalid_exports() {
+        for args in [
+            "",
+            "--since 2h",
+            "log.txt --since",
+            "log.txt --since soon",
+            "log.txt --after 2h",
+            "a.txt b.txt",
+        ] {
+            assert!(Export::parse(args).is_err(), "{}", args);
+        }
+    }
+}
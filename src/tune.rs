@@ -0,0 +1,112 @@
+use crate::{rpc_result, Error};
+use serde_json::json;
+
+/// The highest factor taken, in percent, way past what any print survives.
+const MAX_PERCENT: f64 = 500.0;
+
+/// What `:speed` and `:flow` adjust, in percent of what the file says.
+#[derive(Clone, Copy)]
+pub enum Factor {
+    /// `M220`
+    Speed,
+    /// `M221`, the extrusion multiplier
+    Flow,
+}
+
+#[derive(Clone, Copy)]
+pub enum Adjust {
+    Show,
+    Set(f64),
+    /// Relative to the current factor, in percentage points
+    By(f64),
+}
+
+impl Factor {
+    /// The meta-command adjusting it.
+    pub fn command(self) -> &'static str {
+        match self {
+            Factor::Speed => "speed",
+            Factor::Flow => "flow",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Factor::Speed => "Speed",
+            Factor::Flow => "Flow",
+        }
+    }
+
+    /// The `gcode_move` field, a ratio.
+    fn field(self) -> &'static str {
+        match self {
+            Factor::Speed => "speed_factor",
+            Factor::Flow => "extrude_factor",
+        }
+    }
+
+    fn gcode(self, percent: f64) -> String {
+        match self {
+            Factor::Speed => format!("M220 S{}", percent),
+            Factor::Flow => format!("M221 S{}", percent),
+        }
+    }
+}
+
+impl Adjust {
+    /// `[+n|-n|n]`, in percent.
+    pub fn parse(args: &str) -> Result<Adjust, String> {
+        let args = args.trim();
+        let invalid = || {
+            format!(
+                "Invalid factor {}, expected a percentage up to {} like 110 or +10",
+                args, MAX_PERCENT
+            )
+        };
+
+        // Rust parses inf and NaN too, which no printer should be sent
+        let percent = || {
+            args.parse::<f64>()
+                .ok()
+                .filter(|percent| percent.is_finite() && percent.abs() <= MAX_PERCENT)
+        };
+
+        if args.is_empty() {
+            Ok(Adjust::Show)
+        } else if args.starts_with(['+', '-']) {
+            percent().map(Adjust::By).ok_or_else(invalid)
+        } else {
+            percent()
+                .filter(|percent| *percent > 0.0)
+                .map(Adjust::Set)
+                .ok_or_else(invalid)
+        }
+    }
+}
+
+/// Shows or changes the factor, answering with the value it's left at.
+pub async fn run(
+    client: &reqwest::Client,
+    url: &str,
+    factor: Factor,
+    adjust: Adjust,
+) -> Result<String, Error> {
+    let params = Some(json!({ "objects": { "gcode_move": [factor.field()] } }));
+    let status = rpc_result(client, url, "printer.objects.query", params).await?;
+    let current = status["status"]["gcode_move"][factor.field()]
+        .as_f64()
+        .map(|ratio| (ratio * 100.0).round())
+        .ok_or_else(|| Error::Rpc(format!("{} factor unknown", factor.name())))?;
+
+    let percent = match adjust {
+        Adjust::Show => return Ok(format!("{} {}%", factor.name(), current)),
+        Adjust::Set(percent) => percent.round(),
+        // Never down to nothing, Klipper refuses it
+        Adjust::By(points) => (current + points).round().clamp(1.0, MAX_PERCENT),
+    };
+
+    let params = Some(json!({ "script": factor.gcode(percent) }));
+    rpc_result(client, url, "printer.gcode.script", params).await?;
+
+    Ok(format!("{} {}%, was {}%", factor.name(), percent, current))
+}
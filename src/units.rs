@@ -0,0 +1,106 @@
+use serde::Deserialize;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// Units temperatures and positions are shown in, Klipper always gets
+/// Celsius and millimeters.
+///
+/// ```toml
+/// [units]
+/// temperature = "fahrenheit"  # or "celsius"
+/// length = "in"               # or "mm"
+/// ```
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct Units {
+    pub temperature: Temperature,
+    pub length: Length,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Temperature {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Length {
+    #[default]
+    Mm,
+    In,
+}
+
+impl Units {
+    /// A temperature in Celsius converted to the display unit.
+    pub fn temperature(self, celsius: f64) -> f64 {
+        match self.temperature {
+            Temperature::Celsius => celsius,
+            Temperature::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    pub fn temperature_symbol(self) -> &'static str {
+        match self.temperature {
+            Temperature::Celsius => "°C",
+            Temperature::Fahrenheit => "°F",
+        }
+    }
+
+    /// Like `210°C` or `410°F`.
+    pub fn format_temperature(self, celsius: f64) -> String {
+        format!(
+            "{:.0}{}",
+            self.temperature(celsius),
+            self.temperature_symbol()
+        )
+    }
+
+    /// A length in millimeters converted to the display unit.
+    pub fn length(self, mm: f64) -> f64 {
+        match self.length {
+            Length::Mm => mm,
+            Length::In => mm / MM_PER_INCH,
+        }
+    }
+
+    /// A length typed in the display unit converted to millimeters.
+    pub fn to_mm(self, length: f64) -> f64 {
+        match self.length {
+            Length::Mm => length,
+            Length::In => length * MM_PER_INCH,
+        }
+    }
+
+    pub fn length_symbol(self) -> &'static str {
+        match self.length {
+            Length::Mm => "mm",
+            Length::In => "in",
+        }
+    }
+
+    /// Like `12.50` or `0.492`, `format_length` without the symbol.
+    pub fn format_length_value(self, mm: f64) -> String {
+        match self.length {
+            Length::Mm => format!("{:.2}", mm),
+            Length::In => format!("{:.3}", self.length(mm)),
+        }
+    }
+
+    /// Like `12.50 mm` or `0.492 in`, inches need one more decimal to be
+    /// as precise.
+    pub fn format_length(self, mm: f64) -> String {
+        format!("{} {}", self.format_length_value(mm), self.length_symbol())
+    }
+
+    /// Like `+0.025` or `-0.0012`, signed and precise enough for how far
+    /// from flat a bed is, without the symbol.
+    pub fn format_deviation(self, mm: f64) -> String {
+        match self.length {
+            Length::Mm => format!("{:+.3}", mm),
+            Length::In => format!("{:+.4}", self.length(mm)),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::auth::{self, Auth};
+use crate::cleanup::format_size;
+use crate::cli::OutputFormat;
+use crate::output;
+use crate::tls::Tls;
+use crate::{rpc_result, Error, JSON};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, StatusCode};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::task::JoinHandle;
+
+/// Read from the file and handed to the request at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How often the progress bar is redrawn on stderr.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How many times a file is sent before giving up, each time from the start.
+const UPLOAD_ATTEMPTS: usize = 3;
+
+/// Waited before sending the file again, once more for each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Cells of the progress bar.
+const BAR_WIDTH: usize = 20;
+
+/// The upload or download going on, if any, for the status bar to show.
+#[derive(Clone, Default)]
+pub struct Progress(Arc<Mutex<Option<Transfer>>>);
+
+struct Transfer {
+    name: String,
+    sent: u64,
+    total: u64,
+    started: Instant,
+    /// From 1, an interrupted upload is sent again from the start
+    attempt: usize,
+}
+
+impl Progress {
+    /// `total` is 0 when unknown.
+    pub fn start(&self, name: &str, total: u64) {
+        *self.0.lock().unwrap() = Some(Transfer {
+            name: name.to_string(),
+            sent: 0,
+            total,
+            started: Instant::now(),
+            attempt: 1,
+        });
+    }
+
+    /// The file is sent again from its first byte, as the `attempt`th time.
+    pub fn restart(&self, attempt: usize) {
+        if let Some(transfer) = self.0.lock().unwrap().as_mut() {
+            transfer.sent = 0;
+            transfer.started = Instant::now();
+            transfer.attempt = attempt;
+        }
+    }
+
+    pub fn advance(&self, bytes: u64) {
+        if let Some(transfer) = self.0.lock().unwrap().as_mut() {
+            transfer.sent += bytes;
+        }
+    }
+
+    pub fn finish(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    /// Like `cube.gcode [#########-----------] 45% 12.3 MiB/s`.
+    pub fn render(&self) -> Option<String> {
+        let transfer = self.0.lock().unwrap();
+        let transfer = transfer.as_ref()?;
+        // More than announced when the file grows meanwhile, or a
+        // Content-Length is short
+        let ratio = match transfer.total {
+            0 => 1.0,
+            total => (transfer.sent as f64 / total as f64).clamp(0.0, 1.0),
+        };
+        let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+        let rate = transfer.sent as f64 / transfer.started.elapsed().as_secs_f64().max(0.001);
+
+        let mut bar = format!(
+            "{} [{}{}] {:.0}% {}/s",
+            transfer.name,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            ratio * 100.0,
+            format_size(rate as u64)
+        );
+
+        if transfer.attempt > 1 {
+            bar.push_str(&format!(
+                " restarted, attempt {}/{}",
+                transfer.attempt, UPLOAD_ATTEMPTS
+            ));
+        }
+
+        Some(bar)
+    }
+
+    /// Redraws the bar on stderr while the transfer goes on, unless it's
+    /// not a terminal.
+    pub fn draw(&self) -> Bar {
+        let progress = self.clone();
+
+        Bar(io::stderr().is_terminal().then(|| {
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(REDRAW_INTERVAL);
+
+                loop {
+                    ticker.tick().await;
+
+                    if let Some(bar) = progress.render() {
+                        eprint!("\r\x1b[2K{}", bar);
+                        let _ = io::stderr().flush();
+                    }
+                }
+            })
+        }))
+    }
+}
+
+/// The progress bar drawn by the subcommands.
+pub struct Bar(Option<JoinHandle<()>>);
+
+impl Bar {
+    pub fn erase(self) {
+        if let Some(task) = self.0 {
+            task.abort();
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
+
+/// Uploads `path` into `dir` under the gcodes root, empty for the root
+/// itself, with `/server/files/upload`, a chunk at a time so `progress`
+/// follows, starting the print after with `print`. Returns Moonraker's
+/// response.
+///
+/// Moonraker checks the file against its SHA-256 checksum, refusing it
+/// corrupted. It can't take the rest of a file from an offset, so an upload
+/// interrupted or found corrupted is restarted, sending the whole file again
+/// from its first byte, up to `UPLOAD_ATTEMPTS` times in all. The size it
+/// then reports must match too before the print starts, a mismatch fails
+/// the upload.
+pub async fn upload(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    dir: &str,
+    print: bool,
+    progress: &Progress,
+) -> Result<JSON, Error> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| Error::Env(format!("{} is not a file", path.display())))?;
+    let checksum = checksum(path).await?;
+    let size = tokio::fs::metadata(path).await?.len();
+    let mut attempt = 1;
+    progress.start(&name, size);
+
+    let result = loop {
+        let err = match send(client, url, path, dir, &name, &checksum, progress).await {
+            Ok((status, body)) if status.is_success() => break Ok(body),
+            Ok((status, body)) => {
+                let message = body["error"]["message"].as_str().unwrap_or_default();
+                let corrupted = status == StatusCode::UNPROCESSABLE_ENTITY
+                    && message.to_lowercase().contains("checksum");
+                let err = Error::Rpc(
+                    format!("Upload failed with {} {}", status, message)
+                        .trim()
+                        .to_string(),
+                );
+
+                if !corrupted && !status.is_server_error() {
+                    break Err(err);
+                }
+
+                err
+            }
+            // Reading the file failed, sending it again won't help
+            Err(Error::IO(err)) => break Err(Error::IO(err)),
+            Err(err) => err,
+        };
+
+        if attempt == UPLOAD_ATTEMPTS {
+            break Err(err);
+        }
+
+        tracing::warn!(%err, attempt, "upload interrupted, restarting it from the start");
+        tokio::time::sleep(RETRY_DELAY * attempt as u32).await;
+        attempt += 1;
+        progress.restart(attempt);
+    };
+
+    progress.finish();
+    let mut resp = result?;
+
+    verify(client, url, &resp, size).await?;
+
+    // Started here rather than by Moonraker, after the checks
+    if print {
+        let filename = resp["item"]["path"].as_str().unwrap_or_default();
+        let params = Some(json!({ "filename": filename }));
+        rpc_result(client, url, "printer.print.start", params).await?;
+        resp["print_started"] = JSON::Bool(true);
+    }
+
+    Ok(resp)
+}
+
+/// Fails unless the file Moonraker stored is `size` bytes long.
+async fn verify(client: &reqwest::Client, url: &str, resp: &JSON, size: u64) -> Result<(), Error> {
+    let filename = resp["item"]["path"]
+        .as_str()
+        .ok_or_else(|| Error::Rpc("Upload answered without the file path".to_string()))?;
+    let params = Some(json!({ "filename": filename }));
+    let metadata = rpc_result(client, url, "server.files.metadata", params).await?;
+
+    match metadata["size"].as_u64() {
+        Some(stored) if stored == size => Ok(()),
+        stored => Err(Error::Rpc(format!(
+            "{} is {} on the printer but {} was sent, it may be corrupted",
+            filename,
+            stored.map_or("of unknown size".to_string(), format_size),
+            format_size(size)
+        ))),
+    }
+}
+
+/// A single attempt sending the whole file, the status and body of the
+/// response unless it couldn't be sent.
+async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    dir: &str,
+    name: &str,
+    checksum: &str,
+    progress: &Progress,
+) -> Result<(StatusCode, JSON), Error> {
+    let file = tokio::fs::File::open(path).await?;
+    let total = file.metadata().await?.len();
+
+    let chunks = futures_util::stream::try_unfold(
+        (file, progress.clone()),
+        |(mut file, progress)| async move {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let read = file.read(&mut chunk).await?;
+
+            if read == 0 {
+                return Ok::<_, io::Error>(None);
+            }
+
+            chunk.truncate(read);
+            progress.advance(read as u64);
+            Ok(Some((chunk, (file, progress))))
+        },
+    );
+
+    let part =
+        Part::stream_with_length(Body::wrap_stream(chunks), total).file_name(name.to_string());
+    let mut form = Form::new()
+        .text("root", "gcodes")
+        .text("checksum", checksum.to_string());
+
+    if !dir.is_empty() {
+        form = form.text("path", dir.to_string());
+    }
+
+    let form = form.part("file", part);
+
+    let request = client.post(format!("{}/server/files/upload", url));
+    let resp = auth::authorize(request, url).multipart(form).send().await?;
+    let status = resp.status();
+    // Proxies refusing large bodies answer with HTML
+    let body = serde_json::from_str(&resp.text().await?).unwrap_or_default();
+
+    Ok((status, body))
+}
+
+/// The SHA-256 of the file as hex, read off the async runtime.
+pub async fn checksum(path: &Path) -> Result<String, Error> {
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(Error::JoinError)?
+}
+
+/// What the upload response means for people.
+pub fn describe(resp: &JSON) -> String {
+    let path = resp["item"]["path"].as_str().unwrap_or("the file");
+
+    if resp["print_started"].as_bool() == Some(true) {
+        format!("Uploaded {}, print started", path)
+    } else {
+        format!("Uploaded {}", path)
+    }
+}
+
+/// `moonraker-cli upload <file> [--print]`, uploads with a progress bar
+/// on stderr when it's a terminal.
+pub async fn run(
+    url: &str,
+    path: &Path,
+    print: bool,
+    format: OutputFormat,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+
+    let progress = Progress::default();
+    let bar = progress.draw();
+    let resp = upload(&client, url, path, "", print, &progress).await;
+    bar.erase();
+
+    let resp = resp?;
+
+    match output::render(resp.clone(), format)? {
+        Some(output) => println!("{}", output),
+        None => println!("{}", describe(&resp)),
+    }
+
+    Ok(())
+}
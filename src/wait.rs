@@ -0,0 +1,79 @@
+use crate::auth::Auth;
+use crate::cli::Until;
+use crate::notifications;
+use crate::tls::Tls;
+use crate::traffic::Traffic;
+use crate::{klippy_ready, Error, JSON};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Error as WsError;
+
+/// `moonraker-cli wait --until <state> [--timeout <duration>]`, follows the
+/// print state and returns once it's reached. Waiting for a print to
+/// complete fails when it ends otherwise.
+pub async fn run(
+    url: &str,
+    until: Until,
+    timeout: Option<Duration>,
+    tls: &Tls,
+    auth: Auth,
+) -> Result<(), Error> {
+    let client = tls.client_builder()?.build()?;
+    let auth = auth.for_printer(url);
+    auth.restore(&client, url).await;
+    klippy_ready(&client, url).await?;
+
+    let waiting = follow(&client, url, until, tls);
+
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, waiting)
+            .await
+            .map_err(|_| Error::Timeout(timeout))?,
+        None => waiting.await,
+    }
+}
+
+async fn follow(client: &reqwest::Client, url: &str, until: Until, tls: &Tls) -> Result<(), Error> {
+    let mut socket = notifications::connect(client, url, tls, &Traffic::default()).await?;
+    let subscribe = json!({ "objects": { "print_stats": ["state"] } });
+
+    socket
+        .send(notifications::request(
+            1,
+            "printer.objects.subscribe",
+            subscribe,
+        ))
+        .await?;
+
+    while let Some(message) = socket.next().await {
+        let Ok(value) = serde_json::from_str::<JSON>(message?.to_text()?) else {
+            continue;
+        };
+
+        // The subscription response carries the initial state, later
+        // changes come as notifications
+        let state = match value["method"].as_str() {
+            Some("notify_status_update") => &value["params"][0]["print_stats"]["state"],
+            Some("notify_klippy_shutdown") => return Err(Error::Klippy("shutdown".to_string())),
+            Some(_) => continue,
+            None => &value["result"]["status"]["print_stats"]["state"],
+        };
+
+        let Some(state) = state.as_str() else {
+            continue;
+        };
+
+        match (until, state) {
+            (Until::Idle, "standby" | "complete" | "cancelled" | "error") => return Ok(()),
+            (Until::Complete, "complete") => return Ok(()),
+            (Until::Complete, "cancelled" | "error") => {
+                return Err(Error::Rpc(format!("Print ended {}", state)))
+            }
+            (Until::Paused, "paused") => return Ok(()),
+            _ => {}
+        }
+    }
+
+    Err(Error::Websocket(WsError::ConnectionClosed))
+}
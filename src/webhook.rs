@@ -0,0 +1,109 @@
+use crate::{rpc_result, Error, JSON};
+use base64::Engine;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// Endpoint notified when a print completes or fails.
+#[derive(Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Attach a webcam snapshot to the payload
+    pub snapshot: bool,
+}
+
+/// The webhook currently registered with `:webhook`, if any.
+#[derive(Clone, Default)]
+pub struct Webhooks(Arc<Mutex<Option<Webhook>>>);
+
+impl Webhooks {
+    pub fn set(&self, webhook: Option<Webhook>) {
+        *self.0.lock().unwrap() = webhook;
+    }
+
+    pub fn get(&self) -> Option<Webhook> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// POSTs a JSON payload describing the print `event` to the webhook, e.g.
+///
+/// ```json
+/// { "event": "complete", "printer": "http://voron.local", "filename": "benchy.gcode",
+///   "message": "Print complete",
+///   "snapshot": { "url": "http://voron.local/webcam/?action=snapshot",
+///                 "content_type": "image/jpeg", "data": "<base64>" } }
+/// ```
+///
+/// A snapshot that can't be taken is left out rather than failing the hook.
+pub async fn fire(
+    client: &reqwest::Client,
+    printer_url: &str,
+    webhook: &Webhook,
+    event: &str,
+    filename: Option<&str>,
+    message: &str,
+) -> Result<(), Error> {
+    let mut payload = json!({
+        "event": event,
+        "printer": printer_url,
+        "filename": filename,
+        "message": message,
+    });
+
+    if webhook.snapshot {
+        if let Ok(snapshot) = snapshot(client, printer_url).await {
+            payload["snapshot"] = snapshot;
+        }
+    }
+
+    client
+        .post(&webhook.url)
+        .json(&payload)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+/// Fetches an image from the first enabled webcam configured in Moonraker.
+async fn snapshot(client: &reqwest::Client, printer_url: &str) -> Result<JSON, Error> {
+    let webcams = rpc_result(client, printer_url, "server.webcams.list", None).await?;
+
+    let snapshot_url = webcams["webcams"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|webcam| webcam["enabled"].as_bool().unwrap_or(true))
+        .find_map(|webcam| webcam["snapshot_url"].as_str())
+        .ok_or_else(|| Error::Rpc("no webcam with a snapshot URL".to_string()))?;
+
+    // Snapshot URLs are usually relative, like /webcam/?action=snapshot,
+    // served by the web frontend on the default port rather than by the
+    // Moonraker API on 7125
+    let snapshot_url = reqwest::Url::parse(printer_url)
+        .and_then(|mut base| {
+            let _ = base.set_port(None);
+            base.join(snapshot_url)
+        })
+        .map_err(|err| Error::Env(format!("invalid snapshot URL {}: {}", snapshot_url, err)))?;
+
+    let resp = client
+        .get(snapshot_url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("image/jpeg")
+        .to_string();
+    let image = resp.bytes().await?;
+
+    Ok(json!({
+        "url": snapshot_url.as_str(),
+        "content_type": content_type,
+        "data": base64::engine::general_purpose::STANDARD.encode(image),
+    }))
+}